@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use crate::decode::resolve_target;
+use crate::error::Error;
+use crate::opcode::Opcode;
+use crate::program::Program;
+
+/// The magic number at the beginning of a binary program.
+const MAGIC: &[u8; 4] = b"PCVM";
+/// The version of the binary format written by [`encode`].
+const FORMAT_VERSION: u16 = 1;
+/// The size of the header: magic, version, length of the body, and CRC.
+const HEADER_SIZE: usize = 4 + 2 + 4 + 4;
+
+/// Computes CRC-32 (IEEE 802.3) of bytes.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+fn write_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_str(buf: &mut Vec<u8>, s: &str) {
+    write_u32(buf, s.len() as u32);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// Splits an instruction into its tag and operands.
+fn fields(inst: &Opcode) -> (u8, Option<i64>, Option<&str>) {
+    match inst {
+        Opcode::Pushl(n) => (0, Some(*n as i64), None),
+        Opcode::Storel(n) => (1, Some(*n as i64), None),
+        Opcode::Storet(n) => (2, Some(*n as i64), None),
+        Opcode::Pushi(d) => (3, Some(*d as i64), None),
+        Opcode::Call(label) => (4, None, Some(label)),
+        Opcode::Ret => (5, None, None),
+        Opcode::Enter => (6, None, None),
+        Opcode::Leave => (7, None, None),
+        Opcode::Mvsp(n) => (8, Some(*n as i64), None),
+        Opcode::Jp(label) => (9, None, Some(label)),
+        Opcode::Jt(label) => (10, None, Some(label)),
+        Opcode::Jf(label) => (11, None, Some(label)),
+        Opcode::Add => (12, None, None),
+        Opcode::Sub => (13, None, None),
+        Opcode::Mul => (14, None, None),
+        Opcode::Div => (15, None, None),
+        Opcode::Mod => (16, None, None),
+        Opcode::Eq => (17, None, None),
+        Opcode::Ne => (18, None, None),
+        Opcode::Gt => (19, None, None),
+        Opcode::Ge => (20, None, None),
+        Opcode::Lt => (21, None, None),
+        Opcode::Le => (22, None, None),
+        Opcode::Rd => (23, None, None),
+        Opcode::Rdt => (24, None, None),
+        Opcode::Wr => (25, None, None),
+        Opcode::Wrln => (26, None, None),
+        Opcode::Wrch => (27, None, None),
+        Opcode::Wrf(width) => (28, Some(*width as i64), None),
+        Opcode::Wrz(width) => (29, Some(*width as i64), None),
+        Opcode::Alloc => (30, None, None),
+        Opcode::Free => (31, None, None),
+        Opcode::Ld => (32, None, None),
+        Opcode::St => (33, None, None),
+        Opcode::Newref(n) => (34, Some(*n as i64), None),
+        Opcode::Getf(i) => (35, Some(*i as i64), None),
+        Opcode::Setf(i) => (36, Some(*i as i64), None),
+        Opcode::Pushs(s) => (37, None, Some(s)),
+        Opcode::Scat => (38, None, None),
+        Opcode::Scmp => (39, None, None),
+        Opcode::Slen => (40, None, None),
+        Opcode::Wrs => (41, None, None),
+        Opcode::Custom(name, operand) => (42, Some(*operand as i64), Some(name)),
+        Opcode::Halt => (43, None, None),
+        Opcode::Trap(line) => (44, None, Some(line)),
+        Opcode::Jr(offset) => (45, Some(*offset as i64), None),
+        Opcode::Jrt(offset) => (46, Some(*offset as i64), None),
+        Opcode::Jrf(offset) => (47, Some(*offset as i64), None),
+        Opcode::Storei => (48, None, None),
+        Opcode::Pushpc => (49, None, None),
+        Opcode::Pushsp => (50, None, None),
+        Opcode::Pushfp => (51, None, None),
+        Opcode::Salloc => (52, None, None),
+    }
+}
+
+/// Writes an instruction: a tag followed by its operand.
+fn write_inst(buf: &mut Vec<u8>, inst: &Opcode) {
+    let (tag, int, text) = fields(inst);
+
+    buf.push(tag);
+    if let Some(text) = text {
+        write_str(buf, text);
+    }
+    if let Some(int) = int {
+        write_u32(buf, int as u32);
+    }
+}
+
+/// The range of an operand of an instruction word.
+const WORD_OPERAND_RANGE: std::ops::RangeInclusive<i64> = -(1 << 23)..=(1 << 23) - 1;
+
+/// Encodes an instruction into a word: the tag in the upper 8 bits and the operand in the lower 24 bits.
+pub(crate) fn encode_word(inst: &Opcode, labels: &HashMap<String, usize>) -> Option<i32> {
+    let (tag, int, text) = match inst {
+        Opcode::Call(label) | Opcode::Jp(label) | Opcode::Jt(label) | Opcode::Jf(label) => {
+            let (tag, _, _) = fields(inst);
+            (tag, Some(resolve_target(labels, label)? as i64), None)
+        },
+        _ => fields(inst),
+    };
+    let operand = int.unwrap_or(0);
+    if text.is_some() || !WORD_OPERAND_RANGE.contains(&operand) {
+        return None;
+    }
+
+    Some(((tag as u32) << 24 | (operand as u32 & 0x00ff_ffff)) as i32)
+}
+
+/// Decodes a word encoded by [`encode_word`].
+pub(crate) fn decode_word(word: i32) -> Result<Opcode, Error> {
+    let tag = (word as u32 >> 24) as u8;
+    // Sign-extends the lower 24 bits
+    let operand = (word << 8) >> 8;
+
+    let mut buf = vec![tag];
+    match tag {
+        4 | 9 | 10 | 11 => write_str(&mut buf, &operand.to_string()),
+        37 | 42 | 44 => return Err(Error::InvalidInstructionWord(word)),
+        _ => write_u32(&mut buf, operand as u32),
+    }
+
+    let inst = Reader { bytes: &buf }.inst()
+        .map_err(|_| Error::InvalidInstructionWord(word))?;
+
+    // Rejects the bits which are not encoded from the instruction (e.g. an operand of `add`)
+    if encode_word(&inst, &HashMap::new()) != Some(word) {
+        return Err(Error::InvalidInstructionWord(word));
+    }
+
+    Ok(inst)
+}
+
+/// Encodes a program into the binary format.
+///
+/// Debug information is not included.
+pub fn encode(program: &Program) -> Vec<u8> {
+    let mut body = Vec::new();
+
+    write_u32(&mut body, program.insts.len() as u32);
+    for inst in &program.insts {
+        write_inst(&mut body, inst);
+    }
+
+    // Labels are sorted so that the same program is always encoded into the same bytes
+    let mut labels: Vec<_> = program.labels.iter().collect();
+    labels.sort();
+    write_u32(&mut body, labels.len() as u32);
+    for (label, &addr) in labels {
+        write_str(&mut body, label);
+        write_u32(&mut body, addr as u32);
+    }
+
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + body.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    write_u32(&mut bytes, body.len() as u32);
+    write_u32(&mut bytes, crc32(&body));
+    bytes.extend_from_slice(&body);
+
+    bytes
+}
+
+/// A cursor reading the body of a binary program.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], Error> {
+        if self.bytes.len() < len {
+            return Err(Error::InvalidBinary("unexpected end of data".to_string()));
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, Error> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn usize(&mut self) -> Result<usize, Error> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let len = self.usize()?;
+        let bytes = self.take(len)?;
+
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| Error::InvalidBinary("invalid UTF-8 string".to_string()))
+    }
+
+    fn inst(&mut self) -> Result<Opcode, Error> {
+        let inst = match self.u8()? {
+            0 => Opcode::Pushl(self.i32()?),
+            1 => Opcode::Storel(self.i32()?),
+            2 => Opcode::Storet(self.i32()?),
+            3 => Opcode::Pushi(self.i32()?),
+            4 => Opcode::Call(self.string()?),
+            5 => Opcode::Ret,
+            6 => Opcode::Enter,
+            7 => Opcode::Leave,
+            8 => Opcode::Mvsp(self.i32()?),
+            9 => Opcode::Jp(self.string()?),
+            10 => Opcode::Jt(self.string()?),
+            11 => Opcode::Jf(self.string()?),
+            12 => Opcode::Add,
+            13 => Opcode::Sub,
+            14 => Opcode::Mul,
+            15 => Opcode::Div,
+            16 => Opcode::Mod,
+            17 => Opcode::Eq,
+            18 => Opcode::Ne,
+            19 => Opcode::Gt,
+            20 => Opcode::Ge,
+            21 => Opcode::Lt,
+            22 => Opcode::Le,
+            23 => Opcode::Rd,
+            24 => Opcode::Rdt,
+            25 => Opcode::Wr,
+            26 => Opcode::Wrln,
+            27 => Opcode::Wrch,
+            28 => Opcode::Wrf(self.usize()?),
+            29 => Opcode::Wrz(self.usize()?),
+            30 => Opcode::Alloc,
+            31 => Opcode::Free,
+            32 => Opcode::Ld,
+            33 => Opcode::St,
+            34 => Opcode::Newref(self.usize()?),
+            35 => Opcode::Getf(self.usize()?),
+            36 => Opcode::Setf(self.usize()?),
+            37 => Opcode::Pushs(self.string()?),
+            38 => Opcode::Scat,
+            39 => Opcode::Scmp,
+            40 => Opcode::Slen,
+            41 => Opcode::Wrs,
+            42 => {
+                let name = self.string()?;
+                Opcode::Custom(name, self.i32()?)
+            },
+            43 => Opcode::Halt,
+            44 => Opcode::Trap(self.string()?),
+            45 => Opcode::Jr(self.i32()?),
+            46 => Opcode::Jrt(self.i32()?),
+            47 => Opcode::Jrf(self.i32()?),
+            48 => Opcode::Storei,
+            49 => Opcode::Pushpc,
+            50 => Opcode::Pushsp,
+            51 => Opcode::Pushfp,
+            52 => Opcode::Salloc,
+            tag => return Err(Error::InvalidBinary(format!("unknown instruction tag {}", tag))),
+        };
+
+        Ok(inst)
+    }
+}
+
+/// Decodes a program from the binary format.
+pub fn decode(bytes: &[u8]) -> Result<Program, Error> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(Error::InvalidBinary("not a picoc vm program".to_string()));
+    }
+    if bytes.len() < HEADER_SIZE {
+        return Err(Error::InvalidBinary("truncated header".to_string()));
+    }
+
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != FORMAT_VERSION {
+        return Err(Error::UnsupportedFormatVersion(version));
+    }
+    let len = u32::from_le_bytes(bytes[6..10].try_into().unwrap()) as usize;
+    let expected = u32::from_le_bytes(bytes[10..14].try_into().unwrap());
+    let body = &bytes[HEADER_SIZE..];
+    if body.len() != len {
+        return Err(Error::InvalidBinary(format!("expected {} bytes of body, found {}", len, body.len())));
+    }
+    let actual = crc32(body);
+    if actual != expected {
+        return Err(Error::ChecksumMismatch(expected, actual));
+    }
+
+    let mut reader = Reader { bytes: body };
+    let inst_count = reader.usize()?;
+    let mut insts = Vec::new();
+    for _ in 0..inst_count {
+        insts.push(reader.inst()?);
+    }
+    let label_count = reader.usize()?;
+    let mut labels = HashMap::new();
+    for _ in 0..label_count {
+        let label = reader.string()?;
+        labels.insert(label, reader.usize()?);
+    }
+    if !reader.bytes.is_empty() {
+        return Err(Error::InvalidBinary("trailing data".to_string()));
+    }
+
+    Ok(Program::new(insts, labels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn checksum() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn detect_corruption() {
+        let program = Program::assemble(Cursor::new(b"main:\npushs \"hi\"\nwrs\njp main\n")).unwrap();
+        let bytes = encode(&program);
+
+        assert_eq!(decode(&bytes).unwrap(), program);
+
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(matches!(decode(&corrupted), Err(Error::ChecksumMismatch(_, _))));
+
+        let mut future = bytes.clone();
+        future[4] = 2;
+        assert!(matches!(decode(&future), Err(Error::UnsupportedFormatVersion(2))));
+
+        assert!(matches!(decode(&bytes[..bytes.len() - 1]), Err(Error::InvalidBinary(_))));
+        assert!(matches!(decode(b"pushi 1\n"), Err(Error::InvalidBinary(_))));
+    }
+}
@@ -0,0 +1,233 @@
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use crate::memory::Region;
+
+/// A kind of bad read of a stack slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StackIssueKind {
+    /// The slot is allocated (e.g. by `mvsp` or `salloc`) but never written.
+    Uninitialized,
+    /// The slot is released by `leave` or `mvsp` and not allocated again, e.g. a local of a returned function.
+    Released,
+}
+
+/// A bad read of a stack slot, found while the VM runs with [`Config::check_uninitialized`](crate::Config::check_uninitialized)
+/// or [`Config::poison_frames`](crate::Config::poison_frames).
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{PicocVm, Config, Error, StackIssueKind};
+///
+/// fn main() -> Result<(), Error> {
+///     let mut input = Cursor::new(b"");
+///     let mut output = Cursor::new(Vec::new());
+///
+///     let config = Config { check_uninitialized: true, ..Config::default() };
+///     let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+///
+///     vm.load(Cursor::new(b"
+///         main:
+///             enter
+///             mvsp -2
+///             pushi 1
+///             storel -1
+///             pushl -1
+///             pushl -2
+///             halt"))?;
+///     vm.run_until_halt()?;
+///
+///     let issues = vm.stack_issues();
+///     assert_eq!(issues.len(), 1);
+///     assert_eq!(issues[0].pc, 5);
+///     assert_eq!(issues[0].offset, -2);
+///     assert_eq!(issues[0].kind, StackIssueKind::Uninitialized);
+///     assert_eq!(issues[0].to_string(), "address 5: read of the uninitialized local at FP-2");
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StackIssue {
+    /// The address of the instruction which reads the slot.
+    pub pc: usize,
+    /// The address of the slot.
+    pub addr: usize,
+    /// The offset of the slot from FP.
+    pub offset: i64,
+    /// What is wrong with the slot.
+    pub kind: StackIssueKind,
+}
+
+impl Display for StackIssueKind {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            StackIssueKind::Uninitialized => write!(f, "read of the uninitialized local"),
+            StackIssueKind::Released => write!(f, "read of the released slot"),
+        }
+    }
+}
+
+impl Display for StackIssue {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "address {}: {} at FP{:+}", self.pc, self.kind, self.offset)
+    }
+}
+
+/// The state of a stack slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    Uninitialized,
+    Written,
+    /// Released from a frame.
+    Poisoned,
+}
+
+/// Tracks which stack slots are written since they are allocated, and which are released.
+///
+/// A slot is written by a push or a store, and becomes uninitialized when SP moves over it
+/// without writing (by `mvsp` or `salloc`), so a new frame does not see the values of an old one.
+/// A slot is poisoned when a frame is released by `leave` or `mvsp`, until it is allocated again.
+#[derive(Debug, Clone)]
+pub(crate) struct StackChecker {
+    stack: Region,
+    slots: Vec<Slot>,
+    issues: Vec<StackIssue>,
+    /// Whether reads of uninitialized slots are reported.
+    uninitialized: bool,
+    /// Whether released slots are poisoned.
+    poison: bool,
+}
+
+impl StackChecker {
+    pub(crate) fn new(stack: Region, uninitialized: bool, poison: bool) -> Self {
+        Self {
+            stack,
+            slots: vec![Slot::Uninitialized; stack.size],
+            issues: Vec::new(),
+            uninitialized,
+            poison,
+        }
+    }
+
+    /// Forgets the states of all slots and the issues found.
+    pub(crate) fn reset(&mut self) {
+        self.slots.fill(Slot::Uninitialized);
+        self.issues.clear();
+    }
+
+    /// Regards all slots as written, e.g. after the stack is restored from a snapshot.
+    pub(crate) fn trust_all(&mut self) {
+        self.slots.fill(Slot::Written);
+    }
+
+    fn slot_mut(&mut self, addr: usize) -> Option<&mut Slot> {
+        self.slots.get_mut(addr.checked_sub(self.stack.base)?)
+    }
+
+    /// Marks a slot as written. An address out of the stack is ignored.
+    pub(crate) fn write(&mut self, addr: usize) {
+        if let Some(slot) = self.slot_mut(addr) {
+            *slot = Slot::Written;
+        }
+    }
+
+    /// Marks slots allocated without writing as uninitialized.
+    pub(crate) fn allocate(&mut self, addrs: Range<usize>) {
+        for addr in addrs {
+            if let Some(slot) = self.slot_mut(addr) {
+                *slot = Slot::Uninitialized;
+            }
+        }
+    }
+
+    /// Poisons slots released from a frame.
+    pub(crate) fn release(&mut self, addrs: Range<usize>) {
+        if !self.poison {
+            return;
+        }
+        for addr in addrs {
+            if let Some(slot) = self.slot_mut(addr) {
+                *slot = Slot::Poisoned;
+            }
+        }
+    }
+
+    /// Checks a read of a slot by an instruction at `pc`.
+    ///
+    /// Each instruction is reported once for each kind of issues.
+    pub(crate) fn read(&mut self, pc: usize, addr: usize, fp: usize) {
+        let kind = match self.slot_mut(addr).copied() {
+            Some(Slot::Uninitialized) if self.uninitialized => StackIssueKind::Uninitialized,
+            Some(Slot::Poisoned) => StackIssueKind::Released,
+            _ => return,
+        };
+        if self.issues.iter().any(|issue| issue.pc == pc && issue.kind == kind) {
+            return;
+        }
+
+        self.issues.push(StackIssue { pc, addr, offset: addr as i64 - fp as i64, kind });
+    }
+
+    pub(crate) fn issues(&self) -> &[StackIssue] {
+        &self.issues
+    }
+}
+
+/// What a stack slot holds, tagged as it is written with [`Config::tag_slots`](crate::Config::tag_slots).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotTag {
+    /// A value pushed or stored by an instruction other than `call` and `enter`.
+    Data,
+    /// FP saved by `enter`.
+    SavedFp,
+    /// A return address pushed by `call`.
+    ReturnAddress,
+}
+
+impl Display for SlotTag {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            SlotTag::Data => write!(f, "data"),
+            SlotTag::SavedFp => write!(f, "a saved FP"),
+            SlotTag::ReturnAddress => write!(f, "a return address"),
+        }
+    }
+}
+
+/// The tags of stack slots. A slot not written since it is allocated has no tag.
+#[derive(Debug, Clone)]
+pub(crate) struct SlotTags {
+    stack: Region,
+    tags: Vec<Option<SlotTag>>,
+}
+
+impl SlotTags {
+    pub(crate) fn new(stack: Region) -> Self {
+        Self { stack, tags: vec![None; stack.size] }
+    }
+
+    /// Forgets the tags of all slots.
+    pub(crate) fn clear(&mut self) {
+        self.tags.fill(None);
+    }
+
+    /// Forgets the tags of slots, e.g. when they are allocated without writing.
+    pub(crate) fn forget(&mut self, addrs: Range<usize>) {
+        for addr in addrs {
+            self.tag(addr, None);
+        }
+    }
+
+    /// Tags a slot. An address out of the stack is ignored.
+    pub(crate) fn tag(&mut self, addr: usize, tag: Option<SlotTag>) {
+        if let Some(slot) = addr.checked_sub(self.stack.base).and_then(|i| self.tags.get_mut(i)) {
+            *slot = tag;
+        }
+    }
+
+    pub(crate) fn get(&self, addr: usize) -> Option<SlotTag> {
+        self.tags.get(addr.checked_sub(self.stack.base)?).copied().flatten()
+    }
+}
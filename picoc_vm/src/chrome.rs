@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::ops::Range;
+use crate::debug::DebugInfo;
+use crate::error::Error;
+use crate::event::VmEvent;
+use crate::inst::blocks;
+use crate::program::Program;
+use crate::state::json_string;
+use crate::vm::PicocVm;
+
+/// A recorder of a run in the trace event format of Chrome,
+/// which can be explored in `chrome://tracing` or [Perfetto](https://ui.perfetto.dev).
+///
+/// Each call is a span from `call` to `ret`, named by the function and timed by steps (1 step is 1 µs).
+/// The end of a span has the number of instructions executed in the call.
+/// At the end of the trace, a counter gives the number of instructions executed in each basic block.
+///
+/// Calls are found by [`VmEvent`]s, so the VM should be configured with [`Config::record_events`](crate::Config::record_events).
+/// The events are drained by the recorder.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{PicocVm, ChromeTrace, Config, Error};
+///
+/// fn main() -> Result<(), Error> {
+///     let mut input = Cursor::new(b"");
+///     let mut output = Cursor::new(Vec::new());
+///     let config = Config { record_events: true, ..Config::default() };
+///
+///     let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+///     vm.load(Cursor::new(b"
+///         main:
+///             call f
+///             halt
+///         f:
+///             pushi 1
+///             mvsp 1
+///             ret"))?;
+///
+///     let mut trace = ChromeTrace::default();
+///     trace.run(&mut vm)?;
+///
+///     let mut json = Vec::new();
+///     trace.write_json(vm.program(), &mut json)?;
+///     let json = String::from_utf8(json).unwrap();
+///
+///     assert!(json.contains(r#"{"name":"f","cat":"call","ph":"B","ts":1,"pid":1,"tid":1}"#));
+///     assert!(json.contains(r#"{"name":"f","cat":"call","ph":"E","ts":4,"pid":1,"tid":1,"args":{"instructions":3}}"#));
+///     assert!(json.contains(r#""args":{"00000":1,"00001":1,"00002 (f)":3}"#));
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ChromeTrace {
+    /// Events written so far, each of which is a JSON object.
+    events: Vec<String>,
+    /// The names and the starting steps of calls not returned yet, from the outermost.
+    calls: Vec<(String, u64)>,
+    /// The length of the basic block from every instruction.
+    block_lengths: Vec<u32>,
+    /// The instructions of the block being executed.
+    block: Range<usize>,
+    last_pc: Option<usize>,
+    /// The number of executed instructions in each block, by the address of its first instruction.
+    block_counts: BTreeMap<usize, u64>,
+    /// The number of steps when the VM is sampled last.
+    steps: u64,
+}
+
+impl ChromeTrace {
+    fn span(&mut self, name: &str, phase: char, ts: u64, args: Option<String>) {
+        let mut event = format!(r#"{{"name":{},"cat":"call","ph":"{}","ts":{},"pid":1,"tid":1"#, json_string(name), phase, ts);
+        if let Some(args) = args {
+            event += &format!(r#","args":{}"#, args);
+        }
+        event.push('}');
+        self.events.push(event);
+    }
+
+    fn end_call(&mut self, ts: u64) {
+        if let Some((name, start)) = self.calls.pop() {
+            self.span(&name, 'E', ts, Some(format!(r#"{{"instructions":{}}}"#, ts - start)));
+        }
+    }
+
+    /// Records the calls and returns of the last step, and counts the step which the VM executes next.
+    ///
+    /// This should be called before every step.
+    pub fn sample<T, U>(&mut self, vm: &mut PicocVm<T, U>)
+    where
+        T: BufRead,
+        U: Write,
+    {
+        self.steps = vm.steps();
+        for event in vm.drain_events() {
+            match event {
+                VmEvent::Called(name) => {
+                    self.span(&name, 'B', self.steps, None);
+                    self.calls.push((name, self.steps));
+                },
+                VmEvent::Returned => self.end_call(self.steps),
+                _ => (),
+            }
+        }
+
+        let pc = vm.registers().pc;
+        if vm.is_halted() || pc >= vm.program().len() {
+            return;
+        }
+        if self.block_lengths.len() != vm.program().len() {
+            self.block_lengths = blocks(vm.program());
+        }
+        // A block is entered at its first instruction, and left by a jump or at its end
+        if self.last_pc.map(|last| last + 1) != Some(pc) || !self.block.contains(&pc) {
+            self.block = pc..pc + self.block_lengths[pc] as usize;
+        }
+        *self.block_counts.entry(self.block.start).or_default() += 1;
+        self.last_pc = Some(pc);
+    }
+
+    /// Runs the VM until it halts like [`run_until_halt`](PicocVm::run_until_halt()),
+    /// recording every step.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] under the same situations as [`run_until_halt`](PicocVm::run_until_halt()).
+    pub fn run<T, U>(&mut self, vm: &mut PicocVm<T, U>) -> Result<(), Error>
+    where
+        T: BufRead,
+        U: Write,
+    {
+        loop {
+            self.sample(vm);
+            match vm.step() {
+                Ok(()) => (),
+                Err(Error::VmHalted) => return Ok(()),
+                Err(Error::MemoryOutOfBound) if vm.config().legacy_end => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Writes the trace as a JSON document.
+    ///
+    /// Calls which have not returned end at the last step sampled.
+    /// A block in the counter is named by the address of its first instruction and its function (if known).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if an I/O error occurs.
+    pub fn write_json<W: Write>(&self, program: &Program, mut w: W) -> Result<(), Error> {
+        let functions = DebugInfo {
+            functions: program.functions(),
+            ..DebugInfo::default()
+        };
+
+        let mut trace = self.clone();
+        while !trace.calls.is_empty() {
+            trace.end_call(trace.steps);
+        }
+
+        let counts: Vec<String> = self.block_counts.iter()
+            .map(|(&start, count)| {
+                let name = match functions.function(start) {
+                    Some(function) => format!("{:05} ({})", start, function),
+                    None => format!("{:05}", start),
+                };
+                format!("{}:{}", json_string(&name), count)
+            })
+            .collect();
+
+        write!(w, r#"{{"traceEvents":[{{"name":"thread_name","ph":"M","pid":1,"tid":1,"args":{{"name":"picoc vm"}}}}"#)?;
+        for event in &trace.events {
+            write!(w, ",{}", event)?;
+        }
+        write!(
+            w,
+            r#",{{"name":"instructions per block","ph":"C","ts":{},"pid":1,"tid":1,"args":{{{}}}}}"#,
+            self.steps, counts.join(","),
+        )?;
+        writeln!(w, r#"],"displayTimeUnit":"ns"}}"#)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::config::Config;
+
+    #[test]
+    fn blocks_and_open_calls() {
+        let mut input = Cursor::new(b"");
+        let mut output = Vec::new();
+        let config = Config { record_events: true, ..Config::default() };
+
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+        vm.load(Cursor::new(b"
+            main:
+                pushi 3
+            loop:
+                pushl -1
+                pushi 1
+                sub
+                storel -1
+                jt loop
+                call f
+            f:
+                halt")).unwrap();
+
+        let mut trace = ChromeTrace::default();
+        trace.run(&mut vm).unwrap();
+
+        let mut json = Vec::new();
+        trace.write_json(vm.program(), &mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+
+        // f never returns, so its span ends at the last step
+        assert!(json.contains(r#"{"name":"f","cat":"call","ph":"E","ts":18,"pid":1,"tid":1,"args":{"instructions":1}}"#));
+        assert!(json.contains(r#""args":{"00000":1,"00001":15,"00006":1,"00007 (f)":1}"#));
+    }
+}
@@ -0,0 +1,350 @@
+use std::time::Duration;
+use crate::cost::CycleCosts;
+use crate::snapshot::CheckpointPolicy;
+
+/// Configuration of a VM.
+///
+/// The default configuration enables the strict semantics.
+/// Each `legacy_*` flag restores a behavior of older versions for compatibility.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{PicocVm, Config};
+///
+/// fn main() {
+///     let mut input = Cursor::new(b"");
+///     let mut output = Cursor::new(Vec::new());
+///
+///     let config = Config {
+///         legacy_end: true,
+///         ..Config::default()
+///     };
+///
+///     let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Config {
+    /// Restores the legacy end-of-program semantics.
+    ///
+    /// If `true`, PC wraps around [`VM_INST_MEMORY_SIZE`](crate::VM_INST_MEMORY_SIZE)
+    /// and running past the last instruction stops the VM silently like `halt`.
+    /// Otherwise, running past the last instruction is [`Error::FellOffEnd`](crate::Error::FellOffEnd).
+    pub legacy_end: bool,
+    /// Restores the legacy handling of undefined call targets.
+    ///
+    /// If `true`, `call` with an undefined label is accepted by [`load`](crate::PicocVm::load())
+    /// and pushes a return address without jumping.
+    /// Otherwise, such a label is rejected with [`Error::LabelNotFound`](crate::Error::LabelNotFound)
+    /// when the code is loaded or executed.
+    pub legacy_call: bool,
+    /// How a line with an unknown mnemonic is handled by [`load`](crate::PicocVm::load()).
+    pub load_mode: LoadMode,
+    /// Whether `storei` can write instructions into the instruction memory.
+    ///
+    /// If `false`, the code is read-only and `storei` fails with
+    /// [`Error::CodeNotWritable`](crate::Error::CodeNotWritable).
+    pub writable_code: bool,
+    /// When the output stream is flushed.
+    pub flush_policy: FlushPolicy,
+    /// How `wr` formats a value.
+    pub output_format: OutputFormat,
+    /// Whether lines read by `rd` and `rdt` are written back after the prompt.
+    ///
+    /// This makes a transcript readable when the input is not a terminal.
+    pub echo_input: bool,
+    /// Whether `rd` and `rdt` read without writing the prompt `? `.
+    ///
+    /// This keeps the output clean for pipelines and automated judging.
+    pub no_prompt: bool,
+    /// The radix of integers read by `rd` and `rdt`.
+    ///
+    /// An integer may also be prefixed with `0x`, `0o`, or `0b` like an operand of the assembly.
+    pub input_radix: Radix,
+    /// The number of words in the data segment.
+    ///
+    /// See [`MemoryMap`](crate::MemoryMap) for the layout of the memory.
+    pub data_size: usize,
+    /// The number of words in the heap segment.
+    pub heap_size: usize,
+    /// The maximum number of live reference cells allocated by `newref`.
+    ///
+    /// If `None`, the number of cells is unlimited.
+    pub ref_limit: Option<usize>,
+    /// Resource limits enforced while the VM runs.
+    pub limits: ExecutionLimits,
+    /// Simulated cycles taken by each instruction, accumulated into [`cycles`](crate::PicocVm::cycles()).
+    pub cycle_costs: CycleCosts,
+    /// Whether reads of stack slots never written since they are allocated are reported.
+    ///
+    /// The stack is zero-filled, so reading an uninitialized local by `pushl` (or `ld`) goes unnoticed otherwise.
+    /// See [`stack_issues`](crate::PicocVm::stack_issues()).
+    pub check_uninitialized: bool,
+    /// Whether stack slots released by `leave` or `mvsp` are poisoned, and reads of them are reported.
+    ///
+    /// This finds uses of locals after their function returns, e.g. through an address taken by `pushfp`.
+    /// See [`stack_issues`](crate::PicocVm::stack_issues()).
+    pub poison_frames: bool,
+    /// Whether return addresses pushed by `call` are kept aside and verified by `ret`.
+    ///
+    /// If `true`, `ret` fails with [`Error::ReturnAddressOverwritten`](crate::Error::ReturnAddressOverwritten)
+    /// when the return address on the stack is overwritten, e.g. by `storel` with a wrong offset.
+    pub shadow_stack: bool,
+    /// Whether stack slots are tagged as data, saved FP, or a return address as they are written.
+    ///
+    /// If `true`, the VM fails with [`Error::SlotTagMismatch`](crate::Error::SlotTagMismatch)
+    /// when `ret` pops anything but a return address, `leave` restores FP from anything but a saved FP,
+    /// or an arithmetic or comparison instruction consumes a return address or a saved FP.
+    pub tag_slots: bool,
+    /// Whether a likely infinite loop is detected.
+    ///
+    /// If `true`, the VM fails with [`Error::InfiniteLoop`](crate::Error::InfiniteLoop)
+    /// when the registers and the words on the top of the stack recur without I/O in between.
+    /// This is a heuristic: a loop which only changes words deeper in the stack, globals, or the heap
+    /// is also regarded as infinite.
+    pub detect_loops: bool,
+    /// Whether time is measured by a virtual clock, so a time limit stops a program at the same step in every run.
+    ///
+    /// If `true`, [`ExecutionLimits::max_time`] and [`elapsed`](crate::PicocVm::elapsed())
+    /// count a nanosecond per cycle instead of the wall time.
+    pub deterministic: bool,
+    /// Whether a VM records [`VmEvent`](crate::VmEvent)s.
+    ///
+    /// Recorded events are kept until they are drained.
+    pub record_events: bool,
+    /// The number of latest steps which can be undone by [`undo_step`](crate::PicocVm::undo_step()).
+    ///
+    /// If `0`, no history is recorded.
+    pub history_depth: usize,
+    /// The number of latest instructions kept by [`recent_instructions`](crate::PicocVm::recent_instructions()).
+    ///
+    /// If `0`, no instruction is kept.
+    pub recent_depth: usize,
+    /// Policy of taking checkpoints while the VM runs.
+    ///
+    /// If `None`, no checkpoint is taken.
+    /// See [`checkpoints`](crate::PicocVm::checkpoints()).
+    pub checkpoint: Option<CheckpointPolicy>,
+}
+
+/// Resource limits of a VM.
+///
+/// Each limit is unlimited if `None`.
+/// Exceeding a limit aborts the execution with its own error variant.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use std::time::Duration;
+/// use picoc_vm::{PicocVm, Config, ExecutionLimits, Error};
+///
+/// fn main() -> Result<(), Error> {
+///     let mut input = Cursor::new(b"");
+///     let mut output = Cursor::new(Vec::new());
+///
+///     let config = Config {
+///         limits: ExecutionLimits {
+///             max_steps: Some(1000),
+///             max_time: Some(Duration::from_secs(1)),
+///             ..ExecutionLimits::default()
+///         },
+///         ..Config::default()
+///     };
+///     let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+///
+///     vm.load(Cursor::new(b"loop:\njp loop\n"))?;
+///
+///     assert!(matches!(vm.run_until_halt(), Err(Error::StepLimitExceeded(1000))));
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ExecutionLimits {
+    /// The maximum number of executed instructions.
+    pub max_steps: Option<u64>,
+    /// The maximum wall time from the first step.
+    pub max_time: Option<Duration>,
+    /// The maximum number of words on the stack.
+    pub max_stack_depth: Option<usize>,
+    /// The maximum depth of nested calls.
+    pub max_calls: Option<usize>,
+    /// The maximum number of bytes written to the output stream.
+    pub max_output_bytes: Option<usize>,
+    /// The maximum number of bytes given by [`memory_usage`](crate::PicocVm::memory_usage()).
+    ///
+    /// The limit is checked when a program is loaded, and when a reference cell or a string is made.
+    pub max_memory_bytes: Option<usize>,
+}
+
+impl ExecutionLimits {
+    /// Returns whether no limit is set.
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Handling of unknown mnemonics when code is loaded.
+///
+/// See [`Program::assemble_with_mode`](crate::Program::assemble_with_mode()) for an example.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoadMode {
+    /// Rejects an unknown mnemonic with [`Error::UnknownOpcode`](crate::Error::UnknownOpcode).
+    #[default]
+    Strict,
+    /// Loads an unknown mnemonic as [`Opcode::Trap`](crate::Opcode::Trap),
+    /// which fails only if it is executed.
+    ///
+    /// This loads code with optional extensions (e.g. generated by a newer compiler)
+    /// whose extended instructions are never reached.
+    Permissive,
+}
+
+/// Policy of flushing the output stream of a VM.
+///
+/// The output stream is always flushed before `rd` shows a prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    /// Flushes the output after every write.
+    EveryWrite,
+    /// Flushes the output after writing a line feed.
+    EveryLine,
+    /// Flushes the output only when [`flush`](crate::PicocVm::flush()) is called.
+    #[default]
+    Manual,
+}
+
+/// Radix of integers read by `rd` and `rdt`.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{PicocVm, Config, Error, Radix};
+///
+/// fn main() -> Result<(), Error> {
+///     let mut input = Cursor::new(b"ff\n0b101\n");
+///     let mut output = Cursor::new(Vec::new());
+///
+///     let config = Config {
+///         input_radix: Radix::Hexadecimal,
+///         ..Config::default()
+///     };
+///     let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+///
+///     vm.load(Cursor::new(b"rd\nwr\nrd\nwr\nhalt\n"))?;
+///     vm.run_until_halt()?;
+///
+///     // `0b` is a hexadecimal digit in this radix
+///     assert_eq!(output.get_ref(), b"? 255 ? 45313 ");
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Radix {
+    /// Base 2.
+    Binary,
+    /// Base 8.
+    Octal,
+    /// Base 10.
+    #[default]
+    Decimal,
+    /// Base 16.
+    Hexadecimal,
+}
+
+impl Radix {
+    /// Returns the base of the radix (e.g. `16` for [`Radix::Hexadecimal`]).
+    pub fn base(self) -> u32 {
+        match self {
+            Radix::Binary => 2,
+            Radix::Octal => 8,
+            Radix::Decimal => 10,
+            Radix::Hexadecimal => 16,
+        }
+    }
+}
+
+/// Format of values written by `wr`.
+///
+/// A value is right-aligned in a field of `width` characters,
+/// followed by `separator` and, if `newline` is `true`, a line feed.
+///
+/// The default format writes a value followed by a space, e.g. `"5 "`.
+///
+/// # Example
+///
+/// ```
+/// use picoc_vm::OutputFormat;
+///
+/// fn main() {
+///     let format = OutputFormat {
+///         separator: String::new(),
+///         width: 4,
+///         newline: true,
+///     };
+///
+///     assert_eq!(format.format(-5), "  -5\n");
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputFormat {
+    /// A string written after each value.
+    pub separator: String,
+    /// The minimum width of a field.
+    pub width: usize,
+    /// Whether a line feed is written after each value.
+    pub newline: bool,
+}
+
+impl OutputFormat {
+    /// Formats a value in this format.
+    pub fn format(&self, value: i32) -> String {
+        self.format_field(value, self.width, false)
+    }
+
+    /// Formats a value in this format with a field width overridden.
+    ///
+    /// If `zero_pad` is `true`, the field is padded with zeros instead of spaces.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use picoc_vm::OutputFormat;
+    ///
+    /// fn main() {
+    ///     let format = OutputFormat::default();
+    ///
+    ///     assert_eq!(format.format_field(42, 5, false), "   42 ");
+    ///     assert_eq!(format.format_field(-42, 5, true), "-0042 ");
+    /// }
+    /// ```
+    pub fn format_field(&self, value: i32, width: usize, zero_pad: bool) -> String {
+        let field = if zero_pad {
+            format!("{:0width$}", value)
+        } else {
+            format!("{:>width$}", value)
+        };
+
+        format!(
+            "{}{}{}",
+            field,
+            self.separator,
+            if self.newline { "\n" } else { "" },
+        )
+    }
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        Self {
+            separator: " ".to_string(),
+            width: 0,
+            newline: false,
+        }
+    }
+}
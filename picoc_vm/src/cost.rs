@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use crate::error::Error;
+use crate::opcode::Opcode;
+
+/// Simulated cycles taken by each instruction, given by [`Config::cycle_costs`](crate::Config::cycle_costs).
+///
+/// A VM accumulates the cycles of executed instructions into [`cycles`](crate::PicocVm::cycles()),
+/// which measures the efficiency of a program independently of the host.
+/// By default, every instruction takes one cycle.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{PicocVm, Config, CycleCosts, Error};
+///
+/// fn main() -> Result<(), Error> {
+///     let mut input = Cursor::new(b"");
+///     let mut output = Cursor::new(Vec::new());
+///
+///     let costs = CycleCosts::parse("
+///         mul 4   # multiplication and division are slow
+///         div 20
+///         * 1")?;
+///     let config = Config { cycle_costs: costs, ..Config::default() };
+///     let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+///
+///     vm.load(Cursor::new(b"pushi 6\npushi 7\nmul\nhalt\n"))?;
+///     vm.run_until_halt()?;
+///
+///     assert_eq!(vm.steps(), 4);
+///     assert_eq!(vm.cycles(), 7);
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleCosts {
+    /// The cycles of an instruction not in `costs`.
+    pub default: u64,
+    /// The cycles of instructions by mnemonic (e.g. `mul`).
+    pub costs: HashMap<String, u64>,
+}
+
+impl Default for CycleCosts {
+    /// Makes a table where every instruction takes one cycle.
+    fn default() -> Self {
+        Self { default: 1, costs: HashMap::new() }
+    }
+}
+
+impl CycleCosts {
+    /// Parses a table whose lines are a mnemonic and its cycles (e.g. `mul 4`).
+    ///
+    /// The mnemonic `*` sets the cycles of the other instructions, which is 1 unless given.
+    /// A comment starts with `#`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidCostTable`] with the line number if a line is malformed.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut table = Self::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                [] => (),
+                [mnemonic, cycles] => {
+                    let cycles = cycles.parse().map_err(|_| Error::InvalidCostTable(i + 1))?;
+                    if mnemonic == "*" {
+                        table.default = cycles;
+                    } else {
+                        table.costs.insert(mnemonic.to_lowercase(), cycles);
+                    }
+                },
+                _ => return Err(Error::InvalidCostTable(i + 1)),
+            }
+        }
+
+        Ok(table)
+    }
+
+    /// Gets the cycles of an instruction.
+    pub fn cost(&self, inst: &Opcode) -> u64 {
+        let text = inst.to_string();
+        let mnemonic = text.split_whitespace().next().unwrap_or_default();
+
+        self.costs.get(mnemonic).copied().unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cost_table() {
+        let table = CycleCosts::parse("MUL 4\n\n* 2 # others\n").unwrap();
+
+        assert_eq!(table.cost(&Opcode::Mul), 4);
+        assert_eq!(table.cost(&Opcode::Pushi(3)), 2);
+        assert!(matches!(CycleCosts::parse("add\n"), Err(Error::InvalidCostTable(1))));
+        assert!(matches!(CycleCosts::parse("# costs\nadd -1\n"), Err(Error::InvalidCostTable(2))));
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+
+/// A line of an original source file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    /// The name of the source file.
+    pub file: String,
+    /// The line number in the source file.
+    pub line: usize,
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Debug information given by `.loc`, `.func`, and `.local` directives.
+///
+/// Each directive applies to the instructions from the next one
+/// until another directive of the same kind.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{Program, Error};
+///
+/// fn main() -> Result<(), Error> {
+///     let code = Cursor::new(b"
+///         .func main
+///         .loc \"main.c\" 3
+///             pushi 1
+///         .loc \"main.c\" 4
+///             wr
+///             halt");
+///
+///     let program = Program::assemble(code)?;
+///     let debug_info = program.debug_info();
+///
+///     assert_eq!(debug_info.location(0).unwrap().to_string(), "main.c:3");
+///     assert_eq!(debug_info.location(2).unwrap().to_string(), "main.c:4");
+///     assert_eq!(debug_info.function(2), Some("main"));
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DebugInfo {
+    pub(crate) locations: Vec<(usize, SourceLocation)>,
+    pub(crate) functions: Vec<(usize, String)>,
+    /// Local variables declared by `.local`, scoped by functions
+    pub(crate) locals: Vec<(usize, Vec<(String, i64)>)>,
+    /// Labels referred to by constant expressions in operands (e.g. `pushi end-start`)
+    pub(crate) label_uses: HashSet<String>,
+}
+
+/// Finds the last entry which starts at or before an address.
+fn lookup<T>(entries: &[(usize, T)], addr: usize) -> Option<&T> {
+    let index = entries.partition_point(|&(start, _)| start <= addr);
+
+    index.checked_sub(1).map(|i| &entries[i].1)
+}
+
+impl DebugInfo {
+    /// Gets the source location of an instruction.
+    pub fn location(&self, addr: usize) -> Option<&SourceLocation> {
+        lookup(&self.locations, addr)
+    }
+
+    /// Gets the name of the function which contains an instruction.
+    pub fn function(&self, addr: usize) -> Option<&str> {
+        lookup(&self.functions, addr).map(String::as_str)
+    }
+
+    /// Gets the local variables declared by `.local` in the function which contains an instruction.
+    ///
+    /// Each variable has its name (without `%`) and its offset from FP.
+    pub fn locals(&self, addr: usize) -> &[(String, i64)] {
+        lookup(&self.locals, addr).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns whether no debug information is given.
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty() && self.functions.is_empty() && self.locals.is_empty()
+    }
+
+    /// Appends another debug information, whose addresses are shifted by `base`.
+    pub(crate) fn append(&mut self, other: DebugInfo, base: usize) {
+        self.locations.extend(other.locations.into_iter().map(|(addr, loc)| (base + addr, loc)));
+        self.functions.extend(other.functions.into_iter().map(|(addr, name)| (base + addr, name)));
+        self.locals.extend(other.locals.into_iter().map(|(addr, locals)| (base + addr, locals)));
+        self.label_uses.extend(other.label_uses);
+    }
+}
@@ -1,6 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::BufRead;
+use crate::debug::{DebugInfo, SourceLocation};
+use crate::dialect::Dialect;
 use crate::error::Error;
+use crate::expr::{eval, eval_with_uses};
+use crate::literal::{parse_int, parse_string};
 use crate::opcode::Opcode;
 
 fn include_only_whitespace(s: &str) -> bool {
@@ -13,9 +17,91 @@ fn include_only_whitespace(s: &str) -> bool {
     true
 }
 
-pub fn split_code<T: BufRead>(mut code: T) -> Result<Vec<Vec<String>>, Error> {
+/// Removes a comment (after any of `markers`) unless the marker is quoted.
+fn strip_comment<'a>(s: &'a str, markers: &[&str]) -> &'a str {
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, c) in s.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if markers.iter().any(|m| s[i..].starts_with(m)) => return &s[..i],
+            None => (),
+        }
+    }
+
+    s
+}
+
+/// Splits a line by whitespaces unless they are quoted as a string or a character.
+fn split_words(s: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, c) in s.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c.is_whitespace() => {
+                if let Some(begin) = start.take() {
+                    words.push(&s[begin..i]);
+                }
+            },
+            None => {
+                if c == '"' || c == '\'' {
+                    quote = Some(c);
+                }
+                start.get_or_insert(i);
+            },
+        }
+    }
+    if let Some(begin) = start {
+        words.push(&s[begin..]);
+    }
+
+    words
+}
+
+/// Attaches a line number to an error of assembly.
+///
+/// The file name and the text of the line are left empty for the caller to fill.
+fn locate(line: usize, err: Error) -> Error {
+    match err {
+        Error::InSource(..) => err,
+        err => Error::InSource(SourceLocation { file: String::new(), line }, String::new(), Box::new(err)),
+    }
+}
+
+/// Splits text read by `read_line` into lines.
+///
+/// A line ends with `\n`, `\r\n`, or a bare `\r`, and a byte order mark at the start of a line is removed.
+pub fn source_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split_inclusive('\n').flat_map(|line| {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        line.strip_suffix('\r').unwrap_or(line).split('\r')
+    }).map(|line| line.strip_prefix('\u{feff}').unwrap_or(line))
+}
+
+/// Splits code into lines of words, skipping blank lines.
+///
+/// Lines are split by [`source_lines`], so that files written by editors on any platform are read alike.
+/// The line number (from 1) of each line is also returned.
+pub fn split_code<T: BufRead>(
+    mut code: T,
+    dialect: &dyn Dialect
+) -> Result<(Vec<Vec<String>>, Vec<usize>), Error> {
     let mut ret = Vec::new();
+    let mut line_numbers = Vec::new();
     let mut buf = String::new();
+    let mut line_number = 0;
 
     loop {
         buf.clear();
@@ -25,82 +111,338 @@ pub fn split_code<T: BufRead>(mut code: T) -> Result<Vec<Vec<String>>, Error> {
             _ => (),
         }
 
-        // Ignore a comment (after '#')
-        let buf = buf.split('#').collect::<Vec<_>>()[0];
-
-        // Skip a blank line
-        if include_only_whitespace(buf) {
-            continue;
+        for text in source_lines(&buf) {
+            line_number += 1;
+            let line = dialect.split_line(text).map_err(|err| locate(line_number, err))?;
+            if !line.is_empty() {
+                ret.push(line);
+                line_numbers.push(line_number);
+            }
         }
+    }
 
-        let mut line = Vec::new();
-        buf.split_whitespace().collect::<Vec<_>>()
-            .into_iter()
-            .for_each(|elem| {
-                if elem.ends_with(':') {
-                    // Colon located on a word's end is independent element
-                    line.append(
-                        &mut vec![
-                            elem[..elem.len()-1].to_string(),
-                            ":".to_string(),
-                        ]
-                    );
-                } else {
-                    line.push(elem.to_string());
-                }
-            });
-        ret.push(line);
+    Ok((ret, line_numbers))
+}
+
+/// Splits a line of the default syntax into words, ignoring a comment after any of `comment_markers`.
+pub fn split_line(buf: &str, comment_markers: &[&str]) -> Vec<String> {
+    let buf = strip_comment(buf, comment_markers);
+
+    // Skip a blank line
+    if include_only_whitespace(buf) {
+        return Vec::new();
     }
 
-    Ok(ret)
+    let mut line = Vec::new();
+    split_words(buf)
+        .into_iter()
+        .for_each(|elem| {
+            if let (false, Some(label)) = (elem.starts_with('"'), elem.strip_suffix(':')) {
+                // Colon located on a word's end is independent element
+                line.append(
+                    &mut vec![
+                        label.to_string(),
+                        ":".to_string(),
+                    ]
+                );
+            } else {
+                line.push(elem.to_string());
+            }
+        });
+
+    line
+}
+
+/// Returns whether a line is an assembler directive (e.g. `.equ`).
+fn is_directive(line: &[String]) -> bool {
+    line[0].starts_with('.') && line.get(1).is_none_or(|c| c != ":")
+}
+
+/// Checks that a label can be told from other words, returning why it cannot otherwise.
+fn validate_label(name: &str) -> Result<(), Error> {
+    let invalid = |reason: &str| Err(Error::InvalidLabel(name.to_string(), reason.to_string()));
+
+    if name.is_empty() {
+        invalid("the name is empty")
+    } else if name.contains([':', '#']) {
+        invalid("the name contains ':' or '#'")
+    } else if parse_int(name, 10).is_ok() {
+        invalid("the name is a number")
+    } else if !matches!(Opcode::from_line(&[name.to_string()]), Err(Error::UnknownOpcode(_))) {
+        invalid("the name is the mnemonic of an instruction")
+    } else {
+        Ok(())
+    }
 }
 
+/// Gives each label the address of the instruction following it.
+///
+/// A label which collides with a mnemonic or a number, or contains `:` or `#`, is rejected with [`Error::InvalidLabel`].
+/// `line_numbers` are the source line numbers of `code`, which are reported for an invalid or duplicate label.
 pub fn load_label(
-    code: &Vec<Vec<String>>,
+    code: &[Vec<String>],
+    line_numbers: &[usize],
     label_table: &mut HashMap<String, usize>
-) {
+) -> Result<(), Error> {
     label_table.clear();
 
+    let mut defined_at: HashMap<&String, usize> = HashMap::new();
     let mut line_num = 0;
-    code.iter().for_each(|line| {
-        if line.len() < 2 {
-            line_num += 1;
-            return;
+    for (index, line) in code.iter().enumerate() {
+        if is_directive(line) {
+            continue;
         }
-        if line[1] != ":" {
+        if line.len() < 2 || line[1] != ":" {
             line_num += 1;
-            return;
+            continue;
         }
 
+        let source_line = line_numbers.get(index).copied().unwrap_or(index + 1);
+        validate_label(&line[0]).map_err(|err| locate(source_line, err))?;
+        if let Some(&first) = defined_at.get(&line[0]) {
+            return Err(locate(source_line, Error::DuplicateLabel(line[0].clone(), first, source_line)));
+        }
+        defined_at.insert(&line[0], source_line);
         label_table.insert(line[0].clone(), line_num);
-    });
+    }
+
+    Ok(())
+}
+
+/// Decodes a line into an instruction registered at runtime, if any.
+pub type CustomDecoder<'a> = dyn Fn(&[String]) -> Option<Result<Opcode, Error>> + 'a;
+
+/// Returns the address of a numeric jump target (e.g. `42` of `jp 42`).
+pub(crate) fn numeric_target(inst: &Opcode) -> Option<usize> {
+    match inst {
+        Opcode::Call(label) | Opcode::Jp(label) | Opcode::Jt(label) | Opcode::Jf(label) => numeric_address(label),
+        _ => None,
+    }
+}
+
+/// Parses a jump target which is an address rather than a label (e.g. `42`).
+pub(crate) fn numeric_address(label: &str) -> Option<usize> {
+    label.bytes().all(|b| b.is_ascii_digit()).then(|| label.parse().ok())?
 }
 
+/// Finds the address of a jump target, which is a label or a numeric address.
+pub(crate) fn resolve_target(label_table: &HashMap<String, usize>, label: &str) -> Option<usize> {
+    label_table.get(label).copied().or_else(|| numeric_address(label))
+}
+
+/// Decodes the instructions of code.
+///
+/// `line_numbers` are the source line numbers of `code`, which are attached to an error.
+/// A numeric jump target (e.g. `jp 42`) is an address, which is not added to `label_table`.
+/// Numeric and relative jump targets must be in the program.
 pub fn load_inst(
-    code: &Vec<Vec<String>>,
-    inst_memory: &mut Vec<Opcode>
+    code: &[Vec<String>],
+    line_numbers: &[usize],
+    label_table: &HashMap<String, usize>,
+    inst_memory: &mut Vec<Opcode>,
+    debug_info: &mut DebugInfo,
+    custom: &CustomDecoder
 ) -> Result<(), Error> {
     inst_memory.clear();
+    *debug_info = DebugInfo::default();
+
+    // Label addresses are available in constant expressions
+    let mut symbols = HashMap::new();
+    for (label, &addr) in label_table.iter() {
+        symbols.insert(label.clone(), addr as i64);
+    }
+    // Local variables are scoped by functions, which start at call targets
+    let functions: HashSet<&String> = code.iter()
+        .filter(|line| line.len() >= 2 && line[0].eq_ignore_ascii_case("call"))
+        .map(|line| &line[1])
+        .collect();
+    let mut next_local = -1;
+    let mut numeric_targets = Vec::new();
+    let mut relative_targets = Vec::new();
 
-    for line in code {
+    for (index, line) in code.iter().enumerate() {
+        let locate = |err| locate(line_numbers.get(index).copied().unwrap_or(index + 1), err);
         if let Some(c) = line.get(1) {
             if c == ":" {
+                if functions.contains(&line[0]) {
+                    symbols.retain(|name, _| !name.starts_with('%'));
+                    next_local = -1;
+                    debug_info.locals.push((inst_memory.len(), Vec::new()));
+                }
                 continue;
             }
         }
+        if is_directive(line) {
+            record_label_uses(line.get(2..).unwrap_or_default(), &symbols, label_table, debug_info);
+            let addr = inst_memory.len();
+            load_directive(line, addr, &mut symbols, &mut next_local, debug_info).map_err(locate)?;
+            continue;
+        }
 
-        match Opcode::from_line(line) {
-            Ok(op) => inst_memory.push(op),
-            Err(err) => return Err(err),
+        if let Some(op) = custom(line) {
+            inst_memory.push(op.map_err(locate)?);
+            continue;
+        }
+
+        match Opcode::from_line_with_symbols(line, &symbols) {
+            Ok(op) => {
+                // The targets of jumps and calls are found from the instructions
+                if !matches!(op, Opcode::Call(_) | Opcode::Jp(_) | Opcode::Jt(_) | Opcode::Jf(_)) {
+                    record_label_uses(&line[1..], &symbols, label_table, debug_info);
+                }
+                let line = line_numbers.get(index).copied().unwrap_or(index + 1);
+                if let Some(target) = numeric_target(&op) {
+                    numeric_targets.push((target as i64, line));
+                }
+                if let Some(target) = op.relative_target(inst_memory.len()) {
+                    relative_targets.push((target, line));
+                }
+                inst_memory.push(op);
+            },
+            Err(err) => return Err(locate(err)),
+        }
+    }
+
+    let len = inst_memory.len() as i64;
+    for &(target, line) in numeric_targets.iter().chain(&relative_targets) {
+        if !(0..len).contains(&target) {
+            return Err(locate(line, Error::TargetOutOfBound(target)));
         }
     }
 
     Ok(())
 }
 
+/// Records the labels which an operand refers to, if it is a constant expression.
+fn record_label_uses(
+    operand: &[String],
+    symbols: &HashMap<String, i64>,
+    label_table: &HashMap<String, usize>,
+    debug_info: &mut DebugInfo
+) {
+    let expr = operand.join(" ");
+    if let Ok((_, uses)) = eval_with_uses(&expr, symbols) {
+        debug_info.label_uses.extend(uses.into_iter().filter(|name| label_table.contains_key(name)));
+    }
+}
+
+/// Processes a directive line (e.g. `.equ SIZE 4*2`).
+///
+/// `.local name` allocates the next slot below FP (`fp - 1`, `fp - 2`, ...)
+/// unless an offset is given.
+/// `.func name` also starts a new scope of local variables.
+fn load_directive(
+    line: &[String],
+    addr: usize,
+    symbols: &mut HashMap<String, i64>,
+    next_local: &mut i64,
+    debug_info: &mut DebugInfo
+) -> Result<(), Error> {
+    match line[0].to_lowercase().as_str() {
+        ".loc" => {
+            let (Some(file), Some(num)) = (line.get(1), line.get(2)) else {
+                return Err(Error::OperandNotFound);
+            };
+
+            let file = if file.starts_with('"') {
+                parse_string(file)?
+            } else {
+                file.clone()
+            };
+            let location = SourceLocation { file, line: num.parse()? };
+            debug_info.locations.push((addr, location));
+
+            Ok(())
+        },
+        ".func" => {
+            let Some(name) = line.get(1) else {
+                return Err(Error::OperandNotFound);
+            };
+
+            debug_info.functions.push((addr, name.clone()));
+            debug_info.locals.push((addr, Vec::new()));
+            symbols.retain(|name, _| !name.starts_with('%'));
+            *next_local = -1;
+
+            Ok(())
+        },
+        ".local" => {
+            let Some(name) = line.get(1) else {
+                return Err(Error::OperandNotFound);
+            };
+
+            let offset = if line.len() > 2 {
+                eval(&line[2..].join(" "), symbols)?
+            } else {
+                *next_local -= 1;
+                *next_local + 1
+            };
+            let name = name.trim_start_matches('%');
+            symbols.insert(format!("%{}", name), offset);
+            if debug_info.locals.is_empty() {
+                debug_info.locals.push((0, Vec::new()));
+            }
+            if let Some((_, locals)) = debug_info.locals.last_mut() {
+                locals.push((name.to_string(), offset));
+            }
+
+            Ok(())
+        },
+        ".equ" => {
+            if line.len() < 3 {
+                return Err(Error::OperandNotFound);
+            }
+
+            let value = eval(&line[2..].join(" "), symbols)?;
+            symbols.insert(line[1].clone(), value);
+
+            Ok(())
+        },
+        other => Err(Error::UnknownDirective(other.to_string())),
+    }
+}
+
+pub fn check_call_targets(
+    inst_memory: &[Opcode],
+    label_table: &HashMap<String, usize>
+) -> Result<(), Error> {
+    for inst in inst_memory {
+        if let Opcode::Call(label) = inst {
+            if resolve_target(label_table, label).is_none() {
+                return Err(Error::LabelNotFound(label.clone()));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn translate_address(
+    addr: usize,
+    old_label_table: &HashMap<String, usize>,
+    new_label_table: &HashMap<String, usize>
+) -> Result<usize, Error> {
+    // Find the nearest label preceding the address
+    let base = old_label_table.iter()
+        .filter(|(_, &label_addr)| label_addr <= addr)
+        .max_by(|(l1, a1), (l2, a2)| a1.cmp(a2).then(l2.cmp(l1)));
+
+    match base {
+        Some((label, &label_addr)) => {
+            if let Some(new_addr) = new_label_table.get(label) {
+                Ok(new_addr + (addr - label_addr))
+            } else {
+                Err(Error::LabelNotFound(label.clone()))
+            }
+        },
+        None => Ok(addr),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::dialect::DefaultDialect;
     use std::io;
 
     #[test]
@@ -126,7 +468,7 @@ mod tests {
               \tjp L0"
         );
         
-        let tokens = split_code(cursor).unwrap();
+        let tokens = split_code(cursor, &DefaultDialect).unwrap().0;
 
         assert_eq!(
             tokens,
@@ -144,6 +486,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn split_quoted_words() {
+        let cursor = io::Cursor::new(
+            b"pushs \"Hello, # world\" # comment\n
+              pushs \"say \\\"hi\\\":\"\n
+              pushs\t\"\""
+        );
+
+        let tokens = split_code(cursor, &DefaultDialect).unwrap().0;
+
+        assert_eq!(
+            tokens,
+            vec![
+                vec!["pushs".to_string(), "\"Hello, # world\"".to_string()],
+                vec!["pushs".to_string(), "\"say \\\"hi\\\":\"".to_string()],
+                vec!["pushs".to_string(), "\"\"".to_string()],
+            ]
+        );
+    }
+
     #[test]
     fn give_labels_integers() {
         let code = vec![
@@ -157,7 +519,7 @@ mod tests {
         ];
         let mut table = HashMap::new();
 
-        load_label(&code, &mut table);
+        load_label(&code, &[], &mut table).unwrap();
 
         assert_eq!(
             table,
@@ -169,6 +531,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn duplicate_labels() {
+        let cursor = io::Cursor::new(b"main:\n\n  pushi 1\nloop:\n  jp loop\n# again\nmain:\n  halt\n");
+        let (code, line_numbers) = split_code(cursor, &DefaultDialect).unwrap();
+        let mut table = HashMap::new();
+
+        assert_eq!(line_numbers, [1, 3, 4, 5, 7, 8]);
+        assert!(matches!(
+            load_label(&code, &line_numbers, &mut table).as_ref().map_err(Error::inner),
+            Err(Error::DuplicateLabel(name, 1, 7)) if name == "main"
+        ));
+    }
+
+    #[test]
+    fn invalid_labels() {
+        let invalid = |code: &str| {
+            let (code, line_numbers) = split_code(code.as_bytes(), &DefaultDialect).unwrap();
+            match load_label(&code, &line_numbers, &mut HashMap::new()).as_ref().map_err(Error::inner) {
+                Err(Error::InvalidLabel(name, reason)) => Some((name.clone(), reason.clone())),
+                _ => None,
+            }
+        };
+
+        assert_eq!(invalid("add:\n  jp add\n").unwrap().0, "add");
+        assert_eq!(invalid("pushaddr:\n  halt\n").unwrap().0, "pushaddr");
+        assert_eq!(invalid("42:\n  halt\n").unwrap().1, "the name is a number");
+        assert_eq!(invalid("a:b:\n  halt\n").unwrap().0, "a:b");
+        assert_eq!(invalid("adder:\n_1:\n.L0:\n  halt\n"), None);
+    }
+
     #[test]
     fn code_to_opcode() {
         let code = vec![
@@ -184,7 +576,7 @@ mod tests {
         ];
         let mut memory = Vec::new();
 
-        load_inst(&code, &mut memory).unwrap();
+        load_inst(&code, &[], &HashMap::new(), &mut memory, &mut DebugInfo::default(), &|_| None).unwrap();
 
         assert_eq!(
             memory,
@@ -200,4 +592,307 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn equ_directive() {
+        let cursor = io::Cursor::new(
+            b".equ ARGBASE 2\n
+              .equ SIZE (ARGBASE + 1) * 4\n
+              main:\n
+              \tpushi 3*4+1\n
+              \tpushl ARGBASE+2\n
+              \tmvsp -SIZE\n
+              end:\n
+              \thalt"
+        );
+        let code = split_code(cursor, &DefaultDialect).unwrap().0;
+        let mut table = HashMap::new();
+        let mut memory = Vec::new();
+
+        load_label(&code, &[], &mut table).unwrap();
+        load_inst(&code, &[], &table, &mut memory, &mut DebugInfo::default(), &|_| None).unwrap();
+
+        assert_eq!(
+            table,
+            HashMap::from([
+                ("main".to_string(), 0),
+                ("end".to_string(), 3),
+            ])
+        );
+        assert_eq!(
+            memory,
+            vec![
+                Opcode::Pushi(13),
+                Opcode::Pushl(4),
+                Opcode::Mvsp(-12),
+                Opcode::Halt,
+            ]
+        );
+    }
+
+    #[test]
+    fn line_endings_and_whitespace() {
+        let cursor = io::Cursor::new("\u{feff}main:\r\n\tpushi 1\r\n\t \t\r\n\u{3000}wr\u{a0}# comment\rjp main\r\n\x0b\nhalt".as_bytes());
+        let (code, line_numbers) = split_code(cursor, &DefaultDialect).unwrap();
+
+        assert_eq!(
+            code,
+            vec![
+                vec!["main".to_string(), ":".to_string()],
+                vec!["pushi".to_string(), "1".to_string()],
+                vec!["wr".to_string()],
+                vec!["jp".to_string(), "main".to_string()],
+                vec!["halt".to_string()],
+            ]
+        );
+        assert_eq!(line_numbers, [1, 2, 4, 5, 7]);
+    }
+
+    #[test]
+    fn char_literal_operand() {
+        let cursor = io::Cursor::new(b"pushi 'A'\npushi ' '  # space\npushi '#'\npushi '\\n'\npushi 'a' - 'A'\n");
+        let code = split_code(cursor, &DefaultDialect).unwrap().0;
+        let mut memory = Vec::new();
+
+        load_inst(&code, &[], &HashMap::new(), &mut memory, &mut DebugInfo::default(), &|_| None).unwrap();
+
+        assert_eq!(
+            memory,
+            vec![
+                Opcode::Pushi(65),
+                Opcode::Pushi(32),
+                Opcode::Pushi(35),
+                Opcode::Pushi(10),
+                Opcode::Pushi(32),
+            ]
+        );
+    }
+
+    #[test]
+    fn label_arithmetic() {
+        let cursor = io::Cursor::new(
+            b"start:\n
+              \tpushaddr end - start\n
+              \tpushi table + 1\n
+              \twr\n
+              table:\n
+              \tjp start\n
+              \tjp start\n
+              end:\n
+              .equ SIZE end - table\n
+              \tpushl -SIZE"
+        );
+        let code = split_code(cursor, &DefaultDialect).unwrap().0;
+        let mut table = HashMap::new();
+        let mut memory = Vec::new();
+
+        load_label(&code, &[], &mut table).unwrap();
+        load_inst(&code, &[], &table, &mut memory, &mut DebugInfo::default(), &|_| None).unwrap();
+
+        assert_eq!(
+            memory,
+            vec![
+                Opcode::Pushi(5),
+                Opcode::Pushi(4),
+                Opcode::Wr,
+                Opcode::Jp("start".to_string()),
+                Opcode::Jp("start".to_string()),
+                Opcode::Pushl(-2),
+            ]
+        );
+    }
+
+    #[test]
+    fn local_directive() {
+        let cursor = io::Cursor::new(
+            b"\tcall main\n
+              \thalt\n
+              sum:\n
+              .local a 2\n
+              .local b 3\n
+              \tenter\n
+              \tpushl %a\n
+              \tpushl %b\n
+              \tadd\n
+              \tstorel %a+2\n
+              \tleave\n
+              \tret\n
+              main:\n
+              .local x\n
+              .local %y\n
+              \tenter\n
+              \tmvsp -2\n
+              \tstorel %y\n
+              loop:\n
+              \tpushl %x\n
+              \tleave\n
+              \tret"
+        );
+        let code = split_code(cursor, &DefaultDialect).unwrap().0;
+        let mut table = HashMap::new();
+        let mut memory = Vec::new();
+
+        load_label(&code, &[], &mut table).unwrap();
+        load_inst(&code, &[], &table, &mut memory, &mut DebugInfo::default(), &|_| None).unwrap();
+
+        assert_eq!(
+            memory[2..],
+            [
+                Opcode::Enter,
+                Opcode::Pushl(2),
+                Opcode::Pushl(3),
+                Opcode::Add,
+                Opcode::Storel(4),
+                Opcode::Leave,
+                Opcode::Ret,
+                Opcode::Enter,
+                Opcode::Mvsp(-2),
+                Opcode::Storel(-2),
+                Opcode::Pushl(-1),
+                Opcode::Leave,
+                Opcode::Ret,
+            ]
+        );
+    }
+
+    #[test]
+    fn local_out_of_scope() {
+        let cursor = io::Cursor::new(
+            b"\tcall f\n
+              \tcall g\n
+              f:\n
+              .local x\n
+              \tret\n
+              g:\n
+              \tpushl %x"
+        );
+        let code = split_code(cursor, &DefaultDialect).unwrap().0;
+        let mut table = HashMap::new();
+        let mut memory = Vec::new();
+
+        load_label(&code, &[], &mut table).unwrap();
+
+        assert!(matches!(
+            load_inst(&code, &[], &table, &mut memory, &mut DebugInfo::default(), &|_| None)
+                .as_ref().map_err(Error::inner),
+            Err(Error::UndefinedSymbol(name)) if name == "%x"
+        ));
+    }
+
+    #[test]
+    fn debug_directives() {
+        let cursor = io::Cursor::new(
+            b"\tcall f\n
+              \thalt\n
+              .func f\n
+              .loc \"lib c.c\" 10\n
+              f:\n
+              .local x\n
+              \tenter\n
+              .loc lib.c 11\n
+              .loc lib.c 12\n
+              \tpushl %x\n
+              \tleave\n
+              \tret"
+        );
+        let code = split_code(cursor, &DefaultDialect).unwrap().0;
+        let mut table = HashMap::new();
+        let mut memory = Vec::new();
+        let mut debug_info = DebugInfo::default();
+
+        load_label(&code, &[], &mut table).unwrap();
+        load_inst(&code, &[], &table, &mut memory, &mut debug_info, &|_| None).unwrap();
+
+        assert_eq!(memory[3], Opcode::Pushl(-1));
+        assert_eq!(debug_info.location(1), None);
+        assert_eq!(debug_info.location(2).unwrap().to_string(), "lib c.c:10");
+        assert_eq!(debug_info.location(3).unwrap().to_string(), "lib.c:12");
+        assert_eq!(debug_info.function(1), None);
+        assert_eq!(debug_info.function(5), Some("f"));
+    }
+
+    #[test]
+    fn invalid_directives() {
+        let undefined = vec![vec!["pushi".to_string(), "SIZE".to_string()]];
+        let unknown = vec![vec![".org".to_string(), "0".to_string()]];
+        let overflow = vec![vec!["pushi".to_string(), "2147483647+1".to_string()]];
+        let mut memory = Vec::new();
+
+        assert!(matches!(
+            load_inst(&undefined, &[], &HashMap::new(), &mut memory, &mut DebugInfo::default(), &|_| None)
+                .as_ref().map_err(Error::inner),
+            Err(Error::UndefinedSymbol(name)) if name == "SIZE"
+        ));
+        assert!(matches!(
+            load_inst(&unknown, &[], &HashMap::new(), &mut memory, &mut DebugInfo::default(), &|_| None)
+                .as_ref().map_err(Error::inner),
+            Err(Error::UnknownDirective(name)) if name == ".org"
+        ));
+        assert!(matches!(
+            load_inst(&overflow, &[], &HashMap::new(), &mut memory, &mut DebugInfo::default(), &|_| None)
+                .as_ref().map_err(Error::inner),
+            Err(Error::InvalidExpression(_))
+        ));
+    }
+
+    #[test]
+    fn numeric_targets() {
+        let cursor = io::Cursor::new(b"main:\n  call 3\n  jp 0\n  halt\n  jt main\n  ret\n");
+        let (code, line_numbers) = split_code(cursor, &DefaultDialect).unwrap();
+        let mut table = HashMap::new();
+        let mut memory = Vec::new();
+
+        load_label(&code, &line_numbers, &mut table).unwrap();
+        load_inst(&code, &line_numbers, &table, &mut memory, &mut DebugInfo::default(), &|_| None).unwrap();
+
+        // Numeric targets are addresses, not labels
+        assert_eq!(memory[0], Opcode::Call("3".to_string()));
+        assert_eq!(table, HashMap::from([("main".to_string(), 0)]));
+        assert_eq!(resolve_target(&table, "3"), Some(3));
+        assert_eq!(resolve_target(&table, "main"), Some(0));
+        assert_eq!(resolve_target(&table, "-1"), None);
+
+        let cursor = io::Cursor::new(b"pushi 1\njp 2\n");
+        let (code, line_numbers) = split_code(cursor, &DefaultDialect).unwrap();
+        assert!(matches!(
+            load_inst(&code, &line_numbers, &HashMap::new(), &mut memory, &mut DebugInfo::default(), &|_| None),
+            Err(Error::InSource(location, _, err)) if location.line == 2 && matches!(*err, Error::TargetOutOfBound(2))
+        ));
+    }
+
+    #[test]
+    fn translate_addresses() {
+        let old_table = HashMap::from([
+            ("main".to_string(), 2),
+            ("loop".to_string(), 5),
+        ]);
+        let new_table = HashMap::from([
+            ("main".to_string(), 3),
+            ("loop".to_string(), 10),
+        ]);
+
+        assert_eq!(translate_address(1, &old_table, &new_table).unwrap(), 1);
+        assert_eq!(translate_address(4, &old_table, &new_table).unwrap(), 5);
+        assert_eq!(translate_address(6, &old_table, &new_table).unwrap(), 11);
+        assert!(matches!(
+            translate_address(6, &old_table, &HashMap::new()),
+            Err(Error::LabelNotFound(label)) if label == "loop"
+        ));
+    }
+
+    #[test]
+    fn undefined_call_target() {
+        let memory = vec![
+            Opcode::Call("main".to_string()),
+            Opcode::Halt,
+            Opcode::Call("sub".to_string()),
+            Opcode::Ret,
+        ];
+        let table = HashMap::from([("main".to_string(), 2)]);
+
+        assert!(matches!(
+            check_call_targets(&memory, &table),
+            Err(Error::LabelNotFound(label)) if label == "sub"
+        ));
+    }
 }
@@ -0,0 +1,81 @@
+use crate::opcode::Opcode;
+use crate::vm::Registers;
+
+/// A word of the data memory overwritten by an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryWrite {
+    /// The address of the word.
+    pub addr: usize,
+    /// The value before the write.
+    pub old: i32,
+    /// The value after the write.
+    pub new: i32,
+}
+
+/// Changes of a VM made by a step.
+///
+/// This is returned by [`step_with_delta`](crate::PicocVm::step_with_delta()).
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{PicocVm, MemoryWrite, VM_STACK_SIZE, Error};
+///
+/// fn main() -> Result<(), Error> {
+///     let mut input = Cursor::new(b"");
+///     let mut output = Cursor::new(Vec::new());
+///
+///     let mut vm = PicocVm::new(&mut input, &mut output);
+///
+///     vm.load(Cursor::new(b"pushi 5\nwr\nhalt\n"))?;
+///
+///     let delta = vm.step_with_delta()?;
+///
+///     assert_eq!(delta.before.sp, VM_STACK_SIZE);
+///     assert_eq!(delta.after.sp, VM_STACK_SIZE - 1);
+///     assert_eq!(delta.writes, vec![MemoryWrite { addr: VM_STACK_SIZE - 1, old: 0, new: 5 }]);
+///
+///     let delta = vm.step_with_delta()?;
+///
+///     assert!(delta.writes.is_empty());
+///     assert_eq!(delta.output, "5 ");
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepDelta {
+    /// The registers before the step.
+    pub before: Registers,
+    /// The registers after the step.
+    pub after: Registers,
+    /// Words written in order, including ones written with the same value.
+    pub writes: Vec<MemoryWrite>,
+    /// The output written by the step.
+    pub output: String,
+}
+
+impl StepDelta {
+    pub(crate) fn new(registers: Registers) -> Self {
+        Self {
+            before: registers,
+            after: registers,
+            writes: Vec::new(),
+            output: String::new(),
+        }
+    }
+}
+
+/// What is needed to undo a step.
+#[derive(Debug, Clone)]
+pub(crate) struct UndoRecord {
+    pub(crate) before: Registers,
+    pub(crate) writes: Vec<MemoryWrite>,
+    pub(crate) was_halted: bool,
+    pub(crate) steps: u64,
+    pub(crate) cycles: u64,
+    pub(crate) call_depth: usize,
+    /// The address and the old instruction overwritten by `storei`.
+    pub(crate) code_write: Option<(usize, Opcode)>,
+}
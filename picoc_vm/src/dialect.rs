@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use crate::decode::split_line;
+use crate::error::Error;
+
+/// A syntax of assembly code.
+///
+/// A dialect splits each line of code into words of the default syntax,
+/// so that code written in another syntax can be assembled by
+/// [`Program::assemble_with_dialect`](crate::Program::assemble_with_dialect()).
+///
+/// A line of the default syntax is one of the below:
+///
+/// - a label: `["name", ":"]`
+/// - a directive: `[".equ", "NAME", "1"]`
+/// - an instruction: `["pushi", "1"]`
+///
+/// An empty line (e.g. a comment) is skipped.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{Dialect, Error, Opcode, Program};
+///
+/// /// `PUSH 5`, `; comment`, and `LABEL name`
+/// struct Textbook;
+///
+/// impl Dialect for Textbook {
+///     fn split_line(&self, line: &str) -> Result<Vec<String>, Error> {
+///         let code = line.split(';').next().unwrap_or_default();
+///         let mut words: Vec<String> = code.split_whitespace().map(str::to_lowercase).collect();
+///
+///         match words.first().map(String::as_str) {
+///             Some("push") => words[0] = "pushi".to_string(),
+///             Some("label") => {
+///                 words.remove(0);
+///                 words.push(":".to_string());
+///             },
+///             _ => (),
+///         }
+///
+///         Ok(words)
+///     }
+/// }
+///
+/// fn main() -> Result<(), Error> {
+///     let code = Cursor::new(b"
+///         LABEL main  ; entry point
+///             PUSH 5
+///             WR
+///             HALT");
+///
+///     let program = Program::assemble_with_dialect(code, &Textbook)?;
+///
+///     assert_eq!(program.insts(), &[Opcode::Pushi(5), Opcode::Wr, Opcode::Halt]);
+///     assert_eq!(program.labels().get("main"), Some(&0));
+///
+///     Ok(())
+/// }
+/// ```
+pub trait Dialect {
+    /// Splits a line of code into words of the default syntax.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the line is malformed.
+    fn split_line(&self, line: &str) -> Result<Vec<String>, Error>;
+}
+
+impl<D: Dialect + ?Sized> Dialect for &D {
+    fn split_line(&self, line: &str) -> Result<Vec<String>, Error> {
+        (**self).split_line(line)
+    }
+}
+
+/// The default syntax of picoc vm, used by [`Program::assemble`](crate::Program::assemble()).
+///
+/// A comment starts with `#`, and a label ends with `:`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DefaultDialect;
+
+impl Dialect for DefaultDialect {
+    fn split_line(&self, line: &str) -> Result<Vec<String>, Error> {
+        Ok(split_line(line, &["#"]))
+    }
+}
+
+/// A tolerant syntax accepting listings emitted by the original picoc toolchain.
+///
+/// In addition to the default syntax, a comment may start with `;` or `//`,
+/// and a line may begin with the address of the instruction (e.g. `0012:` or `0012`),
+/// which is ignored.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{CompatDialect, Error, Opcode, Program};
+///
+/// fn main() -> Result<(), Error> {
+///     let code = Cursor::new(b"
+///         ; generated by picoc
+///         0000: pushi 5   // x
+///         0001  wr
+///         0002: halt");
+///
+///     let program = Program::assemble_with_dialect(code, &CompatDialect)?;
+///
+///     assert_eq!(program.insts(), &[Opcode::Pushi(5), Opcode::Wr, Opcode::Halt]);
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompatDialect;
+
+impl Dialect for CompatDialect {
+    fn split_line(&self, line: &str) -> Result<Vec<String>, Error> {
+        let mut words = split_line(line, &["#", ";", "//"]);
+
+        // Skip an address column, which is followed by a colon or not
+        let is_address = words.first()
+            .is_some_and(|w| !w.is_empty() && w.chars().all(|c| c.is_ascii_digit()));
+        if is_address {
+            let len = if words.get(1).is_some_and(|w| w == ":") { 2 } else { 1 };
+            words.drain(..len);
+        }
+
+        Ok(words)
+    }
+}
+
+/// A syntax accepting other mnemonics of instructions (aliases), e.g. `jmp` for `jp`.
+///
+/// Lines are split by another dialect, and then the mnemonic of an instruction is replaced
+/// if it is in the table of aliases. Aliases are case-insensitive like mnemonics.
+/// [`COMMON_ALIASES`](AliasDialect::COMMON_ALIASES) are the mnemonics of similar teaching VMs,
+/// which are given by [`with_common_aliases`](AliasDialect::with_common_aliases()).
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{AliasDialect, DefaultDialect, Error, Opcode, Program};
+///
+/// fn main() -> Result<(), Error> {
+///     let code = Cursor::new(b"
+///         loop:
+///             push 5
+///             out
+///             jmp loop
+///             end");
+///
+///     let mut dialect = AliasDialect::with_common_aliases(DefaultDialect);
+///     dialect.insert("out", "wr");
+///     let program = Program::assemble_with_dialect(code, &dialect)?;
+///
+///     assert_eq!(program.insts(), &[
+///         Opcode::Pushi(5),
+///         Opcode::Wr,
+///         Opcode::Jp("loop".to_string()),
+///         Opcode::Halt,
+///     ]);
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AliasDialect<D> {
+    dialect: D,
+    aliases: HashMap<String, String>,
+}
+
+impl<D: Dialect> AliasDialect<D> {
+    /// Aliases found in similar teaching VMs, each with the mnemonic it stands for.
+    pub const COMMON_ALIASES: &'static [(&'static str, &'static str)] = &[
+        ("jmp", "jp"),
+        ("jz", "jf"),
+        ("jnz", "jt"),
+        ("push", "pushi"),
+        ("cmpeq", "eq"),
+        ("cmpne", "ne"),
+        ("cmpgt", "gt"),
+        ("cmpge", "ge"),
+        ("cmplt", "lt"),
+        ("cmple", "le"),
+        ("end", "halt"),
+    ];
+
+    /// Creates a dialect from another dialect and a table of aliases.
+    pub fn new(dialect: D, aliases: &[(&str, &str)]) -> Self {
+        let aliases = aliases.iter()
+            .map(|(alias, mnemonic)| (alias.to_lowercase(), mnemonic.to_string()))
+            .collect();
+
+        Self { dialect, aliases }
+    }
+
+    /// Creates a dialect from another dialect with [`COMMON_ALIASES`](AliasDialect::COMMON_ALIASES).
+    pub fn with_common_aliases(dialect: D) -> Self {
+        Self::new(dialect, Self::COMMON_ALIASES)
+    }
+
+    /// Adds an alias of a mnemonic, replacing the old one of the same alias.
+    pub fn insert(&mut self, alias: &str, mnemonic: &str) {
+        self.aliases.insert(alias.to_lowercase(), mnemonic.to_string());
+    }
+}
+
+impl<D: Dialect> Dialect for AliasDialect<D> {
+    fn split_line(&self, line: &str) -> Result<Vec<String>, Error> {
+        let mut words = self.dialect.split_line(line)?;
+
+        let is_label = words.get(1).is_some_and(|w| w == ":");
+        if let (false, Some(first)) = (is_label, words.first_mut()) {
+            if let Some(mnemonic) = self.aliases.get(&first.to_lowercase()) {
+                *first = mnemonic.clone();
+            }
+        }
+
+        Ok(words)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_compat_lines() {
+        let split = |line| CompatDialect.split_line(line).unwrap();
+
+        assert_eq!(split("0012: pushi 5 ; push"), vec!["pushi", "5"]);
+        assert_eq!(split("12 wrs"), vec!["wrs"]);
+        assert_eq!(split("0003: main:"), vec!["main", ":"]);
+        assert_eq!(split("pushs \"a;b//c\" // comment"), vec!["pushs", "\"a;b//c\""]);
+        assert_eq!(split("0004:"), Vec::<String>::new());
+        assert_eq!(split("// only a comment"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn replace_aliases() {
+        let dialect = AliasDialect::new(CompatDialect, &[("JMP", "jp")]);
+        let split = |line| dialect.split_line(line).unwrap();
+
+        assert_eq!(split("0001: Jmp loop ; back"), vec!["jp", "loop"]);
+        assert_eq!(split("jmp:"), vec!["jmp", ":"]);
+        assert_eq!(split("pushi 1"), vec!["pushi", "1"]);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::judge::{ExitStatus, Judge, JudgeReport};
+use crate::program::Program;
+
+/// A test embedded in comments of an assembly file.
+///
+/// Each `#! input: TEXT` gives a line of the input, and each `#! expect: TEXT` a line of the expected output.
+/// A space after the colon is not part of the line.
+///
+/// # Example
+///
+/// ```
+/// use picoc_vm::{Directives, Judge, Program, Error};
+///
+/// fn main() -> Result<(), Error> {
+///     let code = "
+///         #! input: 3 5
+///         #! expect: 8
+///         main:
+///             rdt
+///             rdt
+///             add
+///             wr
+///             halt";
+///
+///     let directives = Directives::parse(code).unwrap();
+///     assert_eq!(directives.input, "3 5\n");
+///     assert_eq!(directives.expected, "8\n");
+///
+///     let program = Program::assemble(code.as_bytes())?;
+///     let report = directives.check(&Judge::default(), &program).unwrap();
+///     assert_eq!(report.output, "8 ");
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directives {
+    /// The input given to the program.
+    pub input: String,
+    /// The output expected from the program.
+    pub expected: String,
+}
+
+impl Directives {
+    /// Collects the directives in a code, or returns `None` if there is none.
+    pub fn parse(code: &str) -> Option<Self> {
+        let mut input = String::new();
+        let mut expected = String::new();
+        let mut found = false;
+
+        for line in code.lines() {
+            let Some(directive) = line.trim_start().strip_prefix("#!") else {
+                continue;
+            };
+            let (buf, text) = match directive.trim_start().split_once(':') {
+                Some(("input", text)) => (&mut input, text),
+                Some(("expect", text)) => (&mut expected, text),
+                _ => continue,
+            };
+            *buf += text.strip_prefix(' ').unwrap_or(text);
+            buf.push('\n');
+            found = true;
+        }
+
+        found.then_some(Self { input, expected })
+    }
+
+    /// Returns whether an output is the expected one.
+    ///
+    /// Trailing whitespace of each line is ignored, since `wr` writes a space after a value.
+    pub fn matches(&self, output: &str) -> bool {
+        self.expected.lines().map(str::trim_end).eq(output.lines().map(str::trim_end))
+    }
+
+    /// Runs a program with the input by a judge, and checks that it halts with the expected output.
+    ///
+    /// # Errors
+    ///
+    /// Returns the report as [`Err`] if the program does not halt normally or its output differs.
+    pub fn check(&self, judge: &Judge, program: &Program) -> Result<JudgeReport, JudgeReport> {
+        let report = judge.run_program(program, &self.input);
+        if report.status == ExitStatus::Halted && self.matches(&report.output) {
+            Ok(report)
+        } else {
+            Err(report)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_check() {
+        let code = "#! input: 3\n#! input: 5\n#!expect:8\nmain:\n    rd\n    rd\n    add\n    wr\n    halt # input: 1\n";
+        let directives = Directives::parse(code).unwrap();
+        assert_eq!(directives.input, "3\n5\n");
+        assert_eq!(directives.expected, "8\n");
+        assert!(Directives::parse("main:\n    halt\n").is_none());
+
+        let judge = Judge::default();
+        let program = Program::assemble(code.as_bytes()).unwrap();
+        assert!(directives.check(&judge, &program).is_ok());
+
+        let wrong = Directives { expected: "9\n".to_string(), ..directives.clone() };
+        assert_eq!(wrong.check(&judge, &program).unwrap_err().output, "8 ");
+
+        let short = Directives { input: "3\n".to_string(), ..directives };
+        assert_eq!(short.check(&judge, &program).unwrap_err().status, ExitStatus::RuntimeError);
+    }
+}
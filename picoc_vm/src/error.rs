@@ -1,34 +1,155 @@
 use core::num;
 use std::{error, io};
 use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use crate::checker::SlotTag;
+use crate::debug::SourceLocation;
+use crate::frame::Frame;
+use crate::memory::Segment;
+
+/// The number of functions named by the message of [`Error::CallDepthExceeded`].
+const BACKTRACE_FUNCTIONS: usize = 3;
 
 /// The error type for VM operations.
 #[derive(Debug)]
 pub enum Error {
+    /// An address is not in any segment of the data memory.
+    AddressOutOfBound(i64),
+    /// An instruction is placed at an address which already has another one.
+    AddressInUse(usize),
+    /// The checksum of a binary program does not match its content.
+    ///
+    /// The expected and actual checksums are given.
+    ChecksumMismatch(u32, u32),
+    /// Calls are nested deeper than [`ExecutionLimits::max_calls`](crate::ExecutionLimits::max_calls).
+    ///
+    /// The limit and the frames at the `call` which exceeds it (the innermost first) are given.
+    CallDepthExceeded(usize, Vec<Frame>),
+    /// `storei` is executed without [`Config::writable_code`](crate::Config::writable_code).
+    CodeNotWritable,
+    /// `div` or `mod` is executed with a divisor of zero.
+    DivisionByZero,
+    /// A block on the heap is freed twice.
+    DoubleFree(i64),
+    /// A label is defined twice.
+    ///
+    /// The label and the line numbers of both definitions are given.
+    DuplicateLabel(String, usize, usize),
+    /// PC runs past the last instruction without `halt`.
+    FellOffEnd,
+    /// A field index is out of a reference cell.
+    FieldOutOfBound(usize),
+    /// A snapshot does not fit the memory layout of a VM.
+    IncompatibleSnapshot,
+    /// A likely infinite loop is found with [`Config::detect_loops`](crate::Config::detect_loops).
+    ///
+    /// The address of the instruction where the state recurs is given.
+    InfiniteLoop(usize),
+    /// An error found at a line of assembly code.
+    ///
+    /// The location, the text of the line, and the error are given.
+    /// The file name is empty unless it is given by [`in_file`](Error::in_file()).
+    InSource(SourceLocation, String, Box<Error>),
     /// The error from [`std::io::Error`].
     ///
     /// This error is raised when an I/O error(e.g. File Not Found, Permission denied) occurs.
     IoError(io::Error),
+    /// Bytes are not a valid binary program.
+    InvalidBinary(String),
+    /// A negative size is requested to `alloc`.
+    InvalidAllocationSize(i32),
+    /// An address which is not allocated by `alloc` is freed.
+    InvalidFree(i64),
+    /// A value is not a valid Unicode scalar value.
+    InvalidCodePoint(i32),
+    /// A line of a table of cycle costs is malformed.
+    ///
+    /// The line number is given.
+    InvalidCostTable(usize),
+    /// A word does not encode an instruction (see [`Opcode::from_word`](crate::Opcode::from_word())).
+    InvalidInstructionWord(i32),
+    /// A constant expression in an operand is malformed or overflows.
+    InvalidExpression(String),
+    /// A label cannot be used as a name.
+    ///
+    /// The label and the reason are given.
+    InvalidLabel(String, String),
+    /// A literal in an operand is malformed.
+    InvalidLiteral(String),
+    /// A value is not a handle of a string.
+    InvalidString(i32),
+    /// A line of a symbol file is malformed.
+    ///
+    /// The line number is given.
+    InvalidSymbolFile(usize),
+    /// A value is not a reference to a live reference cell.
+    InvalidReference(i32),
+    /// `mvsp` moves SP outside of the stack.
+    ///
+    /// The resulting SP and the size of the stack are given.
+    InvalidStackPointer(i64, usize),
     /// Unknown label is found in an operand.
     LabelNotFound(String),
+    /// The memory used by a VM exceeds [`ExecutionLimits::max_memory_bytes`](crate::ExecutionLimits::max_memory_bytes).
+    MemoryLimitExceeded(usize),
     /// The value of PC exceeds an instruction memory.
     MemoryOutOfBound,
+    /// `rdt` reads a token after the end of the input.
+    NoMoreInput,
     /// The error from [`std::num::ParseIntError`].
     ///
     /// VM cannot parse an integer operand.
     ParseIntError(num::ParseIntError),
+    /// No free block on the heap is large enough to `alloc`.
+    OutOfMemory,
+    /// The output exceeds [`ExecutionLimits::max_output_bytes`](crate::ExecutionLimits::max_output_bytes).
+    OutputLimitExceeded(usize),
+    /// A mnemonic to register is already defined.
+    OpcodeAlreadyDefined(String),
     /// An opcode is not found.
     OpcodeNotFound,
     /// An operand is not found.
     OperandNotFound,
+    /// `ret` pops a return address which differs from the one pushed by `call`,
+    /// found with [`Config::shadow_stack`](crate::Config::shadow_stack).
+    ///
+    /// The stack address of the return address, the expected return address, and the popped value are given.
+    ReturnAddressOverwritten(usize, usize, i32),
+    /// An address is out of the segment which is accessed.
+    SegmentOutOfBound(Segment, i64),
+    /// An instruction consumes a stack slot which holds something else than it expects,
+    /// found with [`Config::tag_slots`](crate::Config::tag_slots).
+    ///
+    /// The instruction, the tag of the slot, and the stack address of the slot are given.
+    SlotTagMismatch(String, SlotTag, usize),
+    /// The stack is deeper than [`ExecutionLimits::max_stack_depth`](crate::ExecutionLimits::max_stack_depth).
+    StackLimitExceeded(usize),
     /// The value of SP exceeds the top of a stack (SP < 0).
     StackOverflow,
     /// VM attempts to read outside of a stack.
     StackOutOfBound,
     /// The value of SP exceeds the bottom of a stack (SP >= [`VM_STACK_SIZE`](crate::VM_STACK_SIZE)).
     StackUnderflow,
+    /// A symbol in an operand is not defined.
+    UndefinedSymbol(String),
+    /// An unknown assembler directive is found.
+    UnknownDirective(String),
+    /// More instructions than [`ExecutionLimits::max_steps`](crate::ExecutionLimits::max_steps) are executed.
+    StepLimitExceeded(u64),
+    /// A numeric or relative jump target (e.g. `jp 42` or `jr -3`) is not the address of an instruction.
+    TargetOutOfBound(i64),
+    /// The execution takes longer than [`ExecutionLimits::max_time`](crate::ExecutionLimits::max_time).
+    TimeLimitExceeded(Duration),
+    /// A trap (e.g. a line with an unknown mnemonic loaded in [`LoadMode::Permissive`](crate::LoadMode::Permissive)) is executed.
+    ///
+    /// The address and the line of the trap are given.
+    Trap(usize, String),
     /// An unknown opcode is found.
     UnknownOpcode(String),
+    /// A binary program is written in an unsupported version of the format.
+    UnsupportedFormatVersion(u16),
+    /// An instruction is not supported by a backend.
+    UnsupportedInstruction(String),
     /// VM halted.
     VmHalted,
 }
@@ -38,25 +159,140 @@ impl Display for Error {
         match self {
             Error::IoError(err) => err.fmt(f),
             Error::ParseIntError(err) => err.fmt(f),
+            Error::AddressOutOfBound(addr) => write!(f, "Address {} is out of memory", addr),
+            Error::AddressInUse(addr) => write!(f, "Address {} already has an instruction", addr),
+            Error::ChecksumMismatch(expected, actual) => {
+                write!(f, "Checksum mismatch (expected {:08x}, found {:08x})", expected, actual)
+            },
+            Error::CallDepthExceeded(limit, frames) => {
+                write!(f, "Calls are nested deeper than {}", limit)?;
+                // The innermost frames are enough to see which function recurses
+                let functions: Vec<&str> = frames.iter().take(BACKTRACE_FUNCTIONS)
+                    .map(|frame| frame.function_label.as_deref().unwrap_or("?"))
+                    .collect();
+                if !functions.is_empty() {
+                    write!(f, " in {}", functions.join(" <- "))?;
+                }
+                if frames.len() > BACKTRACE_FUNCTIONS {
+                    write!(f, " <- ...")?;
+                }
+                Ok(())
+            },
+            Error::CodeNotWritable => write!(f, "Instruction memory is not writable"),
+            Error::DivisionByZero => write!(f, "Division by zero"),
+            Error::DoubleFree(addr) => write!(f, "Address {} is freed twice", addr),
+            Error::DuplicateLabel(name, first, second) => {
+                write!(f, "Label '{}' is defined at line {} and again at line {}", name, first, second)
+            },
+            Error::InvalidBinary(reason) => write!(f, "Invalid binary program: {}", reason),
+            Error::InvalidLabel(name, reason) => write!(f, "Label '{}' is invalid: {}", name, reason),
+            Error::InvalidAllocationSize(size) => write!(f, "Invalid allocation size {}", size),
+            Error::FieldOutOfBound(field) => write!(f, "Field {} is out of a reference cell", field),
+            Error::IncompatibleSnapshot => write!(f, "Snapshot does not fit the memory of VM"),
+            Error::InfiniteLoop(pc) => write!(f, "Likely infinite loop at address {}: the state recurs without I/O", pc),
+            Error::InSource(location, text, err) => {
+                if location.file.is_empty() {
+                    write!(f, "line {}: {}", location.line, err)?;
+                } else {
+                    write!(f, "{}: {}", location, err)?;
+                }
+                write!(f, "\n{:>5} | {}", location.line, text)
+            },
+            Error::InvalidCodePoint(value) => write!(f, "Value {} is not a valid code point", value),
+            Error::InvalidInstructionWord(word) => write!(f, "Word {:#010x} is not a valid instruction", word),
+            Error::InvalidExpression(expr) => write!(f, "Invalid expression {}", expr),
+            Error::InvalidLiteral(literal) => write!(f, "Invalid literal {}", literal),
+            Error::InvalidString(value) => write!(f, "Value {} is not a valid string", value),
+            Error::InvalidSymbolFile(line) => write!(f, "Invalid symbol file at line {}", line),
+            Error::InvalidCostTable(line) => write!(f, "Invalid cost table at line {}", line),
+            Error::InvalidReference(value) => write!(f, "Value {} is not a valid reference", value),
+            Error::InvalidFree(addr) => write!(f, "Address {} is not allocated", addr),
+            Error::OutOfMemory => write!(f, "Out of heap memory"),
+            Error::OutputLimitExceeded(limit) => write!(f, "Output exceeds {} bytes", limit),
             Error::LabelNotFound(name) => write!(f, "Label '{}' is not found", name),
+            Error::MemoryLimitExceeded(limit) => write!(f, "Memory usage exceeds {} bytes", limit),
             Error::MemoryOutOfBound => write!(f, "PC out of bounds"),
+            Error::NoMoreInput => write!(f, "No more input"),
+            Error::FellOffEnd => write!(f, "Execution fell off the end of the program"),
+            Error::OpcodeAlreadyDefined(name) => write!(f, "Opcode '{}' is already defined", name),
             Error::OpcodeNotFound => write!(f, "Opcode is not found"),
+            Error::ReturnAddressOverwritten(addr, expected, found) => write!(
+                f,
+                "Return address at stack address {} is overwritten: expected {}, found {}",
+                addr, expected, found,
+            ),
+            Error::SegmentOutOfBound(segment, addr) => {
+                write!(f, "Address {} is out of the {} segment", addr, segment)
+            },
+            Error::SlotTagMismatch(inst, tag, addr) => {
+                write!(f, "'{}' consumes {} at stack address {}", inst, tag, addr)
+            },
             Error::OperandNotFound => write!(f, "Operand is not found"),
+            Error::StackLimitExceeded(limit) => write!(f, "Stack is deeper than {} words", limit),
+            Error::InvalidStackPointer(sp, size) => {
+                write!(f, "SP is moved to {}, outside of the stack [0, {}]", sp, size)
+            },
             Error::StackOverflow => write!(f, "Stack overflow"),
             Error::StackOutOfBound => write!(f, "SP out of bounds"),
             Error::StackUnderflow => write!(f, "Stack underflow"),
+            Error::StepLimitExceeded(limit) => write!(f, "Execution exceeds {} steps", limit),
+            Error::TargetOutOfBound(target) => write!(f, "Jump target {} is out of the program", target),
+            Error::TimeLimitExceeded(limit) => write!(f, "Execution exceeds {:?}", limit),
+            Error::Trap(addr, line) => write!(f, "Trap '{}' at address {} is executed", line, addr),
+            Error::UndefinedSymbol(name) => write!(f, "Symbol '{}' is not defined", name),
+            Error::UnknownDirective(name) => write!(f, "Unknown directive '{}' is found", name),
             Error::UnknownOpcode(name) => write!(f, "Unknown opcode '{}' is found", name),
+            Error::UnsupportedFormatVersion(version) => {
+                write!(f, "Binary format version {} is not supported", version)
+            },
+            Error::UnsupportedInstruction(inst) => write!(f, "Instruction '{}' is not supported", inst),
             Error::VmHalted => write!(f, "VM is already halted"),
         }
     }
 }
 
+impl Error {
+    /// Sets the name of the file where an error of assembly is found.
+    ///
+    /// Errors other than [`Error::InSource`] are returned as they are.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{Program, Error};
+    ///
+    /// fn main() {
+    ///     let err = Program::assemble(Cursor::new(b"pushi 1\npuhsi 2\n")).unwrap_err().in_file("foo.s");
+    ///
+    ///     assert_eq!(err.to_string(), "foo.s:2: Unknown opcode 'puhsi' is found\n    2 | puhsi 2");
+    ///     assert!(matches!(err.inner(), Error::UnknownOpcode(name) if name == "puhsi"));
+    /// }
+    /// ```
+    pub fn in_file(self, file: &str) -> Self {
+        match self {
+            Error::InSource(location, text, err) => {
+                Error::InSource(SourceLocation { file: file.to_string(), ..location }, text, err)
+            },
+            err => err,
+        }
+    }
+
+    /// Gets the error without its location in the source.
+    pub fn inner(&self) -> &Error {
+        match self {
+            Error::InSource(_, _, err) => err.inner(),
+            err => err,
+        }
+    }
+}
+
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        if let Error::IoError(err) = self {
-            Some(err)
-        } else {
-            None
+        match self {
+            Error::IoError(err) => Some(err),
+            Error::InSource(_, _, err) => Some(err.as_ref()),
+            _ => None,
         }
     }
 }
@@ -0,0 +1,54 @@
+/// An activity of a VM, recorded if [`Config::record_events`](crate::Config::record_events) is set.
+///
+/// Events are taken out by [`drain_events`](crate::PicocVm::drain_events()).
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{PicocVm, Config, VmEvent, Error};
+///
+/// fn main() -> Result<(), Error> {
+///     let mut input = Cursor::new(b"7\n");
+///     let mut output = Cursor::new(Vec::new());
+///     let config = Config { record_events: true, ..Config::default() };
+///
+///     let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+///
+///     vm.load(Cursor::new(b"
+///             call f
+///             halt
+///         f:
+///             rd
+///             wr
+///             ret"))?;
+///     vm.run_until_halt()?;
+///
+///     assert_eq!(
+///         vm.drain_events(),
+///         vec![
+///             VmEvent::Called("f".to_string()),
+///             VmEvent::InputRequested,
+///             VmEvent::OutputProduced("7 ".to_string()),
+///             VmEvent::Returned,
+///             VmEvent::Halted,
+///         ]
+///     );
+///     assert!(vm.drain_events().is_empty());
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmEvent {
+    /// `rd` or `rdt` reads a line from the input stream.
+    InputRequested,
+    /// A string is written to the output stream.
+    OutputProduced(String),
+    /// A function is called.
+    Called(String),
+    /// A function returns.
+    Returned,
+    /// `halt` is executed.
+    Halted,
+}
@@ -0,0 +1,112 @@
+use std::io::{BufRead, Write};
+use crate::config::Config;
+use crate::error::Error;
+use crate::program::Program;
+use crate::vm::{PicocVm, Register, Registers};
+
+/// A backend which executes a program.
+///
+/// An executor runs a [`Program`] from the given registers until the program halts,
+/// and returns the registers at that time.
+/// Embedders can switch backends by depending on this trait instead of a concrete VM.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{Executor, Interpreter, Program, Registers, Error};
+///
+/// fn run(executor: &mut dyn Executor, program: &Program) -> Result<Vec<u8>, Error> {
+///     let mut input = Cursor::new(b"");
+///     let mut output = Vec::new();
+///
+///     executor.execute(program, Registers::default(), &mut input, &mut output)?;
+///
+///     Ok(output)
+/// }
+///
+/// fn main() -> Result<(), Error> {
+///     let program = Program::assemble(Cursor::new(b"pushi 5\nwr\nhalt\n"))?;
+///
+///     let output = run(&mut Interpreter::default(), &program)?;
+///
+///     assert_eq!(output, b"5 ");
+///
+///     Ok(())
+/// }
+/// ```
+pub trait Executor {
+    /// Executes a program from `registers` until it halts.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the program is invalid or a runtime error occurs.
+    fn execute(
+        &mut self,
+        program: &Program,
+        registers: Registers,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<Registers, Error>;
+}
+
+/// An executor which interprets a program by [`PicocVm`].
+///
+/// Every execution starts with a fresh VM, so memory is not shared between executions.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Interpreter {
+    /// The configuration of VMs.
+    pub config: Config,
+}
+
+impl Interpreter {
+    /// Creates an interpreter with a configuration.
+    pub fn new(config: Config) -> Self {
+        Self { config }
+    }
+}
+
+impl Executor for Interpreter {
+    fn execute(
+        &mut self,
+        program: &Program,
+        registers: Registers,
+        mut input: &mut dyn BufRead,
+        mut output: &mut dyn Write,
+    ) -> Result<Registers, Error> {
+        let mut vm = PicocVm::with_config(&mut input, &mut output, self.config.clone());
+
+        vm.load_program(program.clone())?;
+        vm.set_register(Register::Pc, registers.pc)?;
+        vm.set_register(Register::Sp, registers.sp)?;
+        vm.set_register(Register::Fp, registers.fp)?;
+
+        vm.run_until_halt()?;
+        vm.flush()?;
+
+        Ok(*vm.registers())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn interpret_program() {
+        let program = Program::assemble(io::Cursor::new(
+            b"pushi 1\nmain:\nrd\nwr\nhalt\n"
+        )).unwrap();
+        let mut input = io::Cursor::new(b"42\n");
+        let mut output = Vec::new();
+        let registers = Registers { pc: 1, ..Registers::default() };
+
+        let registers = Interpreter::default()
+            .execute(&program, registers, &mut input, &mut output)
+            .unwrap();
+
+        assert_eq!(registers, Registers { pc: 3, ..Registers::default() });
+        assert_eq!(output, b"? 42 ");
+    }
+}
@@ -0,0 +1,97 @@
+use crate::delta::StepDelta;
+use crate::opcode::Opcode;
+
+fn arithmetic(inst: &Opcode) -> Option<&'static str> {
+    match inst {
+        Opcode::Add => Some("sum"),
+        Opcode::Sub => Some("difference"),
+        Opcode::Mul => Some("product"),
+        Opcode::Div => Some("quotient"),
+        Opcode::Mod => Some("remainder"),
+        _ => None,
+    }
+}
+
+fn comparison(inst: &Opcode) -> Option<&'static str> {
+    match inst {
+        Opcode::Eq => Some("=="),
+        Opcode::Ne => Some("!="),
+        Opcode::Gt => Some(">"),
+        Opcode::Ge => Some(">="),
+        Opcode::Lt => Some("<"),
+        Opcode::Le => Some("<="),
+        _ => None,
+    }
+}
+
+fn truth(value: i32) -> &'static str {
+    if value != 0 { "true" } else { "false" }
+}
+
+/// Describes in plain words what an executed instruction did,
+/// e.g. `pop 5 and 3, push their sum 8; PC now 7`.
+///
+/// `stack` is the stack before the step, from the top.
+pub(crate) fn explain(inst: &Opcode, stack: &[i32], delta: &StepDelta) -> String {
+    let top = stack.first().copied().unwrap_or_default();
+    let second = stack.get(1).copied().unwrap_or_default();
+    // The word pushed or stored last
+    let written = delta.writes.last().map(|write| write.new).unwrap_or_default();
+    let before = &delta.before;
+    let after = &delta.after;
+
+    let action = match inst {
+        Opcode::Pushl(n) => format!("push the local at FP{:+}, which is {}", n, written),
+        Opcode::Storel(n) => format!("store {} from the top of the stack into the local at FP{:+}", top, n),
+        Opcode::Storet(n) => format!("store {} from the top of the stack into SP{:+}", top, n),
+        Opcode::Pushi(d) => format!("push {}", d),
+        Opcode::Pushpc => format!("push PC, which is {}", before.pc),
+        Opcode::Pushsp => format!("push SP, which is {}", before.sp),
+        Opcode::Pushfp => format!("push FP, which is {}", before.fp),
+        Opcode::Call(label) => format!("push the return address {} and call {}", written, label),
+        Opcode::Ret => format!("pop the return address {} and return there", top),
+        Opcode::Enter => format!("push FP ({}) and set FP to SP ({})", before.fp, after.fp),
+        Opcode::Leave => format!("set SP to FP and pop the saved FP {}", after.fp),
+        Opcode::Mvsp(n) => format!("move SP by {} to {}", n, after.sp),
+        Opcode::Salloc => format!("pop {}, allocate as many words on the stack, and push their address {}", top, written),
+        Opcode::Jp(label) => format!("jump to {}", label),
+        Opcode::Jt(label) | Opcode::Jf(label) => {
+            let taken = (top != 0) == matches!(inst, Opcode::Jt(_));
+            let target = if taken { format!("jump to {}", label) } else { "do not jump".to_string() };
+            format!("pop {}, which is {}, so {}", top, truth(top), target)
+        },
+        Opcode::Jr(n) => format!("jump by {:+}", n),
+        Opcode::Jrt(n) | Opcode::Jrf(n) => {
+            let taken = (top != 0) == matches!(inst, Opcode::Jrt(_));
+            let target = if taken { format!("jump by {:+}", n) } else { "do not jump".to_string() };
+            format!("pop {}, which is {}, so {}", top, truth(top), target)
+        },
+        Opcode::Rd | Opcode::Rdt => format!("read {} and push it", written),
+        Opcode::Wr | Opcode::Wrf(_) | Opcode::Wrz(_) => format!("pop {} and write {:?}", top, delta.output),
+        Opcode::Wrln => "write a newline".to_string(),
+        Opcode::Wrch => format!("pop {} and write it as the character {:?}", top, delta.output),
+        Opcode::Alloc => format!("pop {}, allocate as many words on the heap, and push their address {}", top, written),
+        Opcode::Free => format!("pop {} and free the heap block there", top),
+        Opcode::Ld => format!("pop the address {} and push the word there, which is {}", top, written),
+        Opcode::St => format!("pop {} and the address {}, and store {} there", top, second, top),
+        Opcode::Storei => format!("pop the word {:#010x} and the address {}, and write the instruction there", top, second),
+        Opcode::Newref(n) => format!("allocate a reference cell of {} fields and push the reference {}", n, written),
+        Opcode::Getf(i) => format!("pop the reference {} and push its field {}, which is {}", top, i, written),
+        Opcode::Setf(i) => format!("pop {} and store it into the field {} of the reference {}", top, i, second),
+        Opcode::Pushs(s) => format!("push the string {:?} as {}", s, written),
+        Opcode::Scat => format!("pop the strings {} and {}, and push their concatenation {}", second, top, written),
+        Opcode::Scmp => format!("pop the strings {} and {}, and push their comparison {}", second, top, written),
+        Opcode::Slen => format!("pop the string {} and push its length {}", top, written),
+        Opcode::Wrs => format!("pop the string {} and write {:?}", top, delta.output),
+        Opcode::Custom(name, _) => format!("run the custom instruction {}", name),
+        Opcode::Trap(text) => format!("trap at {:?}", text),
+        Opcode::Halt => return "stop the VM".to_string(),
+        inst => match (arithmetic(inst), comparison(inst)) {
+            (Some(name), _) => format!("pop {} and {}, push their {} {}", second, top, name, written),
+            (_, Some(op)) => format!("pop {} and {}, push {} since {} {} {} is {}", second, top, written, second, op, top, truth(written)),
+            (None, None) => inst.to_string(),
+        },
+    };
+
+    format!("{}; PC now {}", action, after.pc)
+}
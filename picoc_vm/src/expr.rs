@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::CharIndices;
+use crate::error::Error;
+use crate::literal::{parse_char, parse_int};
+
+/// Evaluates a constant expression (e.g. `ARGBASE+2`) in an operand.
+///
+/// An expression consists of integers (e.g. `10` or `0xff`), characters (e.g. `'A'` or `'\n'`), symbols, local variables (e.g. `%x`),
+/// unary `+` and `-`, binary `+`, `-`, `*`, `/`, and `%`, and parentheses.
+pub fn eval(expr: &str, symbols: &HashMap<String, i64>) -> Result<i64, Error> {
+    eval_with_uses(expr, symbols).map(|(value, _)| value)
+}
+
+/// Evaluates a constant expression like [`eval`], also returning the symbols which it refers to.
+pub(crate) fn eval_with_uses(expr: &str, symbols: &HashMap<String, i64>) -> Result<(i64, Vec<String>), Error> {
+    let mut parser = Parser {
+        expr,
+        chars: expr.char_indices().peekable(),
+        symbols,
+        uses: Vec::new(),
+    };
+
+    let value = parser.sum()?;
+    if parser.peek().is_some() {
+        return Err(parser.invalid());
+    }
+
+    Ok((value, parser.uses))
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.' || c == '$'
+}
+
+struct Parser<'a> {
+    expr: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    symbols: &'a HashMap<String, i64>,
+    uses: Vec<String>,
+}
+
+impl Parser<'_> {
+    fn invalid(&self) -> Error {
+        Error::InvalidExpression(self.expr.to_string())
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    fn sum(&mut self) -> Result<i64, Error> {
+        let mut value = self.product()?;
+
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.chars.next();
+            let rhs = self.product()?;
+            value = match op {
+                '+' => value.checked_add(rhs),
+                _ => value.checked_sub(rhs),
+            }.ok_or_else(|| self.invalid())?;
+        }
+
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<i64, Error> {
+        let mut value = self.unary()?;
+
+        while let Some(op @ ('*' | '/' | '%')) = self.peek() {
+            self.chars.next();
+            let rhs = self.unary()?;
+            value = match op {
+                '*' => value.checked_mul(rhs),
+                '/' => value.checked_div(rhs),
+                _ => value.checked_rem(rhs),
+            }.ok_or_else(|| self.invalid())?;
+        }
+
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, Error> {
+        match self.peek() {
+            Some('+') => {
+                self.chars.next();
+                self.unary()
+            },
+            Some('-') => {
+                self.chars.next();
+                self.unary()?.checked_neg().ok_or_else(|| self.invalid())
+            },
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, Error> {
+        let Some(c) = self.peek() else {
+            return Err(self.invalid());
+        };
+
+        if c == '(' {
+            self.chars.next();
+            let value = self.sum()?;
+            if self.peek() != Some(')') {
+                return Err(self.invalid());
+            }
+            self.chars.next();
+
+            return Ok(value);
+        }
+        if c == '\'' {
+            return self.character();
+        }
+        if c != '%' && !is_symbol_char(c) {
+            return Err(self.invalid());
+        }
+
+        let (start, _) = *self.chars.peek().unwrap();
+        if c == '%' {
+            // A local variable is named with a leading '%'
+            self.chars.next();
+        }
+        let mut end = start + c.len_utf8();
+        while let Some((i, c)) = self.chars.next_if(|&(_, c)| is_symbol_char(c)) {
+            end = i + c.len_utf8();
+        }
+        let word = &self.expr[start..end];
+
+        if word == "%" {
+            Err(self.invalid())
+        } else if c.is_ascii_digit() {
+            parse_int(word, 10)
+        } else {
+            self.uses.push(word.to_string());
+            self.symbols.get(word)
+                .copied()
+                .ok_or_else(|| Error::UndefinedSymbol(word.to_string()))
+        }
+    }
+
+    /// Parses a character literal, whose closing quote may be escaped.
+    fn character(&mut self) -> Result<i64, Error> {
+        let (start, _) = self.chars.next().unwrap();
+        let mut escaped = false;
+        for (i, c) in self.chars.by_ref() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '\'' => return parse_char(&self.expr[start..=i]),
+                _ => (),
+            }
+        }
+
+        Err(Error::InvalidLiteral(self.expr[start..].to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic() {
+        let symbols = HashMap::from([("ARGBASE".to_string(), 2)]);
+
+        assert_eq!(eval("3*4+1", &symbols).unwrap(), 13);
+        assert_eq!(eval("ARGBASE+2", &symbols).unwrap(), 4);
+        assert_eq!(eval("-(1 + 2) * 3 % 4", &symbols).unwrap(), -1);
+        assert_eq!(eval("-2147483648", &symbols).unwrap(), i32::MIN as i64);
+        assert_eq!(eval("7 / -2", &symbols).unwrap(), -3);
+        assert_eq!(eval("0x10 + 0b11", &symbols).unwrap(), 19);
+        assert_eq!(eval("'a' - 'A'", &symbols).unwrap(), 32);
+        assert_eq!(eval_with_uses("(ARGBASE + 'A') * ARGBASE", &symbols).unwrap(), (134, vec!["ARGBASE".to_string(), "ARGBASE".to_string()]));
+    }
+
+    #[test]
+    fn local_variables() {
+        let symbols = HashMap::from([("%x".to_string(), -1), ("%y".to_string(), 2)]);
+
+        assert_eq!(eval("%x", &symbols).unwrap(), -1);
+        assert_eq!(eval("%y % 2", &symbols).unwrap(), 0);
+        assert_eq!(eval("5%%y", &symbols).unwrap(), 1);
+        assert!(matches!(eval("%", &symbols), Err(Error::InvalidExpression(_))));
+        assert!(matches!(eval("%z", &symbols), Err(Error::UndefinedSymbol(name)) if name == "%z"));
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        let symbols = HashMap::new();
+
+        assert!(matches!(eval("1 +", &symbols), Err(Error::InvalidExpression(_))));
+        assert!(matches!(eval("(1", &symbols), Err(Error::InvalidExpression(_))));
+        assert!(matches!(eval("1 / 0", &symbols), Err(Error::InvalidExpression(_))));
+        assert!(matches!(eval("12x", &symbols), Err(Error::ParseIntError(_))));
+        assert!(matches!(eval("SIZE", &symbols), Err(Error::UndefinedSymbol(name)) if name == "SIZE"));
+    }
+}
@@ -0,0 +1,177 @@
+use std::fmt::Write;
+
+/// A stack frame of a VM, given by [`frames`](crate::PicocVm::frames()).
+///
+/// A frame is made by `call`, which pushes the return address,
+/// and `enter`, which pushes the saved FP and sets FP to the top of the stack.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{PicocVm, Error};
+///
+/// fn main() -> Result<(), Error> {
+///     let mut input = Cursor::new(b"");
+///     let mut output = Cursor::new(Vec::new());
+///
+///     let mut vm = PicocVm::new(&mut input, &mut output);
+///
+///     vm.load(Cursor::new(b"
+///         main:
+///             call f
+///             halt
+///         f:
+///             enter
+///             call g
+///             leave
+///             ret
+///         g:
+///             enter
+///             halt"))?;
+///     vm.run_until_halt()?;
+///
+///     let frames = vm.frames();
+///     let functions: Vec<_> = frames.iter().map(|f| f.function_label.as_deref()).collect();
+///
+///     assert_eq!(functions, [Some("g"), Some("f"), None]);
+///     assert_eq!(frames[0].return_pc, Some(4));
+///     assert_eq!(frames[1].return_pc, Some(1));
+///     assert_eq!(frames[2].return_pc, None);
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// The value of FP in the frame.
+    pub fp: usize,
+    /// The address where the frame returns to, if the frame is called.
+    pub return_pc: Option<usize>,
+    /// The name of the function which the frame runs, if known.
+    ///
+    /// Functions are given by `.func` directives, or by the targets of `call` if there are none.
+    pub function_label: Option<String>,
+    /// The address of the first word in `words`.
+    pub(crate) base: usize,
+    /// The words of the stack which the frame can access.
+    pub(crate) words: Vec<i32>,
+    /// The local variables declared by `.local` in the function.
+    pub(crate) names: Vec<(String, i64)>,
+}
+
+impl Frame {
+    /// Gets the value at an offset from FP, like `pushl`.
+    ///
+    /// Local variables are at negative offsets, and arguments are at offsets from 2.
+    /// Returns `None` if the offset is outside the frame.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     vm.load(Cursor::new(b"
+    ///             pushi 7
+    ///             call f
+    ///             halt
+    ///         f:
+    ///             enter
+    ///         .local sum
+    ///         .local n 2
+    ///             pushl %n
+    ///             pushi 3
+    ///             mul
+    ///             halt"))?;
+    ///     vm.run_until_halt()?;
+    ///
+    ///     let frame = &vm.frames()[0];
+    ///
+    ///     assert_eq!(frame.local(-1), Some(21));
+    ///     assert_eq!(frame.local(2), Some(7));
+    ///     assert_eq!(frame.local(-2), None);
+    ///     assert_eq!(frame.named_local("sum"), Some(21));
+    ///     assert_eq!(frame.locals().collect::<Vec<_>>(), [("sum", Some(21)), ("n", Some(7))]);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn local(&self, offset: i32) -> Option<i32> {
+        let addr = usize::try_from(self.fp as i64 + offset as i64).ok()?;
+
+        self.words.get(addr.checked_sub(self.base)?).copied()
+    }
+
+    /// Gets the value of a local variable declared by `.local`.
+    pub fn named_local(&self, name: &str) -> Option<i32> {
+        let &(_, offset) = self.names.iter().find(|(n, _)| n == name)?;
+
+        self.local(i32::try_from(offset).ok()?)
+    }
+
+    /// Enumerates the local variables declared by `.local` with their values, in the order of declaration.
+    pub fn locals(&self) -> impl Iterator<Item = (&str, Option<i32>)> {
+        self.names.iter().map(|(name, offset)| {
+            let value = i32::try_from(*offset).ok().and_then(|offset| self.local(offset));
+            (name.as_str(), value)
+        })
+    }
+
+    /// Describes what the word at an address is in the frame, e.g. `return address -> 00004`.
+    fn slot(&self, addr: usize) -> String {
+        let offset = addr as i64 - self.fp as i64;
+        let name = self.names.iter().find(|&&(_, o)| o == offset).map(|(name, _)| name);
+
+        match (offset, self.return_pc, name) {
+            (0, Some(_), _) => "saved FP".to_string(),
+            (1, Some(pc), _) => format!("return address -> {:05}", pc),
+            (..0, _, Some(name)) => format!("local %{} (FP{:+})", name, offset),
+            (..0, _, None) => format!("temporary (FP{:+})", offset),
+            (_, _, Some(name)) => format!("argument %{} (FP{:+})", name, offset),
+            (_, _, None) => format!("argument (FP{:+})", offset),
+        }
+    }
+}
+
+/// Renders the words of frames (innermost first) from the outermost one, grouped by frame.
+///
+/// Each word is shown once in the innermost frame which can access it,
+/// so the arguments of a call are shown in the frame of the callee.
+pub(crate) fn format_frames(frames: &[Frame], sp: usize, fp: usize) -> String {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for frame in frames {
+        let top = frame.base + frame.words.len();
+        groups.push((frame, start.max(frame.base)..top));
+        start = top;
+    }
+
+    let mut text = String::new();
+    for (depth, (frame, addrs)) in groups.into_iter().enumerate().rev() {
+        write!(text, "#{} {} (FP = {:05})", depth, frame.function_label.as_deref().unwrap_or("?"), frame.fp).unwrap();
+        if let Some(pc) = frame.return_pc {
+            write!(text, ", returns to {:05}", pc).unwrap();
+        }
+        text.push('\n');
+
+        for addr in addrs.rev() {
+            let line = format!("  {:05} {:11}  {}", addr, frame.words[addr - frame.base], frame.slot(addr));
+            let fp_marker = if addr == fp { " <-- FP" } else { "" };
+            let sp_marker = if addr == sp { " <-- SP" } else { "" };
+            if fp_marker.is_empty() && sp_marker.is_empty() {
+                writeln!(text, "{}", line).unwrap();
+            } else {
+                writeln!(text, "{:<48}{}{}", line, fp_marker, sp_marker).unwrap();
+            }
+        }
+    }
+
+    text
+}
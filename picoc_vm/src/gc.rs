@@ -0,0 +1,197 @@
+use crate::error::Error;
+
+/// The tag of references, which makes them unlikely to collide with ordinary integers.
+const REF_TAG: i32 = 0x4000_0000;
+/// The number of cells allocated before the first collection.
+const INITIAL_THRESHOLD: usize = 256;
+
+/// Reference cells managed by a mark-and-sweep garbage collector.
+///
+/// The collector is conservative: any value in the roots or in a live cell
+/// which equals a reference keeps the cell alive.
+#[derive(Debug, Clone)]
+pub struct RefHeap {
+    cells: Vec<Option<Vec<i32>>>,
+    free_slots: Vec<usize>,
+    live: usize,
+    words: usize,
+    threshold: usize,
+    limit: Option<usize>,
+}
+
+impl RefHeap {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            cells: Vec::new(),
+            free_slots: Vec::new(),
+            live: 0,
+            words: 0,
+            threshold: INITIAL_THRESHOLD,
+            limit,
+        }
+    }
+
+    /// Frees all cells.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.limit);
+    }
+
+    /// Returns the number of live cells.
+    pub fn live(&self) -> usize {
+        self.live
+    }
+
+    /// Returns the number of fields in the live cells.
+    pub fn words(&self) -> usize {
+        self.words
+    }
+
+    fn index(&self, reference: i32) -> Option<usize> {
+        if reference < REF_TAG {
+            return None;
+        }
+
+        let index = (reference - REF_TAG) as usize;
+        match self.cells.get(index) {
+            Some(Some(_)) => Some(index),
+            _ => None,
+        }
+    }
+
+    /// Allocates a cell with `fields` fields, collecting garbage if needed.
+    pub fn alloc<I>(&mut self, fields: usize, roots: I) -> Result<i32, Error>
+    where
+        I: IntoIterator<Item = i32>,
+    {
+        let is_full = self.limit.is_some_and(|limit| self.live >= limit);
+        if is_full || self.live >= self.threshold {
+            self.collect(roots);
+            self.threshold = INITIAL_THRESHOLD.max(self.live * 2);
+
+            if self.limit.is_some_and(|limit| self.live >= limit) {
+                return Err(Error::OutOfMemory);
+            }
+        }
+
+        let cell = Some(vec![0; fields]);
+        let index = if let Some(index) = self.free_slots.pop() {
+            self.cells[index] = cell;
+            index
+        } else {
+            if self.cells.len() >= (i32::MAX - REF_TAG) as usize {
+                return Err(Error::OutOfMemory);
+            }
+            self.cells.push(cell);
+            self.cells.len() - 1
+        };
+        self.live += 1;
+        self.words += fields;
+
+        Ok(REF_TAG + index as i32)
+    }
+
+    pub fn get(&self, reference: i32, field: usize) -> Result<i32, Error> {
+        let index = self.index(reference).ok_or(Error::InvalidReference(reference))?;
+        let cell = self.cells[index].as_ref().unwrap();
+
+        cell.get(field).copied().ok_or(Error::FieldOutOfBound(field))
+    }
+
+    pub fn set(&mut self, reference: i32, field: usize, value: i32) -> Result<(), Error> {
+        let index = self.index(reference).ok_or(Error::InvalidReference(reference))?;
+        let cell = self.cells[index].as_mut().unwrap();
+
+        let slot = cell.get_mut(field).ok_or(Error::FieldOutOfBound(field))?;
+        *slot = value;
+
+        Ok(())
+    }
+
+    /// Frees cells unreachable from the roots and returns the number of freed cells.
+    pub fn collect<I>(&mut self, roots: I) -> usize
+    where
+        I: IntoIterator<Item = i32>,
+    {
+        // Mark
+        let mut marked = vec![false; self.cells.len()];
+        let mut worklist: Vec<usize> = roots.into_iter()
+            .filter_map(|value| self.index(value))
+            .collect();
+
+        while let Some(index) = worklist.pop() {
+            if marked[index] {
+                continue;
+            }
+            marked[index] = true;
+
+            let cell = self.cells[index].as_ref().unwrap();
+            worklist.extend(cell.iter().filter_map(|&value| self.index(value)));
+        }
+
+        // Sweep
+        let mut freed = 0;
+        for (index, cell) in self.cells.iter_mut().enumerate() {
+            if let Some(fields) = cell.as_ref().filter(|_| !marked[index]) {
+                self.words -= fields.len();
+                *cell = None;
+                self.free_slots.push(index);
+                freed += 1;
+            }
+        }
+        self.live -= freed;
+
+        freed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields() {
+        let mut heap = RefHeap::new(None);
+
+        let r = heap.alloc(2, []).unwrap();
+        heap.set(r, 1, 42).unwrap();
+
+        assert_eq!(heap.get(r, 0).unwrap(), 0);
+        assert_eq!(heap.get(r, 1).unwrap(), 42);
+        assert!(matches!(heap.get(r, 2), Err(Error::FieldOutOfBound(2))));
+        assert!(matches!(heap.get(5, 0), Err(Error::InvalidReference(5))));
+    }
+
+    #[test]
+    fn collect_unreachable_cells() {
+        let mut heap = RefHeap::new(None);
+
+        let a = heap.alloc(1, []).unwrap();
+        let b = heap.alloc(1, []).unwrap();
+        let c = heap.alloc(1, []).unwrap();
+        // a -> b, c is unreachable
+        heap.set(a, 0, b).unwrap();
+
+        assert_eq!(heap.collect([1, a, 3]), 1);
+        assert_eq!(heap.live(), 2);
+        assert_eq!(heap.words(), 2);
+        assert!(heap.get(b, 0).is_ok());
+        assert!(matches!(heap.get(c, 0), Err(Error::InvalidReference(_))));
+
+        assert_eq!(heap.collect([]), 2);
+        assert_eq!(heap.live(), 0);
+    }
+
+    #[test]
+    fn limit() {
+        let mut heap = RefHeap::new(Some(2));
+
+        let a = heap.alloc(1, []).unwrap();
+        heap.alloc(1, []).unwrap();
+
+        // The second cell is collected
+        heap.alloc(1, [a]).unwrap();
+
+        let b = heap.alloc(1, [a]).unwrap();
+        assert!(matches!(heap.alloc(1, [a, b]), Err(Error::OutOfMemory)));
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use crate::error::Error;
+use crate::memory::Region;
+
+/// A first-fit free-list allocator over the heap segment.
+#[derive(Debug, Clone)]
+pub struct Allocator {
+    region: Region,
+    /// Free blocks (address -> size), never adjacent to each other
+    free: BTreeMap<usize, usize>,
+    /// Allocated blocks (address -> size)
+    used: BTreeMap<usize, usize>,
+}
+
+impl Allocator {
+    pub fn new(region: Region) -> Self {
+        let mut allocator = Self {
+            region,
+            free: BTreeMap::new(),
+            used: BTreeMap::new(),
+        };
+        allocator.reset();
+
+        allocator
+    }
+
+    /// Frees all blocks.
+    pub fn reset(&mut self) {
+        self.free.clear();
+        self.used.clear();
+
+        if self.region.size > 0 {
+            self.free.insert(self.region.base, self.region.size);
+        }
+    }
+
+    pub fn alloc(&mut self, size: i32) -> Result<usize, Error> {
+        if size < 0 {
+            return Err(Error::InvalidAllocationSize(size));
+        }
+        // Even an empty block has a unique address
+        let size = (size as usize).max(1);
+
+        let (addr, block_size) = self.free.iter()
+            .find(|(_, &block_size)| block_size >= size)
+            .map(|(&addr, &block_size)| (addr, block_size))
+            .ok_or(Error::OutOfMemory)?;
+
+        self.free.remove(&addr);
+        if block_size > size {
+            self.free.insert(addr + size, block_size - size);
+        }
+        self.used.insert(addr, size);
+
+        Ok(addr)
+    }
+
+    pub fn free(&mut self, addr: i64) -> Result<(), Error> {
+        if addr < 0 {
+            return Err(Error::InvalidFree(addr));
+        }
+
+        let Some(mut size) = self.used.remove(&(addr as usize)) else {
+            let is_freed = self.free.range(..=addr as usize)
+                .next_back()
+                .is_some_and(|(&base, &size)| (addr as usize) < base + size);
+
+            return Err(if is_freed { Error::DoubleFree(addr) } else { Error::InvalidFree(addr) });
+        };
+        let mut addr = addr as usize;
+
+        // Coalesce with the following block
+        if let Some(next_size) = self.free.remove(&(addr + size)) {
+            size += next_size;
+        }
+        // Coalesce with the preceding block
+        if let Some((&prev, &prev_size)) = self.free.range(..addr).next_back() {
+            if prev + prev_size == addr {
+                self.free.remove(&prev);
+                addr = prev;
+                size += prev_size;
+            }
+        }
+        self.free.insert(addr, size);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alloc_and_free() {
+        let mut allocator = Allocator::new(Region { base: 100, size: 10 });
+
+        let a = allocator.alloc(4).unwrap();
+        let b = allocator.alloc(4).unwrap();
+        assert_eq!((a, b), (100, 104));
+        assert!(matches!(allocator.alloc(3), Err(Error::OutOfMemory)));
+
+        allocator.free(a as i64).unwrap();
+        assert_eq!(allocator.alloc(2).unwrap(), 100);
+
+        allocator.free(100).unwrap();
+        allocator.free(b as i64).unwrap();
+        assert_eq!(allocator.alloc(10).unwrap(), 100);
+    }
+
+    #[test]
+    fn invalid_free() {
+        let mut allocator = Allocator::new(Region { base: 100, size: 10 });
+
+        let a = allocator.alloc(4).unwrap();
+        allocator.free(a as i64).unwrap();
+
+        assert!(matches!(allocator.free(a as i64), Err(Error::DoubleFree(100))));
+        assert!(matches!(allocator.free(50), Err(Error::InvalidFree(50))));
+        assert!(matches!(allocator.alloc(-1), Err(Error::InvalidAllocationSize(-1))));
+    }
+}
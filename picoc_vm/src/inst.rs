@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use crate::decode::resolve_target;
+use crate::opcode::Opcode;
+use crate::program::Program;
+
+/// The address of a jump target, resolved when a program is loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Target(u32);
+
+impl Target {
+    const UNRESOLVED: Target = Target(u32::MAX);
+
+    fn resolve(label: &str, labels: &HashMap<String, usize>) -> Self {
+        resolve_target(labels, label)
+            .and_then(|addr| u32::try_from(addr).ok())
+            .map_or(Target::UNRESOLVED, Target)
+    }
+
+    /// Gets the address, or `None` if the label is not defined.
+    pub(crate) fn get(self) -> Option<usize> {
+        (self != Target::UNRESOLVED).then_some(self.0 as usize)
+    }
+}
+
+/// The compact form of an [`Opcode`] executed by the interpreter.
+///
+/// Labels are resolved into addresses, so that no string is hashed in the dispatch loop.
+/// Instructions with rare or large payloads (e.g. `pushs`) are [`Inst::Extended`],
+/// whose operands are read from the [`Opcode`] kept by the program.
+/// This keeps an instruction in 8 bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Inst {
+    Pushl(i32),
+    Storel(i32),
+    Storet(i32),
+    Pushi(i32),
+    Pushpc,
+    Pushsp,
+    Pushfp,
+    Call(Target),
+    Ret,
+    Enter,
+    Leave,
+    Mvsp(i32),
+    Salloc,
+    Jp(Target),
+    Jt(Target),
+    Jf(Target),
+    Jr(i32),
+    Jrt(i32),
+    Jrf(i32),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Rd,
+    Rdt,
+    Wr,
+    Wrln,
+    Wrch,
+    Alloc,
+    Free,
+    Ld,
+    St,
+    Scat,
+    Scmp,
+    Slen,
+    Wrs,
+    Halt,
+    /// An instruction executed from its [`Opcode`].
+    Extended,
+}
+
+impl Inst {
+    /// Converts an instruction, resolving its label with `labels`.
+    pub(crate) fn new(inst: &Opcode, labels: &HashMap<String, usize>) -> Self {
+        match inst {
+            Opcode::Pushl(n) => Inst::Pushl(*n),
+            Opcode::Storel(n) => Inst::Storel(*n),
+            Opcode::Storet(n) => Inst::Storet(*n),
+            Opcode::Pushi(d) => Inst::Pushi(*d),
+            Opcode::Pushpc => Inst::Pushpc,
+            Opcode::Pushsp => Inst::Pushsp,
+            Opcode::Pushfp => Inst::Pushfp,
+            Opcode::Call(label) => Inst::Call(Target::resolve(label, labels)),
+            Opcode::Ret => Inst::Ret,
+            Opcode::Enter => Inst::Enter,
+            Opcode::Leave => Inst::Leave,
+            Opcode::Mvsp(n) => Inst::Mvsp(*n),
+            Opcode::Salloc => Inst::Salloc,
+            Opcode::Jp(label) => Inst::Jp(Target::resolve(label, labels)),
+            Opcode::Jt(label) => Inst::Jt(Target::resolve(label, labels)),
+            Opcode::Jf(label) => Inst::Jf(Target::resolve(label, labels)),
+            Opcode::Jr(offset) => Inst::Jr(*offset),
+            Opcode::Jrt(offset) => Inst::Jrt(*offset),
+            Opcode::Jrf(offset) => Inst::Jrf(*offset),
+            Opcode::Add => Inst::Add,
+            Opcode::Sub => Inst::Sub,
+            Opcode::Mul => Inst::Mul,
+            Opcode::Div => Inst::Div,
+            Opcode::Mod => Inst::Mod,
+            Opcode::Eq => Inst::Eq,
+            Opcode::Ne => Inst::Ne,
+            Opcode::Gt => Inst::Gt,
+            Opcode::Ge => Inst::Ge,
+            Opcode::Lt => Inst::Lt,
+            Opcode::Le => Inst::Le,
+            Opcode::Rd => Inst::Rd,
+            Opcode::Rdt => Inst::Rdt,
+            Opcode::Wr => Inst::Wr,
+            Opcode::Wrln => Inst::Wrln,
+            Opcode::Wrch => Inst::Wrch,
+            Opcode::Alloc => Inst::Alloc,
+            Opcode::Free => Inst::Free,
+            Opcode::Ld => Inst::Ld,
+            Opcode::St => Inst::St,
+            Opcode::Scat => Inst::Scat,
+            Opcode::Scmp => Inst::Scmp,
+            Opcode::Slen => Inst::Slen,
+            Opcode::Wrs => Inst::Wrs,
+            Opcode::Halt => Inst::Halt,
+            Opcode::Wrf(_)
+            | Opcode::Wrz(_)
+            | Opcode::Newref(_)
+            | Opcode::Getf(_)
+            | Opcode::Setf(_)
+            | Opcode::Pushs(_)
+            | Opcode::Custom(_, _)
+            | Opcode::Trap(_)
+            | Opcode::Storei => Inst::Extended,
+        }
+    }
+}
+
+/// Converts every instruction of a program.
+pub(crate) fn compile(program: &Program) -> Vec<Inst> {
+    program.insts.iter().map(|inst| Inst::new(inst, &program.labels)).collect()
+}
+
+/// Finds the length of the basic block run from every instruction of a program.
+///
+/// A block ends at a jump, `call`, `ret`, `halt`, or a custom instruction, which may change PC,
+/// or just before a labeled instruction or the target of a relative jump, which may be jumped to.
+/// Thus, the instructions of a block are executed in order unless one of them fails.
+pub(crate) fn blocks(program: &Program) -> Vec<u32> {
+    let mut is_leader = vec![false; program.len() + 1];
+    let relative_targets = program.insts.iter().enumerate()
+        .filter_map(|(addr, inst)| usize::try_from(inst.relative_target(addr)?).ok());
+    for addr in program.labeled_addresses().chain(relative_targets) {
+        if let Some(leader) = is_leader.get_mut(addr) {
+            *leader = true;
+        }
+    }
+
+    let mut lengths = vec![1; program.len()];
+    for (addr, inst) in program.insts.iter().enumerate().rev() {
+        let ends_block = matches!(
+            inst,
+            Opcode::Call(_)
+                | Opcode::Ret
+                | Opcode::Jp(_)
+                | Opcode::Jt(_)
+                | Opcode::Jf(_)
+                | Opcode::Jr(_)
+                | Opcode::Jrt(_)
+                | Opcode::Jrf(_)
+                | Opcode::Halt
+                | Opcode::Custom(_, _)
+                | Opcode::Trap(_)
+                | Opcode::Storei
+        );
+        if !ends_block && !is_leader[addr + 1] && addr + 1 < program.len() {
+            lengths[addr] = lengths[addr + 1] + 1;
+        }
+    }
+
+    lengths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn compact_instructions() {
+        assert_eq!(std::mem::size_of::<Inst>(), 8);
+
+        let program = Program::assemble(Cursor::new(b"
+            main:
+                pushs \"hi\"
+                jp main
+                call undefined
+        ")).unwrap();
+
+        let code = compile(&program);
+        assert_eq!(code[0], Inst::Extended);
+        assert_eq!(code[1], Inst::Jp(Target(0)));
+        assert_eq!(code[2], Inst::Call(Target::UNRESOLVED));
+        assert_eq!(Target(0).get(), Some(0));
+        assert_eq!(Target::UNRESOLVED.get(), None);
+    }
+
+    #[test]
+    fn basic_blocks() {
+        let program = Program::assemble(Cursor::new(b"
+                pushi 1
+                pushi 2
+            loop:
+                pushi 3
+                jf loop
+                add
+                halt
+                wr
+        ")).unwrap();
+
+        assert_eq!(blocks(&program), [2, 1, 2, 1, 2, 1, 1]);
+    }
+}
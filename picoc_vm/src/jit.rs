@@ -0,0 +1,778 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::mem;
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, Block, InstBuilder, JumpTableData, MemFlagsData, Type, Value};
+use cranelift_codegen::isa::TargetFrontendConfig;
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+use crate::config::Config;
+use crate::cost::CycleCosts;
+use crate::decode::resolve_target;
+use crate::error::Error;
+use crate::executor::Executor;
+use crate::opcode::Opcode;
+use crate::program::Program;
+use crate::vm::{PicocVm, Register, Registers, VM_INST_MEMORY_SIZE, VM_STACK_SIZE};
+
+/// Registers shared with native code.
+#[repr(C)]
+struct NativeRegisters {
+    pc: usize,
+    sp: usize,
+    fp: usize,
+}
+
+/// `fn(memory, memory_len, registers)`
+type NativeFn = unsafe extern "C" fn(*mut i32, usize, *mut NativeRegisters);
+
+/// An executor which compiles a program into native code by Cranelift.
+///
+/// Arithmetic, stack, and control instructions run natively.
+/// The other instructions, such as I/O, and any instruction which fails
+/// (e.g. a stack overflow) are handed to the interpreter,
+/// so the result is the same as [`Interpreter`](crate::Interpreter).
+///
+/// If the host is not supported by Cranelift, any [`ExecutionLimits`](crate::ExecutionLimits)
+/// is set, or events, history, or checkpoints are recorded, the whole program is interpreted.
+///
+/// This executor is available with the `jit` feature.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{Executor, Jit, Program, Registers, Error};
+///
+/// fn main() -> Result<(), Error> {
+///     let program = Program::assemble(Cursor::new(b"
+///             pushi 0
+///         loop:
+///             pushl -1
+///             pushi 1
+///             add
+///             storel -1
+///             pushi 1000
+///             lt
+///             jt loop
+///             pushl -1
+///             wr
+///             halt"))?;
+///
+///     let mut input = Cursor::new(b"");
+///     let mut output = Vec::new();
+///
+///     Jit::default().execute(&program, Registers::default(), &mut input, &mut output)?;
+///
+///     assert_eq!(output, b"1000 ");
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Jit {
+    /// The configuration of VMs.
+    pub config: Config,
+}
+
+impl Jit {
+    /// Creates a JIT executor with a configuration.
+    pub fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// Runs the program loaded into a VM until the VM halts.
+    ///
+    /// The configuration of the VM is used instead of [`Jit::config`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] under the same situations as [`PicocVm::run_until_halt`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Jit, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     vm.load(Cursor::new(b"pushi 6\npushi 7\nmul\nhalt\n"))?;
+    ///
+    ///     Jit::default().run(&mut vm)?;
+    ///
+    ///     assert_eq!(vm.stack(), &[42]);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn run<T, U>(&mut self, vm: &mut PicocVm<T, U>) -> Result<(), Error>
+    where
+        T: BufRead,
+        U: Write,
+    {
+        let legacy_end = vm.config().legacy_end;
+        // Native code neither counts steps, records events, history, recent instructions, and checkpoints, nor checks the stack, returns, and loops
+        let config = vm.config();
+        let observed = config.record_events || config.history_depth > 0 || config.recent_depth > 0 || config.checkpoint.is_some()
+            || config.check_uninitialized || config.poison_frames || config.shadow_stack || config.tag_slots
+            || config.detect_loops
+            // Native code is compiled once, so it would run instructions overwritten by `storei`
+            || config.writable_code
+            // Cycles are not counted by native code, and neither is the time of the deterministic clock
+            || config.cycle_costs != CycleCosts::default() || config.deterministic;
+        if !config.limits.is_unlimited() || observed {
+            return vm.run_until_halt();
+        }
+
+        let Ok((module, func)) = compile(vm.program(), legacy_end) else {
+            return vm.run_until_halt();
+        };
+        let code = module.get_finalized_function(func);
+        // SAFETY: the function is compiled with the signature of `NativeFn`
+        let native = unsafe { mem::transmute::<*const u8, NativeFn>(code) };
+
+        let result = (|| {
+            loop {
+                if !vm.is_halted() {
+                    let (memory, reg) = vm.memory_and_registers();
+                    let mut native_reg = NativeRegisters { pc: reg.pc, sp: reg.sp, fp: reg.fp };
+
+                    // SAFETY: the native code only accesses `memory` within `memory.len()`
+                    unsafe { native(memory.as_mut_ptr(), memory.len(), &mut native_reg) };
+
+                    reg.pc = native_reg.pc;
+                    reg.sp = native_reg.sp;
+                    reg.fp = native_reg.fp;
+                    if legacy_end {
+                        reg.pc %= VM_INST_MEMORY_SIZE;
+                    }
+                }
+
+                // The native code stops at an instruction which it cannot execute
+                match vm.step() {
+                    Ok(()) => (),
+                    Err(Error::VmHalted) => break,
+                    Err(Error::MemoryOutOfBound) if legacy_end => break,
+                    Err(err) => return Err(err),
+                }
+            }
+
+            Ok(())
+        })();
+
+        // SAFETY: the function is no longer used
+        unsafe { module.free_memory() };
+
+        result
+    }
+}
+
+impl Executor for Jit {
+    fn execute(
+        &mut self,
+        program: &Program,
+        registers: Registers,
+        mut input: &mut dyn BufRead,
+        mut output: &mut dyn Write,
+    ) -> Result<Registers, Error> {
+        let mut vm = PicocVm::with_config(&mut input, &mut output, self.config.clone());
+
+        vm.load_program(program.clone())?;
+        vm.set_register(Register::Pc, registers.pc)?;
+        vm.set_register(Register::Sp, registers.sp)?;
+        vm.set_register(Register::Fp, registers.fp)?;
+
+        self.run(&mut vm)?;
+        vm.flush()?;
+
+        Ok(*vm.registers())
+    }
+}
+
+fn compile(program: &Program, legacy_end: bool) -> Result<(JITModule, FuncId), String> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").map_err(|e| e.to_string())?;
+    let isa = cranelift_native::builder()?
+        .finish(settings::Flags::new(flags))
+        .map_err(|e| e.to_string())?;
+
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+    let target = module.target_config();
+    let ptr = target.pointer_type();
+
+    let mut ctx = module.make_context();
+    for _ in 0..3 {
+        ctx.func.signature.params.push(AbiParam::new(ptr));
+    }
+
+    let mut func_ctx = FunctionBuilderContext::new();
+    let builder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+
+    // PC must be wrapped by the interpreter beyond the instruction memory
+    let bound = if legacy_end {
+        program.len().min(VM_INST_MEMORY_SIZE)
+    } else {
+        program.len()
+    };
+    Translator::new(builder, target, &program.labels).translate(&program.insts[..bound]);
+
+    let func = module
+        .declare_function("run", Linkage::Local, &ctx.func.signature)
+        .map_err(|e| e.to_string())?;
+    module.define_function(func, &mut ctx).map_err(|e| e.to_string())?;
+    module.clear_context(&mut ctx);
+    module.finalize_definitions().map_err(|e| e.to_string())?;
+
+    Ok((module, func))
+}
+
+/// Translates instructions into a function of Cranelift IR.
+///
+/// Each instruction is a block, which jumps to the exit block
+/// before any side effect if the instruction must be interpreted.
+struct Translator<'a> {
+    builder: FunctionBuilder<'a>,
+    target: TargetFrontendConfig,
+    ptr: Type,
+    labels: &'a HashMap<String, usize>,
+    pc: Variable,
+    sp: Variable,
+    fp: Variable,
+    memory: Value,
+    memory_len: Value,
+    insts: Vec<Block>,
+    dispatch: Block,
+    exit: Block,
+}
+
+impl<'a> Translator<'a> {
+    fn new(
+        mut builder: FunctionBuilder<'a>,
+        target: TargetFrontendConfig,
+        labels: &'a HashMap<String, usize>,
+    ) -> Self {
+        let ptr = target.pointer_type();
+
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+
+        let params = builder.block_params(entry).to_vec();
+        let (memory, memory_len, reg) = (params[0], params[1], params[2]);
+
+        let pc = builder.declare_var(ptr);
+        let sp = builder.declare_var(ptr);
+        let fp = builder.declare_var(ptr);
+        for (i, var) in [pc, sp, fp].into_iter().enumerate() {
+            let value = builder.ins().load(ptr, MemFlagsData::trusted(), reg, Self::offset(ptr, i));
+            builder.def_var(var, value);
+        }
+
+        let dispatch = builder.create_block();
+        let exit = builder.create_block();
+        builder.ins().jump(dispatch, &[]);
+
+        // Write back the registers
+        builder.switch_to_block(exit);
+        for (i, var) in [pc, sp, fp].into_iter().enumerate() {
+            let value = builder.use_var(var);
+            builder.ins().store(MemFlagsData::trusted(), value, reg, Self::offset(ptr, i));
+        }
+        builder.ins().return_(&[]);
+
+        Self {
+            builder,
+            target,
+            ptr,
+            labels,
+            pc,
+            sp,
+            fp,
+            memory,
+            memory_len,
+            insts: Vec::new(),
+            dispatch,
+            exit,
+        }
+    }
+
+    fn offset(ptr: Type, index: usize) -> i32 {
+        (ptr.bytes() as usize * index) as i32
+    }
+
+    fn translate(mut self, insts: &[Opcode]) {
+        self.insts = insts.iter().map(|_| self.builder.create_block()).collect();
+
+        // Jump to the instruction which PC points to
+        self.builder.switch_to_block(self.dispatch);
+        let pc = self.builder.use_var(self.pc);
+        let in_range = self.builder.ins().icmp_imm_u(IntCC::UnsignedLessThan, pc, insts.len() as i64);
+        let table = self.builder.create_block();
+        self.builder.ins().brif(in_range, table, &[], self.exit, &[]);
+
+        self.builder.switch_to_block(table);
+        let index = if self.ptr == types::I32 { pc } else { self.builder.ins().ireduce(types::I32, pc) };
+        let default = self.builder.func.dfg.block_call(self.exit, &[]);
+        let targets: Vec<_> = self.insts.iter()
+            .map(|&block| self.builder.func.dfg.block_call(block, &[]))
+            .collect();
+        let jump_table = self.builder.create_jump_table(JumpTableData::new(default, &targets));
+        self.builder.ins().br_table(index, jump_table);
+
+        for (addr, inst) in insts.iter().enumerate() {
+            self.builder.switch_to_block(self.insts[addr]);
+            let pc = self.builder.ins().iconst(self.ptr, addr as i64);
+            self.builder.def_var(self.pc, pc);
+
+            self.translate_inst(addr, inst);
+        }
+
+        self.builder.seal_all_blocks();
+        self.builder.finalize(self.target);
+    }
+
+    fn translate_inst(&mut self, addr: usize, inst: &Opcode) {
+        let sp = self.builder.use_var(self.sp);
+        let fp = self.builder.use_var(self.fp);
+
+        match inst {
+            Opcode::Pushl(n) => {
+                let target = self.builder.ins().iadd_imm_s(fp, *n as i64);
+                self.bail_unless_stack(target);
+                let value = self.load(target);
+                let sp = self.push(sp, value);
+
+                self.builder.def_var(self.sp, sp);
+            },
+            Opcode::Storel(n) | Opcode::Storet(n) => {
+                let base = if matches!(inst, Opcode::Storel(_)) { fp } else { sp };
+                let target = self.builder.ins().iadd_imm_s(base, *n as i64);
+                self.bail_unless_stack(target);
+                self.bail_unless_stack(sp);
+                let value = self.load(sp);
+                self.store(target, value);
+            },
+            Opcode::Pushi(d) => {
+                let value = self.builder.ins().iconst(types::I32, *d as i64);
+                let sp = self.push(sp, value);
+
+                self.builder.def_var(self.sp, sp);
+            },
+            Opcode::Pushpc | Opcode::Pushsp | Opcode::Pushfp => {
+                let value = match inst {
+                    Opcode::Pushpc => self.builder.ins().iconst(types::I32, addr as i64),
+                    Opcode::Pushsp => self.reduce_to_i32(sp),
+                    _ => self.reduce_to_i32(fp),
+                };
+                let sp = self.push(sp, value);
+
+                self.builder.def_var(self.sp, sp);
+            },
+            Opcode::Call(label) => {
+                let Some(target) = resolve_target(self.labels, label) else {
+                    return self.bail();
+                };
+                let value = self.builder.ins().iconst(types::I32, addr as i64 + 1);
+                let sp = self.push(sp, value);
+
+                self.builder.def_var(self.sp, sp);
+                return self.jump(target);
+            },
+            Opcode::Ret => {
+                let (value, sp) = self.pop(sp);
+                // A negative return address is reported by the interpreter
+                let is_negative = self.builder.ins().icmp_imm_s(IntCC::SignedLessThan, value, 0);
+                self.bail_if(is_negative);
+                let pc = self.builder.ins().sextend(self.ptr, value);
+
+                self.builder.def_var(self.sp, sp);
+                self.builder.def_var(self.pc, pc);
+                self.builder.ins().jump(self.dispatch, &[]);
+                return;
+            },
+            Opcode::Enter => {
+                let value = self.reduce_to_i32(fp);
+                let sp = self.push(sp, value);
+
+                self.builder.def_var(self.sp, sp);
+                self.builder.def_var(self.fp, sp);
+            },
+            Opcode::Leave => {
+                let (value, sp) = self.pop(fp);
+                let fp = self.builder.ins().sextend(self.ptr, value);
+                // FP outside of the stack is reported by the interpreter
+                let is_outside = self.builder.ins().icmp_imm_u(IntCC::UnsignedGreaterThan, fp, VM_STACK_SIZE as i64);
+                self.bail_if(is_outside);
+
+                self.builder.def_var(self.sp, sp);
+                self.builder.def_var(self.fp, fp);
+            },
+            Opcode::Mvsp(n) => {
+                let sp = self.reduce_to_i32(sp);
+                let n = self.builder.ins().iconst(types::I32, *n as i64);
+                let (sum, overflow) = self.builder.ins().sadd_overflow(sp, n);
+                self.bail_if(overflow);
+                let sp = self.builder.ins().sextend(self.ptr, sum);
+                // SP outside of the stack is reported by the interpreter
+                let is_outside = self.builder.ins().icmp_imm_u(IntCC::UnsignedGreaterThan, sp, VM_STACK_SIZE as i64);
+                self.bail_if(is_outside);
+
+                self.builder.def_var(self.sp, sp);
+            },
+            Opcode::Jp(label) => {
+                let Some(target) = resolve_target(self.labels, label) else {
+                    return self.bail();
+                };
+                return self.jump(target);
+            },
+            Opcode::Jt(label) | Opcode::Jf(label) => {
+                let Some(target) = resolve_target(self.labels, label) else {
+                    return self.bail();
+                };
+                let (value, sp) = self.pop(sp);
+                self.builder.def_var(self.sp, sp);
+
+                let cc = if matches!(inst, Opcode::Jt(_)) { IntCC::NotEqual } else { IntCC::Equal };
+                let taken = self.builder.ins().icmp_imm_s(cc, value, 0);
+                let then_block = self.builder.create_block();
+                let else_block = self.builder.create_block();
+                self.builder.ins().brif(taken, then_block, &[], else_block, &[]);
+
+                self.builder.switch_to_block(then_block);
+                self.jump(target);
+                self.builder.switch_to_block(else_block);
+                return self.jump(addr + 1);
+            },
+            Opcode::Add | Opcode::Sub | Opcode::Mul => {
+                let (t1, sp) = self.pop(sp);
+                let (t2, sp) = self.pop(sp);
+                let (value, overflow) = match inst {
+                    Opcode::Add => self.builder.ins().sadd_overflow(t2, t1),
+                    Opcode::Sub => self.builder.ins().ssub_overflow(t2, t1),
+                    _ => self.builder.ins().smul_overflow(t2, t1),
+                };
+                self.bail_if(overflow);
+                let sp = self.push(sp, value);
+
+                self.builder.def_var(self.sp, sp);
+            },
+            Opcode::Div | Opcode::Mod => {
+                let (t1, sp) = self.pop(sp);
+                let (t2, sp) = self.pop(sp);
+                // Division by zero and overflow are left to the interpreter
+                let is_zero = self.builder.ins().icmp_imm_s(IntCC::Equal, t1, 0);
+                self.bail_if(is_zero);
+                let is_min = self.builder.ins().icmp_imm_s(IntCC::Equal, t2, i32::MIN as i64);
+                let is_minus_one = self.builder.ins().icmp_imm_s(IntCC::Equal, t1, -1);
+                let overflow = self.builder.ins().band(is_min, is_minus_one);
+                self.bail_if(overflow);
+                let value = if matches!(inst, Opcode::Div) {
+                    self.builder.ins().sdiv(t2, t1)
+                } else {
+                    self.builder.ins().srem(t2, t1)
+                };
+                let sp = self.push(sp, value);
+
+                self.builder.def_var(self.sp, sp);
+            },
+            Opcode::Eq | Opcode::Ne | Opcode::Gt | Opcode::Ge | Opcode::Lt | Opcode::Le => {
+                let cc = match inst {
+                    Opcode::Eq => IntCC::Equal,
+                    Opcode::Ne => IntCC::NotEqual,
+                    Opcode::Gt => IntCC::SignedGreaterThan,
+                    Opcode::Ge => IntCC::SignedGreaterThanOrEqual,
+                    Opcode::Lt => IntCC::SignedLessThan,
+                    _ => IntCC::SignedLessThanOrEqual,
+                };
+                let (t1, sp) = self.pop(sp);
+                let (t2, sp) = self.pop(sp);
+                let cond = self.builder.ins().icmp(cc, t2, t1);
+                let value = self.builder.ins().uextend(types::I32, cond);
+                let sp = self.push(sp, value);
+
+                self.builder.def_var(self.sp, sp);
+            },
+            Opcode::Ld => {
+                let (addr, sp) = self.pop(sp);
+                let addr = self.builder.ins().sextend(self.ptr, addr);
+                self.bail_unless_memory(addr);
+                let value = self.load(addr);
+                let sp = self.push(sp, value);
+
+                self.builder.def_var(self.sp, sp);
+            },
+            Opcode::St => {
+                let (value, sp) = self.pop(sp);
+                let (addr, sp) = self.pop(sp);
+                let addr = self.builder.ins().sextend(self.ptr, addr);
+                self.bail_unless_memory(addr);
+                self.store(addr, value);
+                let sp = self.push(sp, value);
+
+                self.builder.def_var(self.sp, sp);
+            },
+            _ => return self.bail(),
+        }
+
+        self.jump(addr + 1);
+    }
+
+    /// Leaves the native code to interpret the current instruction.
+    fn bail(&mut self) {
+        self.builder.ins().jump(self.exit, &[]);
+    }
+
+    fn bail_if(&mut self, cond: Value) {
+        let next = self.builder.create_block();
+        self.builder.ins().brif(cond, self.exit, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    fn bail_unless(&mut self, cond: Value) {
+        let next = self.builder.create_block();
+        self.builder.ins().brif(cond, next, &[], self.exit, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    fn bail_unless_stack(&mut self, addr: Value) {
+        let cond = self.builder.ins().icmp_imm_u(IntCC::UnsignedLessThan, addr, VM_STACK_SIZE as i64);
+        self.bail_unless(cond);
+    }
+
+    fn bail_unless_memory(&mut self, addr: Value) {
+        let cond = self.builder.ins().icmp(IntCC::UnsignedLessThan, addr, self.memory_len);
+        self.bail_unless(cond);
+    }
+
+    fn jump(&mut self, target: usize) {
+        let pc = self.builder.ins().iconst(self.ptr, target as i64);
+        self.builder.def_var(self.pc, pc);
+
+        match self.insts.get(target) {
+            Some(&block) => self.builder.ins().jump(block, &[]),
+            None => self.builder.ins().jump(self.exit, &[]),
+        };
+    }
+
+    fn reduce_to_i32(&mut self, value: Value) -> Value {
+        if self.ptr == types::I32 {
+            value
+        } else {
+            self.builder.ins().ireduce(types::I32, value)
+        }
+    }
+
+    fn address(&mut self, index: Value) -> Value {
+        let offset = self.builder.ins().ishl_imm_u(index, 2);
+        self.builder.ins().iadd(self.memory, offset)
+    }
+
+    fn load(&mut self, index: Value) -> Value {
+        let addr = self.address(index);
+        self.builder.ins().load(types::I32, MemFlagsData::trusted(), addr, 0)
+    }
+
+    fn store(&mut self, index: Value, value: Value) {
+        let addr = self.address(index);
+        self.builder.ins().store(MemFlagsData::trusted(), value, addr, 0);
+    }
+
+    /// Pushes a value and returns the new SP.
+    fn push(&mut self, sp: Value, value: Value) -> Value {
+        let sp = self.builder.ins().iadd_imm_s(sp, -1);
+        self.bail_unless_stack(sp);
+        self.store(sp, value);
+
+        sp
+    }
+
+    /// Pops a value and returns it with the new SP.
+    fn pop(&mut self, sp: Value) -> (Value, Value) {
+        self.bail_unless_stack(sp);
+        let value = self.load(sp);
+
+        (value, self.builder.ins().iadd_imm_s(sp, 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    fn execute(code: &[u8], config: Config) -> (Result<Registers, Error>, Vec<u8>) {
+        let program = Program::assemble(io::Cursor::new(code)).unwrap();
+        let mut input = io::Cursor::new(b"3\n");
+        let mut output = Vec::new();
+
+        let result = Jit::new(config).execute(&program, Registers::default(), &mut input, &mut output);
+
+        (result, output)
+    }
+
+    #[test]
+    fn compile_program() {
+        let program = Program::assemble(io::Cursor::new(b"
+            main:
+                pushi 1
+                call main
+                jt main
+                wr
+        ")).unwrap();
+
+        let (module, _) = compile(&program, false).unwrap();
+        unsafe { module.free_memory() };
+    }
+
+    #[test]
+    fn native_arithmetic() {
+        let (result, output) = execute(b"
+                pushi 0
+                pushi 1
+            loop:
+                pushl -2
+                pushl -1
+                storel -2
+                add
+                storel -1
+                mvsp 1
+                pushl -1
+                pushi 100
+                lt
+                jt loop
+                pushl -2
+                wr
+                pushl -1
+                pushi 7
+                mod
+                wr
+                halt
+        ", Config::default());
+
+        assert_eq!(result.unwrap(), Registers { pc: 18, sp: VM_STACK_SIZE - 2, fp: VM_STACK_SIZE });
+        assert_eq!(output, b"89 4 ");
+    }
+
+    #[test]
+    fn call_and_interpreted_instructions() {
+        let (result, output) = execute(b"
+                rd
+                call square
+                wr
+                halt
+            square:
+                enter
+                pushl 2
+                pushl 2
+                mul
+                storel 2
+                leave
+                ret
+        ", Config::default());
+
+        assert!(result.is_ok());
+        assert_eq!(output, b"? 9 ");
+    }
+
+    #[test]
+    fn runtime_errors() {
+        let (result, _) = execute(b"pushl 0\n", Config::default());
+        assert!(matches!(result, Err(Error::StackOutOfBound)));
+
+        let (result, _) = execute(b"add\n", Config::default());
+        assert!(matches!(result, Err(Error::StackUnderflow)));
+
+        let (result, _) = execute(b"mvsp -10001\n", Config::default());
+        assert!(matches!(result, Err(Error::InvalidStackPointer(-1, _))));
+
+        let (result, _) = execute(b"pushi 1\n", Config::default());
+        assert!(matches!(result, Err(Error::FellOffEnd)));
+
+        let config = Config { legacy_end: true, ..Config::default() };
+        let (result, _) = execute(b"pushi 1\n", config);
+        assert_eq!(result.unwrap().pc, 1);
+    }
+
+    #[test]
+    fn corrupted_frames() {
+        for code in [&b"enter\npushi -5\nstorel 0\nleave\nret\npushi 7\nwr\nhalt\n"[..], b"pushi -1\nret\npushi 7\nwr\nhalt\n"] {
+            let program = Program::assemble(io::Cursor::new(code)).unwrap();
+            let mut output = Vec::new();
+            let expected = crate::Interpreter::default().execute(&program, Registers::default(), &mut io::Cursor::new(b""), &mut output);
+
+            let (result, jit_output) = execute(code, Config::default());
+            assert_eq!(format!("{:?}", result), format!("{:?}", expected));
+            assert_eq!(jit_output, output);
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn hostile_programs() {
+        // The programs of `hostile_instructions` in the interpreter's tests
+        let codes: [&[u8]; 10] = [
+            b"pushi -1\nret\n",
+            b"mvsp 2147483647\n",
+            b"mvsp -10001\n",
+            b"storel 0\n",
+            b"storet 0\n",
+            b"pushi 1\nstoret 1\n",
+            b"enter\npushi -5\nstorel 0\nleave\n",
+            b"pushi 1\npushi 0\ndiv\n",
+            b"pushi 1\npushi 0\nmod\n",
+            b"pushi -2147483648\npushi -1\ndiv\npushi 2147483647\npushi 1\nadd\nhalt\n",
+        ];
+        for code in codes {
+            let program = Program::assemble(io::Cursor::new(code)).unwrap();
+            let expected = crate::Interpreter::default().execute(&program, Registers::default(), &mut io::Cursor::new(b""), &mut Vec::new());
+
+            let (result, _) = execute(code, Config::default());
+            assert_eq!(format!("{:?}", result), format!("{:?}", expected), "{}", String::from_utf8_lossy(code));
+        }
+    }
+
+    #[test]
+    fn counted_cycles() {
+        let program = Program::assemble(io::Cursor::new(b"pushi 1\npushi 2\nadd\nhalt\n")).unwrap();
+        let costs = CycleCosts::parse("add 10\n").unwrap();
+
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+        let mut vm = PicocVm::with_config(&mut input, &mut output, Config { cycle_costs: costs, ..Config::default() });
+        vm.load_program(program).unwrap();
+        Jit::default().run(&mut vm).unwrap();
+
+        assert_eq!(vm.cycles(), 13);
+    }
+
+    #[test]
+    fn recent_instructions() {
+        let program = Program::assemble(io::Cursor::new(b"pushi 1\npushi 2\nadd\nhalt\n")).unwrap();
+
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+        let mut vm = PicocVm::with_config(&mut input, &mut output, Config { recent_depth: 4, ..Config::default() });
+        vm.load_program(program).unwrap();
+        Jit::default().run(&mut vm).unwrap();
+
+        assert_eq!(vm.recent_instructions().map(|(pc, _)| pc).collect::<Vec<_>>(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        let word = Opcode::Pushi(2).to_word(&HashMap::new()).unwrap();
+        let code = format!("pushi 3\npushi {}\nstorei\npushi 1\nwr\nhalt\n", word);
+
+        let config = Config { writable_code: true, ..Config::default() };
+        let (result, output) = execute(code.as_bytes(), config);
+        assert!(result.is_ok());
+        assert_eq!(output, b"2 ");
+    }
+}
@@ -0,0 +1,396 @@
+use std::io::{self, BufRead, Cursor};
+use crate::config::{Config, ExecutionLimits};
+use crate::error::Error;
+use crate::program::Program;
+use crate::vm::PicocVm;
+
+/// How a program judged by [`Judge`] stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ExitStatus {
+    /// The program halted normally.
+    Halted,
+    /// The program could not be assembled.
+    AssembleError,
+    /// A runtime error occurred.
+    RuntimeError,
+    /// The program exceeded one of the [`ExecutionLimits`].
+    LimitExceeded,
+    /// The program was stuck in a likely infinite loop, found with [`Config::detect_loops`].
+    InfiniteLoop,
+}
+
+impl ExitStatus {
+    /// Classifies an error which stops a program.
+    ///
+    /// [`Error::VmHalted`] is regarded as [`ExitStatus::Halted`], since running a halted VM returns it.
+    pub fn of_error(err: &Error) -> Self {
+        match err {
+            Error::VmHalted => ExitStatus::Halted,
+            Error::InSource(..) => ExitStatus::AssembleError,
+            Error::StepLimitExceeded(_)
+            | Error::TimeLimitExceeded(_)
+            | Error::StackLimitExceeded(_)
+            | Error::CallDepthExceeded(..)
+            | Error::OutputLimitExceeded(_)
+            | Error::MemoryLimitExceeded(_) => ExitStatus::LimitExceeded,
+            Error::InfiniteLoop(_) => ExitStatus::InfiniteLoop,
+            _ => ExitStatus::RuntimeError,
+        }
+    }
+}
+
+/// The result of a program judged by [`Judge`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct JudgeReport {
+    /// The output written by the program.
+    pub output: String,
+    /// How the program stopped.
+    pub status: ExitStatus,
+    /// The number of executed instructions.
+    pub steps: u64,
+    /// The simulated cycles taken by the executed instructions (see [`CycleCosts`](crate::CycleCosts)).
+    pub cycles: u64,
+    /// The maximum depth of the stack in words.
+    pub max_stack: usize,
+    /// The message of an error, if any.
+    pub error: Option<String>,
+}
+
+/// Runs a program in a sandbox and reports the result.
+///
+/// Every run starts with a fresh VM, and never touches stdin or stdout.
+/// With the `serde` feature, a [`JudgeReport`] can be serialized (e.g. into JSON).
+///
+/// # Example
+///
+/// ```
+/// use picoc_vm::{Judge, ExitStatus};
+///
+/// fn main() {
+///     let judge = Judge::default();
+///
+///     let report = judge.run("rd\npushi 2\nmul\nwr\nhalt\n", "21\n");
+///
+///     assert_eq!(report.output, "42 ");
+///     assert_eq!(report.status, ExitStatus::Halted);
+///     assert_eq!(report.steps, 5);
+///     assert_eq!(report.cycles, 5);
+///     assert_eq!(report.max_stack, 2);
+///
+///     let report = judge.run("loop:\njp loop\n", "");
+///
+///     assert_eq!(report.status, ExitStatus::LimitExceeded);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Judge {
+    /// The configuration of VMs, including resource limits.
+    pub config: Config,
+}
+
+impl Default for Judge {
+    /// Creates a judge which allows up to 1,000,000 steps.
+    fn default() -> Self {
+        Self::new(Config {
+            limits: ExecutionLimits {
+                max_steps: Some(1_000_000),
+                ..ExecutionLimits::default()
+            },
+            ..Config::default()
+        })
+    }
+}
+
+impl Judge {
+    /// Creates a judge with a configuration.
+    pub fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// Assembles and runs a program with an input.
+    pub fn run(&self, code: &str, input: &str) -> JudgeReport {
+        match Program::assemble(code.as_bytes()) {
+            Ok(program) => self.run_program(&program, input),
+            Err(err) => JudgeReport {
+                output: String::new(),
+                status: ExitStatus::AssembleError,
+                steps: 0,
+                cycles: 0,
+                max_stack: 0,
+                error: Some(err.to_string()),
+            },
+        }
+    }
+
+    /// Runs an assembled program with an input.
+    pub fn run_program(&self, program: &Program, input: &str) -> JudgeReport {
+        self.run_inputs(program, &[input]).remove(0)
+    }
+
+    /// Runs an assembled program once per input, and reports the results in the same order.
+    ///
+    /// Every run starts with a fresh state, but the VM is [`reset`](PicocVm::reset()) and reused,
+    /// so running many short inputs doesn't allocate the memory and load the program every time.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use picoc_vm::{Judge, Program, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let program = Program::assemble(&b"rd\npushi 2\nmul\nwr\nhalt\n"[..])?;
+    ///
+    ///     let reports = Judge::default().run_inputs(&program, &["21\n", "5\n"]);
+    ///     let outputs: Vec<_> = reports.iter().map(|report| report.output.as_str()).collect();
+    ///
+    ///     assert_eq!(outputs, ["42 ", "10 "]);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn run_inputs(&self, program: &Program, inputs: &[&str]) -> Vec<JudgeReport> {
+        let mut input = Cursor::new(Vec::new());
+        let mut output = Vec::new();
+        let mut prompt = io::sink();
+
+        let mut vm = PicocVm::with_config(&mut input, &mut output, self.config.clone());
+        vm.set_prompt_output(&mut prompt);
+
+        self.run_inputs_on(&mut vm, program, inputs)
+    }
+
+    /// Loads a program into a VM and runs it once per input, like [`Judge::run_inputs`].
+    ///
+    /// The VM is [`reset`](PicocVm::reset()) before each input, and runs as configured
+    /// (e.g. writing prompts unless [`Config::no_prompt`] is set) instead of by [`Judge::config`],
+    /// so that a caller can keep VMs across programs instead of allocating one for each.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{Config, Judge, PicocVm, Program, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let judge = Judge::new(Config { no_prompt: true, ..Config::default() });
+    ///
+    ///     let mut input = Cursor::new(Vec::new());
+    ///     let mut output = Vec::new();
+    ///     let mut vm = PicocVm::with_config(&mut input, &mut output, judge.config.clone());
+    ///
+    ///     let program = Program::assemble(&b"rd\npushi 2\nmul\nwr\nhalt\n"[..])?;
+    ///     assert_eq!(judge.run_inputs_on(&mut vm, &program, &["21\n"])[0].output, "42 ");
+    ///
+    ///     let program = Program::assemble(&b"rd\npushi 3\nmul\nwr\nhalt\n"[..])?;
+    ///     assert_eq!(judge.run_inputs_on(&mut vm, &program, &["21\n"])[0].output, "63 ");
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn run_inputs_on(&self, vm: &mut PicocVm<Cursor<Vec<u8>>, Vec<u8>>, program: &Program, inputs: &[&str]) -> Vec<JudgeReport> {
+        if let Err(err) = vm.load_program(program.clone()) {
+            return inputs.iter().map(|_| report(Err(&err), String::new(), 0, 0, 0)).collect();
+        }
+
+        inputs.iter().map(|data| {
+            vm.reset();
+            let input = vm.input_mut();
+            input.get_mut().clear();
+            input.get_mut().extend_from_slice(data.as_bytes());
+            input.set_position(0);
+            vm.output_mut().clear();
+
+            let (result, max_stack) = self.execute(vm);
+            let result = result.and_then(|()| vm.flush());
+            let output = String::from_utf8_lossy(vm.output_mut()).into_owned();
+
+            report(result.as_ref().map(|_| ()), output, vm.steps(), vm.cycles(), max_stack)
+        }).collect()
+    }
+
+    /// Runs a VM until it halts, and returns the result with the maximum depth of the stack.
+    fn execute<T: BufRead>(&self, vm: &mut PicocVm<T, Vec<u8>>) -> (Result<(), Error>, usize) {
+        let stack_end = vm.memory_map().stack.end();
+        let legacy_end = vm.config().legacy_end;
+        let mut max_stack = 0;
+
+        let result = loop {
+            match vm.step() {
+                Ok(()) => (),
+                Err(Error::VmHalted) => break Ok(()),
+                Err(Error::MemoryOutOfBound) if legacy_end => break Ok(()),
+                Err(err) => break Err(err),
+            }
+            max_stack = max_stack.max(stack_end - vm.registers().sp);
+        };
+
+        (result, max_stack)
+    }
+}
+
+/// The output and the statistics of a program run by [`run_source`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RunOutput {
+    /// The output written by the program.
+    pub output: String,
+    /// The number of executed instructions.
+    pub steps: u64,
+    /// The simulated cycles taken by the executed instructions (see [`CycleCosts`](crate::CycleCosts)).
+    pub cycles: u64,
+    /// The maximum depth of the stack in words.
+    pub max_stack: usize,
+}
+
+/// Assembles and runs a program with an input, and returns the output.
+///
+/// The program runs like [`Judge::default`], with up to 1,000,000 steps and without prompts.
+///
+/// # Errors
+///
+/// Returns [`Err`] if the program cannot be assembled, or an error (including exceeding the limit) occurs while it runs.
+///
+/// # Example
+///
+/// ```
+/// use picoc_vm::{run_source, Error};
+///
+/// fn main() -> Result<(), Error> {
+///     let run = run_source("rd\npushi 2\nmul\nwr\nhalt\n", "21\n")?;
+///
+///     assert_eq!(run.output, "42 ");
+///     assert_eq!(run.steps, 5);
+///
+///     assert!(matches!(run_source("loop:\njp loop\n", ""), Err(Error::StepLimitExceeded(_))));
+///
+///     Ok(())
+/// }
+/// ```
+pub fn run_source(program: &str, input: &str) -> Result<RunOutput, Error> {
+    let judge = Judge::default();
+    let program = Program::assemble(program.as_bytes())?;
+
+    let mut input = Cursor::new(input.as_bytes());
+    let mut output = Vec::new();
+    let mut prompt = io::sink();
+
+    let mut vm = PicocVm::with_config(&mut input, &mut output, judge.config.clone());
+    vm.set_prompt_output(&mut prompt);
+    vm.load_program(program)?;
+
+    let (result, max_stack) = judge.execute(&mut vm);
+    result?;
+    vm.flush()?;
+
+    Ok(RunOutput {
+        output: String::from_utf8_lossy(vm.output_mut()).into_owned(),
+        steps: vm.steps(),
+        cycles: vm.cycles(),
+        max_stack,
+    })
+}
+
+fn report(result: Result<(), &Error>, output: String, steps: u64, cycles: u64, max_stack: usize) -> JudgeReport {
+    let (status, error) = match result {
+        Ok(()) => (ExitStatus::Halted, None),
+        Err(err) => (ExitStatus::of_error(err), Some(err.to_string())),
+    };
+
+    JudgeReport {
+        output,
+        status,
+        steps,
+        cycles,
+        max_stack,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn judge_errors() {
+        let judge = Judge::new(Config {
+            limits: ExecutionLimits {
+                max_steps: Some(100),
+                ..ExecutionLimits::default()
+            },
+            ..Config::default()
+        });
+
+        let report = judge.run("pushi 1\nwr\nfoo\n", "");
+        assert_eq!(report.status, ExitStatus::AssembleError);
+        assert_eq!(report.error.as_deref(), Some("line 3: Unknown opcode 'foo' is found\n    3 | foo"));
+
+        let report = judge.run("pushi 1\nwr\nadd\n", "");
+        assert_eq!(report.status, ExitStatus::RuntimeError);
+        assert_eq!(report.output, "1 ");
+        assert_eq!(report.steps, 2);
+
+        let report = judge.run("loop:\npushi 1\njp loop\n", "");
+        assert_eq!(report.status, ExitStatus::LimitExceeded);
+        assert_eq!(report.steps, 100);
+        assert_eq!(report.max_stack, 50);
+    }
+
+    #[test]
+    fn judge_infinite_loops() {
+        let judge = Judge::new(Config {
+            detect_loops: true,
+            limits: ExecutionLimits {
+                max_steps: Some(100_000),
+                ..ExecutionLimits::default()
+            },
+            ..Config::default()
+        });
+
+        let report = judge.run("loop:\npushi 1\nmvsp 1\njp loop\n", "");
+        assert_eq!(report.status, ExitStatus::InfiniteLoop);
+        assert!(report.steps < 100);
+
+        let counting = "pushi 0\nloop:\npushl -1\npushi 1\nadd\nstorel -1\npushi 1000\nlt\njt loop\nhalt\n";
+        assert_eq!(judge.run(counting, "").status, ExitStatus::Halted);
+
+        // Writing is progress even if the state recurs
+        let report = judge.run("loop:\npushi 1\nwr\njp loop\n", "");
+        assert_eq!(report.status, ExitStatus::LimitExceeded);
+    }
+
+    #[test]
+    fn reused_vm_starts_fresh() {
+        let program = Program::assemble(&b"
+                rd
+                jf skip
+                pushi 10000
+                pushi 7
+                st
+            skip:
+                pushi 10000
+                ld
+                wr
+                halt
+        "[..]).unwrap();
+
+        let judge = Judge::new(Config { data_size: 1, ..Config::default() });
+        let reports = judge.run_inputs(&program, &["1\n", "0\n"]);
+
+        assert_eq!(reports[0].error, None);
+        assert_eq!(reports[0].output, "7 ");
+        assert_eq!(reports[1].output, "0 ");
+        assert_eq!(reports[1].steps, 6);
+    }
+
+    #[test]
+    fn run_source_errors() {
+        let run = run_source("pushi 1\nmvsp 1\npushi 2\nwr\nhalt\n", "").unwrap();
+        assert_eq!(run, RunOutput { output: "2 ".to_string(), steps: 5, cycles: 5, max_stack: 1 });
+
+        assert!(matches!(run_source("foo\n", "").unwrap_err().inner(), Error::UnknownOpcode(name) if name == "foo"));
+        assert!(matches!(run_source("add\n", ""), Err(Error::StackUnderflow)));
+    }
+}
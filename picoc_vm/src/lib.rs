@@ -2,15 +2,76 @@
 //!
 //! This machine interprets picoc vm instruction sets.
 
+mod binary;
+mod checker;
+mod chrome;
+mod config;
+mod cost;
+mod debug;
 mod decode;
+mod delta;
+mod dialect;
+mod directive;
 mod error;
+mod event;
+mod executor;
+mod explain;
+mod expr;
+mod frame;
+mod gc;
+mod heap;
+mod inst;
+#[cfg(feature = "jit")]
+mod jit;
+mod judge;
+mod literal;
+mod lockstep;
+mod looping;
+mod memory;
 mod opcode;
+mod profile;
+mod program;
+mod report;
+mod snapshot;
+mod state;
+mod strings;
+mod symbols;
+mod trace;
+mod transpile;
 mod vm;
+mod warning;
+mod wasm;
 
+pub use chrome::ChromeTrace;
+pub use checker::{SlotTag, StackIssue, StackIssueKind};
+pub use config::{Config, ExecutionLimits, FlushPolicy, LoadMode, OutputFormat, Radix};
+pub use cost::CycleCosts;
+pub use debug::{DebugInfo, SourceLocation};
+pub use delta::{MemoryWrite, StepDelta};
+pub use dialect::{AliasDialect, CompatDialect, DefaultDialect, Dialect};
+pub use directive::Directives;
 pub use error::Error;
+pub use event::VmEvent;
+pub use executor::{Executor, Interpreter};
+pub use frame::Frame;
+#[cfg(feature = "jit")]
+pub use jit::Jit;
+pub use judge::{run_source, ExitStatus, Judge, JudgeReport, RunOutput};
+pub use lockstep::{Divergence, DivergenceKind, Lockstep};
+pub use memory::{MemoryMap, MemoryUsage, Region, Segment};
 pub use opcode::Opcode;
+pub use profile::Profiler;
+pub use program::Program;
+pub use report::LoadReport;
+pub use snapshot::{CheckpointPolicy, Snapshot};
+pub use state::VmState;
+pub use trace::{TraceFilter, Tracer};
+pub use transpile::transpile;
+pub use warning::{Warning, WarningKind};
+pub use wasm::compile_wasm;
 pub use vm::PicocVm;
 pub use vm::Registers;
+pub use vm::Register;
 
 pub use vm::VM_STACK_SIZE;
 pub use vm::VM_INST_MEMORY_SIZE;
@@ -0,0 +1,151 @@
+use crate::error::Error;
+
+fn unescape(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '0' => Some('\0'),
+        '\\' => Some('\\'),
+        '"' => Some('"'),
+        '\'' => Some('\''),
+        _ => None,
+    }
+}
+
+/// Parses an integer literal (e.g. `-42`, `0xff`, or `0b1010`).
+///
+/// A prefix `0x`, `0o`, or `0b` selects the radix of the digits,
+/// and digits without a prefix are read in `radix`.
+/// A sign may precede the prefix.
+pub fn parse_int(token: &str, radix: u32) -> Result<i64, Error> {
+    let (sign, body) = match token.strip_prefix('-') {
+        Some(body) => ("-", body),
+        None => ("", token.strip_prefix('+').unwrap_or(token)),
+    };
+
+    let prefix = body.get(..2).map(str::to_ascii_lowercase);
+    let (radix, digits) = match prefix.as_deref() {
+        Some("0x") => (16, &body[2..]),
+        Some("0o") => (8, &body[2..]),
+        Some("0b") if radix < 12 => (2, &body[2..]),
+        _ => (radix, body),
+    };
+    // A sign is given to `from_str_radix` together so that the minimum value is accepted
+    if digits.starts_with(['+', '-']) {
+        return Err(Error::InvalidLiteral(token.to_string()));
+    }
+
+    Ok(i64::from_str_radix(&format!("{}{}", sign, digits), radix)?)
+}
+
+/// Parses a string literal (e.g. `"Hello\n"`) in an operand.
+pub fn parse_string(token: &str) -> Result<String, Error> {
+    let invalid = || Error::InvalidLiteral(token.to_string());
+
+    let body = token.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(invalid)?;
+
+    let mut ret = String::new();
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => ret.push(chars.next().and_then(unescape).ok_or_else(invalid)?),
+            '"' => return Err(invalid()),
+            c => ret.push(c),
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Parses a character literal (e.g. `'A'` or `'\n'`) into its code point.
+pub fn parse_char(token: &str) -> Result<i64, Error> {
+    let invalid = || Error::InvalidLiteral(token.to_string());
+
+    let body = token.strip_prefix('\'')
+        .and_then(|s| s.strip_suffix('\''))
+        .ok_or_else(invalid)?;
+
+    let mut chars = body.chars();
+    let c = match chars.next() {
+        Some('\\') => chars.next().and_then(unescape).ok_or_else(invalid)?,
+        Some('\'') | None => return Err(invalid()),
+        Some(c) => c,
+    };
+    if chars.next().is_some() {
+        return Err(invalid());
+    }
+
+    Ok(c as i64)
+}
+
+/// Quotes a string so that [`parse_string`] restores it.
+pub fn escape_string(s: &str) -> String {
+    let mut ret = String::from('"');
+
+    for c in s.chars() {
+        match c {
+            '\n' => ret.push_str("\\n"),
+            '\t' => ret.push_str("\\t"),
+            '\r' => ret.push_str("\\r"),
+            '\0' => ret.push_str("\\0"),
+            '\\' => ret.push_str("\\\\"),
+            '"' => ret.push_str("\\\""),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_literal() {
+        assert_eq!(parse_int("42", 10).unwrap(), 42);
+        assert_eq!(parse_int("-0x1F", 10).unwrap(), -31);
+        assert_eq!(parse_int("0b1010", 10).unwrap(), 10);
+        assert_eq!(parse_int("+0o17", 10).unwrap(), 15);
+        assert_eq!(parse_int("ff", 16).unwrap(), 255);
+        assert_eq!(parse_int("0b1", 16).unwrap(), 0xb1);
+        assert_eq!(parse_int("-101", 2).unwrap(), -5);
+        assert_eq!(parse_int("-0x8000000000000000", 10).unwrap(), i64::MIN);
+        assert!(matches!(parse_int("0x", 10), Err(Error::ParseIntError(_))));
+        assert!(matches!(parse_int("12", 2), Err(Error::ParseIntError(_))));
+        assert!(matches!(parse_int("-+1", 10), Err(Error::InvalidLiteral(_))));
+    }
+
+    #[test]
+    fn string_literal() {
+        assert_eq!(parse_string("\"Hello, world\\n\"").unwrap(), "Hello, world\n");
+        assert_eq!(parse_string("\"\\\"\\\\\"").unwrap(), "\"\\");
+        assert_eq!(parse_string("\"\"").unwrap(), "");
+        assert!(matches!(parse_string("\"abc"), Err(Error::InvalidLiteral(_))));
+        assert!(matches!(parse_string("\"a\\q\""), Err(Error::InvalidLiteral(_))));
+        assert!(matches!(parse_string("\"a\"b\""), Err(Error::InvalidLiteral(_))));
+    }
+
+    #[test]
+    fn char_literal() {
+        assert_eq!(parse_char("'A'").unwrap(), 65);
+        assert_eq!(parse_char("'\\n'").unwrap(), 10);
+        assert_eq!(parse_char("'\\''").unwrap(), 39);
+        assert_eq!(parse_char("'#'").unwrap(), 35);
+        assert_eq!(parse_char("'あ'").unwrap(), 0x3042);
+        assert!(matches!(parse_char("''"), Err(Error::InvalidLiteral(_))));
+        assert!(matches!(parse_char("'ab'"), Err(Error::InvalidLiteral(_))));
+        assert!(matches!(parse_char("'\\q'"), Err(Error::InvalidLiteral(_))));
+    }
+
+    #[test]
+    fn escape_roundtrip() {
+        let s = "tab\there \"quoted\" back\\slash\n";
+
+        assert_eq!(parse_string(&escape_string(s)).unwrap(), s);
+    }
+}
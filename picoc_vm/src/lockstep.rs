@@ -0,0 +1,165 @@
+use std::cell::RefCell;
+use std::io::{self, Cursor, Write};
+use std::rc::Rc;
+use crate::config::Config;
+use crate::error::Error;
+use crate::program::Program;
+use crate::vm::{PicocVm, Registers};
+
+/// What differs between two VMs run by [`Lockstep`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DivergenceKind {
+    /// The registers differ.
+    Registers(Registers, Registers),
+    /// The stacks differ.
+    Stack(Vec<i32>, Vec<i32>),
+    /// The outputs differ.
+    Output(Vec<u8>, Vec<u8>),
+    /// One VM stopped while the other did not, or they stopped with different errors.
+    ///
+    /// `None` means the VM is still running.
+    Status(Option<String>, Option<String>),
+}
+
+/// The first divergence found by [`Lockstep`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// The number of steps executed by both VMs before the divergence is found.
+    pub step: u64,
+    /// What differs.
+    pub kind: DivergenceKind,
+}
+
+/// An output stream shared between a VM and [`Lockstep`].
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Steps two VMs in lockstep and finds the first divergence.
+///
+/// Both VMs run the same program on the same input, each with its own configuration.
+/// After every step, their registers, stacks, and outputs are compared,
+/// and the run stops at the first difference.
+/// This is useful for validating an optimization of the VM against a reference configuration.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{Config, DivergenceKind, ExecutionLimits, Lockstep, Program, Error};
+///
+/// fn main() -> Result<(), Error> {
+///     let program = Program::assemble(Cursor::new(b"rd\nwr\nhalt\n"))?;
+///
+///     let lockstep = Lockstep::default();
+///     assert_eq!(lockstep.run(&program, "5\n")?, None);
+///
+///     let lockstep = Lockstep::new(Config::default(), Config {
+///         limits: ExecutionLimits {
+///             max_steps: Some(1),
+///             ..ExecutionLimits::default()
+///         },
+///         ..Config::default()
+///     });
+///     let divergence = lockstep.run(&program, "5\n")?.unwrap();
+///
+///     assert_eq!(divergence.step, 2);
+///     assert!(matches!(divergence.kind, DivergenceKind::Status(None, Some(_))));
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Lockstep {
+    /// The configurations of the two VMs.
+    pub configs: [Config; 2],
+}
+
+impl Lockstep {
+    /// Creates a harness with the configurations of the two VMs.
+    pub fn new(a: Config, b: Config) -> Self {
+        Self { configs: [a, b] }
+    }
+
+    /// Runs a program on both VMs until they stop or diverge.
+    ///
+    /// Returns `None` if both VMs stop in the same way without any divergence.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if the program cannot be loaded into either VM.
+    pub fn run(&self, program: &Program, input: &str) -> Result<Option<Divergence>, Error> {
+        let (mut input_a, mut input_b) = (Cursor::new(input.as_bytes()), Cursor::new(input.as_bytes()));
+        let (shared_a, shared_b) = (SharedOutput::default(), SharedOutput::default());
+        let (mut output_a, mut output_b) = (shared_a.clone(), shared_b.clone());
+        let (mut prompt_a, mut prompt_b) = (io::sink(), io::sink());
+
+        let mut a = PicocVm::with_config(&mut input_a, &mut output_a, self.configs[0].clone());
+        let mut b = PicocVm::with_config(&mut input_b, &mut output_b, self.configs[1].clone());
+        a.set_prompt_output(&mut prompt_a);
+        b.set_prompt_output(&mut prompt_b);
+        a.load_program(program.clone())?;
+        b.load_program(program.clone())?;
+
+        let mut step = 0;
+        let mut compared = 0;
+        loop {
+            let kind = if a.registers() != b.registers() {
+                Some(DivergenceKind::Registers(*a.registers(), *b.registers()))
+            } else if a.stack() != b.stack() {
+                Some(DivergenceKind::Stack(a.stack().to_vec(), b.stack().to_vec()))
+            } else {
+                let (out_a, out_b) = (shared_a.0.borrow(), shared_b.0.borrow());
+                let differ = out_a.len() != out_b.len() || out_a[compared..] != out_b[compared..];
+                compared = out_a.len();
+                differ.then(|| DivergenceKind::Output(out_a.clone(), out_b.clone()))
+            };
+            if let Some(kind) = kind {
+                return Ok(Some(Divergence { step, kind }));
+            }
+
+            match (a.step(), b.step()) {
+                (Ok(()), Ok(())) => step += 1,
+                (Err(ea), Err(eb)) if ea.to_string() == eb.to_string() => return Ok(None),
+                (ra, rb) => {
+                    let status = |r: Result<(), Error>| r.err().map(|err| err.to_string());
+                    let kind = DivergenceKind::Status(status(ra), status(rb));
+                    return Ok(Some(Divergence { step: step + 1, kind }));
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OutputFormat;
+
+    #[test]
+    fn find_output_divergence() {
+        let program = Program::assemble(Cursor::new(b"pushi 1\npushi 5\nwr\nhalt\n")).unwrap();
+        let lockstep = Lockstep::new(Config::default(), Config {
+            output_format: OutputFormat {
+                newline: true,
+                ..OutputFormat::default()
+            },
+            ..Config::default()
+        });
+
+        let divergence = lockstep.run(&program, "").unwrap().unwrap();
+
+        assert_eq!(divergence.step, 3);
+        assert_eq!(divergence.kind, DivergenceKind::Output(b"5 ".to_vec(), b"5 \n".to_vec()));
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use crate::vm::Registers;
+
+/// The number of words on the top of the stack which are part of a state.
+pub(crate) const LOOP_STACK_WINDOW: usize = 8;
+
+/// Finds a state of a VM which recurs without I/O, a likely infinite loop.
+///
+/// A state is the registers and the words on the top of the stack, hashed.
+/// States are checked at every backward transfer of control (e.g. a jump to a loop head),
+/// and compared by Brent's algorithm, which keeps only one state
+/// but finds a recurrence within twice the length of the loop.
+#[derive(Debug, Clone)]
+pub(crate) struct LoopDetector {
+    /// The state to compare with.
+    saved: Option<u64>,
+    /// The number of checks until the state is saved again, doubled every time.
+    power: u64,
+    /// The number of checks since the state is saved.
+    count: u64,
+}
+
+impl Default for LoopDetector {
+    fn default() -> Self {
+        Self { saved: None, power: 1, count: 0 }
+    }
+}
+
+impl LoopDetector {
+    /// Forgets the states, e.g. after I/O.
+    pub(crate) fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Checks a state, returning whether it is the same as the saved one.
+    pub(crate) fn check(&mut self, reg: &Registers, stack_top: &[i32]) -> bool {
+        let mut hasher = DefaultHasher::new();
+        (reg.pc, reg.sp, reg.fp, stack_top).hash(&mut hasher);
+        let state = hasher.finish();
+
+        if self.saved == Some(state) {
+            return true;
+        }
+        self.count += 1;
+        if self.count == self.power {
+            self.saved = Some(state);
+            self.power *= 2;
+            self.count = 0;
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recurring_state() {
+        let mut detector = LoopDetector::default();
+        let reg = |pc| Registers { pc, sp: 10, fp: 10 };
+
+        // A loop of 3 states after 5 states
+        let pcs = [1, 2, 3, 4, 5].into_iter().chain([6, 7, 8].into_iter().cycle());
+        let found = pcs.take(100).position(|pc| detector.check(&reg(pc), &[]));
+        assert!(found.is_some_and(|i| i < 5 + 2 * 3 * 2));
+
+        detector.reset();
+        assert!(!detector.check(&reg(1), &[1]));
+        assert!(!detector.check(&reg(1), &[2]));
+        assert!((0..3).any(|_| detector.check(&reg(1), &[2])));
+    }
+}
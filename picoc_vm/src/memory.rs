@@ -0,0 +1,237 @@
+use std::fmt::{Display, Formatter};
+use crate::error::Error;
+use crate::inst::Inst;
+use crate::opcode::Opcode;
+use crate::program::Program;
+use crate::vm::{VM_INST_MEMORY_SIZE, VM_STACK_SIZE};
+
+/// Segments of the memory of a VM.
+///
+/// The code segment is the instruction memory, which has its own address space.
+/// The other segments share the address space of the data memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    /// Instructions.
+    Code,
+    /// Stack frames and temporaries.
+    Stack,
+    /// Global variables.
+    Data,
+    /// Dynamically allocated memory.
+    Heap,
+}
+
+impl Display for Segment {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Segment::Code => write!(f, "code"),
+            Segment::Stack => write!(f, "stack"),
+            Segment::Data => write!(f, "data"),
+            Segment::Heap => write!(f, "heap"),
+        }
+    }
+}
+
+/// A range of addresses occupied by a segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    /// The first address of the region.
+    pub base: usize,
+    /// The number of words in the region.
+    pub size: usize,
+}
+
+impl Region {
+    /// Returns the address next to the last address of the region.
+    pub fn end(&self) -> usize {
+        self.base + self.size
+    }
+
+    /// Returns whether the region contains an address.
+    pub fn contains(&self, addr: usize) -> bool {
+        self.base <= addr && addr < self.end()
+    }
+}
+
+/// Layout of the memory of a VM.
+///
+/// The data memory is laid out as below:
+///
+/// ```text
+/// 0                VM_STACK_SIZE
+/// +----------------+--------------+--------------+
+/// | stack          | data         | heap         |
+/// +----------------+--------------+--------------+
+/// ```
+///
+/// The stack grows toward address 0,
+/// so SP and FP start at [`VM_STACK_SIZE`].
+///
+/// # Example
+///
+/// ```
+/// use picoc_vm::{MemoryMap, Segment, VM_STACK_SIZE};
+///
+/// fn main() {
+///     let map = MemoryMap::new(100, 1000);
+///
+///     assert_eq!(map.data.base, VM_STACK_SIZE);
+///     assert_eq!(map.find(VM_STACK_SIZE + 100), Some(Segment::Heap));
+///     assert_eq!(map.find(VM_STACK_SIZE + 1100), None);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryMap {
+    /// The region of the instruction memory.
+    pub code: Region,
+    /// The region of the stack.
+    pub stack: Region,
+    /// The region of global variables.
+    pub data: Region,
+    /// The region of dynamically allocated memory.
+    pub heap: Region,
+}
+
+impl MemoryMap {
+    /// Creates a memory map with sizes of the data and heap segments.
+    pub fn new(data_size: usize, heap_size: usize) -> Self {
+        let code = Region { base: 0, size: VM_INST_MEMORY_SIZE };
+        let stack = Region { base: 0, size: VM_STACK_SIZE };
+        let data = Region { base: stack.end(), size: data_size };
+        let heap = Region { base: data.end(), size: heap_size };
+
+        Self { code, stack, data, heap }
+    }
+
+    /// Gets the region of a segment.
+    pub fn region(&self, segment: Segment) -> Region {
+        match segment {
+            Segment::Code => self.code,
+            Segment::Stack => self.stack,
+            Segment::Data => self.data,
+            Segment::Heap => self.heap,
+        }
+    }
+
+    /// Finds a segment of the data memory containing an address.
+    pub fn find(&self, addr: usize) -> Option<Segment> {
+        [Segment::Stack, Segment::Data, Segment::Heap]
+            .into_iter()
+            .find(|&segment| self.region(segment).contains(addr))
+    }
+
+    /// Returns the number of words in the data memory.
+    pub fn data_memory_size(&self) -> usize {
+        self.heap.end()
+    }
+
+    /// Checks that an address is in a segment.
+    pub(crate) fn check(&self, segment: Segment, addr: i64) -> Result<usize, Error> {
+        if addr >= 0 && self.region(segment).contains(addr as usize) {
+            return Ok(addr as usize);
+        }
+
+        Err(match segment {
+            Segment::Code => Error::MemoryOutOfBound,
+            Segment::Stack => Error::StackOutOfBound,
+            _ => Error::SegmentOutOfBound(segment, addr),
+        })
+    }
+
+    /// Checks that an address is in any segment of the data memory.
+    pub(crate) fn check_data_memory(&self, addr: i64) -> Result<usize, Error> {
+        if addr >= 0 && self.find(addr as usize).is_some() {
+            Ok(addr as usize)
+        } else {
+            Err(Error::AddressOutOfBound(addr))
+        }
+    }
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self::new(0, 0)
+    }
+}
+
+/// Memory used by a VM in bytes, given by [`memory_usage`](crate::PicocVm::memory_usage()).
+///
+/// The sizes are estimated from the contents of the buffers,
+/// and do not include the overhead of the allocator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryUsage {
+    /// The stack, which is allocated with its full capacity.
+    pub stack: usize,
+    /// Global variables.
+    pub data: usize,
+    /// The heap for `alloc`, which is allocated with its full capacity.
+    pub heap: usize,
+    /// The instructions, including their operands.
+    pub instructions: usize,
+    /// The label table.
+    pub labels: usize,
+    /// Reference cells allocated by `newref`.
+    pub refs: usize,
+    /// Strings made by `pushs` and `scat`.
+    pub strings: usize,
+}
+
+impl MemoryUsage {
+    /// Returns the total number of bytes.
+    pub fn total(&self) -> usize {
+        self.stack + self.data + self.heap + self.instructions + self.labels + self.refs + self.strings
+    }
+
+    /// Estimates the memory used by the instructions and the label table of a program.
+    pub(crate) fn of_program(program: &Program) -> Self {
+        let operands: usize = program.insts.iter()
+            .map(|inst| match inst {
+                Opcode::Call(label) | Opcode::Jp(label) | Opcode::Jt(label) | Opcode::Jf(label) => label.len(),
+                Opcode::Pushs(text) | Opcode::Custom(text, _) | Opcode::Trap(text) => text.len(),
+                _ => 0,
+            })
+            .sum();
+        let labels: usize = program.labels.keys()
+            .map(|label| label.len() + size_of::<(String, usize)>())
+            .sum();
+
+        Self {
+            instructions: program.len() * (size_of::<Opcode>() + size_of::<Inst>()) + operands,
+            labels,
+            ..Self::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout() {
+        let map = MemoryMap::new(10, 20);
+
+        assert_eq!(map.find(0), Some(Segment::Stack));
+        assert_eq!(map.find(VM_STACK_SIZE - 1), Some(Segment::Stack));
+        assert_eq!(map.find(VM_STACK_SIZE), Some(Segment::Data));
+        assert_eq!(map.find(VM_STACK_SIZE + 10), Some(Segment::Heap));
+        assert_eq!(map.find(VM_STACK_SIZE + 30), None);
+        assert_eq!(map.data_memory_size(), VM_STACK_SIZE + 30);
+    }
+
+    #[test]
+    fn check_address() {
+        let map = MemoryMap::new(10, 0);
+
+        assert_eq!(map.check(Segment::Data, VM_STACK_SIZE as i64 + 9).unwrap(), VM_STACK_SIZE + 9);
+        assert!(matches!(map.check(Segment::Stack, -1), Err(Error::StackOutOfBound)));
+        assert!(matches!(
+            map.check(Segment::Heap, VM_STACK_SIZE as i64),
+            Err(Error::SegmentOutOfBound(Segment::Heap, _))
+        ));
+        assert!(matches!(
+            map.check_data_memory(VM_STACK_SIZE as i64 + 10),
+            Err(Error::AddressOutOfBound(_))
+        ));
+    }
+}
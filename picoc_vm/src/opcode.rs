@@ -1,5 +1,8 @@
-use std::string::ToString;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use crate::error::Error;
+use crate::expr::eval;
+use crate::literal::{escape_string, parse_string};
 
 /// Opcode of picoc vm instruction sets.
 ///
@@ -19,12 +22,15 @@ use crate::error::Error;
 ///     return t;
 /// }
 /// ```
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Opcode {
     /// Pushes a value of a local variable
+    ///
+    /// A local variable declared with `.local` can be referred to as `%name`.
     /// # Assembly
     /// ```asm
     /// pushl n
+    /// pushl %name
     /// ```
     /// # Actions
     /// ```c
@@ -52,20 +58,55 @@ pub enum Opcode {
     /// ```
     Storet(i32),
     /// Pushes a immediate value.
+    ///
+    /// `d` may be a character literal (e.g. `'A'` or `'\n'`), which pushes its code point.
+    /// `pushaddr` is an alias for an operand which refers to label addresses.
     /// # Assembly
     /// ```asm
     /// pushi d
+    /// pushaddr end - start
     /// ```
     /// # Actions
     /// ```c
     /// push(d);
     /// ```
     Pushi(i32),
+    /// Pushes the address of this instruction.
+    /// # Assembly
+    /// ```asm
+    /// pushpc
+    /// ```
+    /// # Actions
+    /// ```c
+    /// push(PC);
+    /// ```
+    Pushpc,
+    /// Pushes SP before this instruction.
+    /// # Assembly
+    /// ```asm
+    /// pushsp
+    /// ```
+    /// # Actions
+    /// ```c
+    /// push(SP);
+    /// ```
+    Pushsp,
+    /// Pushes FP.
+    /// # Assembly
+    /// ```asm
+    /// pushfp
+    /// ```
+    /// # Actions
+    /// ```c
+    /// push(FP);
+    /// ```
+    Pushfp,
     /// Calls a function.
     /// # Assembly
     /// ```asm
     /// call label
     /// ```
+    /// The label may be the address of an instruction (e.g. `call 42`).
     /// # Actions
     /// ```c
     /// push(pc + 1);
@@ -114,11 +155,26 @@ pub enum Opcode {
     /// sp = sp + n;
     /// ```
     Mvsp(i32),
+    /// Allocates words on the stack, and pushes the address of the first word.
+    ///
+    /// The words are not cleared, and are released when SP is restored (e.g. by `leave`).
+    /// # Assembly
+    /// ```asm
+    /// salloc
+    /// ```
+    /// # Actions
+    /// ```c
+    /// t = pop();
+    /// sp = sp - t;
+    /// push(sp);
+    /// ```
+    Salloc,
     /// Jumps to an instruction where the label is located on unconditionally.
     /// # Assembly
     /// ```asm
     /// jp label
     /// ```
+    /// The label may be the address of an instruction (e.g. `jp 42`).
     /// # Actions
     /// ```c
     /// pc = label;
@@ -129,6 +185,7 @@ pub enum Opcode {
     /// ```asm
     /// jt label
     /// ```
+    /// The label may be the address of an instruction (e.g. `jt 42`).
     /// # Actions
     /// ```c
     /// if (pop() != 0) {
@@ -141,6 +198,7 @@ pub enum Opcode {
     /// ```asm
     /// jf label
     /// ```
+    /// The label may be the address of an instruction (e.g. `jf 42`).
     /// # Actions
     /// ```c
     /// if (pop() == 0) {
@@ -148,6 +206,40 @@ pub enum Opcode {
     /// }
     /// ```
     Jf(String),
+    /// Jumps by an offset from the current instruction unconditionally.
+    /// # Assembly
+    /// ```asm
+    /// jr offset
+    /// ```
+    /// # Actions
+    /// ```c
+    /// pc = pc + offset;
+    /// ```
+    Jr(i32),
+    /// Jumps by an offset from the current instruction if a value popped is true.
+    /// # Assembly
+    /// ```asm
+    /// jrt offset
+    /// ```
+    /// # Actions
+    /// ```c
+    /// if (pop() != 0) {
+    ///     pc = pc + offset;
+    /// }
+    /// ```
+    Jrt(i32),
+    /// Jumps by an offset from the current instruction if a value popped is false.
+    /// # Assembly
+    /// ```asm
+    /// jrf offset
+    /// ```
+    /// # Actions
+    /// ```c
+    /// if (pop() == 0) {
+    ///     pc = pc + offset;
+    /// }
+    /// ```
+    Jrf(i32),
     /// Performs addition.
     /// # Assembly
     /// ```asm
@@ -315,6 +407,20 @@ pub enum Opcode {
     /// push(t);
     /// ```
     Rd,
+    /// Reads a whitespace-separated value from an input.
+    ///
+    /// Unlike `rd`, several values can be written on one line.
+    /// The rest of the line is kept by a VM and consumed by following `rdt`s.
+    /// # Assembly
+    /// ```asm
+    /// rdt
+    /// ```
+    /// # Actions
+    /// ```c
+    /// scanf("%d", &t);
+    /// push(t);
+    /// ```
+    Rdt,
     /// Writes a value popped to an output.
     /// # Assembly
     /// ```asm
@@ -336,6 +442,216 @@ pub enum Opcode {
     /// printf("\n");
     /// ```
     Wrln,
+    /// Writes a character whose code point is popped to an output.
+    ///
+    /// The character is encoded in UTF-8.
+    /// # Assembly
+    /// ```asm
+    /// wrch
+    /// ```
+    /// # Actions
+    /// ```c
+    /// t = pop();
+    /// printf("%lc", t);
+    /// ```
+    Wrch,
+    /// Writes a value popped to an output, right-aligned in a field.
+    /// # Assembly
+    /// ```asm
+    /// wrf width
+    /// ```
+    /// # Actions
+    /// ```c
+    /// t = pop();
+    /// printf("%*d ", width, t);
+    /// ```
+    Wrf(usize),
+    /// Writes a value popped to an output, zero-padded in a field.
+    /// # Assembly
+    /// ```asm
+    /// wrz width
+    /// ```
+    /// # Actions
+    /// ```c
+    /// t = pop();
+    /// printf("%0*d ", width, t);
+    /// ```
+    Wrz(usize),
+    /// Allocates a block on the heap.
+    ///
+    /// The heap segment must be configured by [`Config::heap_size`](crate::Config::heap_size).
+    /// # Assembly
+    /// ```asm
+    /// alloc
+    /// ```
+    /// # Actions
+    /// ```c
+    /// t = pop();
+    /// push(malloc(t));
+    /// ```
+    Alloc,
+    /// Frees a block allocated by `alloc`.
+    /// # Assembly
+    /// ```asm
+    /// free
+    /// ```
+    /// # Actions
+    /// ```c
+    /// t = pop();
+    /// free(t);
+    /// ```
+    Free,
+    /// Pushes a value at an address of the data memory.
+    /// # Assembly
+    /// ```asm
+    /// ld
+    /// ```
+    /// # Actions
+    /// ```c
+    /// t = pop();
+    /// push(*t);
+    /// ```
+    Ld,
+    /// Stores a value at an address of the data memory.
+    /// # Assembly
+    /// ```asm
+    /// st
+    /// ```
+    /// # Actions
+    /// ```c
+    /// t1 = pop();
+    /// t2 = pop();
+    /// *t2 = t1;
+    /// push(t1);
+    /// ```
+    St,
+    /// Stores an instruction word at an address of the instruction memory.
+    ///
+    /// The word is an instruction encoded by [`Opcode::to_word`].
+    /// This fails with [`Error::CodeNotWritable`] unless [`Config::writable_code`](crate::Config::writable_code) is set.
+    /// # Assembly
+    /// ```asm
+    /// storei
+    /// ```
+    /// # Actions
+    /// ```c
+    /// t1 = pop();
+    /// t2 = pop();
+    /// code[t2] = decode(t1);
+    /// ```
+    Storei,
+    /// Allocates a reference cell with `n` fields managed by a garbage collector.
+    ///
+    /// The fields are initialized with 0.
+    /// A cell is freed when no value in the stack, data, and heap segments
+    /// or in other live cells refers to it.
+    /// # Assembly
+    /// ```asm
+    /// newref n
+    /// ```
+    /// # Actions
+    /// ```c
+    /// push(gc_alloc(n));
+    /// ```
+    Newref(usize),
+    /// Pushes a field of a reference cell.
+    /// # Assembly
+    /// ```asm
+    /// getf i
+    /// ```
+    /// # Actions
+    /// ```c
+    /// t = pop();
+    /// push(t->fields[i]);
+    /// ```
+    Getf(usize),
+    /// Stores a value popped on a field of a reference cell peeked from a stack.
+    /// # Assembly
+    /// ```asm
+    /// setf i
+    /// ```
+    /// # Actions
+    /// ```c
+    /// t = pop();
+    /// (*sp)->fields[i] = t;
+    /// ```
+    Setf(usize),
+    /// Pushes a handle of a string.
+    ///
+    /// The operand is a string literal quoted by `"`,
+    /// which may contain escape sequences `\n`, `\t`, `\r`, `\0`, `\\`, `\"`, and `\'`.
+    /// # Assembly
+    /// ```asm
+    /// pushs "text"
+    /// ```
+    /// # Actions
+    /// ```c
+    /// push("text");
+    /// ```
+    Pushs(String),
+    /// Concatenates two strings.
+    /// # Assembly
+    /// ```asm
+    /// scat
+    /// ```
+    /// # Actions
+    /// ```c
+    /// t1 = pop();
+    /// t2 = pop();
+    /// push(strcat(t2, t1));
+    /// ```
+    Scat,
+    /// Compares two strings lexicographically.
+    /// # Assembly
+    /// ```asm
+    /// scmp
+    /// ```
+    /// # Actions
+    /// ```c
+    /// t1 = pop();
+    /// t2 = pop();
+    /// push(sign(strcmp(t2, t1)));
+    /// ```
+    Scmp,
+    /// Gets the number of characters in a string.
+    /// # Assembly
+    /// ```asm
+    /// slen
+    /// ```
+    /// # Actions
+    /// ```c
+    /// t = pop();
+    /// push(strlen(t));
+    /// ```
+    Slen,
+    /// Writes a string popped to an output.
+    /// # Assembly
+    /// ```asm
+    /// wrs
+    /// ```
+    /// # Actions
+    /// ```c
+    /// t = pop();
+    /// printf("%s", t);
+    /// ```
+    Wrs,
+    /// Executes an instruction registered by [`register_opcode`](crate::PicocVm::register_opcode()).
+    ///
+    /// The first field is the mnemonic, and the second is the operand decoded by the registered function.
+    /// # Assembly
+    /// ```asm
+    /// mnemonic operand
+    /// ```
+    Custom(String, i32),
+    /// Stands for a line with an unknown mnemonic, loaded in [`LoadMode::Permissive`](crate::LoadMode::Permissive).
+    ///
+    /// The field is the line. Executing it fails with [`Error::Trap`].
+    /// `trap` itself is also a trap, which fills the gaps of [`Program::place`](crate::Program::place()).
+    /// # Assembly
+    /// ```asm
+    /// trap
+    /// ```
+    Trap(String),
     /// Halts a VM.
     /// # Assembly
     /// ```asm
@@ -345,6 +661,96 @@ pub enum Opcode {
 }
 
 impl Opcode {
+    /// Gets the address which a relative jump at `addr` branches to.
+    ///
+    /// Returns `None` unless the instruction is `jr`, `jrt`, or `jrf`.
+    pub(crate) fn relative_target(&self, addr: usize) -> Option<i64> {
+        match self {
+            Opcode::Jr(offset) | Opcode::Jrt(offset) | Opcode::Jrf(offset) => Some(addr as i64 + *offset as i64),
+            _ => None,
+        }
+    }
+
+    /// Encodes the instruction into a word, which `storei` writes into the instruction memory.
+    ///
+    /// The upper 8 bits are the kind of the instruction, and the lower 24 bits are its signed operand.
+    /// A label is encoded as the address given by `labels`.
+    /// Returns `None` if the instruction has a string operand (e.g. `pushs`),
+    /// its label is not found, or its operand does not fit in 24 bits.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use picoc_vm::{Opcode, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let labels = HashMap::from([("loop".to_string(), 5)]);
+    ///
+    ///     let word = Opcode::Jp("loop".to_string()).to_word(&labels).unwrap();
+    ///     assert_eq!(Opcode::from_word(word)?, Opcode::Jp("5".to_string()));
+    ///
+    ///     let word = Opcode::Pushi(-3).to_word(&labels).unwrap();
+    ///     assert_eq!(Opcode::from_word(word)?, Opcode::Pushi(-3));
+    ///
+    ///     assert_eq!(Opcode::Pushs("text".to_string()).to_word(&labels), None);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn to_word(&self, labels: &HashMap<String, usize>) -> Option<i32> {
+        crate::binary::encode_word(self, labels)
+    }
+
+    /// Decodes a word encoded by [`to_word`](Opcode::to_word()).
+    ///
+    /// A jump or call targets its address as a numeric target (e.g. `jp 5`).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidInstructionWord`] if the word does not encode an instruction.
+    pub fn from_word(word: i32) -> Result<Opcode, Error> {
+        crate::binary::decode_word(word)
+    }
+
+    /// Lists the mnemonics of the built-in instructions.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use picoc_vm::Opcode;
+    ///
+    /// fn main() {
+    ///     let mnemonics = Opcode::mnemonics();
+    ///
+    ///     assert!(mnemonics.contains(&"pushi"));
+    ///     assert!(mnemonics.contains(&"halt"));
+    /// }
+    /// ```
+    pub fn mnemonics() -> Vec<&'static str> {
+        docs().into_iter().map(|(mnemonic, _)| mnemonic).collect()
+    }
+
+    /// Gets the documentation of a built-in instruction by its mnemonic,
+    /// which is the doc comment of its variant in Markdown.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use picoc_vm::Opcode;
+    ///
+    /// fn main() {
+    ///     let doc = Opcode::doc("pushi").unwrap();
+    ///
+    ///     assert!(doc.starts_with("Pushes a immediate value."));
+    ///     assert_eq!(Opcode::doc("pushaddr"), Opcode::doc("pushi"));
+    ///     assert_eq!(Opcode::doc("foo"), None);
+    /// }
+    /// ```
+    pub fn doc(mnemonic: &str) -> Option<String> {
+        docs().into_iter().find(|&(name, _)| name == mnemonic).map(|(_, doc)| doc)
+    }
+
     /// Converts strings (e.g. `["pushi", "123"]`) into an instruction.
     ///
     /// # Errors
@@ -364,39 +770,53 @@ impl Opcode {
     ///     assert_eq!(opcode, Opcode::Pushi(123));
     /// }
     /// ```
-    pub fn from_line(line: &Vec<String>) -> Result<Opcode, Error> {
-        if line.len() < 1 {
+    pub fn from_line(line: &[String]) -> Result<Opcode, Error> {
+        Opcode::from_line_with_symbols(line, &HashMap::new())
+    }
+
+    /// Converts strings into an instruction, evaluating a numeric operand
+    /// as a constant expression (e.g. `ARGBASE+2`) with `symbols`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if an opcode and an operand required cannot be parsed,
+    /// or an operand refers to a symbol not in `symbols`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use picoc_vm::Opcode;
+    ///
+    /// fn main() {
+    ///     let line = vec!["pushl".to_string(), "ARGBASE".to_string(), "+2".to_string()];
+    ///     let symbols = HashMap::from([("ARGBASE".to_string(), 2)]);
+    ///
+    ///     let opcode = Opcode::from_line_with_symbols(&line, &symbols).unwrap();
+    ///
+    ///     assert_eq!(opcode, Opcode::Pushl(4));
+    /// }
+    /// ```
+    pub fn from_line_with_symbols(
+        line: &[String],
+        symbols: &HashMap<String, i64>
+    ) -> Result<Opcode, Error> {
+        if line.is_empty() {
             return Err(Error::OpcodeNotFound);
         }
 
         match line[0].to_lowercase().as_str() {
             "pushl" => {
-                if let Some(num) = line.get(1) {
-                    inst_with_i32("pushl", num.parse()?)
-                } else {
-                    Err(Error::OperandNotFound)
-                }
+                inst_with_i32("pushl", eval_operand(line, symbols)?)
             },
             "storel" => {
-                if let Some(num) = line.get(1) {
-                    inst_with_i32("storel", num.parse()?)
-                } else {
-                    Err(Error::OperandNotFound)
-                }
+                inst_with_i32("storel", eval_operand(line, symbols)?)
             },
             "storet" => {
-                if let Some(num) = line.get(1) {
-                    inst_with_i32("storet", num.parse()?)
-                } else {
-                    Err(Error::OperandNotFound)
-                }
+                inst_with_i32("storet", eval_operand(line, symbols)?)
             },
-            "pushi" => {
-                if let Some(num) = line.get(1) {
-                    inst_with_i32("pushi", num.parse()?)
-                } else {
-                    Err(Error::OperandNotFound)
-                }
+            "pushi" | "pushaddr" => {
+                inst_with_i32("pushi", eval_operand(line, symbols)?)
             },
             "call" => {
                 if let Some(label) = line.get(1) {
@@ -405,6 +825,15 @@ impl Opcode {
                     Err(Error::OperandNotFound)
                 }
             },
+            "pushpc" => {
+                Ok(Opcode::Pushpc)
+            },
+            "pushsp" => {
+                Ok(Opcode::Pushsp)
+            },
+            "pushfp" => {
+                Ok(Opcode::Pushfp)
+            },
             "ret" => {
                 Ok(Opcode::Ret)
             },
@@ -415,11 +844,10 @@ impl Opcode {
                 Ok(Opcode::Leave)
             },
             "mvsp" => {
-                if let Some(num) = line.get(1) {
-                    inst_with_i32("mvsp", num.parse()?)
-                } else {
-                    Err(Error::OperandNotFound)
-                }
+                inst_with_i32("mvsp", eval_operand(line, symbols)?)
+            },
+            "salloc" => {
+                Ok(Opcode::Salloc)
             },
             "jp" => {
                 if let Some(label) = line.get(1) {
@@ -442,6 +870,15 @@ impl Opcode {
                     Err(Error::OperandNotFound)
                 }
             },
+            "jr" => {
+                inst_with_i32("jr", eval_operand(line, symbols)?)
+            },
+            "jrt" => {
+                inst_with_i32("jrt", eval_operand(line, symbols)?)
+            },
+            "jrf" => {
+                inst_with_i32("jrf", eval_operand(line, symbols)?)
+            },
             "add" => {
                 Ok(Opcode::Add)
             },
@@ -478,20 +915,93 @@ impl Opcode {
             "rd" => {
                 Ok(Opcode::Rd)
             },
+            "rdt" => {
+                Ok(Opcode::Rdt)
+            },
             "wr" => {
                 Ok(Opcode::Wr)
             },
             "wrln" => {
                 Ok(Opcode::Wrln)
             },
+            "wrch" => {
+                Ok(Opcode::Wrch)
+            },
+            "wrf" => {
+                Ok(Opcode::Wrf(eval_operand(line, symbols)?))
+            },
+            "wrz" => {
+                Ok(Opcode::Wrz(eval_operand(line, symbols)?))
+            },
+            "alloc" => {
+                Ok(Opcode::Alloc)
+            },
+            "free" => {
+                Ok(Opcode::Free)
+            },
+            "ld" => {
+                Ok(Opcode::Ld)
+            },
+            "st" => {
+                Ok(Opcode::St)
+            },
+            "storei" => {
+                Ok(Opcode::Storei)
+            },
+            "newref" => {
+                Ok(Opcode::Newref(eval_operand(line, symbols)?))
+            },
+            "getf" => {
+                Ok(Opcode::Getf(eval_operand(line, symbols)?))
+            },
+            "setf" => {
+                Ok(Opcode::Setf(eval_operand(line, symbols)?))
+            },
+            "pushs" => {
+                if let Some(literal) = line.get(1) {
+                    Ok(Opcode::Pushs(parse_string(literal)?))
+                } else {
+                    Err(Error::OperandNotFound)
+                }
+            },
+            "scat" => {
+                Ok(Opcode::Scat)
+            },
+            "scmp" => {
+                Ok(Opcode::Scmp)
+            },
+            "slen" => {
+                Ok(Opcode::Slen)
+            },
+            "wrs" => {
+                Ok(Opcode::Wrs)
+            },
             "halt" => {
                 Ok(Opcode::Halt)
             },
+            "trap" => {
+                Ok(Opcode::Trap("trap".to_string()))
+            },
             other => Err(Error::UnknownOpcode(other.to_string())),
         }
     }
 }
 
+/// Evaluates the operand of a line, which may be split by whitespaces.
+fn eval_operand<T: TryFrom<i64>>(
+    line: &[String],
+    symbols: &HashMap<String, i64>
+) -> Result<T, Error> {
+    if line.len() < 2 {
+        return Err(Error::OperandNotFound);
+    }
+
+    let expr = line[1..].join(" ");
+    let value = eval(&expr, symbols)?;
+
+    T::try_from(value).map_err(|_| Error::InvalidExpression(expr))
+}
+
 fn inst_with_i32(op: &str, num: i32) -> Result<Opcode, Error> {
     match op {
         "pushl" => Ok(Opcode::Pushl(num)),
@@ -499,6 +1009,9 @@ fn inst_with_i32(op: &str, num: i32) -> Result<Opcode, Error> {
         "storet" => Ok(Opcode::Storet(num)),
         "pushi" => Ok(Opcode::Pushi(num)),
         "mvsp" => Ok(Opcode::Mvsp(num)),
+        "jr" => Ok(Opcode::Jr(num)),
+        "jrt" => Ok(Opcode::Jrt(num)),
+        "jrf" => Ok(Opcode::Jrf(num)),
         other => Err(Error::UnknownOpcode(other.to_string())),
     }
 }
@@ -513,36 +1026,96 @@ fn inst_with_string(op: &str, str: String) -> Result<Opcode, Error> {
     }
 }
 
-impl ToString for Opcode {
-    fn to_string(&self) -> String {
+/// Extracts the doc comments of the variants of [`Opcode`] from this file,
+/// pairing each with the mnemonics in its `# Assembly` section.
+fn docs() -> Vec<(&'static str, String)> {
+    let source = include_str!("opcode.rs");
+    let body = source.split_once("pub enum Opcode {").map_or("", |(_, body)| body);
+
+    let mut docs = Vec::new();
+    let mut lines = Vec::new();
+    for line in body.lines().map(str::trim) {
+        if line == "}" {
+            break;
+        }
+        if let Some(doc) = line.strip_prefix("///") {
+            lines.push(doc.strip_prefix(' ').unwrap_or(doc));
+            continue;
+        }
+
+        let asm = lines.iter()
+            .skip_while(|&&line| line != "```asm")
+            .skip(1)
+            .take_while(|&&line| line != "```");
+        let doc = lines.join("\n");
+        for mnemonic in asm.filter_map(|line| line.split_whitespace().next()) {
+            // The placeholder of a custom instruction
+            if mnemonic != "mnemonic" && docs.iter().all(|&(name, _)| name != mnemonic) {
+                docs.push((mnemonic, doc.clone()));
+            }
+        }
+        lines.clear();
+    }
+
+    docs
+}
+
+impl Display for Opcode {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         match self {
-            Opcode::Pushl(n) => format!("pushl {}", n).to_string(),
-            Opcode::Storel(n) => format!("storel {}", n).to_string(),
-            Opcode::Storet(n) => format!("storet {}", n).to_string(),
-            Opcode::Pushi(d) => format!("pushi {}", d).to_string(),
-            Opcode::Call(label) => format!("call {}", label).to_string(),
-            Opcode::Ret => format!("ret").to_string(),
-            Opcode::Enter => format!("enter").to_string(),
-            Opcode::Leave => format!("leave").to_string(),
-            Opcode::Mvsp(n) => format!("mvsp {}", n).to_string(),
-            Opcode::Jp(label) => format!("jp {}", label).to_string(),
-            Opcode::Jt(label) => format!("jt {}", label).to_string(),
-            Opcode::Jf(label) => format!("jf {}", label).to_string(),
-            Opcode::Add => format!("add").to_string(),
-            Opcode::Sub => format!("sub").to_string(),
-            Opcode::Mul => format!("mul").to_string(),
-            Opcode::Div => format!("div").to_string(),
-            Opcode::Mod => format!("mod").to_string(),
-            Opcode::Eq => format!("eq").to_string(),
-            Opcode::Ne => format!("ne").to_string(),
-            Opcode::Gt => format!("gt").to_string(),
-            Opcode::Ge => format!("ge").to_string(),
-            Opcode::Lt => format!("lt").to_string(),
-            Opcode::Le => format!("le").to_string(),
-            Opcode::Rd => format!("rd").to_string(),
-            Opcode::Wr => format!("wr").to_string(),
-            Opcode::Wrln => format!("wrln").to_string(),
-            Opcode::Halt => format!("halt").to_string(),
+            Opcode::Pushl(n) => write!(f, "pushl {}", n),
+            Opcode::Storel(n) => write!(f, "storel {}", n),
+            Opcode::Storet(n) => write!(f, "storet {}", n),
+            Opcode::Pushi(d) => write!(f, "pushi {}", d),
+            Opcode::Pushpc => write!(f, "pushpc"),
+            Opcode::Pushsp => write!(f, "pushsp"),
+            Opcode::Pushfp => write!(f, "pushfp"),
+            Opcode::Call(label) => write!(f, "call {}", label),
+            Opcode::Ret => write!(f, "ret"),
+            Opcode::Enter => write!(f, "enter"),
+            Opcode::Leave => write!(f, "leave"),
+            Opcode::Mvsp(n) => write!(f, "mvsp {}", n),
+            Opcode::Salloc => write!(f, "salloc"),
+            Opcode::Jp(label) => write!(f, "jp {}", label),
+            Opcode::Jt(label) => write!(f, "jt {}", label),
+            Opcode::Jf(label) => write!(f, "jf {}", label),
+            Opcode::Jr(offset) => write!(f, "jr {:+}", offset),
+            Opcode::Jrt(offset) => write!(f, "jrt {:+}", offset),
+            Opcode::Jrf(offset) => write!(f, "jrf {:+}", offset),
+            Opcode::Add => write!(f, "add"),
+            Opcode::Sub => write!(f, "sub"),
+            Opcode::Mul => write!(f, "mul"),
+            Opcode::Div => write!(f, "div"),
+            Opcode::Mod => write!(f, "mod"),
+            Opcode::Eq => write!(f, "eq"),
+            Opcode::Ne => write!(f, "ne"),
+            Opcode::Gt => write!(f, "gt"),
+            Opcode::Ge => write!(f, "ge"),
+            Opcode::Lt => write!(f, "lt"),
+            Opcode::Le => write!(f, "le"),
+            Opcode::Rd => write!(f, "rd"),
+            Opcode::Rdt => write!(f, "rdt"),
+            Opcode::Wr => write!(f, "wr"),
+            Opcode::Wrln => write!(f, "wrln"),
+            Opcode::Wrch => write!(f, "wrch"),
+            Opcode::Wrf(width) => write!(f, "wrf {}", width),
+            Opcode::Wrz(width) => write!(f, "wrz {}", width),
+            Opcode::Alloc => write!(f, "alloc"),
+            Opcode::Free => write!(f, "free"),
+            Opcode::Ld => write!(f, "ld"),
+            Opcode::St => write!(f, "st"),
+            Opcode::Storei => write!(f, "storei"),
+            Opcode::Newref(n) => write!(f, "newref {}", n),
+            Opcode::Getf(i) => write!(f, "getf {}", i),
+            Opcode::Setf(i) => write!(f, "setf {}", i),
+            Opcode::Pushs(s) => write!(f, "pushs {}", escape_string(s)),
+            Opcode::Scat => write!(f, "scat"),
+            Opcode::Scmp => write!(f, "scmp"),
+            Opcode::Slen => write!(f, "slen"),
+            Opcode::Wrs => write!(f, "wrs"),
+            Opcode::Custom(name, operand) => write!(f, "{} {}", name, operand),
+            Opcode::Trap(line) => write!(f, "{}", line),
+            Opcode::Halt => write!(f, "halt"),
         }
     }
 }
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::ops::Range;
+use crate::debug::DebugInfo;
+use crate::error::Error;
+use crate::program::Program;
+use crate::vm::PicocVm;
+
+/// The number of instructions listed in a report.
+const HOT_INSTRUCTIONS: usize = 20;
+
+/// A profiler counting where a VM spends its steps.
+///
+/// An exact profiler records PC at every step.
+/// A sampling profiler records PC only once every N steps,
+/// trading accuracy for near-zero overhead in very long runs.
+/// Both write the same report, where samples are summed up by function and by instruction.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{PicocVm, Error, Profiler};
+///
+/// fn main() -> Result<(), Error> {
+///     let mut input = Cursor::new(b"");
+///     let mut output = Cursor::new(Vec::new());
+///
+///     let mut vm = PicocVm::new(&mut input, &mut output);
+///     vm.load(Cursor::new(b"
+///         main:
+///             call f
+///             call f
+///             halt
+///         f:
+///             ret"))?;
+///
+///     let mut profiler = Profiler::exact();
+///     profiler.run(&mut vm)?;
+///
+///     assert_eq!(profiler.total(), 5);
+///     assert_eq!(profiler.count(3), 2);
+///
+///     let mut report = Vec::new();
+///     profiler.write_report(vm.program(), &mut report)?;
+///     let report = String::from_utf8(report).unwrap();
+///
+///     assert!(report.starts_with("5 samples (1 per 1 instructions)\n"));
+///     assert!(report.contains("      2   40.0  f\n"));
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profiler {
+    interval: u64,
+    countdown: u64,
+    counts: HashMap<usize, u64>,
+    total: u64,
+    ranges: Vec<Range<usize>>,
+}
+
+impl Profiler {
+    /// Creates a profiler recording every step.
+    pub fn exact() -> Self {
+        Self::sampling(1)
+    }
+
+    /// Creates a profiler recording once every `interval` steps.
+    ///
+    /// An interval of `0` is regarded as `1`.
+    pub fn sampling(interval: u64) -> Self {
+        let interval = interval.max(1);
+
+        Self {
+            interval,
+            countdown: interval,
+            counts: HashMap::new(),
+            total: 0,
+            ranges: Vec::new(),
+        }
+    }
+
+    /// Restricts profiling to the instructions in any of `ranges`, like [`Tracer::set_ranges`](crate::Tracer::set_ranges()).
+    ///
+    /// If `ranges` is empty, every instruction is profiled.
+    pub fn set_ranges(&mut self, ranges: Vec<Range<usize>>) {
+        self.ranges = ranges;
+    }
+
+    /// Counts the step which the VM executes next.
+    ///
+    /// This should be called before every step.
+    /// PC is recorded only if the step is a sample.
+    pub fn sample<T, U>(&mut self, vm: &PicocVm<T, U>)
+    where
+        T: BufRead,
+        U: Write,
+    {
+        self.countdown -= 1;
+        if self.countdown > 0 {
+            return;
+        }
+        self.countdown = self.interval;
+
+        let pc = vm.registers().pc;
+        if vm.is_halted() || !(self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&pc))) {
+            return;
+        }
+        *self.counts.entry(pc).or_default() += 1;
+        self.total += 1;
+    }
+
+    /// Runs the VM until it halts like [`run_until_halt`](PicocVm::run_until_halt()),
+    /// profiling every step.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] under the same situations as [`run_until_halt`](PicocVm::run_until_halt()).
+    pub fn run<T, U>(&mut self, vm: &mut PicocVm<T, U>) -> Result<(), Error>
+    where
+        T: BufRead,
+        U: Write,
+    {
+        loop {
+            self.sample(vm);
+            match vm.step() {
+                Ok(()) => (),
+                Err(Error::VmHalted) => return Ok(()),
+                Err(Error::MemoryOutOfBound) if vm.config().legacy_end => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Returns the number of samples recorded.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns the number of samples recorded at an instruction.
+    pub fn count(&self, pc: usize) -> u64 {
+        self.counts.get(&pc).copied().unwrap_or_default()
+    }
+
+    /// Sums up the samples by function, from the most sampled.
+    ///
+    /// Functions are found in the same way as [`Frame::function_label`](crate::Frame::function_label),
+    /// and instructions outside any function are counted as `(top level)`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Profiler, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///     vm.load(Cursor::new(b"call f\nhalt\nf:\npushi 1\nmvsp 1\nret\n"))?;
+    ///
+    ///     let mut profiler = Profiler::exact();
+    ///     profiler.run(&mut vm)?;
+    ///
+    ///     let by_function = profiler.by_function(vm.program());
+    ///     assert_eq!(by_function, [("f".to_string(), 3), ("(top level)".to_string(), 2)]);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn by_function(&self, program: &Program) -> Vec<(String, u64)> {
+        let functions = DebugInfo {
+            functions: program.functions(),
+            ..DebugInfo::default()
+        };
+
+        let mut by_function: HashMap<&str, u64> = HashMap::new();
+        for (&pc, &count) in &self.counts {
+            *by_function.entry(functions.function(pc).unwrap_or("(top level)")).or_default() += count;
+        }
+        let mut by_function: Vec<_> = by_function.into_iter().map(|(name, count)| (name.to_string(), count)).collect();
+        by_function.sort_by(|(f1, c1), (f2, c2)| c2.cmp(c1).then(f1.cmp(f2)));
+
+        by_function
+    }
+
+    /// Writes a report of the samples, summed up by function and by instruction.
+    ///
+    /// Functions are summed up as [`by_function`](Profiler::by_function()).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if an I/O error occurs.
+    pub fn write_report<W: Write>(&self, program: &Program, mut w: W) -> Result<(), Error> {
+        let functions = DebugInfo {
+            functions: program.functions(),
+            ..DebugInfo::default()
+        };
+        let percent = |count: u64| count as f64 * 100.0 / self.total.max(1) as f64;
+
+        writeln!(w, "{} samples (1 per {} instructions)", self.total, self.interval)?;
+        writeln!(w)?;
+        writeln!(w, "{:>7} {:>6}  function", "samples", "%")?;
+        for (name, count) in self.by_function(program) {
+            writeln!(w, "{:7} {:6.1}  {}", count, percent(count), name)?;
+        }
+
+        let mut hot: Vec<_> = self.counts.iter().map(|(&pc, &count)| (pc, count)).collect();
+        hot.sort_by(|(pc1, c1), (pc2, c2)| c2.cmp(c1).then(pc1.cmp(pc2)));
+        writeln!(w)?;
+        writeln!(w, "{:>7} {:>6}  instruction", "samples", "%")?;
+        for (pc, count) in hot.into_iter().take(HOT_INSTRUCTIONS) {
+            let inst = program.insts.get(pc).map(ToString::to_string).unwrap_or_default();
+            write!(w, "{:7} {:6.1}  {:05}: {}", count, percent(count), pc, inst)?;
+            match functions.function(pc) {
+                Some(name) => writeln!(w, " in {}", name)?,
+                None => writeln!(w)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn sample_every_interval() {
+        let mut input = Cursor::new(b"");
+        let mut output = Vec::new();
+        let mut vm = PicocVm::new(&mut input, &mut output);
+        vm.load(Cursor::new(b"
+                pushi 0
+            loop:
+                pushi 1
+                add
+                jp loop
+        ")).unwrap();
+
+        let mut profiler = Profiler::sampling(3);
+        for _ in 0..30 {
+            profiler.sample(&vm);
+            vm.step().unwrap();
+        }
+
+        // Steps 3, 6, ..., 30 are in a loop of 3 instructions, all at `add`
+        assert_eq!(profiler.total(), 10);
+        assert_eq!(profiler.count(2), 10);
+    }
+}
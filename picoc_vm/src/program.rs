@@ -0,0 +1,492 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{BufRead, Write};
+use crate::binary;
+use crate::debug::DebugInfo;
+use crate::decode::*;
+use crate::config::LoadMode;
+use crate::dialect::{DefaultDialect, Dialect};
+use crate::error::Error;
+use crate::opcode::Opcode;
+use crate::symbols;
+use crate::vm::VM_INST_MEMORY_SIZE;
+use crate::warning::{self, Warning};
+
+/// An assembled program of picoc vm.
+///
+/// A program consists of instructions and a label table,
+/// which maps each label to the address of an instruction.
+/// It also has debug information if the code has `.loc` or `.func` directives.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{Program, Error, Opcode};
+///
+/// fn main() -> Result<(), Error> {
+///     let code = Cursor::new(b"
+///         main:
+///             pushi 1
+///             jp main");
+///
+///     let program = Program::assemble(code)?;
+///
+///     assert_eq!(program.insts(), &[Opcode::Pushi(1), Opcode::Jp("main".to_string())]);
+///     assert_eq!(program.labels().get("main"), Some(&0));
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    pub(crate) insts: Vec<Opcode>,
+    pub(crate) labels: HashMap<String, usize>,
+    pub(crate) debug_info: DebugInfo,
+}
+
+impl Program {
+    /// Creates a program from instructions and a label table.
+    pub fn new(insts: Vec<Opcode>, labels: HashMap<String, usize>) -> Self {
+        Self { insts, labels, debug_info: DebugInfo::default() }
+    }
+
+    /// Assembles a program from a stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if an invalid opcode or operand is found,
+    /// a label is defined twice ([`Error::DuplicateLabel`]), or any I/O error occurs.
+    /// An error found at a line is wrapped in [`Error::InSource`] with the line number and its text,
+    /// and [`Error::in_file`] gives the name of the file.
+    pub fn assemble<T: BufRead>(code: T) -> Result<Self, Error> {
+        Self::assemble_with(code, &DefaultDialect, LoadMode::Strict, &|_| None)
+    }
+
+    /// Assembles a program written in another syntax from a stream.
+    ///
+    /// See [`Dialect`] for an example.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] under the same situations as [`assemble`](Program::assemble()),
+    /// or if the dialect fails to split a line.
+    pub fn assemble_with_dialect<T: BufRead>(code: T, dialect: &dyn Dialect) -> Result<Self, Error> {
+        Self::assemble_with(code, dialect, LoadMode::Strict, &|_| None)
+    }
+
+    /// Assembles a program in a syntax, handling unknown mnemonics by a mode.
+    ///
+    /// In [`LoadMode::Permissive`], a line with an unknown mnemonic is loaded as [`Opcode::Trap`],
+    /// which fails only if it is executed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] under the same situations as [`assemble_with_dialect`](Program::assemble_with_dialect()),
+    /// except unknown mnemonics in [`LoadMode::Permissive`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{DefaultDialect, Error, LoadMode, Opcode, Program};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let code = b"pushi 1\nhalt\nvext 3\n";
+    ///
+    ///     assert!(Program::assemble_with_mode(&code[..], &DefaultDialect, LoadMode::Strict).is_err());
+    ///
+    ///     let program = Program::assemble_with_mode(&code[..], &DefaultDialect, LoadMode::Permissive)?;
+    ///     assert_eq!(program.insts()[2], Opcode::Trap("vext 3".to_string()));
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn assemble_with_mode<T: BufRead>(code: T, dialect: &dyn Dialect, mode: LoadMode) -> Result<Self, Error> {
+        Self::assemble_with(code, dialect, mode, &|_| None)
+    }
+
+    /// Assembles a program from a stream, decoding instructions registered at runtime by `custom`.
+    pub(crate) fn assemble_with<T: BufRead>(
+        mut code: T,
+        dialect: &dyn Dialect,
+        mode: LoadMode,
+        custom: &CustomDecoder
+    ) -> Result<Self, Error> {
+        let mut source = String::new();
+        code.read_to_string(&mut source)?;
+
+        // An unknown mnemonic is decoded after the built-in and the custom ones fail
+        let decode = |line: &[String]| custom(line).or_else(|| {
+            let is_unknown = matches!(Opcode::from_line(&line[..1]), Err(Error::UnknownOpcode(_)));
+            (mode == LoadMode::Permissive && is_unknown).then(|| Ok(Opcode::Trap(line.join(" "))))
+        });
+
+        Self::assemble_source(&source, dialect, &decode).map_err(|err| match err {
+            Error::InSource(location, _, err) => {
+                let text = source_lines(&source).nth(location.line - 1).unwrap_or_default().trim().to_string();
+                Error::InSource(location, text, err)
+            },
+            err => err,
+        })
+    }
+
+    fn assemble_source(source: &str, dialect: &dyn Dialect, custom: &CustomDecoder) -> Result<Self, Error> {
+        let (lines, line_numbers) = split_code(source.as_bytes(), dialect)?;
+        let mut program = Self::default();
+
+        load_label(&lines, &line_numbers, &mut program.labels)?; // 1st pass
+        load_inst(&lines, &line_numbers, &program.labels, &mut program.insts, &mut program.debug_info, custom)?; // 2nd pass
+
+        Ok(program)
+    }
+
+    /// Encodes the program into a binary format.
+    ///
+    /// The binary starts with a magic number (`PCVM`), the format version,
+    /// and CRC-32 of the rest, which are checked by [`from_bytes`](Program::from_bytes()).
+    /// Debug information is not included.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{Program, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let program = Program::assemble(Cursor::new(b"main:\npushi 1\njp main\n"))?;
+    ///
+    ///     let bytes = program.to_bytes();
+    ///
+    ///     assert_eq!(&bytes[..4], b"PCVM");
+    ///     assert_eq!(Program::from_bytes(&bytes)?, program);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        binary::encode(self)
+    }
+
+    /// Decodes a program encoded by [`to_bytes`](Program::to_bytes()).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnsupportedFormatVersion`] if the binary is written in another version
+    /// of the format, [`Error::ChecksumMismatch`] if it is corrupted,
+    /// or [`Error::InvalidBinary`] if it is not a program or is truncated.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        binary::decode(bytes)
+    }
+
+    /// Writes the symbols of the program: the label table and the function boundaries.
+    ///
+    /// Each line is `label NAME ADDR` or `func NAME ADDR`.
+    /// Functions are given by `.func` directives, or by the targets of `call` if there are none.
+    /// The symbols can be loaded into a program decoded from a binary
+    /// by [`load_symbols`](Program::load_symbols()).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if any I/O error occurs.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{Program, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let program = Program::assemble(Cursor::new(b"
+    ///         main:
+    ///             call f
+    ///             halt
+    ///         f:
+    ///             ret"))?;
+    ///
+    ///     let mut symbols = Vec::new();
+    ///     program.write_symbols(&mut symbols)?;
+    ///
+    ///     let mut stripped = Program::from_bytes(&program.to_bytes())?;
+    ///     assert_eq!(stripped.debug_info().function(3), None);
+    ///
+    ///     stripped.load_symbols(symbols.as_slice())?;
+    ///     assert_eq!(stripped.debug_info().function(3), Some("f"));
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn write_symbols<W: Write>(&self, w: W) -> Result<(), Error> {
+        symbols::write(self, w)
+    }
+
+    /// Loads symbols written by [`write_symbols`](Program::write_symbols()).
+    ///
+    /// Labels which are not defined in the program are added,
+    /// and the function boundaries in the debug information are replaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidSymbolFile`] if a line is malformed,
+    /// or [`Err`] if any I/O error occurs.
+    /// If an error occurs, the program is left unchanged.
+    pub fn load_symbols<R: BufRead>(&mut self, r: R) -> Result<(), Error> {
+        symbols::read(self, r)
+    }
+
+    /// Gets the instructions of the program.
+    pub fn insts(&self) -> &[Opcode] {
+        &self.insts
+    }
+
+    /// Gets the label table of the program.
+    ///
+    /// Numeric jump targets (e.g. `3` of `jp 3`) are addresses, which are not in the table.
+    pub fn labels(&self) -> &HashMap<String, usize> {
+        &self.labels
+    }
+
+    /// Finds the address of a jump or call target, which is a label or a numeric address.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{Program, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let program = Program::assemble(Cursor::new(b"start:\npushi 1\njf 0\njp 4\nhalt\nhalt\n"))?;
+    ///
+    ///     assert_eq!(program.target("start"), Some(0));
+    ///     assert_eq!(program.target("4"), Some(4));
+    ///     assert_eq!(program.target("end"), None);
+    ///     assert_eq!(program.labels().len(), 1);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn target(&self, label: &str) -> Option<usize> {
+        resolve_target(&self.labels, label)
+    }
+
+    /// Gets the addresses which are jumped to by name: those of labels and numeric targets.
+    pub(crate) fn labeled_addresses(&self) -> impl Iterator<Item = usize> + '_ {
+        self.labels.values().copied().chain(self.insts.iter().filter_map(numeric_target))
+    }
+
+    /// Gets the debug information given by `.loc` and `.func` directives.
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug_info
+    }
+
+    /// Returns the number of instructions.
+    pub fn len(&self) -> usize {
+        self.insts.len()
+    }
+
+    /// Returns whether the program has no instruction.
+    pub fn is_empty(&self) -> bool {
+        self.insts.is_empty()
+    }
+
+    /// Checks that every `call` refers to a defined label.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::LabelNotFound`] if an undefined label is found.
+    pub fn check_call_targets(&self) -> Result<(), Error> {
+        check_call_targets(&self.insts, &self.labels)
+    }
+
+    /// Finds suspicious code which is loaded but likely a mistake.
+    ///
+    /// See [`Warning`] for an example.
+    pub fn warnings(&self) -> Vec<Warning> {
+        warning::check(self)
+    }
+
+    /// Finds the first instruction of every basic block.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::LabelNotFound`] if a jump refers to an undefined label,
+    /// or [`Error::MemoryOutOfBound`] if a relative jump branches out of the program.
+    pub(crate) fn leaders(&self) -> Result<BTreeSet<usize>, Error> {
+        let mut leaders = BTreeSet::from([0]);
+        leaders.extend(self.labeled_addresses());
+
+        for (addr, inst) in self.insts.iter().enumerate() {
+            match inst {
+                Opcode::Call(label) | Opcode::Jp(label) | Opcode::Jt(label) | Opcode::Jf(label) => {
+                    if self.target(label).is_none() {
+                        return Err(Error::LabelNotFound(label.clone()));
+                    }
+                    leaders.insert(addr + 1);
+                },
+                Opcode::Jr(_) | Opcode::Jrt(_) | Opcode::Jrf(_) => {
+                    let target = inst.relative_target(addr)
+                        .and_then(|target| usize::try_from(target).ok())
+                        .filter(|&target| target < self.insts.len())
+                        .ok_or(Error::MemoryOutOfBound)?;
+                    leaders.insert(target);
+                    leaders.insert(addr + 1);
+                },
+                Opcode::Ret | Opcode::Halt => {
+                    leaders.insert(addr + 1);
+                },
+                _ => (),
+            }
+        }
+
+        Ok(leaders)
+    }
+
+    /// Finds the function boundaries of the program.
+    ///
+    /// Functions given by `.func` directives are preferred.
+    /// Otherwise, every target of `call` is regarded as the start of a function.
+    pub(crate) fn functions(&self) -> Vec<(usize, String)> {
+        if !self.debug_info.functions.is_empty() {
+            return self.debug_info.functions.clone();
+        }
+
+        let targets: BTreeSet<(usize, &String)> = self.insts.iter()
+            .filter_map(|inst| match inst {
+                Opcode::Call(label) => self.target(label).map(|addr| (addr, label)),
+                _ => None,
+            })
+            .collect();
+
+        targets.into_iter().map(|(addr, label)| (addr, label.clone())).collect()
+    }
+
+    /// Places another program at an address, so that programs are linked like modules.
+    ///
+    /// The labels of `other` are shifted by `base`, which adjusts its jump and call targets.
+    /// A numeric target (e.g. `jp 3`) is also shifted (into `jp 103` if `base` is 100),
+    /// and relative jumps need no adjustment.
+    /// The gap between the end of this program and `base` is filled with `trap`,
+    /// which fails if executed.
+    /// A label defined in both programs refers to the definition in `other`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AddressInUse`] if `base` is before the end of this program,
+    /// or [`Error::AddressOutOfBound`] if `other` does not fit in the instruction memory
+    /// ([`VM_INST_MEMORY_SIZE`](crate::VM_INST_MEMORY_SIZE)).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{Program, Error, Opcode};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut program = Program::assemble(Cursor::new(b"call print\nhalt\n"))?;
+    ///     let library = Program::assemble(Cursor::new(b"print:\nwr\njp 2\nret\n"))?;
+    ///
+    ///     program.place(library, 100)?;
+    ///
+    ///     assert_eq!(program.len(), 103);
+    ///     assert_eq!(program.insts()[50], Opcode::Trap("trap".to_string()));
+    ///     assert_eq!(program.insts()[101], Opcode::Jp("102".to_string()));
+    ///     assert_eq!(program.labels().get("print"), Some(&100));
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn place(&mut self, other: Program, base: usize) -> Result<(), Error> {
+        if base < self.insts.len() {
+            return Err(Error::AddressInUse(base));
+        }
+        let end = base + other.insts.len();
+        if end > VM_INST_MEMORY_SIZE {
+            return Err(Error::AddressOutOfBound(end as i64));
+        }
+
+        self.insts.resize(base, Opcode::Trap("trap".to_string()));
+        self.append(other);
+
+        Ok(())
+    }
+
+    /// Moves the program to an address, filling the instructions before it with `trap`.
+    ///
+    /// This is the same as placing the program at `base` of an empty program by [`place`](Program::place()).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AddressOutOfBound`] if the program does not fit in the instruction memory.
+    pub fn relocate(self, base: usize) -> Result<Program, Error> {
+        let mut program = Program::default();
+        program.place(self, base)?;
+
+        Ok(program)
+    }
+
+    /// Appends another program, whose labels and numeric targets are shifted to the end of this program.
+    pub(crate) fn append(&mut self, mut other: Program) {
+        let base = self.insts.len();
+
+        for inst in &mut other.insts {
+            if let Some(addr) = numeric_target(inst) {
+                if let Opcode::Call(label) | Opcode::Jp(label) | Opcode::Jt(label) | Opcode::Jf(label) = inst {
+                    *label = (base + addr).to_string();
+                }
+            }
+        }
+        self.insts.extend(other.insts);
+        self.debug_info.append(other.debug_info, base);
+        self.labels.extend(other.labels.into_iter().map(|(label, addr)| (label, base + addr)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn append_program() -> Result<(), Error> {
+        let mut program = Program::assemble(io::Cursor::new(b"a:\npushi 1\nb:\nhalt\n"))?;
+        let other = Program::assemble(io::Cursor::new(b"pushi 2\nb:\nc:\ncall a\n"))?;
+
+        program.append(other);
+
+        assert_eq!(program.len(), 4);
+        assert_eq!(
+            program.labels,
+            HashMap::from([
+                ("a".to_string(), 0),
+                ("b".to_string(), 3),
+                ("c".to_string(), 3),
+            ])
+        );
+        program.check_call_targets()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn place_programs() -> Result<(), Error> {
+        let mut program = Program::assemble(io::Cursor::new(b"call 2\nhalt\nret\n"))?;
+        let module = Program::assemble(io::Cursor::new(b"f:\njt 2\nret\njr -2\n"))?;
+
+        assert!(matches!(program.clone().place(module.clone(), 2), Err(Error::AddressInUse(2))));
+        assert!(matches!(
+            program.clone().place(module.clone(), VM_INST_MEMORY_SIZE - 1),
+            Err(Error::AddressOutOfBound(_))
+        ));
+
+        program.place(module, 10)?;
+
+        assert_eq!(program.len(), 13);
+        assert_eq!(program.insts[0], Opcode::Call("2".to_string()));
+        assert_eq!(program.insts[3], Opcode::Trap("trap".to_string()));
+        assert_eq!(program.insts[10], Opcode::Jt("12".to_string()));
+        assert_eq!(program.insts[12], Opcode::Jr(-2));
+        assert_eq!(program.labels, HashMap::from([("f".to_string(), 10)]));
+        assert_eq!(program.target("12"), Some(12));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use crate::warning::Warning;
+
+/// A summary of a program loaded by [`load_with_report`](crate::PicocVm::load_with_report()).
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{PicocVm, Error};
+///
+/// fn main() -> Result<(), Error> {
+///     let mut input = Cursor::new(b"");
+///     let mut output = Cursor::new(Vec::new());
+///
+///     let mut vm = PicocVm::new(&mut input, &mut output);
+///
+///     let report = vm.load_with_report(Cursor::new(b"
+///         __start__:
+///             call main
+///             halt
+///         main:
+///             pushi 1
+///             ret
+///         unused:
+///             halt"))?;
+///
+///     assert_eq!(report.instructions, 5);
+///     assert_eq!(report.labels, 3);
+///     assert_eq!(report.warnings.len(), 1);
+///     assert_eq!(report.entry, 0);
+///     assert_eq!(report.entry_label.as_deref(), Some("__start__"));
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadReport {
+    /// The number of instructions loaded.
+    pub instructions: usize,
+    /// The number of labels defined.
+    pub labels: usize,
+    /// Warnings found in the program.
+    pub warnings: Vec<Warning>,
+    /// The address where the program starts.
+    pub entry: usize,
+    /// The label at the entry point, if any.
+    ///
+    /// If several labels are there, `__start__` and `main` come first, and then the first one by name.
+    pub entry_label: Option<String>,
+}
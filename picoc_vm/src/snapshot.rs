@@ -0,0 +1,52 @@
+use std::collections::VecDeque;
+use crate::gc::RefHeap;
+use crate::heap::Allocator;
+use crate::program::Program;
+use crate::strings::StringTable;
+use crate::vm::Registers;
+
+/// A saved execution state of a VM.
+///
+/// A snapshot is taken by [`snapshot`](crate::PicocVm::snapshot())
+/// and restored by [`restore`](crate::PicocVm::restore()).
+/// It does not include the loaded code (except instructions rewritten by `storei`), the configuration, or I/O streams.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub(crate) memory: Vec<i32>,
+    pub(crate) reg: Registers,
+    pub(crate) is_halted: bool,
+    pub(crate) heap: Allocator,
+    pub(crate) refs: RefHeap,
+    pub(crate) strings: StringTable,
+    pub(crate) steps: u64,
+    pub(crate) cycles: u64,
+    pub(crate) call_depth: usize,
+    pub(crate) output_bytes: usize,
+    pub(crate) input_tokens: VecDeque<String>,
+    /// The program if `storei` has rewritten it since it is loaded.
+    pub(crate) program: Option<Program>,
+}
+
+impl Snapshot {
+    /// Gets the registers at the time of the snapshot.
+    pub fn registers(&self) -> &Registers {
+        &self.reg
+    }
+
+    /// Gets the number of instructions executed at the time of the snapshot.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+}
+
+/// Policy of taking checkpoints automatically.
+///
+/// A snapshot is taken every `interval` steps,
+/// and the latest `capacity` snapshots are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointPolicy {
+    /// The number of steps between checkpoints.
+    pub interval: u64,
+    /// The maximum number of checkpoints kept.
+    pub capacity: usize,
+}
@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use crate::vm::Registers;
+
+/// A view of the state of a VM, for visualizers and bug reports.
+///
+/// This is taken by [`state`](crate::PicocVm::state()).
+/// Unlike [`Snapshot`](crate::Snapshot), it cannot be restored,
+/// but it is exported by [`to_json`](VmState::to_json()) without any dependency.
+/// With the `serde` feature, it can also be serialized by serde.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{PicocVm, Error};
+///
+/// fn main() -> Result<(), Error> {
+///     let mut input = Cursor::new(b"");
+///     let mut output = Cursor::new(Vec::new());
+///
+///     let mut vm = PicocVm::new(&mut input, &mut output);
+///
+///     vm.load(Cursor::new(b"main:\npushi 1\npushi 2\nadd\nhalt\n"))?;
+///     vm.step()?;
+///     vm.step()?;
+///
+///     let state = vm.state();
+///
+///     assert_eq!(state.stack, [2, 1]);
+///     assert_eq!(state.instruction.as_deref(), Some("add"));
+///     assert_eq!(
+///         state.to_json(),
+///         r#"{"registers":{"pc":2,"sp":9998,"fp":10000},"stack":[2,1],"labels":{"main":0},"instruction":"add","halted":false,"steps":2}"#,
+///     );
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct VmState {
+    /// The registers.
+    pub registers: Registers,
+    /// The live region of the stack from the top, which includes the current frame.
+    pub stack: Vec<i32>,
+    /// The label table, sorted by name.
+    pub labels: BTreeMap<String, usize>,
+    /// The instruction which PC points to, or `None` if PC is beyond the code.
+    pub instruction: Option<String>,
+    /// Whether the VM has halted.
+    pub halted: bool,
+    /// The number of executed instructions.
+    pub steps: u64,
+}
+
+impl VmState {
+    /// Converts the state into a JSON object.
+    ///
+    /// The keys are always in the same order, so the same state gives the same document.
+    pub fn to_json(&self) -> String {
+        let reg = &self.registers;
+        let mut json = format!(r#"{{"registers":{{"pc":{},"sp":{},"fp":{}}},"stack":["#, reg.pc, reg.sp, reg.fp);
+
+        for (i, value) in self.stack.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write!(json, "{}", value).unwrap();
+        }
+        json += r#"],"labels":{"#;
+        for (i, (label, addr)) in self.labels.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write!(json, "{}:{}", json_string(label), addr).unwrap();
+        }
+        json += r#"},"instruction":"#;
+        match &self.instruction {
+            Some(inst) => json += &json_string(inst),
+            None => json += "null",
+        }
+        write!(json, r#","halted":{},"steps":{}}}"#, self.halted, self.steps).unwrap();
+
+        json
+    }
+}
+
+/// Quotes a string as a JSON string.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            '\r' => quoted += "\\r",
+            '\t' => quoted += "\\t",
+            c if c.is_control() => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_json() {
+        assert_eq!(json_string("a\"b\\c\n\u{1}"), r#""a\"b\\c\n\u0001""#);
+    }
+}
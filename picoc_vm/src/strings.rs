@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use crate::error::Error;
+
+/// The tag of string handles, which makes them unlikely to collide with ordinary integers.
+const STR_TAG: i32 = 0x2000_0000;
+
+/// A table of strings referred to by handles.
+///
+/// Equal strings share the same handle.
+#[derive(Debug, Clone, Default)]
+pub struct StringTable {
+    strings: Vec<String>,
+    handles: HashMap<String, i32>,
+    bytes: usize,
+}
+
+impl StringTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.strings.clear();
+        self.handles.clear();
+        self.bytes = 0;
+    }
+
+    /// Returns the number of bytes of the strings.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Returns a handle of a string, adding it to the table if needed.
+    pub fn intern(&mut self, s: &str) -> Result<i32, Error> {
+        if let Some(&handle) = self.handles.get(s) {
+            return Ok(handle);
+        }
+
+        if self.strings.len() >= STR_TAG as usize {
+            return Err(Error::OutOfMemory);
+        }
+        let handle = STR_TAG + self.strings.len() as i32;
+        self.strings.push(s.to_string());
+        self.bytes += s.len();
+        self.handles.insert(s.to_string(), handle);
+
+        Ok(handle)
+    }
+
+    pub fn get(&self, handle: i32) -> Result<&str, Error> {
+        if handle < STR_TAG {
+            return Err(Error::InvalidString(handle));
+        }
+
+        self.strings.get((handle - STR_TAG) as usize)
+            .map(|s| s.as_str())
+            .ok_or(Error::InvalidString(handle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intern_strings() {
+        let mut table = StringTable::new();
+
+        let hello = table.intern("hello").unwrap();
+        let world = table.intern("world").unwrap();
+
+        assert_ne!(hello, world);
+        assert_eq!(table.intern("hello").unwrap(), hello);
+        assert_eq!(table.get(world).unwrap(), "world");
+        assert!(matches!(table.get(5), Err(Error::InvalidString(5))));
+        assert!(matches!(table.get(world + 1), Err(Error::InvalidString(_))));
+    }
+}
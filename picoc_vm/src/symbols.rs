@@ -0,0 +1,94 @@
+use std::io::{BufRead, Write};
+use crate::error::Error;
+use crate::program::Program;
+
+/// Writes the symbols of a program, one per line (e.g. `label main 0`).
+pub fn write<W: Write>(program: &Program, mut w: W) -> Result<(), Error> {
+    let mut labels: Vec<_> = program.labels.iter().map(|(label, &addr)| (addr, label)).collect();
+    labels.sort();
+
+    writeln!(w, "# picoc vm symbols")?;
+    for (addr, label) in labels {
+        writeln!(w, "label {} {}", label, addr)?;
+    }
+    for (addr, name) in program.functions() {
+        writeln!(w, "func {} {}", name, addr)?;
+    }
+
+    Ok(())
+}
+
+/// Reads symbols into a program.
+///
+/// Labels already defined are kept, and function boundaries are replaced.
+/// If an error occurs, the program is left unchanged.
+pub fn read<R: BufRead>(program: &mut Program, r: R) -> Result<(), Error> {
+    let mut labels = Vec::new();
+    let mut functions = Vec::new();
+
+    for (i, line) in r.lines().enumerate() {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (kind, name, addr) = match words.as_slice() {
+            [] => continue,
+            [comment, ..] if comment.starts_with('#') => continue,
+            &[kind, name, addr] => (kind, name, addr),
+            _ => return Err(Error::InvalidSymbolFile(i + 1)),
+        };
+        let addr = addr.parse().map_err(|_| Error::InvalidSymbolFile(i + 1))?;
+
+        match kind {
+            "label" => labels.push((name.to_string(), addr)),
+            "func" => functions.push((addr, name.to_string())),
+            _ => return Err(Error::InvalidSymbolFile(i + 1)),
+        }
+    }
+    for (label, addr) in labels {
+        program.labels.entry(label).or_insert(addr);
+    }
+    functions.sort();
+    program.debug_info.functions = functions;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn derive_functions_from_calls() {
+        let program = Program::assemble(Cursor::new(b"
+            main:
+                call double
+                halt
+            double:
+                pushi 2
+                mul
+                ret
+        ")).unwrap();
+
+        let mut buf = Vec::new();
+        write(&program, &mut buf).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "# picoc vm symbols\nlabel main 0\nlabel double 2\nfunc double 2\n",
+        );
+    }
+
+    #[test]
+    fn reject_malformed_lines() {
+        let mut program = Program::default();
+
+        assert!(matches!(
+            read(&mut program, Cursor::new(b"label main 0\nfunc main\n")),
+            Err(Error::InvalidSymbolFile(2))
+        ));
+        assert!(matches!(
+            read(&mut program, Cursor::new(b"\nvar x 1\n")),
+            Err(Error::InvalidSymbolFile(2))
+        ));
+    }
+}
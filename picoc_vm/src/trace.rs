@@ -0,0 +1,250 @@
+use std::io::{BufRead, Write};
+use std::ops::Range;
+use crate::error::Error;
+use crate::program::Program;
+use crate::vm::PicocVm;
+
+/// A part of a program, which restricts where a [`Tracer`] writes.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{Program, TraceFilter, Error};
+///
+/// fn main() -> Result<(), Error> {
+///     let program = Program::assemble(Cursor::new(b"
+///         main:
+///             call f
+///         end:
+///             halt
+///         f:
+///             ret"))?;
+///
+///     assert_eq!(TraceFilter::Function("main".to_string()).resolve(&program)?, 0..2);
+///     assert_eq!(TraceFilter::Function("f".to_string()).resolve(&program)?, 2..3);
+///     assert_eq!(TraceFilter::Labels("main".to_string(), "end".to_string()).resolve(&program)?, 0..1);
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceFilter {
+    /// The instructions of a function, from its label up to the start of the next function.
+    ///
+    /// Functions are given by `.func` directives, or by the targets of `call` if there are none.
+    Function(String),
+    /// The instructions from a label up to another label, which is excluded.
+    Labels(String, String),
+}
+
+impl TraceFilter {
+    /// Finds the addresses of the instructions in a program.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::LabelNotFound`] if a label is not defined in the program.
+    pub fn resolve(&self, program: &Program) -> Result<Range<usize>, Error> {
+        let address = |label: &String| {
+            program.target(label)
+                .ok_or_else(|| Error::LabelNotFound(label.clone()))
+        };
+
+        match self {
+            TraceFilter::Function(name) => {
+                let start = address(name)?;
+                let end = program.functions().into_iter()
+                    .map(|(addr, _)| addr)
+                    .find(|&addr| addr > start)
+                    .unwrap_or(program.insts.len());
+
+                Ok(start..end)
+            },
+            TraceFilter::Labels(start, end) => Ok(address(start)?..address(end)?),
+        }
+    }
+}
+
+/// A tracer writing every executed instruction with the registers, one per line.
+///
+/// A line is `PC SP FP INSTRUCTION` (e.g. `4 9998 10000 pushl 2`),
+/// where the registers are the values before the instruction is executed.
+/// The N-th line is the N-th step, so millions of steps can be written and compared with tools like `diff`.
+///
+/// Tracing can be restricted to some parts of a program by [`set_ranges`](Tracer::set_ranges()).
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{PicocVm, Error, Tracer};
+///
+/// fn main() -> Result<(), Error> {
+///     let mut input = Cursor::new(b"");
+///     let mut output = Cursor::new(Vec::new());
+///
+///     let mut vm = PicocVm::new(&mut input, &mut output);
+///     vm.load(Cursor::new(b"pushi 1\npushi 2\nadd\nhalt\n"))?;
+///
+///     let mut tracer = Tracer::new(Vec::new());
+///     tracer.run(&mut vm)?;
+///
+///     assert_eq!(tracer.lines(), 4);
+///     assert_eq!(
+///         String::from_utf8(tracer.into_inner()).unwrap(),
+///         "0 10000 10000 pushi 1\n1 9999 10000 pushi 2\n2 9998 10000 add\n3 9999 10000 halt\n",
+///     );
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Tracer<W: Write> {
+    writer: W,
+    lines: u64,
+    ranges: Vec<Range<usize>>,
+}
+
+impl<W: Write> Tracer<W> {
+    /// Creates a tracer writing to `writer`.
+    ///
+    /// Lines are written one by one, so `writer` should be buffered.
+    pub fn new(writer: W) -> Self {
+        Self { writer, lines: 0, ranges: Vec::new() }
+    }
+
+    /// Restricts tracing to the instructions in any of `ranges`.
+    ///
+    /// If `ranges` is empty, every instruction is traced.
+    /// Ranges are usually given by [`TraceFilter::resolve`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error, TraceFilter, Tracer};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///     vm.load(Cursor::new(b"
+    ///         main:
+    ///             call f
+    ///             call f
+    ///             halt
+    ///         f:
+    ///             ret"))?;
+    ///
+    ///     let mut tracer = Tracer::new(Vec::new());
+    ///     tracer.set_ranges(vec![TraceFilter::Function("f".to_string()).resolve(vm.program())?]);
+    ///     tracer.run(&mut vm)?;
+    ///
+    ///     assert_eq!(String::from_utf8(tracer.into_inner()).unwrap(), "3 9999 10000 ret\n3 9999 10000 ret\n");
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_ranges(&mut self, ranges: Vec<Range<usize>>) {
+        self.ranges = ranges;
+    }
+
+    /// Returns whether an instruction at `pc` is traced.
+    pub fn is_traced(&self, pc: usize) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&pc))
+    }
+
+    /// Writes the instruction which the VM executes next.
+    ///
+    /// Nothing is written if the VM is halted, PC is out of the code, or the instruction is not traced.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if an I/O error occurs.
+    pub fn trace<T, U>(&mut self, vm: &PicocVm<T, U>) -> Result<(), Error>
+    where
+        T: BufRead,
+        U: Write,
+    {
+        let reg = vm.registers();
+        let Some(inst) = vm.inst_memory().get(reg.pc).filter(|_| !vm.is_halted() && self.is_traced(reg.pc)) else {
+            return Ok(());
+        };
+
+        writeln!(self.writer, "{} {} {} {}", reg.pc, reg.sp, reg.fp, inst)?;
+        self.lines += 1;
+
+        Ok(())
+    }
+
+    /// Runs the VM until it halts like [`run_until_halt`](PicocVm::run_until_halt()),
+    /// tracing every step.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] under the same situations as [`run_until_halt`](PicocVm::run_until_halt()),
+    /// or if an I/O error occurs while writing the trace.
+    /// The instruction which fails is also written.
+    pub fn run<T, U>(&mut self, vm: &mut PicocVm<T, U>) -> Result<(), Error>
+    where
+        T: BufRead,
+        U: Write,
+    {
+        let result = loop {
+            self.trace(vm)?;
+            match vm.step() {
+                Ok(()) => (),
+                Err(Error::VmHalted) => break Ok(()),
+                Err(Error::MemoryOutOfBound) if vm.config().legacy_end => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        };
+        self.writer.flush()?;
+
+        result
+    }
+
+    /// Returns the number of lines written.
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    /// Gets the writer, consuming the tracer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn trace_failing_instruction() {
+        let mut input = Cursor::new(b"");
+        let mut output = Vec::new();
+        let mut vm = PicocVm::new(&mut input, &mut output);
+        vm.load(Cursor::new(b"pushi 1\nadd\n")).unwrap();
+
+        let mut tracer = Tracer::new(Vec::new());
+
+        assert!(matches!(tracer.run(&mut vm), Err(Error::StackUnderflow)));
+        assert_eq!(tracer.into_inner(), b"0 10000 10000 pushi 1\n1 9999 10000 add\n");
+    }
+
+    #[test]
+    fn resolve_undefined_labels() {
+        let program = Program::assemble(Cursor::new(b"main:\nhalt\n")).unwrap();
+
+        assert!(matches!(
+            TraceFilter::Function("f".to_string()).resolve(&program),
+            Err(Error::LabelNotFound(label)) if label == "f"
+        ));
+        assert!(matches!(
+            TraceFilter::Labels("main".to_string(), "end".to_string()).resolve(&program),
+            Err(Error::LabelNotFound(label)) if label == "end"
+        ));
+    }
+}
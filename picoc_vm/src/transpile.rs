@@ -0,0 +1,473 @@
+use crate::config::{Config, FlushPolicy};
+use crate::error::Error;
+use crate::opcode::Opcode;
+use crate::program::Program;
+use crate::vm::{VM_INST_MEMORY_SIZE, VM_STACK_SIZE};
+
+/// The runtime of a transpiled program.
+///
+/// It depends on the constants emitted by [`transpile`].
+const RUNTIME: &str = r#"
+/// Returned by a block when the program halts.
+const HALT: usize = usize::MAX;
+
+struct Machine {
+    memory: Vec<i32>,
+    sp: usize,
+    fp: usize,
+    input: io::StdinLock<'static>,
+    output: io::BufWriter<io::Stdout>,
+    tokens: VecDeque<String>,
+}
+
+impl Machine {
+    fn new() -> Self {
+        Self {
+            memory: vec![0; MEMORY_SIZE],
+            sp: STACK_SIZE,
+            fp: STACK_SIZE,
+            input: io::stdin().lock(),
+            output: io::BufWriter::new(io::stdout()),
+            tokens: VecDeque::new(),
+        }
+    }
+
+    fn fail(&mut self, message: &str) -> ! {
+        let _ = self.output.flush();
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+
+    fn push(&mut self, value: i32) {
+        if self.sp == 0 || self.sp > STACK_SIZE {
+            self.fail("Stack overflow");
+        }
+        self.sp -= 1;
+        self.memory[self.sp] = value;
+    }
+
+    fn pop(&mut self) -> i32 {
+        if self.sp >= STACK_SIZE {
+            self.fail("Stack underflow");
+        }
+        self.sp += 1;
+        self.memory[self.sp - 1]
+    }
+
+    fn top(&mut self) -> i32 {
+        if self.sp >= STACK_SIZE {
+            self.fail("Stack underflow");
+        }
+        self.memory[self.sp]
+    }
+
+    fn stack_address(&mut self, base: usize, offset: i32) -> usize {
+        let addr = base as i64 + offset as i64;
+        if addr < 0 || addr >= STACK_SIZE as i64 {
+            self.fail("SP out of bounds");
+        }
+        addr as usize
+    }
+
+    fn move_sp(&mut self, n: i32) {
+        let sp = self.sp as i64 + n as i64;
+        if sp < 0 || sp > STACK_SIZE as i64 {
+            self.fail("SP out of bounds");
+        }
+        self.sp = sp as usize;
+    }
+
+    fn return_address(&mut self, addr: i32) -> usize {
+        if addr < 0 {
+            self.fail("PC out of bounds");
+        }
+        addr as usize
+    }
+
+    fn frame_pointer(&mut self, fp: i32) -> usize {
+        if fp < 0 || fp as usize > STACK_SIZE {
+            self.fail("SP out of bounds");
+        }
+        fp as usize
+    }
+
+    fn data_address(&mut self, addr: i32) -> usize {
+        if addr < 0 || addr as usize >= MEMORY_SIZE {
+            self.fail(&format!("Address {} is out of memory", addr));
+        }
+        addr as usize
+    }
+
+    fn divide(&mut self, t2: i32, t1: i32) -> i32 {
+        if t1 == 0 {
+            self.fail("attempt to divide by zero");
+        }
+        t2.wrapping_div(t1)
+    }
+
+    fn remainder(&mut self, t2: i32, t1: i32) -> i32 {
+        if t1 == 0 {
+            self.fail("attempt to calculate the remainder with a divisor of zero");
+        }
+        t2.wrapping_rem(t1)
+    }
+
+    fn write_prompt(&mut self, buf: &[u8]) {
+        if let Err(err) = self.output.write_all(buf).and_then(|_| self.output.flush()) {
+            self.fail(&err.to_string());
+        }
+    }
+
+    fn read_line(&mut self) -> String {
+        let mut line = String::new();
+
+        if !NO_PROMPT {
+            self.write_prompt(b"? ");
+        }
+        if let Err(err) = self.input.read_line(&mut line) {
+            self.fail(&err.to_string());
+        }
+        if ECHO_INPUT && !line.is_empty() {
+            self.write_prompt(line.trim_end().as_bytes());
+            self.write_prompt(b"\n");
+        }
+
+        line
+    }
+
+    /// Parses an integer like the VM: a prefix `0x`, `0o`, or `0b` selects the radix,
+    /// and digits without a prefix are read in `INPUT_RADIX`.
+    fn parse(&mut self, s: &str) -> i32 {
+        let (sign, body) = match s.strip_prefix('-') {
+            Some(body) => ("-", body),
+            None => ("", s.strip_prefix('+').unwrap_or(s)),
+        };
+        let prefix = body.get(..2).map(str::to_ascii_lowercase);
+        let (radix, digits) = match prefix.as_deref() {
+            Some("0x") => (16, &body[2..]),
+            Some("0o") => (8, &body[2..]),
+            Some("0b") if INPUT_RADIX < 12 => (2, &body[2..]),
+            _ => (INPUT_RADIX, body),
+        };
+        if digits.starts_with(['+', '-']) {
+            self.fail(&format!("Invalid literal {}", s));
+        }
+
+        match i64::from_str_radix(&format!("{}{}", sign, digits), radix) {
+            Ok(value) => match i32::try_from(value) {
+                Ok(value) => value,
+                Err(_) => self.fail(&format!("Invalid literal {}", s)),
+            },
+            Err(err) => self.fail(&err.to_string()),
+        }
+    }
+
+    fn read_int(&mut self) -> i32 {
+        let line = self.read_line();
+        self.parse(line.trim())
+    }
+
+    fn read_token(&mut self) -> i32 {
+        while self.tokens.is_empty() {
+            let line = self.read_line();
+            if line.is_empty() {
+                self.fail("No more input");
+            }
+            self.tokens.extend(line.split_whitespace().map(str::to_string));
+        }
+
+        let token = self.tokens.pop_front().unwrap();
+        self.parse(&token)
+    }
+
+    fn write(&mut self, buf: &[u8]) {
+        let mut result = self.output.write_all(buf);
+        if FLUSH_EVERY_WRITE || (FLUSH_EVERY_LINE && buf.contains(&b'\n')) {
+            result = result.and_then(|_| self.output.flush());
+        }
+        if let Err(err) = result {
+            self.fail(&err.to_string());
+        }
+    }
+
+    fn write_field(&mut self, width: usize, zero_pad: bool) {
+        let value = self.pop();
+        let field = if zero_pad {
+            format!("{:0width$}", value)
+        } else {
+            format!("{:>width$}", value)
+        };
+        let content = format!("{}{}{}", field, SEPARATOR, if NEWLINE { "\n" } else { "" });
+
+        self.write(content.as_bytes());
+    }
+
+    fn write_char(&mut self) {
+        let value = self.pop();
+        let Some(c) = u32::try_from(value).ok().and_then(char::from_u32) else {
+            self.fail(&format!("Value {} is not a valid code point", value));
+        };
+        let mut buf = [0; 4];
+
+        self.write(c.encode_utf8(&mut buf).as_bytes());
+    }
+}
+
+fn main() {
+    let mut m = Machine::new();
+    let mut pc = 0;
+
+    while pc != HALT {
+        if LEGACY_END {
+            pc %= INST_MEMORY_SIZE;
+        }
+        pc = match BLOCKS.get(pc) {
+            Some(Some(block)) => block(&mut m),
+            Some(None) => m.fail(&format!("Address {} is not the start of a block", pc)),
+            None if LEGACY_END => HALT,
+            None if pc == BLOCKS.len() => m.fail("Execution fell off the end of the program"),
+            None => m.fail("PC out of bounds"),
+        };
+    }
+
+    if let Err(err) = m.output.flush() {
+        m.fail(&err.to_string());
+    }
+}
+"#;
+
+/// Transpiles a program into a standalone Rust source file.
+///
+/// The generated program behaves like [`PicocVm`](crate::PicocVm) configured by `config`,
+/// reading the standard input and writing the standard output.
+/// Instructions are grouped into basic blocks of straight-line code,
+/// each of which is a function returning the address of the next block.
+///
+/// A `ret` may only return to the instruction next to a `call`.
+///
+/// # Errors
+///
+/// Returns [`Error::LabelNotFound`] if a jump refers to an undefined label,
+/// or [`Error::UnsupportedInstruction`] if the program uses the heap, reference cells, or strings.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{transpile, Config, Error, Program};
+///
+/// fn main() -> Result<(), Error> {
+///     let program = Program::assemble(Cursor::new(b"pushi 5\nwr\nhalt\n"))?;
+///
+///     let source = transpile(&program, &Config::default())?;
+///
+///     assert!(source.contains("fn main()"));
+///
+///     Ok(())
+/// }
+/// ```
+pub fn transpile(program: &Program, config: &Config) -> Result<String, Error> {
+    let insts = program.insts();
+    let leaders = program.leaders()?;
+
+    let mut source = String::new();
+    source.push_str("// Transpiled from a picoc VM program.\n");
+    source.push_str("// Build with `rustc --edition 2021 -O`.\n");
+    source.push_str("#![allow(dead_code, unreachable_code, unused_mut)]\n\n");
+    source.push_str("use std::collections::VecDeque;\n");
+    source.push_str("use std::io::{self, BufRead, Write};\n");
+    source.push_str("use std::process;\n\n");
+
+    let format = &config.output_format;
+    source.push_str(&format!("const STACK_SIZE: usize = {};\n", VM_STACK_SIZE));
+    source.push_str(&format!("const MEMORY_SIZE: usize = {};\n", VM_STACK_SIZE + config.data_size + config.heap_size));
+    source.push_str(&format!("const INST_MEMORY_SIZE: usize = {};\n", VM_INST_MEMORY_SIZE));
+    source.push_str(&format!("const LEGACY_END: bool = {};\n", config.legacy_end));
+    source.push_str(&format!("const ECHO_INPUT: bool = {};\n", config.echo_input));
+    source.push_str(&format!("const NO_PROMPT: bool = {};\n", config.no_prompt));
+    source.push_str(&format!("const INPUT_RADIX: u32 = {};\n", config.input_radix.base()));
+    source.push_str(&format!("const FLUSH_EVERY_WRITE: bool = {};\n", config.flush_policy == FlushPolicy::EveryWrite));
+    source.push_str(&format!("const FLUSH_EVERY_LINE: bool = {};\n", config.flush_policy == FlushPolicy::EveryLine));
+    source.push_str(&format!("const SEPARATOR: &str = {:?};\n", format.separator));
+    source.push_str(&format!("const WIDTH: usize = {};\n", format.width));
+    source.push_str(&format!("const NEWLINE: bool = {};\n", format.newline));
+    source.push_str(RUNTIME);
+
+    let blocks: Vec<usize> = leaders.iter().copied().filter(|&addr| addr < insts.len()).collect();
+    for (i, &start) in blocks.iter().enumerate() {
+        let end = blocks.get(i + 1).copied().unwrap_or(insts.len());
+
+        let mut body = String::new();
+        for (addr, inst) in insts.iter().enumerate().take(end).skip(start) {
+            body.push_str(&format!("    // {}\n", inst));
+            body.push_str(&translate(program, addr, inst)?);
+        }
+        // A block of only a jump does not touch the machine
+        let param = if body.contains("m.") { "m" } else { "_m" };
+        source.push_str(&format!("\nfn block_{}({}: &mut Machine) -> usize {{\n", start, param));
+        source.push_str(&body);
+        source.push_str(&format!("    {}\n}}\n", end));
+    }
+
+    source.push_str(&format!(
+        "\nconst BLOCKS: [Option<fn(&mut Machine) -> usize>; {}] = [\n",
+        insts.len()
+    ));
+    for addr in 0..insts.len() {
+        if leaders.contains(&addr) {
+            source.push_str(&format!("    Some(block_{}),\n", addr));
+        } else {
+            source.push_str("    None,\n");
+        }
+    }
+    source.push_str("];\n");
+
+    Ok(source)
+}
+
+fn translate(program: &Program, addr: usize, inst: &Opcode) -> Result<String, Error> {
+    let target = |label: &String| program.target(label).unwrap_or_default();
+    // The target is checked by `leaders`
+    let relative_target = || inst.relative_target(addr).unwrap_or_default();
+    let binary = |expr: &str| format!(
+        "    let t1 = m.pop();\n    let t2 = m.pop();\n    let value = {};\n    m.push(value);\n",
+        expr
+    );
+
+    let code = match inst {
+        Opcode::Pushl(n) => format!("    let addr = m.stack_address(m.fp, {});\n    m.push(m.memory[addr]);\n", n),
+        Opcode::Storel(n) => format!("    let value = m.top();\n    let addr = m.stack_address(m.fp, {});\n    m.memory[addr] = value;\n", n),
+        Opcode::Storet(n) => format!("    let value = m.top();\n    let addr = m.stack_address(m.sp, {});\n    m.memory[addr] = value;\n", n),
+        Opcode::Pushi(d) => format!("    m.push({});\n", d),
+        Opcode::Pushpc => format!("    m.push({});\n", addr),
+        Opcode::Pushsp => "    m.push(m.sp as i32);\n".to_string(),
+        Opcode::Pushfp => "    m.push(m.fp as i32);\n".to_string(),
+        Opcode::Call(label) => format!("    m.push({});\n    return {};\n", addr + 1, target(label)),
+        Opcode::Ret => "    let addr = m.pop();\n    return m.return_address(addr);\n".to_string(),
+        Opcode::Enter => "    m.push(m.fp as i32);\n    m.fp = m.sp;\n".to_string(),
+        Opcode::Leave => "    m.sp = m.fp;\n    let fp = m.pop();\n    m.fp = m.frame_pointer(fp);\n".to_string(),
+        Opcode::Mvsp(n) => format!("    m.move_sp({});\n", n),
+        Opcode::Jp(label) => format!("    return {};\n", target(label)),
+        Opcode::Jt(label) => format!("    return if m.pop() != 0 {{ {} }} else {{ {} }};\n", target(label), addr + 1),
+        Opcode::Jf(label) => format!("    return if m.pop() == 0 {{ {} }} else {{ {} }};\n", target(label), addr + 1),
+        Opcode::Jr(_) => format!("    return {};\n", relative_target()),
+        Opcode::Jrt(_) => format!("    return if m.pop() != 0 {{ {} }} else {{ {} }};\n", relative_target(), addr + 1),
+        Opcode::Jrf(_) => format!("    return if m.pop() == 0 {{ {} }} else {{ {} }};\n", relative_target(), addr + 1),
+        Opcode::Add => binary("t2.wrapping_add(t1)"),
+        Opcode::Sub => binary("t2.wrapping_sub(t1)"),
+        Opcode::Mul => binary("t2.wrapping_mul(t1)"),
+        Opcode::Div => binary("m.divide(t2, t1)"),
+        Opcode::Mod => binary("m.remainder(t2, t1)"),
+        Opcode::Eq => binary("(t2 == t1) as i32"),
+        Opcode::Ne => binary("(t2 != t1) as i32"),
+        Opcode::Gt => binary("(t2 > t1) as i32"),
+        Opcode::Ge => binary("(t2 >= t1) as i32"),
+        Opcode::Lt => binary("(t2 < t1) as i32"),
+        Opcode::Le => binary("(t2 <= t1) as i32"),
+        Opcode::Rd => "    let value = m.read_int();\n    m.push(value);\n".to_string(),
+        Opcode::Rdt => "    let value = m.read_token();\n    m.push(value);\n".to_string(),
+        Opcode::Wr => "    m.write_field(WIDTH, false);\n".to_string(),
+        Opcode::Wrln => "    m.write(b\"\\n\");\n".to_string(),
+        Opcode::Wrch => "    m.write_char();\n".to_string(),
+        Opcode::Wrf(width) => format!("    m.write_field({}, false);\n", width),
+        Opcode::Wrz(width) => format!("    m.write_field({}, true);\n", width),
+        Opcode::Ld => "    let t = m.pop();\n    let addr = m.data_address(t);\n    m.push(m.memory[addr]);\n".to_string(),
+        Opcode::St => concat!(
+            "    let value = m.pop();\n",
+            "    let t = m.pop();\n",
+            "    let addr = m.data_address(t);\n",
+            "    m.memory[addr] = value;\n",
+            "    m.push(value);\n",
+        ).to_string(),
+        Opcode::Halt => "    return HALT;\n".to_string(),
+        _ => return Err(Error::UnsupportedInstruction(inst.to_string())),
+    };
+
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use crate::config::Radix;
+    use std::io;
+
+    fn assemble(code: &[u8]) -> Program {
+        Program::assemble(io::Cursor::new(code)).unwrap()
+    }
+
+    #[test]
+    fn basic_blocks() {
+        let program = assemble(b"
+                pushi 1
+                call subr
+                wr
+                halt
+            subr:
+                pushi 2
+                jt subr
+                ret
+        ");
+
+        assert_eq!(program.leaders().unwrap(), BTreeSet::from([0, 2, 4, 6, 7]));
+
+        let source = transpile(&program, &Config::default()).unwrap();
+        assert!(source.contains("fn block_0(m: &mut Machine) -> usize {\n    // pushi 1\n    m.push(1);\n"));
+        assert!(source.contains("    m.push(2);\n    return 4;\n"));
+        assert!(source.contains("    None,\n    Some(block_2),\n"));
+
+        let source = transpile(&assemble(b"loop:\njp loop\n"), &Config::default()).unwrap();
+        assert!(source.contains("fn block_0(_m: &mut Machine) -> usize {\n"));
+    }
+
+    #[test]
+    fn input_radix() {
+        let program = assemble(b"rd\nwr\nhalt\n");
+
+        let source = transpile(&program, &Config::default()).unwrap();
+        assert!(source.contains("const INPUT_RADIX: u32 = 10;\n"));
+
+        let config = Config { input_radix: Radix::Hexadecimal, ..Config::default() };
+        let source = transpile(&program, &config).unwrap();
+        assert!(source.contains("const INPUT_RADIX: u32 = 16;\n"));
+    }
+
+    #[test]
+    fn no_prompt() {
+        let program = assemble(b"rd\nwr\nhalt\n");
+
+        let source = transpile(&program, &Config::default()).unwrap();
+        assert!(source.contains("const NO_PROMPT: bool = false;\n"));
+
+        let config = Config { no_prompt: true, ..Config::default() };
+        let source = transpile(&program, &config).unwrap();
+        assert!(source.contains("const NO_PROMPT: bool = true;\n"));
+    }
+
+    #[test]
+    fn checked_registers() {
+        let program = assemble(b"enter\nmvsp -1\npushi 0\nstorel -1\nleave\nret\n");
+
+        let source = transpile(&program, &Config::default()).unwrap();
+        assert!(source.contains("    m.move_sp(-1);\n"));
+        assert!(source.contains("    let value = m.top();\n    let addr = m.stack_address(m.fp, -1);\n"));
+        assert!(source.contains("    let fp = m.pop();\n    m.fp = m.frame_pointer(fp);\n"));
+        assert!(source.contains("    let addr = m.pop();\n    return m.return_address(addr);\n"));
+    }
+
+    #[test]
+    fn reject_programs() {
+        let program = assemble(b"jp nowhere\n");
+        assert!(matches!(
+            transpile(&program, &Config::default()),
+            Err(Error::LabelNotFound(label)) if label == "nowhere"
+        ));
+
+        let program = assemble(b"pushi 1\nalloc\n");
+        assert!(matches!(
+            transpile(&program, &Config::default()),
+            Err(Error::UnsupportedInstruction(inst)) if inst == "alloc"
+        ));
+    }
+}
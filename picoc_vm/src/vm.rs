@@ -1,9 +1,30 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::io::{BufRead, Write};
 use std::cmp;
+use std::time::{Duration, Instant};
+use crate::checker::{SlotTag, SlotTags, StackChecker, StackIssue};
+use crate::config::{Config, FlushPolicy};
+use crate::gc::RefHeap;
+use crate::heap::Allocator;
+use crate::memory::{MemoryMap, MemoryUsage, Segment};
+use crate::snapshot::Snapshot;
+use crate::state::VmState;
+use crate::strings::StringTable;
 use crate::opcode::Opcode;
+use crate::inst::{blocks, compile, Inst};
 use crate::decode::*;
+use crate::dialect::DefaultDialect;
+use crate::literal::parse_int;
+use crate::looping::{LoopDetector, LOOP_STACK_WINDOW};
+use crate::program::Program;
+use crate::report::LoadReport;
+use crate::warning::Warning;
+use crate::debug::DebugInfo;
+use crate::delta::{MemoryWrite, StepDelta, UndoRecord};
 use crate::error::Error;
+use crate::event::VmEvent;
+use crate::explain::explain;
+use crate::frame::{format_frames, Frame};
 
 pub const VM_INST_MEMORY_SIZE: usize = 10000;
 pub const VM_STACK_SIZE: usize = 10000;
@@ -13,6 +34,10 @@ pub const VM_STACK_SIZE: usize = 10000;
 /// `PicocVm` runs codes written in picoc vm instruction sets.
 /// See [`Opcode`] for more about picoc vm instruction sets.
 ///
+/// With the `log` feature, the VM emits records through the [`log`](https://docs.rs/log) crate:
+/// every executed instruction at the debug level,
+/// and loading code, halting, and errors at the info level.
+///
 /// # Example
 ///
 /// ```
@@ -37,16 +62,69 @@ pub const VM_STACK_SIZE: usize = 10000;
 /// }
 /// ```
 pub struct PicocVm<'a, T: BufRead, U: Write> {
-    inst_memory: Vec<Opcode>,
-    stack: Vec<i32>,
-    label_table: HashMap<String, usize>,
+    program: Program,
+    code: Vec<Inst>,
+    /// The cycles of each instruction by [`Config::cycle_costs`].
+    code_cycles: Vec<u64>,
+    blocks: Vec<u32>,
+    program_usage: MemoryUsage,
+    warnings: Vec<Warning>,
+    /// The address where a program starts, given by [`load_program_at`](PicocVm::load_program_at()).
+    entry: usize,
+    memory: Vec<i32>,
+    memory_map: MemoryMap,
+    heap: Allocator,
+    refs: RefHeap,
+    strings: StringTable,
     reg: Registers,
     is_halted: bool,
+    steps: u64,
+    cycles: u64,
+    call_depth: usize,
+    output_bytes: usize,
+    started_at: Option<Instant>,
+    events: Vec<VmEvent>,
+    delta: Option<StepDelta>,
+    history: VecDeque<UndoRecord>,
+    /// The instruction overwritten by `storei` in the current step, kept to undo it.
+    code_write: Option<(usize, Opcode)>,
+    /// The program as loaded, kept when `storei` rewrites an instruction of it.
+    loaded_program: Option<Program>,
+    recent: VecDeque<usize>,
+    checkpoints: VecDeque<Snapshot>,
+    /// Tracks stack slots if [`Config::check_uninitialized`] or [`Config::poison_frames`] is set.
+    stack_checker: Option<StackChecker>,
+    /// Pairs of the stack address and the value of return addresses pushed by `call`
+    /// if [`Config::shadow_stack`] is set.
+    shadow_stack: Vec<(usize, usize)>,
+    /// Tags stack slots if [`Config::tag_slots`] is set.
+    slot_tags: Option<SlotTags>,
+    /// Finds a likely infinite loop if [`Config::detect_loops`] is set.
+    loop_detector: Option<LoopDetector>,
+    input_tokens: VecDeque<String>,
+    custom_opcodes: HashMap<String, CustomOpcode<T, U>>,
+    config: Config,
     input: &'a mut T,
     output: &'a mut U,
+    prompt_output: Option<&'a mut dyn Write>,
+}
+
+/// A function decoding the operands of an instruction registered by [`PicocVm::register_opcode`].
+type DecodeFn = dyn Fn(&[String]) -> Result<i32, Error>;
+
+/// A function executing an instruction registered by [`PicocVm::register_opcode`].
+type ExecuteFn<T, U> = dyn for<'b> FnMut(&mut PicocVm<'b, T, U>, i32) -> Result<(), Error>;
+
+/// An instruction registered by [`PicocVm::register_opcode`].
+struct CustomOpcode<T: BufRead, U: Write> {
+    decode: Box<DecodeFn>,
+    /// Taken out while the instruction is executed
+    execute: Option<Box<ExecuteFn<T, U>>>,
 }
 
 /// Registers for a VM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Registers {
     /// Program Counter
     ///
@@ -62,6 +140,30 @@ pub struct Registers {
     pub fp: usize,
 }
 
+impl Default for Registers {
+    /// Returns the registers of a VM which has just loaded a program.
+    fn default() -> Self {
+        Self {
+            pc: 0,
+            sp: VM_STACK_SIZE,
+            fp: VM_STACK_SIZE,
+        }
+    }
+}
+
+/// Names of the registers of a VM.
+///
+/// See [`Registers`] for the meaning of each register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    /// Program Counter
+    Pc,
+    /// Stack Pointer
+    Sp,
+    /// Frame Pointer
+    Fp,
+}
+
 impl<'a, T, U> PicocVm<'a, T, U>
 where 
     T: BufRead,
@@ -83,54 +185,276 @@ where
     /// }
     /// ```
     pub fn new(input: &'a mut T, output: &'a mut U) -> Self {
-        let stack = vec![0; VM_STACK_SIZE];
-        let reg = Registers {
-            pc: 0,
-            sp: VM_STACK_SIZE,
-            fp: VM_STACK_SIZE,
-        };
+        Self::with_config(input, output, Config::default())
+    }
+
+    /// Creates a new VM with a configuration.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Config};
+    ///
+    /// fn main() {
+    ///     let mut input = Cursor::new(b"10\n");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let config = Config {
+    ///         legacy_end: true,
+    ///         ..Config::default()
+    ///     };
+    ///
+    ///     let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+    /// }
+    /// ```
+    pub fn with_config(input: &'a mut T, output: &'a mut U, config: Config) -> Self {
+        let memory_map = MemoryMap::new(config.data_size, config.heap_size);
+        let memory = vec![0; memory_map.data_memory_size()];
+        let reg = Registers::default();
+        let loop_detector = config.detect_loops.then(LoopDetector::default);
+        let stack_checker = (config.check_uninitialized || config.poison_frames)
+            .then(|| StackChecker::new(memory_map.stack, config.check_uninitialized, config.poison_frames));
+        let slot_tags = config.tag_slots.then(|| SlotTags::new(memory_map.stack));
 
         Self {
-            inst_memory: Vec::with_capacity(VM_INST_MEMORY_SIZE),
-            stack,
-            label_table: HashMap::new(),
+            program: Program::default(),
+            code: Vec::new(),
+            code_cycles: Vec::new(),
+            blocks: Vec::new(),
+            program_usage: MemoryUsage::default(),
+            warnings: Vec::new(),
+            entry: 0,
+            memory,
+            heap: Allocator::new(memory_map.heap),
+            refs: RefHeap::new(config.ref_limit),
+            strings: StringTable::new(),
+            memory_map,
             reg,
             is_halted: false,
+            steps: 0,
+            cycles: 0,
+            call_depth: 0,
+            output_bytes: 0,
+            started_at: None,
+            events: Vec::new(),
+            delta: None,
+            history: VecDeque::new(),
+            code_write: None,
+            loaded_program: None,
+            recent: VecDeque::new(),
+            checkpoints: VecDeque::new(),
+            stack_checker,
+            shadow_stack: Vec::new(),
+            slot_tags,
+            loop_detector,
+            input_tokens: VecDeque::new(),
+            custom_opcodes: HashMap::new(),
+            config,
             input,
             output,
+            prompt_output: None,
         }
     }
 
-    fn push(&mut self, data: i32) -> Result<(), Error> {
+    /// Sets a stream where the prompt of `rd` is written.
+    ///
+    /// By default, the prompt is written to the output stream of the VM.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::{self, Cursor};
+    /// use picoc_vm::{PicocVm, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"10\n");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///     let mut prompt = io::stderr();
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///     vm.set_prompt_output(&mut prompt);
+    ///
+    ///     let code = Cursor::new(b"rd\nwr\nhalt\n");
+    ///
+    ///     vm.load(code)?;
+    ///     vm.run_until_halt()?;
+    ///
+    ///     assert_eq!(output.get_ref(), b"10 ");
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_prompt_output(&mut self, prompt_output: &'a mut dyn Write) {
+        self.prompt_output = Some(prompt_output);
+    }
+
+    /// Pushes a value onto the stack.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::StackOverflow`] if the stack is full,
+    /// or [`Error::VmHalted`] if the VM is halted.
+    pub fn push(&mut self, data: i32) -> Result<(), Error> {
         if self.is_halted {
             return Err(Error::VmHalted);
         }
 
-        self.reg.sp -= 1;
-
-        if self.reg.sp >= VM_STACK_SIZE {
-            return Err(Error::StackOverflow)
-        }
-        self.stack[self.reg.sp] = data;
+        self.reg.sp = self.memory_map.check(Segment::Stack, self.reg.sp as i64 - 1)
+            .map_err(|_| Error::StackOverflow)?;
+        self.write_word(self.reg.sp, data);
 
         Ok(())
     }
 
-    fn pop(&mut self) -> Result<i32, Error> {
+    /// Writes a word of the data memory, recording it into the current delta.
+    fn write_word(&mut self, addr: usize, value: i32) {
+        if let Some(delta) = &mut self.delta {
+            delta.writes.push(MemoryWrite { addr, old: self.memory[addr], new: value });
+        }
+        if let Some(checker) = &mut self.stack_checker {
+            checker.write(addr);
+        }
+        if let Some(tags) = &mut self.slot_tags {
+            tags.tag(addr, Some(SlotTag::Data));
+        }
+        self.memory[addr] = value;
+    }
+
+    /// Pops a value from the stack.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::StackUnderflow`] if the stack is empty,
+    /// or [`Error::VmHalted`] if the VM is halted.
+    pub fn pop(&mut self) -> Result<i32, Error> {
         if self.is_halted {
             return Err(Error::VmHalted);
         }
 
-        if self.reg.sp >= VM_STACK_SIZE {
-            return Err(Error::StackUnderflow);
-        }
+        let sp = self.memory_map.check(Segment::Stack, self.reg.sp as i64)
+            .map_err(|_| Error::StackUnderflow)?;
 
-        let ret = self.stack[self.reg.sp];
+        let ret = self.memory[sp];
         self.reg.sp += 1;
 
         Ok(ret)
     }
 
+    /// Gets the value on the top of the stack without popping it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::StackUnderflow`] if the stack is empty.
+    pub fn peek(&self) -> Result<i32, Error> {
+        let sp = self.memory_map.check(Segment::Stack, self.reg.sp as i64)
+            .map_err(|_| Error::StackUnderflow)?;
+
+        Ok(self.memory[sp])
+    }
+
+    fn read_line(&mut self) -> Result<String, Error> {
+        let mut line = String::new();
+
+        self.reset_loop_detector();
+        self.output.flush()?;
+        self.record(VmEvent::InputRequested);
+        if !self.config.no_prompt {
+            self.write_prompt(b"? ")?;
+        }
+        self.input.read_line(&mut line)?;
+
+        if self.config.echo_input && !line.is_empty() {
+            self.write_prompt(line.trim_end().as_bytes())?;
+            self.write_prompt(b"\n")?;
+        }
+
+        Ok(line)
+    }
+
+    fn write_prompt(&mut self, buf: &[u8]) -> Result<(), Error> {
+        if let Some(prompt_output) = &mut self.prompt_output {
+            prompt_output.write_all(buf)?;
+            prompt_output.flush()?;
+        } else {
+            self.output.write_all(buf)?;
+            self.output.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn read_token(&mut self) -> Result<String, Error> {
+        self.reset_loop_detector();
+        while self.input_tokens.is_empty() {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                return Err(Error::NoMoreInput);
+            }
+
+            self.input_tokens.extend(line.split_whitespace().map(str::to_string));
+        }
+
+        Ok(self.input_tokens.pop_front().unwrap())
+    }
+
+    /// Writes bytes to the output stream like `wr`.
+    ///
+    /// The bytes count toward [`ExecutionLimits::max_output_bytes`](crate::ExecutionLimits::max_output_bytes),
+    /// and the output is flushed according to [`Config::flush_policy`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutputLimitExceeded`] if the output exceeds the limit,
+    /// or [`Err`] if any I/O error occurs.
+    pub fn write_output(&mut self, buf: &[u8]) -> Result<(), Error> {
+        if let Some(max) = self.config.limits.max_output_bytes {
+            if self.output_bytes + buf.len() > max {
+                // Write up to the limit so that the output is cut exactly
+                let rest = max.saturating_sub(self.output_bytes);
+                self.output.write_all(&buf[..rest])?;
+                self.output_bytes += rest;
+                self.record(VmEvent::OutputProduced(String::from_utf8_lossy(&buf[..rest]).into_owned()));
+
+                return Err(Error::OutputLimitExceeded(max));
+            }
+        }
+        self.output_bytes += buf.len();
+        self.reset_loop_detector();
+        self.output.write_all(buf)?;
+        self.record(VmEvent::OutputProduced(String::from_utf8_lossy(buf).into_owned()));
+        if let Some(delta) = &mut self.delta {
+            delta.output.push_str(&String::from_utf8_lossy(buf));
+        }
+
+        match self.config.flush_policy {
+            FlushPolicy::EveryWrite => self.output.flush()?,
+            FlushPolicy::EveryLine if buf.contains(&b'\n') => self.output.flush()?,
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Parses an integer read by `rd` or `rdt` in [`Config::input_radix`].
+    fn parse_input(&self, token: &str) -> Result<i32, Error> {
+        let value = parse_int(token, self.config.input_radix.base())?;
+
+        i32::try_from(value).map_err(|_| Error::InvalidLiteral(token.to_string()))
+    }
+
+    /// Gets the address of a relative jump at PC by an offset.
+    fn relative_target(&self, offset: i32) -> Result<usize, Error> {
+        usize::try_from(self.reg.pc as i64 + offset as i64).map_err(|_| Error::MemoryOutOfBound)
+    }
+
+    fn write_field(&mut self, width: usize, zero_pad: bool) -> Result<(), Error> {
+        let value = self.pop()?;
+        let content = self.config.output_format.format_field(value, width, zero_pad);
+
+        self.write_output(content.as_bytes())
+    }
+
     /// Loads a code into the VM from a stream.
     ///
     /// This method also initializes the VM's registers, which are PC, SP, and FP.
@@ -138,7 +462,10 @@ where
     /// # Errors
     ///
     /// This method returns [`Err`] if an invalid opcode or operand is found,
-    /// of any I/O error occurs.
+    /// `call` refers to an undefined label (unless [`Config::legacy_call`] is set),
+    /// or any I/O error occurs.
+    /// An error at a line of the code is given as [`Error::InSource`],
+    /// which can be named by [`Error::in_file`].
     /// See [`Error`] for details.
     ///
     /// # Example
@@ -167,359 +494,333 @@ where
     /// }
     /// ```
     pub fn load<V: BufRead>(&mut self, inst: V) -> Result<(), Error> {
-        let lines = split_code(inst)?;
+        self.load_program(self.assemble(inst)?)
+    }
 
-        load_label(&lines, &mut self.label_table); // 1st pass
-        load_inst(&lines, &mut self.inst_memory)?; // 2nd pass
+    /// Loads a code like [`load`](PicocVm::load()), and returns a summary of the program loaded.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] under the same situations as [`load`](PicocVm::load()).
+    /// See [`LoadReport`] for an example.
+    pub fn load_with_report<V: BufRead>(&mut self, inst: V) -> Result<LoadReport, Error> {
+        self.load(inst)?;
 
-        self.reg.pc = 0;
-        self.reg.sp = VM_STACK_SIZE;
-        self.reg.fp = VM_STACK_SIZE;
-        self.is_halted = false;
+        Ok(self.load_report())
+    }
 
-        Ok(())
+    /// Summarizes the program loaded, as [`load_with_report`](PicocVm::load_with_report()) returns.
+    pub fn load_report(&self) -> LoadReport {
+        let labels = self.program.labels();
+        let mut entry_labels: Vec<&String> = labels.iter()
+            .filter(|&(_, &addr)| addr == self.entry)
+            .map(|(name, _)| name)
+            .collect();
+        entry_labels.sort_by_key(|name| (name.as_str() != "__start__", name.as_str() != "main", name.as_str()));
+
+        LoadReport {
+            instructions: self.program.len() - self.entry,
+            labels: labels.len(),
+            warnings: self.warnings.clone(),
+            entry: self.entry,
+            entry_label: entry_labels.first().map(|name| name.to_string()),
+        }
     }
 
-    /// Executes once the instruction that PC points to and (mostly) increments PC.
+    /// Registers an additional instruction with its mnemonic.
+    ///
+    /// `decode` converts the operands of a line (the words after the mnemonic) into an operand,
+    /// and `execute` runs the instruction with the operand.
+    /// After `execute` returns, PC is incremented unless `execute` changes it or halts the VM.
+    /// The instruction is available in code loaded after the registration.
     ///
     /// # Errors
     ///
-    /// This method returns [`Err`] if a value of PC or SP is out of bounds,
-    /// or an unknown label is found.
+    /// Returns [`Error::OpcodeAlreadyDefined`] if the mnemonic is a built-in or registered one.
     ///
     /// # Example
     ///
     /// ```
-    /// use std::io::{self, Cursor};
+    /// use std::io::Cursor;
     /// use picoc_vm::{PicocVm, Error};
     ///
     /// fn main() -> Result<(), Error> {
-    ///     let mut input = io::stdin().lock();
-    ///     let mut output = io::stdout();
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
     ///
     ///     let mut vm = PicocVm::new(&mut input, &mut output);
     ///
-    ///     let code = Cursor::new(b"
-    ///         pushi 5
-    ///         pushi 3
-    ///         add
-    ///         pushi 4
-    ///         pushi 2
-    ///         sub
-    ///         div
-    ///         wr
-    ///         wrln
-    ///         halt");
-    ///
-    ///     vm.load(code)?;
-    ///
-    ///     let mut count = 0;
+    ///     // `wrstr` writes the string of a handle followed by a line feed
+    ///     vm.register_opcode("wrstr", |_| Ok(0), |vm, _| {
+    ///         let handle = vm.pop()?;
+    ///         let text = format!("{}\n", vm.string(handle)?);
+    ///         vm.write_output(text.as_bytes())
+    ///     })?;
     ///
-    ///     while let Ok(()) = vm.step() {
-    ///         count += 1;
-    ///     }
+    ///     vm.load(Cursor::new(b"pushs \"hello\"\nwrstr\nhalt\n"))?;
+    ///     vm.run_until_halt()?;
     ///
-    ///     println!("execution count: {count}");
+    ///     assert_eq!(output.get_ref(), b"hello\n");
     ///
     ///     Ok(())
     /// }
     /// ```
-    pub fn step(&mut self) -> Result<(), Error> {
-        if self.is_halted {
-            return Err(Error::VmHalted);
+    pub fn register_opcode<D, E>(&mut self, mnemonic: &str, decode: D, execute: E) -> Result<(), Error>
+    where
+        D: Fn(&[String]) -> Result<i32, Error> + 'static,
+        E: for<'b> FnMut(&mut PicocVm<'b, T, U>, i32) -> Result<(), Error> + 'static,
+    {
+        let name = mnemonic.to_lowercase();
+        let is_builtin = !matches!(Opcode::from_line(std::slice::from_ref(&name)), Err(Error::UnknownOpcode(_)));
+        if is_builtin || self.custom_opcodes.contains_key(&name) {
+            return Err(Error::OpcodeAlreadyDefined(name));
         }
 
-        if self.reg.pc >= self.inst_memory.len() {
-            return Err(Error::MemoryOutOfBound);
-        }
+        self.custom_opcodes.insert(name, CustomOpcode {
+            decode: Box::new(decode),
+            execute: Some(Box::new(execute)),
+        });
 
-        match &self.inst_memory[self.reg.pc] {
-            Opcode::Pushl(n) => {
-                let target = self.reg.fp as i32 + n;
-                if target < 0 || target >= VM_STACK_SIZE.try_into().unwrap() {
-                    return Err(Error::StackOutOfBound);
-                }
+        Ok(())
+    }
 
-                let elem = self.stack[target as usize];
-                self.push(elem)?;
+    /// Assembles a code, decoding the registered instructions.
+    fn assemble<V: BufRead>(&self, inst: V) -> Result<Program, Error> {
+        Program::assemble_with(inst, &DefaultDialect, self.config.load_mode, &|line: &[String]| {
+            let name = line[0].to_lowercase();
+            let custom = self.custom_opcodes.get(&name)?;
 
-                self.reg.pc += 1;
-            },
-            Opcode::Storel(n) => {
-                let target = self.reg.fp as i32 + n;
-                if target < 0 || target >= VM_STACK_SIZE.try_into().unwrap() {
-                    return Err(Error::StackOutOfBound);
-                }
+            Some((custom.decode)(&line[1..]).map(|operand| Opcode::Custom(name, operand)))
+        })
+    }
 
-                self.stack[target as usize] = self.stack[self.reg.sp];
+    /// Loads an assembled program into the VM.
+    ///
+    /// This method also initializes the VM's registers, which are PC, SP, and FP.
+    ///
+    /// # Errors
+    ///
+    /// This method returns [`Err`] if `call` refers to an undefined label
+    /// (unless [`Config::legacy_call`] is set).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error, Program};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     let program = Program::assemble(Cursor::new(b"pushi 5\nhalt\n"))?;
+    ///
+    ///     vm.load_program(program)?;
+    ///     vm.run_until_halt()?;
+    ///
+    ///     assert_eq!(vm.stack(), &[5]);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn load_program(&mut self, program: Program) -> Result<(), Error> {
+        self.check_program(&program)?;
 
-                self.reg.pc += 1;
-            },
-            Opcode::Storet(n) => {
-                let target = self.reg.sp as i32 + n;
-                if target < 0 || target >= VM_STACK_SIZE.try_into().unwrap() {
-                    return Err(Error::StackOutOfBound);
-                }
+        self.set_program(program);
+        self.entry = 0;
+        #[cfg(feature = "log")]
+        log::info!("loaded {} instructions", self.program.len());
 
-                self.stack[target as usize] = self.stack[self.reg.sp];
+        self.reset_state();
 
-                self.reg.pc += 1;
-            },
-            Opcode::Pushi(d) => {
-                self.push(*d)?;
+        Ok(())
+    }
 
-                self.reg.pc += 1;
-            },
-            Opcode::Call(label) => {
-                let previous_pc = self.reg.pc as i32;
-                if let Some(target) = self.label_table.get(label) {
-                    self.reg.pc = *target;
-                }
-                self.push(previous_pc + 1)?;
-            },
-            Opcode::Ret => {
-                self.reg.pc = self.pop()? as usize;
-            },
-            Opcode::Enter => {
-                self.push(self.reg.fp as i32)?;
-                self.reg.fp = self.reg.sp;
+    /// Loads an assembled program at an address of the instruction memory.
+    ///
+    /// The program is moved by [`Program::relocate`], and PC starts at `base`,
+    /// also after [`reset`](PicocVm::reset()).
+    /// The instructions before `base` are `trap`, which fails if executed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] under the same situations as [`load_program`](PicocVm::load_program()),
+    /// or [`Error::AddressOutOfBound`] if the program does not fit in the instruction memory.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error, Program};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     let program = Program::assemble(Cursor::new(b"loop:\npushi 5\njp 3\njp loop\nhalt\n"))?;
+    ///
+    ///     vm.load_program_at(program, 1000)?;
+    ///     assert_eq!(vm.registers().pc, 1000);
+    ///     vm.run_until_halt()?;
+    ///
+    ///     assert_eq!(vm.stack(), &[5]);
+    ///     assert_eq!(vm.registers().pc, 1003);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn load_program_at(&mut self, program: Program, base: usize) -> Result<(), Error> {
+        let program = program.relocate(base)?;
+        self.check_program(&program)?;
 
-                self.reg.pc += 1;
-            },
-            Opcode::Leave => {
-                self.reg.sp = self.reg.fp;
-                self.reg.fp = self.pop()? as usize;
+        self.set_program(program);
+        self.entry = base;
+        #[cfg(feature = "log")]
+        log::info!("loaded {} instructions at {}", self.program.len() - base, base);
 
-                self.reg.pc += 1;
-            },
-            Opcode::Mvsp(n) => {
-                self.reg.sp = (self.reg.sp as i32 + n) as usize;
+        self.reset_state();
 
-                self.reg.pc += 1;
-            },
-            Opcode::Jp(label) => {
-                if let Some(target) = self.label_table.get(label) {
-                    self.reg.pc = *target;
-                } else {
-                    return Err(Error::LabelNotFound(label.clone()));
-                }
-            },
-            Opcode::Jt(label) => {
-                if let Some(target) = self.label_table.get(label) {
-                    let num = *target;
+        Ok(())
+    }
 
-                    if self.pop()? != 0 {
-                        self.reg.pc = num;
-                    } else {
-                        self.reg.pc += 1;
-                    }
-                } else {
-                    return Err(Error::LabelNotFound(label.clone()));
-                }
-            },
-            Opcode::Jf(label) => {
-                if let Some(target) = self.label_table.get(label) {
-                    let num = *target;
-
-                    if self.pop()? == 0 {
-                        self.reg.pc = num;
-                    } else {
-                        self.reg.pc += 1;
-                    }
-                } else {
-                    return Err(Error::LabelNotFound(label.clone()));
-                }
-            },
-            Opcode::Add => {
-                let t1 = self.pop()?;
-                let t2 = self.pop()?;
-
-                self.push(t2 + t1)?;
-
-                self.reg.pc += 1;
-            },
-            Opcode::Sub => {
-                let t1 = self.pop()?;
-                let t2 = self.pop()?;
-
-                self.push(t2 - t1)?;
-
-                self.reg.pc += 1;
-            },
-            Opcode::Mul => {
-                let t1 = self.pop()?;
-                let t2 = self.pop()?;
-
-                self.push(t2 * t1)?;
-
-                self.reg.pc += 1;
-            },
-            Opcode::Div => {
-                let t1 = self.pop()?;
-                let t2 = self.pop()?;
-
-                self.push(t2 / t1)?;
-
-                self.reg.pc += 1;
-            },
-            Opcode::Mod => {
-                let t1 = self.pop()?;
-                let t2 = self.pop()?;
-
-                self.push(t2 % t1)?;
-
-                self.reg.pc += 1;
-            },
-            Opcode::Eq => {
-                let t1 = self.pop()?;
-                let t2 = self.pop()?;
-
-                self.push(if t2 == t1 { 1 } else { 0 })?;
-
-                self.reg.pc += 1;
-            },
-            Opcode::Ne => {
-                let t1 = self.pop()?;
-                let t2 = self.pop()?;
-
-                self.push(if t2 != t1 { 1 } else { 0 })?;
-
-                self.reg.pc += 1;
-            },
-            Opcode::Gt => {
-                let t1 = self.pop()?;
-                let t2 = self.pop()?;
-
-                self.push(if t2 > t1 { 1 } else { 0 })?;
-
-                self.reg.pc += 1;
-            },
-            Opcode::Ge => {
-                let t1 = self.pop()?;
-                let t2 = self.pop()?;
-
-                self.push(if t2 >= t1 { 1 } else { 0 })?;
-
-                self.reg.pc += 1;
-            },
-            Opcode::Lt => {
-                let t1 = self.pop()?;
-                let t2 = self.pop()?;
-
-                self.push(if t2 < t1 { 1 } else { 0 })?;
-
-                self.reg.pc += 1;
-            },
-            Opcode::Le => {
-                let t1 = self.pop()?;
-                let t2 = self.pop()?;
-
-                self.push(if t2 <= t1 { 1 } else { 0 })?;
-
-                self.reg.pc += 1;
-            },
-            Opcode::Rd => {
-                let mut line = String::new();
-
-                self.output.write(b"? ")?;
-                self.output.flush()?;
-                self.input.read_line(&mut line)?;
-                self.push(line.trim().parse()?)?;
-
-                self.reg.pc += 1;
-            },
-            Opcode::Wr => {
-                let content = self.pop()?.to_string() + " ";
-
-                self.output.write(content.as_bytes())?;
-
-                self.reg.pc += 1;
-            },
-            Opcode::Wrln => {
-                self.output.write(b"\n")?;
-
-                self.reg.pc += 1;
-            },
-            Opcode::Halt => {
-                self.is_halted = true;
-            },
-        }
-
-        self.reg.pc %= VM_INST_MEMORY_SIZE;
-
-        Ok(())
-    }
-
-    /// Runs the code until VM halts or PC exceeds the length of the instruction memory.
+    /// Resets the VM to the state just after the program is loaded, so that it can run again.
     ///
-    /// # Errors
-    ///
-    /// Returns [`Err`] under the same situations as [`step`](PicocVm::step()).
+    /// The data memory is cleared, the input tokens left by `rdt` are discarded,
+    /// and the instructions rewritten by `storei` are put back.
+    /// The program, the configuration, and the custom opcodes are kept,
+    /// and no memory is reallocated, which makes repeated runs of short programs cheap.
+    /// The streams can be rewound by [`input_mut`](PicocVm::input_mut()) and [`output_mut`](PicocVm::output_mut()).
     ///
     /// # Example
     ///
     /// ```
-    /// use std::io::{self, BufReader};
-    /// use std::fs::File;
-    /// use picoc_vm::{PicocVm, Error};
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Config, Error};
     ///
     /// fn main() -> Result<(), Error> {
-    ///     let mut input = io::stdin().lock();
-    ///     let mut output = io::stdout();
+    ///     let mut input = Cursor::new(Vec::new());
+    ///     let mut output = Vec::new();
+    ///     let config = Config { no_prompt: true, ..Config::default() };
     ///
-    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///     let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+    ///     vm.load(Cursor::new(b"rd\npushi 2\nmul\nwr\nhalt\n"))?;
     ///
-    ///     let f = File::open("test.out")?;
-    ///     let reader = BufReader::new(f);
+    ///     for (data, expected) in [("21\n", "42 "), ("5\n", "10 ")] {
+    ///         vm.reset();
+    ///         *vm.input_mut() = Cursor::new(data.as_bytes().to_vec());
+    ///         vm.output_mut().clear();
     ///
-    ///     vm.load(reader)?;
+    ///         vm.run_until_halt()?;
     ///
-    ///     vm.run_until_halt()?;
+    ///         assert_eq!(vm.output_mut(), expected.as_bytes());
+    ///     }
     ///
     ///     Ok(())
     /// }
     /// ```
-    pub fn run_until_halt(&mut self) -> Result<(), Error> {
-        loop {
-            match self.step() {
-                Ok(()) => (),
-                Err(Error::VmHalted) => break,
-                Err(Error::MemoryOutOfBound) => break,
-                Err(err) => return Err(err),
-            }
+    pub fn reset(&mut self) {
+        self.memory.fill(0);
+        self.input_tokens.clear();
+        self.reset_state();
+    }
+
+    /// Replaces the program, converting its instructions into the compact form.
+    fn set_program(&mut self, program: Program) {
+        self.code = compile(&program);
+        self.code_cycles = program.insts.iter().map(|inst| self.config.cycle_costs.cost(inst)).collect();
+        self.blocks = blocks(&program);
+        self.program_usage = MemoryUsage::of_program(&program);
+        self.warnings = program.warnings();
+        self.program = program;
+        self.loaded_program = None;
+    }
+
+    /// Puts back the instructions rewritten by `storei` since the program is loaded.
+    fn restore_code(&mut self) {
+        if let Some(program) = self.loaded_program.take() {
+            self.set_program(program);
         }
+    }
 
-        Ok(())
+    /// Resets the registers and the execution state for a program loaded.
+    fn reset_state(&mut self) {
+        self.restore_code();
+        self.reg = Registers { pc: self.entry, ..Registers::default() };
+        self.is_halted = false;
+        self.steps = 0;
+        self.cycles = 0;
+        self.call_depth = 0;
+        self.output_bytes = 0;
+        self.started_at = None;
+        self.events.clear();
+        self.history.clear();
+        self.recent.clear();
+        self.checkpoints.clear();
+        if let Some(checker) = &mut self.stack_checker {
+            checker.reset();
+        }
+        self.shadow_stack.clear();
+        self.clear_slot_tags();
+        self.reset_loop_detector();
+        self.heap.reset();
+        self.refs.reset();
+        self.strings.clear();
     }
 
-    /// Gets a reference to the instruction memory of the VM.
+    /// Gets the input stream of the VM.
+    pub fn input_mut(&mut self) -> &mut T {
+        self.input
+    }
+
+    /// Gets the output stream of the VM.
+    pub fn output_mut(&mut self) -> &mut U {
+        self.output
+    }
+
+    /// Consumes the VM and gives back its input and output streams.
+    ///
+    /// The streams can be lent to another VM, e.g. with another configuration.
     ///
     /// # Example
     ///
     /// ```
     /// use std::io::Cursor;
-    /// use picoc_vm::{PicocVm, Error, Opcode};
+    /// use picoc_vm::{PicocVm, Config};
     ///
-    /// fn main() -> Result<(), Error> {
+    /// fn main() {
     ///     let mut input = Cursor::new(b"");
-    ///     let mut output = Cursor::new(Vec::new());
-    ///
-    ///     let mut vm = PicocVm::new(&mut input, &mut output);
-    ///
-    ///     let code = Cursor::new(b"pushi 5\nhalt\n");
+    ///     let mut output = Vec::new();
     ///
-    ///     vm.load(code)?;
+    ///     let vm = PicocVm::new(&mut input, &mut output);
+    ///     let (input, output) = vm.into_streams();
     ///
-    ///     assert_eq!(vm.inst_memory(), &[Opcode::Pushi(5), Opcode::Halt]);
+    ///     let vm = PicocVm::with_config(input, output, Config { data_size: 16, ..Config::default() });
     ///
-    ///     Ok(())
+    ///     assert_eq!(vm.config().data_size, 16);
     /// }
     /// ```
-    pub fn inst_memory(&self) -> &[Opcode] {
-        &self.inst_memory[..]
+    pub fn into_streams(self) -> (&'a mut T, &'a mut U) {
+        (self.input, self.output)
     }
 
-    /// Gets a reference to the label table of the VM.
+    /// Reloads a code into the VM from a stream, preserving the execution state.
+    ///
+    /// The stack, SP, and FP are kept as they are.
+    /// PC and the return addresses saved in the chain of stack frames are translated
+    /// to the new code, keeping their offsets from the nearest preceding label.
+    /// Other return addresses (e.g. pushed by `call` but not yet framed by `enter`)
+    /// are not translated.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] under the same situations as [`load`](PicocVm::load()),
+    /// or [`Error::LabelNotFound`] if a label needed to translate an address
+    /// is removed from the new code.
+    /// If an error occurs, the VM is left unchanged.
     ///
     /// # Example
     ///
@@ -528,35 +829,86 @@ where
     /// use picoc_vm::{PicocVm, Error};
     ///
     /// fn main() -> Result<(), Error> {
-    ///     let mut input = Cursor::new(b"10\n20\n");
+    ///     let mut input = Cursor::new(b"");
     ///     let mut output = Cursor::new(Vec::new());
     ///
     ///     let mut vm = PicocVm::new(&mut input, &mut output);
     ///
-    ///     let code = Cursor::new(b"
-    ///             pushi 2
-    ///         .L0:
-    ///             rd
-    ///             wr
-    ///             pushl -1
+    ///     vm.load(Cursor::new(b"
+    ///         main:
     ///             pushi 1
-    ///             sub
+    ///             wr
+    ///             halt"))?;
+    ///     vm.step()?;
+    ///
+    ///     vm.reload_code(Cursor::new(b"
     ///             pushi 0
-    ///             gt
-    ///             jt .L0");
+    ///         main:
+    ///             pushi 1
+    ///             pushi 2
+    ///             add
+    ///             wr
+    ///             halt"))?;
     ///
-    ///     vm.load(code)?;
+    ///     assert_eq!(vm.registers().pc, 2);
     ///
-    ///     assert_eq!(vm.label_table().get(".L0"), Some(&1));
+    ///     vm.run_until_halt()?;
+    ///
+    ///     assert_eq!(output.get_ref(), b"3 ");
     ///
     ///     Ok(())
     /// }
     /// ```
-    pub fn label_table(&self) -> &HashMap<String, usize> {
-        &self.label_table
+    pub fn reload_code<V: BufRead>(&mut self, inst: V) -> Result<(), Error> {
+        let program = self.assemble(inst)?;
+        self.check_program(&program)?;
+
+        let pc = translate_address(self.reg.pc, &self.program.labels, &program.labels)?;
+
+        let mut return_addresses = Vec::new();
+        let mut fp = self.reg.fp;
+        while fp + 1 < self.memory_map.stack.end() {
+            let addr = self.memory[fp + 1];
+            if addr >= 0 {
+                let new_addr = translate_address(addr as usize, &self.program.labels, &program.labels)?;
+                return_addresses.push((fp + 1, new_addr as i32));
+            }
+
+            let saved_fp = self.memory[fp];
+            if saved_fp <= fp as i32 {
+                break;
+            }
+            fp = saved_fp as usize;
+        }
+
+        for (addr, value) in return_addresses {
+            self.memory[addr] = value;
+        }
+        self.reg.pc = pc;
+        self.recent.clear();
+        // Return addresses are translated, so those pushed before are no longer verified
+        self.shadow_stack.clear();
+        self.set_program(program);
+        #[cfg(feature = "log")]
+        log::info!("reloaded {} instructions", self.program.len());
+
+        Ok(())
     }
 
-    /// Gets a reference to the stack of the VM.
+    /// Loads an additional code into the VM from a stream.
+    ///
+    /// The instructions are appended to the end of the instruction memory,
+    /// and their labels are added to the label table.
+    /// The appended code can call or jump to labels loaded previously.
+    /// A label defined again refers to the new definition.
+    ///
+    /// Unlike [`load`](PicocVm::load()), this method does not reset the registers.
+    /// Thus, a VM that stopped with [`Error::FellOffEnd`] continues with the appended code.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] under the same situations as [`load`](PicocVm::load()).
+    /// If an error occurs, the VM is left unchanged.
     ///
     /// # Example
     ///
@@ -570,322 +922,3690 @@ where
     ///
     ///     let mut vm = PicocVm::new(&mut input, &mut output);
     ///
-    ///     let code = Cursor::new(b"
-    ///         pushi 4
-    ///         pushi 5
-    ///         pushi 6
-    ///         pushi 7
-    ///         add");
+    ///     vm.load(Cursor::new(b"pushi 1\n"))?;
+    ///     assert!(matches!(vm.run_until_halt(), Err(Error::FellOffEnd)));
     ///
-    ///     vm.load(code)?;
+    ///     vm.load_append(Cursor::new(b"pushi 2\nadd\nhalt\n"))?;
     ///     vm.run_until_halt()?;
     ///
-    ///     assert_eq!(vm.stack(), &[13, 5, 4]);
+    ///     assert_eq!(vm.stack(), &[3]);
     ///
     ///     Ok(())
     /// }
     /// ```
-    pub fn stack(&self) -> &[i32] {
-        let stack_bottom = cmp::min(self.reg.sp, self.reg.fp);
-        return &self.stack[stack_bottom..VM_STACK_SIZE];
+    pub fn load_append<V: BufRead>(&mut self, inst: V) -> Result<(), Error> {
+        let mut program = self.program.clone();
+        program.append(self.assemble(inst)?);
+        self.check_program(&program)?;
+
+        self.set_program(program);
+        #[cfg(feature = "log")]
+        log::info!("appended code, now {} instructions", self.program.len());
+
+        Ok(())
     }
 
-    /// Gets a reference to the registers of the VM.
+    fn check_program(&self, program: &Program) -> Result<(), Error> {
+        if !self.config.legacy_call {
+            program.check_call_targets()?;
+        }
+
+        if let Some(max) = self.config.limits.max_memory_bytes {
+            let usage = MemoryUsage {
+                refs: 0,
+                strings: 0,
+                ..MemoryUsage::of_program(program)
+            };
+            if self.segment_usage(usage).total() > max {
+                return Err(Error::MemoryLimitExceeded(max));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks [`ExecutionLimits::max_memory_bytes`](crate::ExecutionLimits::max_memory_bytes)
+    /// after a reference cell or a string is made, collecting garbage if it is exceeded.
+    fn check_memory(&mut self) -> Result<(), Error> {
+        if let Some(max) = self.config.limits.max_memory_bytes {
+            if self.memory_usage().total() > max {
+                self.collect_garbage();
+
+                if self.memory_usage().total() > max {
+                    return Err(Error::MemoryLimitExceeded(max));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Executes once the instruction that PC points to and (mostly) increments PC.
+    ///
+    /// # Errors
+    ///
+    /// This method returns [`Err`] if a value of PC or SP is out of bounds,
+    /// or an unknown label is found.
+    /// Running past the last instruction returns [`Error::FellOffEnd`]
+    /// unless [`Config::legacy_end`] is set.
+    /// Exceeding a limit in [`Config::limits`] returns its own error
+    /// (e.g. [`Error::StepLimitExceeded`]).
     ///
     /// # Example
     ///
     /// ```
-    /// use std::io::Cursor;
+    /// use std::io::{self, Cursor};
     /// use picoc_vm::{PicocVm, Error};
-    /// use picoc_vm::VM_STACK_SIZE;
     ///
     /// fn main() -> Result<(), Error> {
-    ///     let mut input = Cursor::new(b"");
-    ///     let mut output = Cursor::new(Vec::new());
+    ///     let mut input = io::stdin().lock();
+    ///     let mut output = io::stdout();
     ///
     ///     let mut vm = PicocVm::new(&mut input, &mut output);
     ///
     ///     let code = Cursor::new(b"
-    ///         __start__:
-    ///             call main
-    ///             halt
-    ///         main:
-    ///             enter
-    ///             pushi 2147483647
-    ///             leave
-    ///             ret");
+    ///         pushi 5
+    ///         pushi 3
+    ///         add
+    ///         pushi 4
+    ///         pushi 2
+    ///         sub
+    ///         div
+    ///         wr
+    ///         wrln
+    ///         halt");
     ///
     ///     vm.load(code)?;
     ///
-    ///     for _ in 0..3 {
-    ///         println!("a");
-    ///         vm.step()?;
+    ///     let mut count = 0;
+    ///
+    ///     while let Ok(()) = vm.step() {
+    ///         count += 1;
     ///     }
     ///
-    ///     let reg = vm.registers();
-    ///     assert_eq!(reg.pc, 4);
-    ///     assert_eq!(reg.sp, VM_STACK_SIZE - 3);
-    ///     assert_eq!(reg.fp, VM_STACK_SIZE - 2);
+    ///     println!("execution count: {count}");
     ///
     ///     Ok(())
     /// }
     /// ```
-    pub fn registers(&self) -> &Registers {
-        &self.reg 
+    pub fn step(&mut self) -> Result<(), Error> {
+        #[cfg(feature = "log")]
+        if let (false, Some(inst)) = (self.is_halted, self.program.insts.get(self.reg.pc)) {
+            log::debug!("{:05}: {} (SP = {}, FP = {})", self.reg.pc, inst, self.reg.sp, self.reg.fp);
+        }
+
+        // The delta of the step is also needed to record the history
+        let owns_delta = self.config.history_depth > 0 && self.delta.is_none();
+        if owns_delta {
+            self.delta = Some(StepDelta::new(self.reg));
+        }
+        let (was_halted, steps, cycles, call_depth) = (self.is_halted, self.steps, self.cycles, self.call_depth);
+
+        if !self.is_halted && self.reg.pc < self.program.len() {
+            self.record_recent();
+        }
+
+        self.code_write = None;
+        let result = self.execute_inst();
+
+        let delta = if owns_delta { self.delta.take() } else { self.delta.clone() };
+        if let (true, Some(delta)) = (self.config.history_depth > 0, delta) {
+            // Nothing is changed by a step which fails before executing an instruction
+            if result.is_ok() || !delta.writes.is_empty() || delta.before != self.reg || self.code_write.is_some() {
+                if self.history.len() >= self.config.history_depth {
+                    self.history.pop_front();
+                }
+                self.history.push_back(UndoRecord {
+                    before: delta.before,
+                    writes: delta.writes,
+                    was_halted,
+                    steps,
+                    cycles,
+                    call_depth,
+                    code_write: self.code_write.take(),
+                });
+            }
+        }
+
+        if let (Ok(()), Some(policy)) = (&result, self.config.checkpoint) {
+            if policy.interval > 0 && policy.capacity > 0 && self.steps.is_multiple_of(policy.interval) {
+                if self.checkpoints.len() >= policy.capacity {
+                    self.checkpoints.pop_front();
+                }
+                self.checkpoints.push_back(self.snapshot());
+            }
+        }
+
+        #[cfg(feature = "log")]
+        match &result {
+            Ok(()) if self.is_halted => log::info!("halted after {} steps", self.steps),
+            Ok(()) | Err(Error::VmHalted) => (),
+            Err(err) => log::info!("error at PC = {}: {}", self.reg.pc, err),
+        }
+
+        result
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs::File;
-    use std::io::{self, BufReader};
+    /// Executes once the instruction like [`step`](PicocVm::step()),
+    /// and returns the changes made by it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] under the same situations as [`step`](PicocVm::step()).
+    /// See [`StepDelta`] for an example.
+    pub fn step_with_delta(&mut self) -> Result<StepDelta, Error> {
+        self.delta = Some(StepDelta::new(self.reg));
 
-    #[test]
-    fn load_assembly_code() -> Result<(), Error> {
-        let mut input = io::stdin().lock();
-        let mut output = io::stdout();
+        let result = self.step();
+        let mut delta = self.delta.take().unwrap();
+        result?;
+        delta.after = self.reg;
 
-        let mut vm = PicocVm::new(&mut input, &mut output);
+        Ok(delta)
+    }
 
-        let code = File::open("test.out")?;
-        let code = BufReader::new(code);
+    /// Executes once the instruction like [`step`](PicocVm::step()),
+    /// and describes in plain words what it did.
+    ///
+    /// The description tells the values popped and pushed and where PC goes,
+    /// which is meant for demonstrating the VM.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] under the same situations as [`step`](PicocVm::step()).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     vm.load(Cursor::new(b"
+    ///         pushi 5
+    ///         pushi 3
+    ///         add
+    ///         jt end
+    ///     end:
+    ///         halt"))?;
+    ///
+    ///     assert_eq!(vm.step_explained()?, "push 5; PC now 1");
+    ///     vm.step()?;
+    ///     assert_eq!(vm.step_explained()?, "pop 5 and 3, push their sum 8; PC now 3");
+    ///     assert_eq!(vm.step_explained()?, "pop 8, which is true, so jump to end; PC now 4");
+    ///     assert_eq!(vm.step_explained()?, "stop the VM");
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn step_explained(&mut self) -> Result<String, Error> {
+        let inst = self.program.insts.get(self.reg.pc).cloned();
+        let stack = self.memory[self.reg.sp..self.memory_map.stack.end()].iter().take(2).copied().collect::<Vec<_>>();
 
-        vm.load(code)?;
+        let delta = self.step_with_delta()?;
 
-        assert_eq!(
-            vm.inst_memory,
-            vec![
-                // __start__
-                Opcode::Call("main".to_string()),
-                Opcode::Halt,
-                // read()
-                Opcode::Enter,
-                Opcode::Rd,
-                Opcode::Storel(2),
-                Opcode::Leave,
-                Opcode::Ret,
-                // write()
-                Opcode::Enter,
-                Opcode::Pushl(3),
-                Opcode::Wr,
-                Opcode::Leave,
-                Opcode::Ret,
-                // writeln()
-                Opcode::Enter,
-                Opcode::Wrln,
-                Opcode::Leave,
-                Opcode::Ret,
-                // main()
-                Opcode::Enter,
-                Opcode::Pushi(1),
-                Opcode::Mvsp(-1),
-                Opcode::Call("write".to_string()),
-                Opcode::Storet(1),
-                Opcode::Mvsp(1),
-                Opcode::Mvsp(1),
-                Opcode::Mvsp(-1),
-                Opcode::Call("writeln".to_string()),
-                Opcode::Mvsp(1),
-                Opcode::Leave,
-                Opcode::Ret,
-            ]
-        );
+        Ok(inst.map_or_else(String::new, |inst| explain(&inst, &stack, &delta)))
+    }
+
+    /// Undoes the last step recorded in the history.
+    ///
+    /// Registers, the data memory, and an instruction written by `storei` are restored,
+    /// but the input consumed and the output written are not.
+    /// Allocations on the heap, reference cells, and strings are not restored either.
+    /// Returns `false` if the history is empty.
+    /// See [`Config::history_depth`] to enable the history.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Config, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///     let config = Config { history_depth: 2, ..Config::default() };
+    ///
+    ///     let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+    ///
+    ///     vm.load(Cursor::new(b"pushi 1\npushi 2\nadd\nhalt\n"))?;
+    ///     vm.run_until_halt()?;
+    ///
+    ///     assert!(vm.undo_step());
+    ///     assert!(vm.undo_step());
+    ///     assert!(!vm.undo_step());
+    ///
+    ///     assert_eq!(vm.registers().pc, 2);
+    ///     assert_eq!(vm.stack(), &[2, 1]);
+    ///
+    ///     vm.run_until_halt()?;
+    ///     assert_eq!(vm.stack(), &[3]);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn undo_step(&mut self) -> bool {
+        let Some(record) = self.history.pop_back() else {
+            return false;
+        };
+
+        for write in record.writes.iter().rev() {
+            self.memory[write.addr] = write.old;
+        }
+        if let Some((pc, inst)) = record.code_write {
+            self.replace_instruction(pc, inst);
+        }
+        self.reg = record.before;
+        self.is_halted = record.was_halted;
+        self.steps = record.steps;
+        self.cycles = record.cycles;
+        self.call_depth = record.call_depth;
+        // Stepping again goes through the same states
+        self.reset_loop_detector();
+        // The tags of the slots before the step are not recorded
+        self.clear_slot_tags();
+
+        true
+    }
+
+    /// Enumerates the latest executed instructions with their addresses, from the oldest.
+    ///
+    /// An instruction which fails is also included, so the last one is where an error occurs.
+    /// See [`Config::recent_depth`] to keep instructions.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Config, Error, Opcode};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///     let config = Config { recent_depth: 2, ..Config::default() };
+    ///
+    ///     let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+    ///
+    ///     vm.load(Cursor::new(b"pushi 1\npushi 0\ndiv\nhalt\n"))?;
+    ///     assert!(matches!(vm.run_until_halt(), Err(Error::DivisionByZero)));
+    ///
+    ///     let recent: Vec<_> = vm.recent_instructions().collect();
+    ///     assert_eq!(recent, [(1, &Opcode::Pushi(0)), (2, &Opcode::Div)]);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn recent_instructions(&self) -> impl DoubleEndedIterator<Item = (usize, &Opcode)> {
+        let insts = &self.program.insts;
+        self.recent.iter().map(move |&pc| (pc, &insts[pc]))
+    }
+
+    fn reset_loop_detector(&mut self) {
+        if let Some(detector) = &mut self.loop_detector {
+            detector.reset();
+        }
+    }
+
+    fn clear_slot_tags(&mut self) {
+        if let Some(tags) = &mut self.slot_tags {
+            tags.clear();
+        }
+    }
+
+    fn tag_slot(&mut self, addr: usize, tag: SlotTag) {
+        if let Some(tags) = &mut self.slot_tags {
+            tags.tag(addr, Some(tag));
+        }
+    }
+
+    /// Checks that the stack slots consumed by the instruction at PC hold what it expects.
+    ///
+    /// A slot without a tag (e.g. after [`restore`](PicocVm::restore())) is not checked.
+    fn check_slot_tags(&self) -> Result<(), Error> {
+        let Some(tags) = &self.slot_tags else {
+            return Ok(());
+        };
+        let sp = self.reg.sp;
+        let (expected, slots) = match self.code[self.reg.pc] {
+            Inst::Ret => (SlotTag::ReturnAddress, sp..sp + 1),
+            Inst::Leave => (SlotTag::SavedFp, self.reg.fp..self.reg.fp + 1),
+            Inst::Add | Inst::Sub | Inst::Mul | Inst::Div | Inst::Mod
+            | Inst::Eq | Inst::Ne | Inst::Gt | Inst::Ge | Inst::Lt | Inst::Le => (SlotTag::Data, sp..sp + 2),
+            _ => return Ok(()),
+        };
+
+        for addr in slots {
+            match tags.get(addr) {
+                Some(tag) if tag != expected => {
+                    return Err(Error::SlotTagMismatch(self.program.insts[self.reg.pc].to_string(), tag, addr));
+                },
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Verifies a return address popped from a stack address against the shadow stack.
+    ///
+    /// A return address which is not in the shadow stack (e.g. after [`restore`](PicocVm::restore())
+    /// or [`reload_code`](PicocVm::reload_code())) is not verified.
+    fn check_return_address(&mut self, slot: usize, found: i32) -> Result<(), Error> {
+        // Return addresses below the slot are of frames discarded without `ret`
+        while self.shadow_stack.last().is_some_and(|&(addr, _)| addr < slot) {
+            self.shadow_stack.pop();
+        }
+
+        match self.shadow_stack.last() {
+            Some(&(addr, expected)) if addr == slot => {
+                self.shadow_stack.pop();
+                if found as i64 != expected as i64 {
+                    return Err(Error::ReturnAddressOverwritten(slot, expected, found));
+                }
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn record_recent(&mut self) {
+        if self.config.recent_depth > 0 {
+            if self.recent.len() >= self.config.recent_depth {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(self.reg.pc);
+        }
+    }
+
+    fn execute_inst(&mut self) -> Result<(), Error> {
+        if self.is_halted {
+            return Err(Error::VmHalted);
+        }
+
+        if self.reg.pc >= self.program.insts.len() {
+            if !self.config.legacy_end && self.reg.pc == self.program.insts.len() {
+                return Err(Error::FellOffEnd);
+            }
+            return Err(Error::MemoryOutOfBound);
+        }
+        self.check_limits()?;
+
+        self.dispatch()
+    }
+
+    /// Executes the instruction at PC, which is assumed to be in the program,
+    /// without checking the limits of steps and time.
+    fn dispatch(&mut self) -> Result<(), Error> {
+        let pc = self.reg.pc;
+        let cycles = self.code_cycles[pc];
+        self.check_slot_tags()?;
+
+        match self.code[self.reg.pc] {
+            Inst::Pushl(n) => {
+                let target = self.memory_map.check(Segment::Stack, self.reg.fp as i64 + n as i64)?;
+                if let Some(checker) = &mut self.stack_checker {
+                    checker.read(self.reg.pc, target, self.reg.fp);
+                }
+
+                let elem = self.memory[target];
+                self.push(elem)?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Storel(n) => {
+                let top = self.peek()?;
+                let target = self.memory_map.check(Segment::Stack, self.reg.fp as i64 + n as i64)?;
+
+                self.write_word(target, top);
+
+                self.reg.pc += 1;
+            },
+            Inst::Storet(n) => {
+                let top = self.peek()?;
+                let target = self.memory_map.check(Segment::Stack, self.reg.sp as i64 + n as i64)?;
+
+                self.write_word(target, top);
+
+                self.reg.pc += 1;
+            },
+            Inst::Pushi(d) => {
+                self.push(d)?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Pushpc => {
+                self.push(self.reg.pc as i32)?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Pushsp => {
+                self.push(self.reg.sp as i32)?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Pushfp => {
+                self.push(self.reg.fp as i32)?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Call(target) => {
+                if let Some(max) = self.config.limits.max_calls {
+                    if self.call_depth >= max {
+                        return Err(Error::CallDepthExceeded(max, self.frames()));
+                    }
+                }
+                let previous_pc = self.reg.pc as i32;
+                let event = self.config.record_events.then(|| VmEvent::Called(self.label_operand()));
+                if let Some(target) = target.get() {
+                    self.reg.pc = target;
+                } else if !self.config.legacy_call {
+                    return Err(Error::LabelNotFound(self.label_operand()));
+                }
+                self.push(previous_pc + 1)?;
+                self.tag_slot(self.reg.sp, SlotTag::ReturnAddress);
+                if self.config.shadow_stack {
+                    self.shadow_stack.push((self.reg.sp, previous_pc as usize + 1));
+                }
+
+                self.call_depth += 1;
+                if let Some(event) = event {
+                    self.record(event);
+                }
+            },
+            Inst::Ret => {
+                let slot = self.reg.sp;
+                let addr = self.pop()?;
+                if self.config.shadow_stack {
+                    self.check_return_address(slot, addr)?;
+                }
+                self.reg.pc = usize::try_from(addr).map_err(|_| Error::MemoryOutOfBound)?;
+                self.call_depth = self.call_depth.saturating_sub(1);
+                self.record(VmEvent::Returned);
+            },
+            Inst::Enter => {
+                self.push(self.reg.fp as i32)?;
+                self.tag_slot(self.reg.sp, SlotTag::SavedFp);
+                self.reg.fp = self.reg.sp;
+
+                self.reg.pc += 1;
+            },
+            Inst::Leave => {
+                let sp = self.reg.sp;
+                self.reg.sp = self.reg.fp;
+                let fp = self.pop()?;
+                if let Some(checker) = &mut self.stack_checker {
+                    checker.release(sp..self.reg.sp);
+                }
+                self.reg.fp = usize::try_from(fp).ok()
+                    .filter(|&fp| fp <= self.memory_map.stack.end())
+                    .ok_or(Error::StackOutOfBound)?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Mvsp(n) => {
+                let sp = self.reg.sp as i64 + n as i64;
+                let size = self.memory_map.stack.end();
+                if sp < 0 || sp > size as i64 {
+                    return Err(Error::InvalidStackPointer(sp, size));
+                }
+                if let Some(checker) = &mut self.stack_checker {
+                    checker.allocate(sp as usize..self.reg.sp);
+                    checker.release(self.reg.sp..sp as usize);
+                }
+                if let Some(tags) = &mut self.slot_tags {
+                    tags.forget(sp as usize..self.reg.sp);
+                }
+                self.reg.sp = sp as usize;
+
+                self.reg.pc += 1;
+            },
+            Inst::Salloc => {
+                let size = self.pop()?;
+                if size < 0 {
+                    return Err(Error::InvalidAllocationSize(size));
+                }
+                // Leaves a word to push the address
+                let base = self.reg.sp as i64 - size as i64;
+                if base < self.memory_map.stack.base as i64 + 1 {
+                    return Err(Error::StackOverflow);
+                }
+                if let Some(checker) = &mut self.stack_checker {
+                    checker.allocate(base as usize..self.reg.sp);
+                }
+                if let Some(tags) = &mut self.slot_tags {
+                    tags.forget(base as usize..self.reg.sp);
+                }
+                self.reg.sp = base as usize;
+                self.push(base as i32)?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Jp(target) => {
+                if let Some(target) = target.get() {
+                    self.reg.pc = target;
+                } else {
+                    return Err(Error::LabelNotFound(self.label_operand()));
+                }
+            },
+            Inst::Jt(target) => {
+                if let Some(num) = target.get() {
+                    if self.pop()? != 0 {
+                        self.reg.pc = num;
+                    } else {
+                        self.reg.pc += 1;
+                    }
+                } else {
+                    return Err(Error::LabelNotFound(self.label_operand()));
+                }
+            },
+            Inst::Jf(target) => {
+                if let Some(num) = target.get() {
+                    if self.pop()? == 0 {
+                        self.reg.pc = num;
+                    } else {
+                        self.reg.pc += 1;
+                    }
+                } else {
+                    return Err(Error::LabelNotFound(self.label_operand()));
+                }
+            },
+            Inst::Jr(offset) => {
+                self.reg.pc = self.relative_target(offset)?;
+            },
+            Inst::Jrt(offset) => {
+                if self.pop()? != 0 {
+                    self.reg.pc = self.relative_target(offset)?;
+                } else {
+                    self.reg.pc += 1;
+                }
+            },
+            Inst::Jrf(offset) => {
+                if self.pop()? == 0 {
+                    self.reg.pc = self.relative_target(offset)?;
+                } else {
+                    self.reg.pc += 1;
+                }
+            },
+            Inst::Add => {
+                let t1 = self.pop()?;
+                let t2 = self.pop()?;
+
+                self.push(t2.wrapping_add(t1))?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Sub => {
+                let t1 = self.pop()?;
+                let t2 = self.pop()?;
+
+                self.push(t2.wrapping_sub(t1))?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Mul => {
+                let t1 = self.pop()?;
+                let t2 = self.pop()?;
+
+                self.push(t2.wrapping_mul(t1))?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Div => {
+                let t1 = self.pop()?;
+                let t2 = self.pop()?;
+
+                if t1 == 0 {
+                    return Err(Error::DivisionByZero);
+                }
+                self.push(t2.wrapping_div(t1))?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Mod => {
+                let t1 = self.pop()?;
+                let t2 = self.pop()?;
+
+                if t1 == 0 {
+                    return Err(Error::DivisionByZero);
+                }
+                self.push(t2.wrapping_rem(t1))?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Eq => {
+                let t1 = self.pop()?;
+                let t2 = self.pop()?;
+
+                self.push(if t2 == t1 { 1 } else { 0 })?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Ne => {
+                let t1 = self.pop()?;
+                let t2 = self.pop()?;
+
+                self.push(if t2 != t1 { 1 } else { 0 })?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Gt => {
+                let t1 = self.pop()?;
+                let t2 = self.pop()?;
+
+                self.push(if t2 > t1 { 1 } else { 0 })?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Ge => {
+                let t1 = self.pop()?;
+                let t2 = self.pop()?;
+
+                self.push(if t2 >= t1 { 1 } else { 0 })?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Lt => {
+                let t1 = self.pop()?;
+                let t2 = self.pop()?;
+
+                self.push(if t2 < t1 { 1 } else { 0 })?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Le => {
+                let t1 = self.pop()?;
+                let t2 = self.pop()?;
+
+                self.push(if t2 <= t1 { 1 } else { 0 })?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Rd => {
+                let line = self.read_line()?;
+                let value = self.parse_input(line.trim())?;
+                self.push(value)?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Rdt => {
+                let token = self.read_token()?;
+                let value = self.parse_input(&token)?;
+                self.push(value)?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Wr => {
+                let value = self.pop()?;
+                let content = self.config.output_format.format(value);
+
+                self.write_output(content.as_bytes())?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Wrln => {
+                self.write_output(b"\n")?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Wrch => {
+                let t = self.pop()?;
+
+                let c = u32::try_from(t).ok()
+                    .and_then(char::from_u32)
+                    .ok_or(Error::InvalidCodePoint(t))?;
+                let mut buf = [0; 4];
+                self.write_output(c.encode_utf8(&mut buf).as_bytes())?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Alloc => {
+                let size = self.pop()?;
+                let addr = self.heap.alloc(size)?;
+                self.push(addr as i32)?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Free => {
+                let addr = self.pop()?;
+                self.heap.free(addr as i64)?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Ld => {
+                let addr = self.pop()?;
+                let addr = self.memory_map.check_data_memory(addr as i64)?;
+                if let Some(checker) = &mut self.stack_checker {
+                    checker.read(self.reg.pc, addr, self.reg.fp);
+                }
+                self.push(self.memory[addr])?;
+
+                self.reg.pc += 1;
+            },
+            Inst::St => {
+                let value = self.pop()?;
+                let addr = self.pop()?;
+                let addr = self.memory_map.check_data_memory(addr as i64)?;
+                self.write_word(addr, value);
+                self.push(value)?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Scat => {
+                let t1 = self.pop()?;
+                let t2 = self.pop()?;
+
+                let s = self.strings.get(t2)?.to_string() + self.strings.get(t1)?;
+                let handle = self.strings.intern(&s)?;
+                self.push(handle)?;
+                self.check_memory()?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Scmp => {
+                let t1 = self.pop()?;
+                let t2 = self.pop()?;
+
+                let ordering = self.strings.get(t2)?.cmp(self.strings.get(t1)?);
+                self.push(ordering as i32)?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Slen => {
+                let t = self.pop()?;
+
+                let len = self.strings.get(t)?.chars().count();
+                self.push(len as i32)?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Wrs => {
+                let t = self.pop()?;
+
+                let s = self.strings.get(t)?.to_string();
+                self.write_output(s.as_bytes())?;
+
+                self.reg.pc += 1;
+            },
+            Inst::Halt => {
+                self.is_halted = true;
+                self.record(VmEvent::Halted);
+            },
+            Inst::Extended => self.execute_extended()?,
+        }
+        self.steps += 1;
+        self.cycles += cycles;
+
+        // A loop goes back to its head, so states are checked only when going backward
+        if self.reg.pc <= pc {
+            if let Some(detector) = &mut self.loop_detector {
+                let top = self.reg.sp.min(self.memory_map.stack.end());
+                let window = &self.memory[top..(top + LOOP_STACK_WINDOW).min(self.memory_map.stack.end())];
+                if detector.check(&self.reg, window) {
+                    return Err(Error::InfiniteLoop(self.reg.pc));
+                }
+            }
+        }
+
+        if let Some(max) = self.config.limits.max_stack_depth {
+            if self.memory_map.stack.end() - self.reg.sp > max {
+                return Err(Error::StackLimitExceeded(max));
+            }
+        }
+
+        if self.config.legacy_end {
+            self.reg.pc %= VM_INST_MEMORY_SIZE;
+        }
+
+        Ok(())
+    }
+
+    /// Gets the label of the jump or call at PC.
+    fn label_operand(&self) -> String {
+        match &self.program.insts[self.reg.pc] {
+            Opcode::Call(label) | Opcode::Jp(label) | Opcode::Jt(label) | Opcode::Jf(label) => label.clone(),
+            other => other.to_string(),
+        }
+    }
+
+    /// Executes an instruction whose operand is read from its [`Opcode`].
+    fn execute_extended(&mut self) -> Result<(), Error> {
+        match &self.program.insts[self.reg.pc] {
+            Opcode::Wrf(width) => {
+                let width = *width;
+                self.write_field(width, false)?;
+
+                self.reg.pc += 1;
+            },
+            Opcode::Wrz(width) => {
+                let width = *width;
+                self.write_field(width, true)?;
+
+                self.reg.pc += 1;
+            },
+            Opcode::Newref(n) => {
+                let n = *n;
+                let roots = self.memory[self.reg.sp..].iter().copied();
+                let reference = self.refs.alloc(n, roots)?;
+                self.push(reference)?;
+                self.check_memory()?;
+
+                self.reg.pc += 1;
+            },
+            Opcode::Getf(i) => {
+                let i = *i;
+                let reference = self.pop()?;
+                self.push(self.refs.get(reference, i)?)?;
+
+                self.reg.pc += 1;
+            },
+            Opcode::Setf(i) => {
+                let i = *i;
+                let value = self.pop()?;
+                let reference = self.pop()?;
+                self.refs.set(reference, i, value)?;
+                self.push(reference)?;
+
+                self.reg.pc += 1;
+            },
+            Opcode::Pushs(text) => {
+                let handle = self.strings.intern(text)?;
+                self.push(handle)?;
+                self.check_memory()?;
+
+                self.reg.pc += 1;
+            },
+            Opcode::Custom(name, operand) => {
+                let (name, operand) = (name.clone(), *operand);
+                let execute = self.custom_opcodes.get_mut(&name).and_then(|custom| custom.execute.take());
+                let Some(mut execute) = execute else {
+                    return Err(Error::UnknownOpcode(name));
+                };
+
+                let pc = self.reg.pc;
+                let result = execute(self, operand);
+                if let Some(custom) = self.custom_opcodes.get_mut(&name) {
+                    custom.execute = Some(execute);
+                }
+                result?;
+
+                if self.reg.pc == pc && !self.is_halted {
+                    self.reg.pc += 1;
+                }
+            },
+            Opcode::Storei => {
+                if !self.config.writable_code {
+                    return Err(Error::CodeNotWritable);
+                }
+                let word = self.pop()?;
+                let addr = self.pop()?;
+                let pc = usize::try_from(addr).ok()
+                    .filter(|&pc| pc < self.program.len())
+                    .ok_or(Error::AddressOutOfBound(addr as i64))?;
+
+                let inst = Opcode::from_word(word)?;
+                // A decoded target is a numeric target, which must be in the program
+                if let Some(target) = numeric_target(&inst).filter(|&target| target >= self.program.len()) {
+                    return Err(Error::TargetOutOfBound(target as i64));
+                }
+                if self.loaded_program.is_none() {
+                    self.loaded_program = Some(self.program.clone());
+                }
+                let old = self.replace_instruction(pc, inst);
+                if self.config.history_depth > 0 {
+                    self.code_write = Some((pc, old));
+                }
+
+                self.reg.pc += 1;
+            },
+            Opcode::Trap(line) => return Err(Error::Trap(self.reg.pc, line.clone())),
+            other => return Err(Error::UnsupportedInstruction(other.to_string())),
+        }
+
+        Ok(())
+    }
+
+    fn record(&mut self, event: VmEvent) {
+        if self.config.record_events {
+            self.events.push(event);
+        }
+    }
+
+    /// Takes out the events recorded since the last call.
+    ///
+    /// Nothing is recorded unless [`Config::record_events`] is set.
+    /// See [`VmEvent`] for an example.
+    pub fn drain_events(&mut self) -> Vec<VmEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Checks the limits which are independent of an instruction.
+    fn check_limits(&mut self) -> Result<(), Error> {
+        let limits = &self.config.limits;
+
+        if let Some(max) = limits.max_steps {
+            if self.steps >= max {
+                return Err(Error::StepLimitExceeded(max));
+            }
+        }
+        self.started_at.get_or_insert_with(Instant::now);
+        if let Some(max) = limits.max_time {
+            if self.elapsed() > max {
+                return Err(Error::TimeLimitExceeded(max));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gets the number of instructions executed since the code is loaded.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     vm.load(Cursor::new(b"pushi 1\npushi 2\nadd\nhalt\n"))?;
+    ///     vm.run_until_halt()?;
+    ///
+    ///     assert_eq!(vm.steps(), 4);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Gets the time taken since the first step after the code is loaded.
+    ///
+    /// With [`Config::deterministic`], this is the time on a virtual clock which advances 1 ns per cycle
+    /// (see [`cycles`](PicocVm::cycles())), so it does not depend on the speed of the host.
+    /// Otherwise, this is the wall time.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use std::time::Duration;
+    /// use picoc_vm::{PicocVm, Config, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///     let config = Config { deterministic: true, ..Config::default() };
+    ///
+    ///     let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+    ///
+    ///     vm.load(Cursor::new(b"pushi 1\npushi 2\nadd\nhalt\n"))?;
+    ///     vm.run_until_halt()?;
+    ///
+    ///     assert_eq!(vm.elapsed(), Duration::from_nanos(4));
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn elapsed(&self) -> Duration {
+        if self.config.deterministic {
+            return Duration::from_nanos(self.cycles);
+        }
+        self.started_at.map(|started_at| started_at.elapsed()).unwrap_or_default()
+    }
+
+    /// Gets the simulated cycles taken by the instructions executed,
+    /// which are given by [`Config::cycle_costs`].
+    ///
+    /// Like [`steps`](PicocVm::steps()), this is not counted while native code of the JIT compiler runs,
+    /// which falls back to the interpreter unless the costs are the default.
+    /// See [`CycleCosts`](crate::CycleCosts) for an example.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Gets the bad reads of stack slots found so far, in the order they are found.
+    ///
+    /// Nothing is found unless [`Config::check_uninitialized`] or [`Config::poison_frames`] is set.
+    /// See [`StackIssue`](crate::StackIssue) for an example.
+    pub fn stack_issues(&self) -> &[StackIssue] {
+        self.stack_checker.as_ref().map(StackChecker::issues).unwrap_or_default()
+    }
+
+    /// Runs the code until VM halts.
+    ///
+    /// If [`Config::legacy_end`] is set, the VM also stops
+    /// when PC exceeds the length of the instruction memory.
+    ///
+    /// Unless each step is recorded (by [`Config::history_depth`] or [`Config::checkpoint`]),
+    /// the instructions are executed by basic blocks, checking the limits once for each block.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] under the same situations as [`step`](PicocVm::step()).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::{self, BufReader};
+    /// use std::fs::File;
+    /// use picoc_vm::{PicocVm, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = io::stdin().lock();
+    ///     let mut output = io::stdout();
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     let f = File::open("test.out")?;
+    ///     let reader = BufReader::new(f);
+    ///
+    ///     vm.load(reader)?;
+    ///
+    ///     vm.run_until_halt()?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn run_until_halt(&mut self) -> Result<(), Error> {
+        let by_block = self.runs_by_block();
+
+        loop {
+            let result = if by_block { self.run_block() } else { self.step() };
+            match result {
+                Ok(()) => (),
+                Err(Error::VmHalted) => break,
+                Err(Error::MemoryOutOfBound) if self.config.legacy_end => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether no state of each step is observed,
+    /// so that [`run_until_halt`](PicocVm::run_until_halt()) can execute whole basic blocks.
+    fn runs_by_block(&self) -> bool {
+        !cfg!(feature = "log")
+            && self.config.history_depth == 0
+            && self.config.checkpoint.is_none()
+            && self.delta.is_none()
+    }
+
+    /// Executes the instructions from PC to the end of its basic block.
+    ///
+    /// The checks of PC, the halt state, and the limits of steps and time are done
+    /// once at the entry of the block. A block which may exceed the step limit
+    /// is executed by [`step`](PicocVm::step()) to stop at the exact step.
+    fn run_block(&mut self) -> Result<(), Error> {
+        let Some(&len) = self.blocks.get(self.reg.pc).filter(|_| !self.is_halted) else {
+            return self.step();
+        };
+        if let Some(max) = self.config.limits.max_steps {
+            if self.steps + len as u64 > max {
+                return self.step();
+            }
+        }
+        self.check_limits()?;
+
+        for _ in 0..len {
+            self.record_recent();
+            self.dispatch()?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the output stream of the VM.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Err`] if an I/O error occurs.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::{self, Cursor};
+    /// use picoc_vm::{PicocVm, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = io::stdout();
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     let code = Cursor::new(b"pushi 5\nwr\nhalt\n");
+    ///
+    ///     vm.load(code)?;
+    ///     vm.run_until_halt()?;
+    ///     vm.flush()?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.output.flush()?;
+
+        Ok(())
+    }
+
+    /// Gets a reference to the instruction memory of the VM.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error, Opcode};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     let code = Cursor::new(b"pushi 5\nhalt\n");
+    ///
+    ///     vm.load(code)?;
+    ///
+    ///     assert_eq!(vm.inst_memory(), &[Opcode::Pushi(5), Opcode::Halt]);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn inst_memory(&self) -> &[Opcode] {
+        &self.program.insts[..]
+    }
+
+    /// Gets a reference to the program loaded into the VM.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     vm.load(Cursor::new(b"pushi 5\nhalt\n"))?;
+    ///
+    ///     assert_eq!(vm.program().len(), 2);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// Gets the warnings found when the program is loaded.
+    ///
+    /// Warnings do not stop the program from running.
+    /// See [`Program::warnings`] for the kinds of warnings.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error, WarningKind};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     vm.load(Cursor::new(b"pushi 5\nhalt\nwr\n"))?;
+    ///
+    ///     assert_eq!(vm.warnings()[0].kind, WarningKind::UnreachableAfterHalt);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Gets a reference to the label table of the VM.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"10\n20\n");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     let code = Cursor::new(b"
+    ///             pushi 2
+    ///         .L0:
+    ///             rd
+    ///             wr
+    ///             pushl -1
+    ///             pushi 1
+    ///             sub
+    ///             pushi 0
+    ///             gt
+    ///             jt .L0");
+    ///
+    ///     vm.load(code)?;
+    ///
+    ///     assert_eq!(vm.label_table().get(".L0"), Some(&1));
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn label_table(&self) -> &HashMap<String, usize> {
+        &self.program.labels
+    }
+
+    /// Gets a reference to the stack of the VM.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     let code = Cursor::new(b"
+    ///         pushi 4
+    ///         pushi 5
+    ///         pushi 6
+    ///         pushi 7
+    ///         add
+    ///         halt");
+    ///
+    ///     vm.load(code)?;
+    ///     vm.run_until_halt()?;
+    ///
+    ///     assert_eq!(vm.stack(), &[13, 5, 4]);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn stack(&self) -> &[i32] {
+        let stack_bottom = cmp::min(self.reg.sp, self.reg.fp);
+        &self.memory[stack_bottom..self.memory_map.stack.end()]
+    }
+
+    /// Gets the stack frames of the VM by walking the chain of saved FPs.
+    ///
+    /// The innermost frame comes first.
+    /// Between `call` and `enter`, the new frame is not made yet,
+    /// so the innermost frame has the FP of the caller.
+    /// See [`Frame`] for an example.
+    pub fn frames(&self) -> Vec<Frame> {
+        let functions = DebugInfo {
+            functions: self.program.functions(),
+            ..DebugInfo::default()
+        };
+        let debug_info = &self.program.debug_info;
+        let stack_end = self.memory_map.stack.end();
+
+        let mut frames = Vec::new();
+        let mut fp = self.reg.fp;
+        let mut pc = self.reg.pc;
+        let mut bottom = self.reg.sp;
+        loop {
+            let return_pc = (fp + 1 < stack_end)
+                .then(|| self.memory[fp + 1])
+                .and_then(|addr| usize::try_from(addr).ok());
+            let saved_fp = return_pc
+                .and_then(|_| usize::try_from(self.memory[fp]).ok())
+                .filter(|&saved_fp| saved_fp > fp && saved_fp <= stack_end);
+
+            // The words of a frame lie up to the frame of the caller, including arguments
+            let top = saved_fp.unwrap_or(stack_end);
+            let base = bottom.min(top);
+            frames.push(Frame {
+                fp,
+                return_pc,
+                function_label: functions.function(pc).map(str::to_string),
+                base,
+                words: self.memory[base..top].to_vec(),
+                names: debug_info.locals(pc).to_vec(),
+            });
+
+            let (Some(return_pc), Some(saved_fp)) = (return_pc, saved_fp) else {
+                break;
+            };
+            bottom = fp + 2;
+            fp = saved_fp;
+            // The caller is running the `call` just before the return address
+            pc = return_pc.saturating_sub(1);
+        }
+
+        frames
+    }
+
+    /// Renders the stack grouped by frame, from the outermost frame down to SP.
+    ///
+    /// Each word is annotated as a saved FP, a return address, an argument, a local variable
+    /// (named by `.local`), or a temporary, with its offset from FP.
+    /// A frame whose function is unknown is named `?`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     vm.load(Cursor::new(b"
+    ///         main:
+    ///             pushi 7
+    ///             call f
+    ///             halt
+    ///         f:
+    ///             enter
+    ///         .local sum
+    ///             pushi 3
+    ///             halt"))?;
+    ///     vm.run_until_halt()?;
+    ///
+    ///     let text = vm.format_stack();
+    ///     let lines: Vec<&str> = text.lines().collect();
+    ///
+    ///     assert_eq!(lines, [
+    ///         "#1 ? (FP = 10000)",
+    ///         "#0 f (FP = 09997), returns to 00002",
+    ///         "  09999           7  argument (FP+2)",
+    ///         "  09998           2  return address -> 00002",
+    ///         "  09997       10000  saved FP                    <-- FP",
+    ///         "  09996           3  local %sum (FP-1)           <-- SP",
+    ///     ]);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn format_stack(&self) -> String {
+        format_frames(&self.frames(), self.reg.sp, self.reg.fp)
+    }
+
+    /// Sets a value of a register.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MemoryOutOfBound`] if PC is set beyond the instruction memory,
+    /// or [`Error::StackOutOfBound`] if SP or FP is set beyond the stack.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error, Register};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     vm.set_register(Register::Sp, 100)?;
+    ///
+    ///     assert_eq!(vm.registers().sp, 100);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn set_register(&mut self, reg: Register, value: usize) -> Result<(), Error> {
+        match reg {
+            Register::Pc => {
+                self.reg.pc = self.memory_map.check(Segment::Code, value as i64)?;
+            },
+            Register::Sp | Register::Fp => {
+                if value > self.memory_map.stack.end() {
+                    return Err(Error::StackOutOfBound);
+                }
+                if reg == Register::Sp {
+                    self.reg.sp = value;
+                } else {
+                    self.reg.fp = value;
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Reads a value at an address of the stack.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::StackOutOfBound`] if `addr` is beyond the stack.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error, VM_STACK_SIZE};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     vm.load(Cursor::new(b"pushi 3\nhalt\n"))?;
+    ///     vm.run_until_halt()?;
+    ///
+    ///     assert_eq!(vm.read_stack(VM_STACK_SIZE - 1)?, 3);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn read_stack(&self, addr: usize) -> Result<i32, Error> {
+        let addr = self.memory_map.check(Segment::Stack, addr as i64)?;
+
+        Ok(self.memory[addr])
+    }
+
+    /// Writes a value at an address of the stack.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::StackOutOfBound`] if `addr` is beyond the stack.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error, VM_STACK_SIZE};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     vm.load(Cursor::new(b"pushi 3\nhalt\n"))?;
+    ///     vm.run_until_halt()?;
+    ///     vm.write_stack(VM_STACK_SIZE - 1, 4)?;
+    ///
+    ///     assert_eq!(vm.stack(), &[4]);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn write_stack(&mut self, addr: usize, value: i32) -> Result<(), Error> {
+        let addr = self.memory_map.check(Segment::Stack, addr as i64)?;
+        if let Some(checker) = &mut self.stack_checker {
+            checker.write(addr);
+        }
+        if let Some(tags) = &mut self.slot_tags {
+            tags.tag(addr, Some(SlotTag::Data));
+        }
+        self.memory[addr] = value;
+
+        Ok(())
+    }
+
+    /// Reads a value at an address of the data memory.
+    ///
+    /// Unlike [`read_stack`](PicocVm::read_stack()),
+    /// the address can be in any segment described by [`memory_map`](PicocVm::memory_map()).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AddressOutOfBound`] if `addr` is not in any segment.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error, Config};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let config = Config {
+    ///         data_size: 16,
+    ///         ..Config::default()
+    ///     };
+    ///     let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+    ///
+    ///     let global = vm.memory_map().data.base;
+    ///     vm.write_memory(global, 7)?;
+    ///
+    ///     assert_eq!(vm.read_memory(global)?, 7);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn read_memory(&self, addr: usize) -> Result<i32, Error> {
+        let addr = self.memory_map.check_data_memory(addr as i64)?;
+
+        Ok(self.memory[addr])
+    }
+
+    /// Writes a value at an address of the data memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AddressOutOfBound`] if `addr` is not in any segment.
+    ///
+    /// # Example
+    ///
+    /// See [`read_memory`](PicocVm::read_memory()).
+    pub fn write_memory(&mut self, addr: usize, value: i32) -> Result<(), Error> {
+        let addr = self.memory_map.check_data_memory(addr as i64)?;
+        if let Some(checker) = &mut self.stack_checker {
+            checker.write(addr);
+        }
+        if let Some(tags) = &mut self.slot_tags {
+            tags.tag(addr, Some(SlotTag::Data));
+        }
+        self.memory[addr] = value;
+
+        Ok(())
+    }
+
+    /// Frees reference cells unreachable from the VM and returns the number of freed cells.
+    ///
+    /// The collector also runs automatically when `newref` allocates many cells.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     let code = Cursor::new(b"
+    ///         newref 2
+    ///         newref 1
+    ///         mvsp 1
+    ///         halt");
+    ///
+    ///     vm.load(code)?;
+    ///     vm.run_until_halt()?;
+    ///
+    ///     assert_eq!(vm.live_refs(), 2);
+    ///     assert_eq!(vm.collect_garbage(), 1);
+    ///     assert_eq!(vm.live_refs(), 1);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn collect_garbage(&mut self) -> usize {
+        let roots = self.memory[self.reg.sp..].iter().copied();
+        self.refs.collect(roots)
+    }
+
+    /// Returns the number of live reference cells.
+    ///
+    /// # Example
+    ///
+    /// See [`collect_garbage`](PicocVm::collect_garbage()).
+    pub fn live_refs(&self) -> usize {
+        self.refs.live()
+    }
+
+    /// Returns the memory currently used by the VM.
+    ///
+    /// The total can be limited by [`ExecutionLimits::max_memory_bytes`](crate::ExecutionLimits::max_memory_bytes).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error, VM_STACK_SIZE};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     vm.load(Cursor::new(br#"
+    ///         pushs "hello"
+    ///         newref 3
+    ///         halt"#))?;
+    ///     vm.run_until_halt()?;
+    ///
+    ///     let usage = vm.memory_usage();
+    ///     assert_eq!(usage.stack, VM_STACK_SIZE * 4);
+    ///     assert_eq!(usage.refs, 12);
+    ///     assert_eq!(usage.strings, 5);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.segment_usage(MemoryUsage {
+            refs: self.refs.words() * size_of::<i32>(),
+            strings: self.strings.bytes(),
+            ..self.program_usage
+        })
+    }
+
+    /// Adds the segments of the data memory to the usage.
+    fn segment_usage(&self, usage: MemoryUsage) -> MemoryUsage {
+        MemoryUsage {
+            stack: self.memory_map.stack.size * size_of::<i32>(),
+            data: self.memory_map.data.size * size_of::<i32>(),
+            heap: self.memory_map.heap.size * size_of::<i32>(),
+            ..usage
+        }
+    }
+
+    /// Saves the execution state of the VM.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     vm.load(Cursor::new(b"pushi 1\npushi 2\nadd\nhalt\n"))?;
+    ///     vm.step()?;
+    ///
+    ///     let snapshot = vm.snapshot();
+    ///     vm.run_until_halt()?;
+    ///     vm.restore(&snapshot)?;
+    ///
+    ///     assert_eq!(vm.registers().pc, 1);
+    ///     assert_eq!(vm.stack(), &[1]);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.memory.clone(),
+            reg: self.reg,
+            is_halted: self.is_halted,
+            heap: self.heap.clone(),
+            refs: self.refs.clone(),
+            strings: self.strings.clone(),
+            steps: self.steps,
+            cycles: self.cycles,
+            call_depth: self.call_depth,
+            output_bytes: self.output_bytes,
+            input_tokens: self.input_tokens.clone(),
+            program: self.loaded_program.is_some().then(|| self.program.clone()),
+        }
+    }
+
+    /// Gets a view of the state of the VM, which can be exported as JSON.
+    ///
+    /// See [`VmState`] for an example.
+    pub fn state(&self) -> VmState {
+        VmState {
+            registers: self.reg,
+            stack: self.memory[self.reg.sp..self.memory_map.stack.end()].to_vec(),
+            labels: self.program.labels.iter().map(|(label, &addr)| (label.clone(), addr)).collect(),
+            instruction: self.program.insts.get(self.reg.pc).map(Opcode::to_string),
+            halted: self.is_halted,
+            steps: self.steps,
+        }
+    }
+
+    /// Restores the execution state saved by [`snapshot`](PicocVm::snapshot()).
+    ///
+    /// The history for [`undo_step`](PicocVm::undo_step()) is cleared,
+    /// and checkpoints taken after the snapshot are discarded.
+    /// The instructions are put back as they are at the time of the snapshot,
+    /// but the output already written is not restored.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IncompatibleSnapshot`] if the snapshot is taken
+    /// from a VM with a different memory layout.
+    ///
+    /// # Example
+    ///
+    /// See [`snapshot`](PicocVm::snapshot()).
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Error> {
+        if snapshot.memory.len() != self.memory.len() {
+            return Err(Error::IncompatibleSnapshot);
+        }
+
+        self.memory.copy_from_slice(&snapshot.memory);
+        self.reg = snapshot.reg;
+        self.is_halted = snapshot.is_halted;
+        self.heap = snapshot.heap.clone();
+        self.refs = snapshot.refs.clone();
+        self.strings = snapshot.strings.clone();
+        self.steps = snapshot.steps;
+        self.cycles = snapshot.cycles;
+        self.call_depth = snapshot.call_depth;
+        self.output_bytes = snapshot.output_bytes;
+        self.input_tokens = snapshot.input_tokens.clone();
+        match &snapshot.program {
+            Some(program) => {
+                let loaded = self.loaded_program.take().unwrap_or_else(|| self.program.clone());
+                self.set_program(program.clone());
+                self.loaded_program = Some(loaded);
+            },
+            None => self.restore_code(),
+        }
+        self.history.clear();
+        self.recent.clear();
+        if let Some(checker) = &mut self.stack_checker {
+            checker.trust_all();
+        }
+        self.shadow_stack.clear();
+        self.clear_slot_tags();
+        self.reset_loop_detector();
+        self.checkpoints.retain(|checkpoint| checkpoint.steps <= snapshot.steps);
+
+        Ok(())
+    }
+
+    /// Gets the checkpoints taken automatically, from the oldest.
+    ///
+    /// See [`Config::checkpoint`] to enable checkpoints.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Config, CheckpointPolicy, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///     let config = Config {
+    ///         checkpoint: Some(CheckpointPolicy { interval: 1000, capacity: 3 }),
+    ///         ..Config::default()
+    ///     };
+    ///
+    ///     let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+    ///
+    ///     // Overflows the stack after 20000 steps
+    ///     vm.load(Cursor::new(b"
+    ///         loop:
+    ///             pushi 1
+    ///             jp loop"))?;
+    ///     assert!(matches!(vm.run_until_halt(), Err(Error::StackOverflow)));
+    ///
+    ///     let steps: Vec<u64> = vm.checkpoints().map(|checkpoint| checkpoint.steps()).collect();
+    ///     assert_eq!(steps, vec![18000, 19000, 20000]);
+    ///
+    ///     let checkpoint = vm.checkpoints().next().unwrap().clone();
+    ///     vm.restore(&checkpoint)?;
+    ///     assert_eq!(vm.steps(), 18000);
+    ///     assert_eq!(vm.checkpoints().count(), 1);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn checkpoints(&self) -> impl DoubleEndedIterator<Item = &Snapshot> {
+        self.checkpoints.iter()
+    }
+
+    /// Gets a string referred to by a handle pushed by string opcodes (e.g. `pushs`).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidString`] if `handle` does not refer to a string.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     let code = Cursor::new(b"
+    ///         pushs \"foo\"
+    ///         pushs \"bar\"
+    ///         scat
+    ///         halt");
+    ///
+    ///     vm.load(code)?;
+    ///     vm.run_until_halt()?;
+    ///
+    ///     assert_eq!(vm.string(vm.stack()[0])?, "foobar");
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn string(&self, handle: i32) -> Result<&str, Error> {
+        self.strings.get(handle)
+    }
+
+    /// Gets a reference to the memory map of the VM.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Segment, VM_STACK_SIZE};
+    ///
+    /// fn main() {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     assert_eq!(vm.memory_map().find(VM_STACK_SIZE - 1), Some(Segment::Stack));
+    /// }
+    /// ```
+    pub fn memory_map(&self) -> &MemoryMap {
+        &self.memory_map
+    }
+
+    /// Replaces an instruction at an address of the instruction memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MemoryOutOfBound`] if `pc` is beyond the loaded code,
+    /// or [`Error::LabelNotFound`] if `call` refers to an undefined label
+    /// (unless [`Config::legacy_call`] is set).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error, Opcode};
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     vm.load(Cursor::new(b"pushi 3\nhalt\n"))?;
+    ///     vm.patch_instruction(0, Opcode::Pushi(5))?;
+    ///     vm.run_until_halt()?;
+    ///
+    ///     assert_eq!(vm.stack(), &[5]);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn patch_instruction(&mut self, pc: usize, inst: Opcode) -> Result<(), Error> {
+        if pc >= self.program.insts.len() {
+            return Err(Error::MemoryOutOfBound);
+        }
+
+        if !self.config.legacy_call {
+            check_call_targets(std::slice::from_ref(&inst), &self.program.labels)?;
+        }
+
+        self.replace_instruction(pc, inst);
+
+        Ok(())
+    }
+
+    /// Replaces an instruction without checks, and returns the old one.
+    fn replace_instruction(&mut self, pc: usize, inst: Opcode) -> Opcode {
+        self.code[pc] = Inst::new(&inst, &self.program.labels);
+        self.code_cycles[pc] = self.config.cycle_costs.cost(&inst);
+        let old = std::mem::replace(&mut self.program.insts[pc], inst);
+        self.blocks = blocks(&self.program);
+        self.program_usage = MemoryUsage::of_program(&self.program);
+        self.warnings = self.program.warnings();
+
+        old
+    }
+
+    /// Gets a reference to the configuration of the VM.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Config};
+    ///
+    /// fn main() {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     assert_eq!(vm.config(), &Config::default());
+    /// }
+    /// ```
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Gets a reference to the registers of the VM.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use picoc_vm::{PicocVm, Error};
+    /// use picoc_vm::VM_STACK_SIZE;
+    ///
+    /// fn main() -> Result<(), Error> {
+    ///     let mut input = Cursor::new(b"");
+    ///     let mut output = Cursor::new(Vec::new());
+    ///
+    ///     let mut vm = PicocVm::new(&mut input, &mut output);
+    ///
+    ///     let code = Cursor::new(b"
+    ///         __start__:
+    ///             call main
+    ///             halt
+    ///         main:
+    ///             enter
+    ///             pushi 2147483647
+    ///             leave
+    ///             ret");
+    ///
+    ///     vm.load(code)?;
+    ///
+    ///     for _ in 0..3 {
+    ///         println!("a");
+    ///         vm.step()?;
+    ///     }
+    ///
+    ///     let reg = vm.registers();
+    ///     assert_eq!(reg.pc, 4);
+    ///     assert_eq!(reg.sp, VM_STACK_SIZE - 3);
+    ///     assert_eq!(reg.fp, VM_STACK_SIZE - 2);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn registers(&self) -> &Registers {
+        &self.reg 
+    }
+
+    pub(crate) fn is_halted(&self) -> bool {
+        self.is_halted
+    }
+
+    /// Gets the data memory and the registers at once for native code.
+    #[cfg(feature = "jit")]
+    pub(crate) fn memory_and_registers(&mut self) -> (&mut [i32], &mut Registers) {
+        (&mut self.memory, &mut self.reg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::StackIssueKind;
+    use crate::config::{ExecutionLimits, LoadMode, OutputFormat};
+    use std::fs::File;
+    use std::time::Duration;
+    use std::io::{self, BufReader};
+
+    #[test]
+    fn load_assembly_code() -> Result<(), Error> {
+        let mut input = io::stdin().lock();
+        let mut output = io::stdout();
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        let code = File::open("test.out")?;
+        let code = BufReader::new(code);
+
+        vm.load(code)?;
+
+        assert_eq!(
+            vm.program.insts,
+            vec![
+                // __start__
+                Opcode::Call("main".to_string()),
+                Opcode::Halt,
+                // read()
+                Opcode::Enter,
+                Opcode::Rd,
+                Opcode::Storel(2),
+                Opcode::Leave,
+                Opcode::Ret,
+                // write()
+                Opcode::Enter,
+                Opcode::Pushl(3),
+                Opcode::Wr,
+                Opcode::Leave,
+                Opcode::Ret,
+                // writeln()
+                Opcode::Enter,
+                Opcode::Wrln,
+                Opcode::Leave,
+                Opcode::Ret,
+                // main()
+                Opcode::Enter,
+                Opcode::Pushi(1),
+                Opcode::Mvsp(-1),
+                Opcode::Call("write".to_string()),
+                Opcode::Storet(1),
+                Opcode::Mvsp(1),
+                Opcode::Mvsp(1),
+                Opcode::Mvsp(-1),
+                Opcode::Call("writeln".to_string()),
+                Opcode::Mvsp(1),
+                Opcode::Leave,
+                Opcode::Ret,
+            ]
+        );
+
+        assert_eq!(
+            vm.program.labels,
+            HashMap::from([
+                ("__start__".to_string(), 0),
+                ("read".to_string(), 2),
+                ("write".to_string(), 7),
+                ("writeln".to_string(), 12),
+                ("main".to_string(), 16),
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn arithmetic_operations() -> Result<(), Error> {
+        let mut input = io::stdin().lock();
+        let mut output = io::stdout();
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        // (3+2) * (8-2) / 5 = 6
+        let code = io::Cursor::new(b"
+            pushi 3
+            pushi 2
+            add
+            pushi 8
+            pushi 2
+            sub
+            mul
+            pushi 5
+            div
+        ");
+
+        vm.load(code)?;
+
+        while let Ok(()) = vm.step() {}
+
+        assert_eq!(vm.memory[VM_STACK_SIZE - 1], 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn comparison_operations() -> Result<(), Error> {
+        let mut input = io::stdin().lock();
+        let mut output = io::stdout();
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        let code_eq = io::Cursor::new(b"
+            pushi 10
+            pushi -1
+            eq
+        ");
+        let code_ne = io::Cursor::new(b"
+            pushi 1
+            pushi -1
+            ne
+        ");
+        let code_gt = io::Cursor::new(b"
+            pushi 4
+            pushi 4
+            gt
+        ");
+        let code_ge = io::Cursor::new(b"
+            pushi 4
+            pushi 4
+            ge
+        ");
+        let code_lt = io::Cursor::new(b"
+            pushi 10
+            pushi -1
+            lt
+        ");
+        let code_le = io::Cursor::new(b"
+            pushi -1
+            pushi 10
+            le
+        ");
+
+        vm.load(code_eq)?;
+        for _ in 0..3 {
+            vm.step()?;
+        }
+        assert_eq!(vm.memory[VM_STACK_SIZE - 1], 0); // 10 == -1 is false
+        vm.load(code_ne)?;
+        for _ in 0..3 {
+            vm.step()?;
+        }
+        assert_eq!(vm.memory[VM_STACK_SIZE - 1], 1); // 1 != -1 is true
+        vm.load(code_gt)?;
+        for _ in 0..3 {
+            vm.step()?;
+        }
+        assert_eq!(vm.memory[VM_STACK_SIZE - 1], 0); // 4 > 4 is false
+        vm.load(code_ge)?;
+        for _ in 0..3 {
+            vm.step()?;
+        }
+        assert_eq!(vm.memory[VM_STACK_SIZE - 1], 1); // 4 >= 4 is true
+        vm.load(code_lt)?;
+        for _ in 0..3 {
+            vm.step()?;
+        }
+        assert_eq!(vm.memory[VM_STACK_SIZE - 1], 0); // 10 < -1 is false
+        vm.load(code_le)?;
+        for _ in 0..3 {
+            vm.step()?;
+        }
+        assert_eq!(vm.memory[VM_STACK_SIZE - 1], 1); // -1 <= 10 is true
+
+        Ok(())
+    }
+
+    #[test]
+    fn io_operations() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"-123\n");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        let code = io::Cursor::new(b"
+            rd
+            wr
+            wrln
+        ");
+
+        vm.load(code)?;
+
+        for _ in 0..3 {
+            vm.step()?;
+        }
+
+        assert_eq!(output.get_ref(), b"? -123 \n");
+
+        Ok(())
+    }
+
+    struct FlushCounter {
+        buf: Vec<u8>,
+        flushed: Vec<usize>,
+    }
+
+    impl Write for FlushCounter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.buf.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed.push(self.buf.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn flush_policy() -> Result<(), Error> {
+        let code = b"
+            pushi 1
+            wr
+            pushi 2
+            wr
+            wrln
+            halt
+        ";
+        let policies = [
+            (FlushPolicy::EveryWrite, vec![2, 4, 5]),
+            (FlushPolicy::EveryLine, vec![5]),
+            (FlushPolicy::Manual, vec![]),
+        ];
+
+        for (policy, expected) in policies {
+            let mut input = io::Cursor::new(b"");
+            let mut output = FlushCounter { buf: Vec::new(), flushed: Vec::new() };
+
+            let config = Config {
+                flush_policy: policy,
+                ..Config::default()
+            };
+            let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+
+            vm.load(io::Cursor::new(code))?;
+            vm.run_until_halt()?;
+
+            assert_eq!(output.flushed, expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_format() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let config = Config {
+            output_format: OutputFormat {
+                separator: ",".to_string(),
+                width: 3,
+                newline: true,
+            },
+            ..Config::default()
+        };
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+
+        let code = io::Cursor::new(b"
+            pushi 7
+            wr
+            pushi -12
+            wr
+            halt
+        ");
+
+        vm.load(code)?;
+        vm.run_until_halt()?;
+
+        assert_eq!(output.get_ref(), b"  7,\n-12,\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn formatted_write() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        let code = io::Cursor::new(b"
+            pushi 42
+            wrf 5
+            pushi -7
+            wrz 4
+            pushi 123456
+            wrf 3
+            wrln
+            halt
+        ");
+
+        vm.load(code)?;
+        vm.run_until_halt()?;
+
+        assert_eq!(output.get_ref(), b"   42 -007 123456 \n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_tokens() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"3 5\n\n  7\n");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        let code = io::Cursor::new(b"
+            rdt
+            rdt
+            rdt
+            add
+            add
+            wr
+            rdt
+        ");
+
+        vm.load(code)?;
+
+        assert!(matches!(vm.run_until_halt(), Err(Error::NoMoreInput)));
+        assert_eq!(output.get_ref(), b"? ? ? 15 ? ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn prompt_output() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"1\n2\n");
+        let mut output = io::Cursor::new(Vec::new());
+        let mut prompt = io::Cursor::new(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+        vm.set_prompt_output(&mut prompt);
+
+        let code = io::Cursor::new(b"
+            rd
+            rdt
+            add
+            wr
+            halt
+        ");
+
+        vm.load(code)?;
+        vm.run_until_halt()?;
+
+        assert_eq!(output.get_ref(), b"3 ");
+        assert_eq!(prompt.get_ref(), b"? ? ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_prompt() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"1
+2
+");
+        let mut output = io::Cursor::new(Vec::new());
+        let config = Config { no_prompt: true, ..Config::default() };
+
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+
+        vm.load(io::Cursor::new(b"rd
+rdt
+add
+wr
+halt
+"))?;
+        vm.run_until_halt()?;
+
+        assert_eq!(output.get_ref(), b"3 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn echo_input() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"4\n5 6\r\n");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let config = Config {
+            echo_input: true,
+            ..Config::default()
+        };
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+
+        let code = io::Cursor::new(b"
+            rd
+            rdt
+            rdt
+            add
+            add
+            wr
+            wrln
+            halt
+        ");
+
+        vm.load(code)?;
+        vm.run_until_halt()?;
+
+        assert_eq!(output.get_ref(), b"? 4\n? 5 6\n15 \n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn heap_operations() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let config = Config {
+            heap_size: 8,
+            ..Config::default()
+        };
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+
+        // p = alloc(2); p[1] = 5; write(p[1] * 3); free(p); free(p);
+        let code = io::Cursor::new(b"
+            pushi 2
+            alloc
+            pushl -1
+            pushi 1
+            add
+            pushi 5
+            st
+            mvsp 1
+            pushl -1
+            pushi 1
+            add
+            ld
+            pushi 3
+            mul
+            wr
+            pushl -1
+            free
+            pushl -1
+            free
+        ");
+
+        vm.load(code)?;
+
+        assert!(matches!(
+            vm.run_until_halt(),
+            Err(Error::DoubleFree(addr)) if addr == VM_STACK_SIZE as i64
+        ));
+        assert_eq!(vm.read_memory(VM_STACK_SIZE + 1)?, 5);
+
+        let code = io::Cursor::new(b"
+            pushi 9
+            alloc
+        ");
+
+        vm.load(code)?;
+
+        assert!(matches!(vm.run_until_halt(), Err(Error::OutOfMemory)));
+        assert_eq!(output.get_ref(), b"15 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn reference_cells() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let config = Config {
+            ref_limit: Some(4),
+            ..Config::default()
+        };
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+
+        // Builds a list 1 -> 2 -> 3 and prints the sum,
+        // then drops the list and allocates cells beyond the limit.
+        let code = io::Cursor::new(b"
+            enter
+            newref 2
+            pushi 3
+            setf 0
+            newref 2
+            pushi 2
+            setf 0
+            pushl -1
+            setf 1
+            newref 2
+            pushi 1
+            setf 0
+            pushl -2
+            setf 1
+            storel -1
+            mvsp 2
+            pushl -1
+            getf 0
+            pushl -1
+            getf 1
+            getf 0
+            add
+            pushl -1
+            getf 1
+            getf 1
+            getf 0
+            add
+            wr
+            pushi 0
+            storel -1
+            mvsp 1
+            newref 1
+            newref 1
+            halt
+        ");
+
+        vm.load(code)?;
+        vm.run_until_halt()?;
+
+        assert_eq!(vm.live_refs(), 2);
+
+        vm.load(io::Cursor::new(b"
+            newref 1
+            newref 1
+            newref 1
+            newref 1
+            newref 1
+        "))?;
+
+        assert!(matches!(vm.run_until_halt(), Err(Error::OutOfMemory)));
+        assert_eq!(output.get_ref(), b"6 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn string_operations() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        let code = io::Cursor::new("
+            pushs \"Hello, \"
+            pushs \"世界 # not a comment\\n\"
+            scat
+            wrs
+            pushs \"abc\"
+            pushs \"abd\"
+            scmp
+            wr
+            pushs \"\\\"é\\\"\"
+            slen
+            wr
+            pushs \"x\"
+            pushs \"x\"
+            scmp
+            wr
+            pushi 3
+            wrs
+        ".as_bytes());
+
+        vm.load(code)?;
+
+        assert!(matches!(vm.run_until_halt(), Err(Error::InvalidString(3))));
+        assert_eq!(
+            String::from_utf8(output.into_inner()).unwrap(),
+            "Hello, 世界 # not a comment\n-1 3 0 "
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_characters() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        let code = io::Cursor::new(b"
+            pushi 72
+            wrch
+            pushi 233
+            wrch
+            pushi 12354
+            wrch
+            pushi 128512
+            wrch
+            pushi 55296
+            wrch
+        ");
+
+        vm.load(code)?;
+
+        assert!(matches!(vm.run_until_halt(), Err(Error::InvalidCodePoint(55296))));
+
+        for code in ["pushi -1\nwrch\n", "pushi 1114112\nwrch\n"] {
+            vm.load(io::Cursor::new(code))?;
+
+            assert!(matches!(vm.run_until_halt(), Err(Error::InvalidCodePoint(_))));
+        }
+
+        assert_eq!(String::from_utf8(output.into_inner()).unwrap(), "H\u{e9}\u{3042}\u{1F600}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn call_function() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"10\n20\n");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        let code = io::Cursor::new(b"
+            __start__:
+                call main
+                halt
+            main:
+                enter
+                rd
+                rd
+                mvsp -1
+                call sum
+                storet 2
+                mvsp 2
+                wr
+                wrln
+                leave
+                ret
+            sum:
+                enter
+                pushl 4
+                pushl 3
+                add
+                storel 2
+                leave
+                ret
+        ");
+
+        vm.load(code)?;
+
+        while let Ok(()) = vm.step() {}
+
+        assert_eq!(output.get_ref(), b"? ? 30 \n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn execution_limits() -> Result<(), Error> {
+        let run = |code: &[u8], limits: ExecutionLimits| {
+            let mut input = io::Cursor::new(b"");
+            let mut output = Vec::new();
+            let config = Config { limits, ..Config::default() };
+            let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+
+            vm.load(io::Cursor::new(code))?;
+            vm.run_until_halt()
+        };
+        let recursion = b"f:\npushi 1\ncall f\n";
+
+        assert!(matches!(
+            run(recursion, ExecutionLimits { max_steps: Some(10), ..ExecutionLimits::default() }),
+            Err(Error::StepLimitExceeded(10))
+        ));
+        assert!(matches!(
+            run(recursion, ExecutionLimits { max_calls: Some(3), ..ExecutionLimits::default() }),
+            Err(Error::CallDepthExceeded(3, _))
+        ));
+        assert!(matches!(
+            run(recursion, ExecutionLimits { max_stack_depth: Some(5), ..ExecutionLimits::default() }),
+            Err(Error::StackLimitExceeded(5))
+        ));
+        assert!(matches!(
+            run(b"loop:\njp loop\n", ExecutionLimits {
+                max_time: Some(Duration::from_millis(10)),
+                ..ExecutionLimits::default()
+            }),
+            Err(Error::TimeLimitExceeded(_))
+        ));
+        run(b"call f\nhalt\nf:\nret\n", ExecutionLimits { max_calls: Some(1), ..ExecutionLimits::default() })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn call_depth_backtrace() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+        let limits = ExecutionLimits { max_calls: Some(4), ..ExecutionLimits::default() };
+        let config = Config { limits, ..Config::default() };
+
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+        vm.load(io::Cursor::new(b"
+            main:
+                call f
+                halt
+            f:
+                enter
+                call g
+                leave
+                ret
+            g:
+                enter
+                call g
+                leave
+                ret"))?;
+
+        let err = vm.run_until_halt().unwrap_err();
+        let Error::CallDepthExceeded(4, frames) = &err else {
+            panic!("unexpected error: {}", err);
+        };
+        let functions: Vec<_> = frames.iter().map(|frame| frame.function_label.as_deref()).collect();
+        assert_eq!(functions, [Some("g"), Some("g"), Some("g"), Some("f"), None]);
+        assert_eq!(err.to_string(), "Calls are nested deeper than 4 in g <- g <- g <- ...");
+        assert_eq!(vm.registers().pc, 7);
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_limit() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+        let config = Config {
+            limits: ExecutionLimits { max_output_bytes: Some(7), ..ExecutionLimits::default() },
+            flush_policy: FlushPolicy::EveryWrite,
+            ..Config::default()
+        };
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+
+        vm.load(io::Cursor::new(b"loop:\npushi 10\nwr\njp loop\n"))?;
+
+        assert!(matches!(vm.run_until_halt(), Err(Error::OutputLimitExceeded(7))));
+        drop(vm);
+        assert_eq!(output, b"10 10 1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_by_block() -> Result<(), Error> {
+        let code = b"
+                pushi 0
+            loop:
+                pushl -1
+                pushi 1
+                add
+                storet 1
+                mvsp 1
+                pushl -1
+                wr
+                pushl -1
+                pushi 5
+                lt
+                jt loop
+                halt
+        ";
+        let run = |config: Config| {
+            let mut input = io::Cursor::new(b"");
+            let mut output = Vec::new();
+            let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+            vm.load(io::Cursor::new(code)).unwrap();
+            let result = vm.run_until_halt().map_err(|err| err.to_string());
+            let recent: Vec<usize> = vm.recent_instructions().map(|(pc, _)| pc).collect();
+            let state = (vm.steps(), *vm.registers(), recent);
+            drop(vm);
+
+            (result, state, output)
+        };
+
+        // The history is recorded by every step
+        let by_step = Config { history_depth: 1, recent_depth: 3, ..Config::default() };
+        let by_block = Config { recent_depth: 3, ..Config::default() };
+
+        let (result, state, output) = run(by_block.clone());
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, b"1 2 3 4 5 ");
+        assert_eq!((result, state, output), run(by_step.clone()));
+
+        for max_steps in [1, 7, 12, 13] {
+            let limits = ExecutionLimits { max_steps: Some(max_steps), ..ExecutionLimits::default() };
+            let by_block = run(Config { limits, ..by_block.clone() });
+
+            assert_eq!(by_block.0, Err(Error::StepLimitExceeded(max_steps).to_string()));
+            assert_eq!(by_block, run(Config { limits, ..by_step.clone() }));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_limit() -> Result<(), Error> {
+        let run = |code: &[u8], extra: usize| {
+            let mut input = io::Cursor::new(b"");
+            let mut output = Vec::new();
+            let mut vm = PicocVm::new(&mut input, &mut output);
+            vm.load(io::Cursor::new(code))?;
+            let max = vm.memory_usage().total() + extra;
+
+            let config = Config {
+                limits: ExecutionLimits {
+                    max_steps: Some(10000),
+                    max_memory_bytes: Some(max),
+                    ..ExecutionLimits::default()
+                },
+                ..Config::default()
+            };
+            let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+            vm.load(io::Cursor::new(code))?;
+            vm.run_until_halt()?;
+
+            Ok(vm.memory_usage())
+        };
+
+        // Garbage is collected to keep the usage under the limit
+        assert!(matches!(
+            run(b"loop:\nnewref 100\nmvsp 1\njp loop\n", 4000),
+            Err(Error::StepLimitExceeded(10000))
+        ));
+        assert!(matches!(
+            run(b"loop:\nnewref 100\njp loop\n", 4000),
+            Err(Error::MemoryLimitExceeded(_))
+        ));
+        assert!(matches!(
+            run(b"pushs \"hello\"\npushs \"world\"\nscat\nhalt\n", 15),
+            Err(Error::MemoryLimitExceeded(_))
+        ));
+        assert_eq!(run(b"pushs \"hello\"\npushs \"world\"\nscat\nhalt\n", 20)?.strings, 20);
+
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+        let config = Config {
+            limits: ExecutionLimits { max_memory_bytes: Some(VM_STACK_SIZE * 4), ..ExecutionLimits::default() },
+            ..Config::default()
+        };
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+        assert!(matches!(vm.load(io::Cursor::new(b"halt\n")), Err(Error::MemoryLimitExceeded(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn step_delta() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        vm.load(io::Cursor::new(b"pushi 1\npushi 2\nstoret 1\nhalt\n"))?;
+        vm.step()?;
+        vm.step()?;
+
+        let delta = vm.step_with_delta()?;
+        assert_eq!(delta.before, Registers { pc: 2, ..delta.after });
+        assert_eq!(delta.writes, vec![MemoryWrite { addr: VM_STACK_SIZE - 1, old: 1, new: 2 }]);
+        assert!(delta.output.is_empty());
+
+        assert!(vm.step_with_delta()?.writes.is_empty());
+        assert!(matches!(vm.step_with_delta(), Err(Error::VmHalted)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn fall_off_end() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        let code = io::Cursor::new(b"
+            pushi 1
+            pushi 2
+        ");
+
+        vm.load(code)?;
+
+        assert!(matches!(vm.run_until_halt(), Err(Error::FellOffEnd)));
+        assert_eq!(vm.registers().pc, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fall_off_end_legacy() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let config = Config {
+            legacy_end: true,
+            ..Config::default()
+        };
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+
+        let code = io::Cursor::new(b"
+            pushi 1
+            pushi 2
+        ");
+
+        vm.load(code)?;
+        vm.run_until_halt()?;
+
+        assert_eq!(vm.stack(), &[2, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn undefined_call_target() {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        let code = io::Cursor::new(b"
+            call main
+            halt
+        ");
+
+        assert!(matches!(
+            vm.load(code),
+            Err(Error::LabelNotFound(label)) if label == "main"
+        ));
+    }
+
+    #[test]
+    fn undefined_call_target_legacy() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let config = Config {
+            legacy_call: true,
+            ..Config::default()
+        };
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+
+        let code = io::Cursor::new(b"
+            call main
+            halt
+        ");
+
+        vm.load(code)?;
+        vm.step()?;
+
+        assert_eq!(vm.registers().pc, 0);
+        assert_eq!(vm.stack(), &[1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mutate_state() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        let code = io::Cursor::new(b"
+            pushi 1
+            pushi 2
+            add
+            wr
+            halt
+        ");
+
+        vm.load(code)?;
+        vm.step()?;
+        vm.write_stack(VM_STACK_SIZE - 1, 10)?;
+        vm.patch_instruction(2, Opcode::Mul)?;
+        vm.run_until_halt()?;
+
+        vm.set_register(Register::Pc, 1)?;
+        vm.set_register(Register::Sp, VM_STACK_SIZE - 2)?;
+        vm.set_register(Register::Fp, VM_STACK_SIZE - 2)?;
+        assert_eq!(vm.read_stack(VM_STACK_SIZE - 2)?, 2);
+
+        assert!(matches!(vm.set_register(Register::Sp, VM_STACK_SIZE + 1), Err(Error::StackOutOfBound)));
+        assert!(matches!(vm.read_stack(VM_STACK_SIZE), Err(Error::StackOutOfBound)));
+        assert!(matches!(vm.patch_instruction(5, Opcode::Halt), Err(Error::MemoryOutOfBound)));
+        assert!(matches!(
+            vm.patch_instruction(0, Opcode::Call("none".to_string())),
+            Err(Error::LabelNotFound(_))
+        ));
+
+        assert_eq!(output.get_ref(), b"20 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn reload_code() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        let code = io::Cursor::new(b"
+            __start__:
+                call main
+                halt
+            main:
+                enter
+                pushi 1
+                wr
+                leave
+                ret
+        ");
+        let new_code = io::Cursor::new(b"
+            __start__:
+                call main
+                halt
+            subr:
+                ret
+            main:
+                enter
+                pushi 1
+                pushi 10
+                wr
+                wr
+                leave
+                ret
+        ");
+
+        vm.load(code)?;
+        for _ in 0..3 {
+            vm.step()?;
+        }
+        vm.reload_code(new_code)?;
+
+        assert_eq!(vm.registers().pc, 5);
+        assert_eq!(vm.stack(), &[1, VM_STACK_SIZE as i32, 1]);
+
+        vm.run_until_halt()?;
+
+        assert_eq!(vm.registers().pc, 1);
+        assert_eq!(output.get_ref(), b"10 1 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn reload_code_removed_label() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        vm.load(io::Cursor::new(b"main:\npushi 1\nhalt\n"))?;
+        vm.step()?;
+
+        assert!(matches!(
+            vm.reload_code(io::Cursor::new(b"start:\npushi 1\nhalt\n")),
+            Err(Error::LabelNotFound(label)) if label == "main"
+        ));
+        assert_eq!(vm.label_table().get("main"), Some(&0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_append() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        vm.load(io::Cursor::new(b"
+                jp main
+            double:
+                pushi 2
+                mul
+                wr
+                halt
+        "))?;
+        vm.load_append(io::Cursor::new(b"
+            main:
+                pushi 21
+                jp double
+        "))?;
+
+        assert_eq!(vm.label_table().get("main"), Some(&5));
+        assert!(matches!(
+            vm.load_append(io::Cursor::new(b"call none\n")),
+            Err(Error::LabelNotFound(label)) if label == "none"
+        ));
+        assert_eq!(vm.inst_memory().len(), 7);
+
+        vm.run_until_halt()?;
+
+        assert_eq!(output.get_ref(), b"42 ");
+
+        Ok(())
+    }
+
+    #[test]
+    fn numeric_targets_are_not_labels() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+        let report = vm.load_with_report(io::Cursor::new(b"start:\npushi 1\njf 0\njp 4\nhalt\nhalt\n"))?;
+        assert_eq!(report.labels, 1);
+        assert_eq!(report.entry_label.as_deref(), Some("start"));
+        assert_eq!(vm.state().labels.len(), 1);
+
+        // A numeric target of appended code is shifted like its labels
+        vm.load_append(io::Cursor::new(b"jp 2\nwr\nhalt\n"))?;
+        assert_eq!(vm.program().insts()[5], Opcode::Jp("7".to_string()));
+        assert_eq!(vm.label_table().len(), 1);
 
-        assert_eq!(
-            vm.label_table,
-            HashMap::from([
-                ("__start__".to_string(), 0),
-                ("read".to_string(), 2),
-                ("write".to_string(), 7),
-                ("writeln".to_string(), 12),
-                ("main".to_string(), 16),
-            ])
-        );
+        Ok(())
+    }
+
+    #[test]
+    fn custom_opcodes() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = io::Cursor::new(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+
+        // `jz n` skips n instructions if the top is zero
+        vm.register_opcode("JZ", |operands| Ok(operands.join("").parse()?), |vm, n| {
+            if vm.pop()? == 0 {
+                let pc = vm.registers().pc + 1 + n as usize;
+                vm.set_register(Register::Pc, pc)?;
+            }
+            Ok(())
+        })?;
+        assert!(matches!(
+            vm.register_opcode("jz", |_| Ok(0), |_, _| Ok(())),
+            Err(Error::OpcodeAlreadyDefined(name)) if name == "jz"
+        ));
+        assert!(matches!(
+            vm.register_opcode("pushi", |_| Ok(0), |_, _| Ok(())),
+            Err(Error::OpcodeAlreadyDefined(_))
+        ));
+
+        vm.load(io::Cursor::new(b"
+            pushi 0
+            jz 2
+            pushi 1
+            wr
+            pushi 1
+            jz 1
+            pushi 2
+            wr
+            halt
+        "))?;
+
+        assert_eq!(vm.inst_memory()[1], Opcode::Custom("jz".to_string(), 2));
+
+        vm.run_until_halt()?;
+
+        assert_eq!(output.get_ref(), b"2 ");
 
         Ok(())
     }
 
     #[test]
-    fn arithmetic_operations() -> Result<(), Error> {
+    fn hostile_instructions() {
+        let run = |code: &[u8]| {
+            let mut input = io::Cursor::new(b"");
+            let mut output = Vec::new();
+            let mut vm = PicocVm::new(&mut input, &mut output);
+
+            vm.load(io::Cursor::new(code))?;
+            vm.run_until_halt()
+        };
+
+        assert!(matches!(run(b"pushi -1\nret\n"), Err(Error::MemoryOutOfBound)));
+        assert!(matches!(run(b"mvsp 2147483647\n"), Err(Error::InvalidStackPointer(_, VM_STACK_SIZE))));
+        assert!(matches!(run(b"mvsp -10001\n"), Err(Error::InvalidStackPointer(-1, VM_STACK_SIZE))));
+        assert!(matches!(run(b"storel 0\n"), Err(Error::StackUnderflow)));
+        assert!(matches!(run(b"storet 0\n"), Err(Error::StackUnderflow)));
+        assert!(matches!(run(b"pushi 1\nstoret 1\n"), Err(Error::StackOutOfBound)));
+        assert!(matches!(run(b"enter\npushi -5\nstorel 0\nleave\n"), Err(Error::StackOutOfBound)));
+        assert!(matches!(run(b"pushi 1\npushi 0\ndiv\n"), Err(Error::DivisionByZero)));
+        assert!(matches!(run(b"pushi 1\npushi 0\nmod\n"), Err(Error::DivisionByZero)));
+        assert!(run(b"pushi -2147483648\npushi -1\ndiv\npushi 2147483647\npushi 1\nadd\nhalt\n").is_ok());
+    }
+
+    #[test]
+    fn random_programs_do_not_panic() {
+        // xorshift, so that failures are reproducible
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move |n: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % n as u64) as usize
+        };
+        let values = [0, 1, -1, 2, 7, i32::MIN, i32::MAX, VM_STACK_SIZE as i32, -(VM_STACK_SIZE as i32)];
+
+        for _ in 0..500 {
+            let len = 1 + next(16);
+            let labels: HashMap<String, usize> = (0..len).map(|i| (format!("l{}", i), i)).collect();
+            let insts = (0..len).map(|_| {
+                let label = format!("l{}", next(len + 1));
+                match next(28) {
+                    0 => Opcode::Pushi(values[next(values.len())]),
+                    1 => Opcode::Pushl(values[next(values.len())]),
+                    2 => Opcode::Storel(values[next(values.len())]),
+                    3 => Opcode::Storet(values[next(values.len())]),
+                    4 => Opcode::Mvsp(values[next(values.len())]),
+                    5 => Opcode::Call(label),
+                    6 => Opcode::Ret,
+                    7 => Opcode::Enter,
+                    8 => Opcode::Leave,
+                    9 => Opcode::Jp(label),
+                    10 => Opcode::Jt(label),
+                    11 => Opcode::Jf(label),
+                    12 => Opcode::Add,
+                    13 => Opcode::Sub,
+                    14 => Opcode::Mul,
+                    15 => Opcode::Div,
+                    16 => Opcode::Mod,
+                    17 => Opcode::Rd,
+                    18 => Opcode::Wrch,
+                    19 => Opcode::Wrf(next(8)),
+                    20 => Opcode::Alloc,
+                    21 => Opcode::Free,
+                    22 => Opcode::Ld,
+                    23 => Opcode::St,
+                    24 => Opcode::Newref(next(4)),
+                    25 => Opcode::Getf(next(4)),
+                    26 => Opcode::Setf(next(4)),
+                    _ => Opcode::Halt,
+                }
+            }).collect();
+
+            let mut input = io::Cursor::new(b"3 -1 x");
+            let mut output = Vec::new();
+            let config = Config {
+                limits: ExecutionLimits { max_steps: Some(1000), ..ExecutionLimits::default() },
+                legacy_call: true,
+                ..Config::default()
+            };
+            let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+
+            // Only the absence of panics matters here
+            if vm.load_program(Program::new(insts, labels)).is_ok() {
+                let _ = vm.run_until_halt();
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown opcode 'hoge' is found")]
+    fn unknown_operation() {
         let mut input = io::stdin().lock();
         let mut output = io::stdout();
 
         let mut vm = PicocVm::new(&mut input, &mut output);
 
-        // (3+2) * (8-2) / 5 = 6
-        let code = io::Cursor::new(b"
+        let code = io::Cursor::new(b"hoge");
+
+        vm.load(code).unwrap_or_else(|err| {
+            panic!("{}", err.to_string());
+        });
+    }
+
+    #[test]
+    fn relative_jumps() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+        // Writes 3 2 1 by a loop without labels
+        vm.load(io::Cursor::new(b"
+            enter
             pushi 3
-            pushi 2
+            pushl -1
+            wr
+            pushl -1
+            pushi -1
             add
-            pushi 8
-            pushi 2
-            sub
-            mul
-            pushi 5
-            div
-        ");
+            storel -1
+            jrt -6
+            jr +2
+            wr
+            halt
+        "))?;
+        vm.run_until_halt()?;
 
-        vm.load(code)?;
+        assert_eq!(output, b"3 2 1 ");
+        assert_eq!(Opcode::Jrt(-4).to_string(), "jrt -4");
 
-        while let Ok(()) = vm.step() {}
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+        let mut vm = PicocVm::new(&mut input, &mut output);
+        assert!(matches!(
+            vm.load(io::Cursor::new(b"pushi 1\njr 2\n")).as_ref().map_err(Error::inner),
+            Err(Error::TargetOutOfBound(3))
+        ));
 
-        assert_eq!(vm.stack[VM_STACK_SIZE - 1], 6);
+        Ok(())
+    }
+
+    #[test]
+    fn push_registers() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+        vm.load(io::Cursor::new(b"pushi 0\nenter\npushpc\npushsp\npushfp\nhalt\n"))?;
+        vm.run_until_halt()?;
+
+        let fp = VM_STACK_SIZE as i32 - 2;
+        assert_eq!(vm.stack(), &[fp, fp - 1, 2, VM_STACK_SIZE as i32, 0]);
 
         Ok(())
     }
 
     #[test]
-    fn comparison_operations() -> Result<(), Error> {
-        let mut input = io::stdin().lock();
-        let mut output = io::stdout();
+    fn stack_allocation() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
 
         let mut vm = PicocVm::new(&mut input, &mut output);
+        vm.load(io::Cursor::new(b"pushi 3\nsalloc\npushi 42\nst\nhalt\n"))?;
+        vm.run_until_halt()?;
 
-        let code_eq = io::Cursor::new(b"
-            pushi 10
-            pushi -1
-            eq
-        ");
-        let code_ne = io::Cursor::new(b"
-            pushi 1
-            pushi -1
-            ne
-        ");
-        let code_gt = io::Cursor::new(b"
-            pushi 4
-            pushi 4
-            gt
-        ");
-        let code_ge = io::Cursor::new(b"
-            pushi 4
-            pushi 4
-            ge
-        ");
-        let code_lt = io::Cursor::new(b"
-            pushi 10
-            pushi -1
-            lt
-        ");
-        let code_le = io::Cursor::new(b"
-            pushi -1
-            pushi 10
-            le
-        ");
+        // The words are not cleared
+        assert_eq!(vm.stack(), &[42, 42, 0, 3]);
 
-        vm.load(code_eq)?;
-        for _ in 0..3 {
-            vm.step()?;
-        }
-        assert_eq!(vm.stack[VM_STACK_SIZE - 1], 0); // 10 == -1 is false
-        vm.load(code_ne)?;
-        for _ in 0..3 {
-            vm.step()?;
-        }
-        assert_eq!(vm.stack[VM_STACK_SIZE - 1], 1); // 1 != -1 is true
-        vm.load(code_gt)?;
-        for _ in 0..3 {
-            vm.step()?;
-        }
-        assert_eq!(vm.stack[VM_STACK_SIZE - 1], 0); // 4 > 4 is false
-        vm.load(code_ge)?;
-        for _ in 0..3 {
-            vm.step()?;
-        }
-        assert_eq!(vm.stack[VM_STACK_SIZE - 1], 1); // 4 >= 4 is true
-        vm.load(code_lt)?;
-        for _ in 0..3 {
-            vm.step()?;
-        }
-        assert_eq!(vm.stack[VM_STACK_SIZE - 1], 0); // 10 < -1 is false
-        vm.load(code_le)?;
-        for _ in 0..3 {
-            vm.step()?;
+        for (size, err) in [(VM_STACK_SIZE as i32, Error::StackOverflow), (-1, Error::InvalidAllocationSize(-1))] {
+            let code = format!("pushi {}\nsalloc\nhalt\n", size);
+            let mut input = io::Cursor::new(b"");
+            let mut output = Vec::new();
+            let mut vm = PicocVm::new(&mut input, &mut output);
+            vm.load(code.as_bytes())?;
+
+            assert_eq!(vm.run_until_halt().unwrap_err().to_string(), err.to_string());
         }
-        assert_eq!(vm.stack[VM_STACK_SIZE - 1], 1); // -1 <= 10 is true
 
         Ok(())
     }
 
     #[test]
-    fn io_operations() -> Result<(), Error> {
-        let mut input = io::Cursor::new(b"-123\n");
-        let mut output = io::Cursor::new(Vec::new());
+    fn writable_code() -> Result<(), Error> {
+        let word = Opcode::Pushi(7).to_word(&HashMap::new()).unwrap();
+        let code = format!("pushi 3\npushi {}\nstorei\npushi 1\nwr\nhalt\n", word);
+
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+        let config = Config { writable_code: true, history_depth: 4, ..Config::default() };
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+        vm.load(code.as_bytes())?;
+        vm.run_until_halt()?;
 
+        assert_eq!(vm.program().insts()[3], Opcode::Pushi(7));
+        for _ in 0..4 {
+            vm.undo_step();
+        }
+        assert_eq!(vm.program().insts()[3], Opcode::Pushi(1));
+        assert_eq!(output, b"7 ");
+
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
         let mut vm = PicocVm::new(&mut input, &mut output);
+        vm.load(code.as_bytes())?;
+        assert!(matches!(vm.run_until_halt(), Err(Error::CodeNotWritable)));
 
-        let code = io::Cursor::new(b"
-            rd
-            wr
-            wrln
-        ");
+        assert!(matches!(Opcode::from_word(5 << 24 | 1), Err(Error::InvalidInstructionWord(_))));
 
-        vm.load(code)?;
+        Ok(())
+    }
 
-        for _ in 0..3 {
-            vm.step()?;
-        }
+    #[test]
+    fn rewritten_code_is_restored() -> Result<(), Error> {
+        // The program rewrites its first instruction into `pushi 2`
+        let word = Opcode::Pushi(2).to_word(&HashMap::new()).unwrap();
+        let code = format!("pushi 1\nwr\npushi 0\npushi {}\nstorei\nhalt\n", word);
 
-        assert_eq!(output.get_ref(), b"? -123 \n");
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+        let config = Config { writable_code: true, ..Config::default() };
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config.clone());
+        vm.load(code.as_bytes())?;
+        vm.run_until_halt()?;
+        assert_eq!(vm.program().insts()[0], Opcode::Pushi(2));
+        let rewritten = vm.snapshot();
+
+        vm.reset();
+        assert_eq!(vm.program().insts()[0], Opcode::Pushi(1));
+        let loaded = vm.snapshot();
+
+        vm.restore(&rewritten)?;
+        assert_eq!(vm.program().insts()[0], Opcode::Pushi(2));
+        vm.restore(&loaded)?;
+        assert_eq!(vm.program().insts()[0], Opcode::Pushi(1));
+
+        vm.run_until_halt()?;
+        assert_eq!(output, b"1 1 ");
+
+        let program = Program::assemble(code.as_bytes())?;
+        let reports = crate::judge::Judge::new(config).run_inputs(&program, &["", ""]);
+        assert_eq!(reports[1].output, "1 ");
 
         Ok(())
     }
 
     #[test]
-    fn call_function() -> Result<(), Error> {
-        let mut input = io::Cursor::new(b"10\n20\n");
-        let mut output = io::Cursor::new(Vec::new());
+    fn permissive_unknown_operation() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"0\n1\n");
+        let mut output = Vec::new();
+        let config = Config { load_mode: LoadMode::Permissive, no_prompt: true, ..Config::default() };
 
-        let mut vm = PicocVm::new(&mut input, &mut output);
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+        vm.load(io::Cursor::new(b"rd\njt ext\npushi 1\nwr\nhalt\next:\nvext 1 2\nhalt\n"))?;
+        vm.run_until_halt()?;
 
-        let code = io::Cursor::new(b"
-            __start__:
-                call main
-                halt
+        vm.reset();
+        assert!(matches!(vm.run_until_halt(), Err(Error::Trap(5, line)) if line == "vext 1 2"));
+        assert_eq!(vm.registers().pc, 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn uninitialized_stack_reads() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+        let config = Config { check_uninitialized: true, ..Config::default() };
+
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+        // g reads the slot where f has left 5
+        vm.load(io::Cursor::new(b"
             main:
+                call f
+                call g
+                call g
+                pushi 2
+                salloc
+                ld
+                halt
+            f:
                 enter
-                rd
-                rd
                 mvsp -1
-                call add
-                storet 2
-                mvsp 2
-                wr
-                wrln
+                pushi 5
+                storel -1
                 leave
                 ret
-            add:
+            g:
                 enter
-                pushl 4
-                pushl 3
-                add
+                mvsp -1
+                pushl -1
+                leave
+                ret"))?;
+        vm.run_until_halt()?;
+
+        let issues: Vec<_> = vm.stack_issues().iter().map(|issue| (issue.pc, issue.offset)).collect();
+        assert_eq!(issues, [(15, -1), (5, -2)]);
+
+        vm.reset();
+        assert!(vm.stack_issues().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn poisoned_frames() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+        let config = Config { poison_frames: true, ..Config::default() };
+
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+        // f returns the address of its local, and main reads a word popped by mvsp
+        vm.load(io::Cursor::new(b"
+            main:
+                mvsp -1
+                call f
+                ld
+                pushi 1
+                mvsp 1
+                pushl -2
+                halt
+            f:
+                enter
+                mvsp -1
+                pushi 7
+                storel -1
+                pushfp
+                pushi 1
+                sub
                 storel 2
                 leave
+                ret"))?;
+        vm.run_until_halt()?;
+
+        let issues: Vec<_> = vm.stack_issues().iter().map(|issue| (issue.pc, issue.kind)).collect();
+        assert_eq!(issues, [(2, StackIssueKind::Released), (5, StackIssueKind::Released)]);
+        assert_eq!(vm.stack(), &[1, 7]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn shadow_stack() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+        let config = Config { shadow_stack: true, ..Config::default() };
+
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+        // g stores its argument into the return address instead of the argument at FP+2
+        vm.load(io::Cursor::new(b"
+            main:
+                call f
+                halt
+            f:
+                enter
+                pushi 9
+                call g
+                mvsp 1
+                leave
                 ret
-        ");
+            g:
+                enter
+                pushi 0
+                storel 1
+                leave
+                ret"))?;
 
-        vm.load(code)?;
+        let slot = VM_STACK_SIZE - 4;
+        assert!(matches!(vm.run_until_halt(), Err(Error::ReturnAddressOverwritten(addr, 5, 0)) if addr == slot));
+        assert_eq!(vm.registers().pc, 12);
 
-        while let Ok(()) = vm.step() {}
+        // Return addresses popped by other than ret are not verified
+        vm.load(io::Cursor::new(b"
+            main:
+                call f
+                halt
+            f:
+                call g
+            g:
+                mvsp 1
+                ret"))?;
+        vm.run_until_halt()?;
 
-        assert_eq!(output.get_ref(), b"? ? 30 \n");
+        Ok(())
+    }
+
+    #[test]
+    fn deterministic_runs() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+        let config = Config {
+            deterministic: true,
+            limits: ExecutionLimits { max_time: Some(Duration::from_nanos(10)), ..ExecutionLimits::default() },
+            ..Config::default()
+        };
+
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config.clone());
+        vm.load(io::Cursor::new(b"loop:\njp loop\n"))?;
+
+        // The time limit is on the virtual clock, so the VM stops at the same step every time
+        for _ in 0..2 {
+            vm.reset();
+            assert!(matches!(vm.run_until_halt(), Err(Error::TimeLimitExceeded(_))));
+            assert_eq!(vm.steps(), 11);
+            assert_eq!(vm.elapsed(), Duration::from_nanos(11));
+        }
+
+        // The clock goes beyond u32::MAX nanoseconds
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+        let slow = Config {
+            cycle_costs: crate::cost::CycleCosts::parse("jp 1000000\n")?,
+            limits: ExecutionLimits { max_time: Some(Duration::from_secs(5)), ..ExecutionLimits::default() },
+            ..config
+        };
+        let mut vm = PicocVm::with_config(&mut input, &mut output, slow);
+        vm.load(io::Cursor::new(b"loop:\njp loop\n"))?;
+        assert!(matches!(vm.run_until_halt(), Err(Error::TimeLimitExceeded(_))));
+        assert_eq!(vm.steps(), 5001);
 
         Ok(())
     }
 
     #[test]
-    #[should_panic(expected = "Unknown opcode 'hoge' is found")]
-    fn unknown_operation() {
-        let mut input = io::stdin().lock();
-        let mut output = io::stdout();
+    fn slot_tags() -> Result<(), Error> {
+        let mut input = io::Cursor::new(b"");
+        let mut output = Vec::new();
+        let config = Config { tag_slots: true, ..Config::default() };
 
-        let mut vm = PicocVm::new(&mut input, &mut output);
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+        vm.load(io::Cursor::new(b"
+            main:
+                pushi 3
+                call f
+                wr
+                halt
+            f:
+                enter
+                pushl 2
+                pushi 1
+                add
+                storel 2
+                leave
+                ret"))?;
+        vm.run_until_halt()?;
 
-        let code = io::Cursor::new(b"hoge");
+        // g stores its argument into the return address instead of the argument at FP+2
+        vm.load(io::Cursor::new(b"
+            main:
+                pushi 9
+                call g
+                halt
+            g:
+                enter
+                pushi 0
+                storel 1
+                leave
+                ret"))?;
+        let err = vm.run_until_halt().unwrap_err();
+        assert!(matches!(err, Error::SlotTagMismatch(_, SlotTag::Data, addr) if addr == VM_STACK_SIZE - 2));
+        assert_eq!(err.to_string(), format!("'ret' consumes data at stack address {}", VM_STACK_SIZE - 2));
 
-        vm.load(code).unwrap_or_else(|err| {
-            panic!("{}", err.to_string());
-        });
+        // f returns without leave
+        vm.load(io::Cursor::new(b"
+            main:
+                call f
+                halt
+            f:
+                enter
+                ret"))?;
+        assert!(matches!(vm.run_until_halt(), Err(Error::SlotTagMismatch(_, SlotTag::SavedFp, _))));
+
+        // f adds its argument to the return address
+        vm.load(io::Cursor::new(b"
+            main:
+                pushi 2
+                call f
+                halt
+            f:
+                pushi 1
+                add
+                ret"))?;
+        let err = vm.run_until_halt().unwrap_err();
+        assert_eq!(err.to_string(), format!("'add' consumes a return address at stack address {}", VM_STACK_SIZE - 2));
+
+        Ok(())
     }
 
     #[test]
@@ -0,0 +1,156 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use crate::opcode::Opcode;
+use crate::program::Program;
+
+/// A kind of suspicious code, which is loaded but likely a mistake.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WarningKind {
+    /// A label is not the target of any jump or call.
+    UnusedLabel(String),
+    /// `mvsp` follows `leave`, which has already restored SP for `ret`.
+    MvspAfterLeave,
+    /// An instruction follows `halt` without a label, so it is never executed.
+    UnreachableAfterHalt,
+    /// A line with an unknown mnemonic is loaded as [`Opcode::Trap`], which fails if executed.
+    UnknownOpcode(String),
+}
+
+/// A non-fatal diagnostic of a program, found when it is loaded.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{Program, Error, WarningKind};
+///
+/// fn main() -> Result<(), Error> {
+///     let program = Program::assemble(Cursor::new(b"pushi 1\nhalt\nunused:\nwr\nhalt\n"))?;
+///     let warnings = program.warnings();
+///
+///     assert_eq!(warnings.len(), 1);
+///     assert_eq!(warnings[0].kind, WarningKind::UnusedLabel("unused".to_string()));
+///     assert_eq!(warnings[0].to_string(), "address 2: Label 'unused' is never used");
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Warning {
+    /// The address of the instruction.
+    pub addr: usize,
+    /// What is suspicious.
+    pub kind: WarningKind,
+}
+
+impl Display for WarningKind {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            WarningKind::UnusedLabel(name) => write!(f, "Label '{}' is never used", name),
+            WarningKind::MvspAfterLeave => write!(f, "mvsp after leave moves SP away from the return address"),
+            WarningKind::UnreachableAfterHalt => write!(f, "Instruction after halt is never executed"),
+            WarningKind::UnknownOpcode(line) => write!(f, "Unknown instruction '{}' fails if executed", line),
+        }
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "address {}: {}", self.addr, self.kind)
+    }
+}
+
+/// The functions which the compiler emits into every program, whether they are called or not.
+const BUILTIN_LABELS: [&str; 3] = ["read", "write", "writeln"];
+
+/// Finds the warnings of a program, ordered by address.
+///
+/// A label at the entry point (address 0), named `main`, or of a built-in function of the compiler
+/// is not reported as unused, nor is one referred to by a constant expression in an operand
+/// (e.g. `pushi end-start`).
+pub(crate) fn check(program: &Program) -> Vec<Warning> {
+    let insts = program.insts();
+    let mut warnings = Vec::new();
+
+    let targets: HashSet<&String> = insts.iter()
+        .filter_map(|inst| match inst {
+            Opcode::Call(label) | Opcode::Jp(label) | Opcode::Jt(label) | Opcode::Jf(label) => Some(label),
+            _ => None,
+        })
+        .chain(&program.debug_info().label_uses)
+        .collect();
+    for (name, &addr) in program.labels() {
+        if addr != 0 && name != "main" && !BUILTIN_LABELS.contains(&name.as_str()) && !targets.contains(name) {
+            warnings.push(Warning { addr, kind: WarningKind::UnusedLabel(name.clone()) });
+        }
+    }
+
+    for (addr, inst) in insts.iter().enumerate() {
+        // An explicit `trap` is not a mistake
+        match inst {
+            Opcode::Trap(line) if line != "trap" => {
+                warnings.push(Warning { addr, kind: WarningKind::UnknownOpcode(line.clone()) });
+            },
+            _ => (),
+        }
+    }
+
+    let relative_targets = insts.iter().enumerate()
+        .filter_map(|(addr, inst)| usize::try_from(inst.relative_target(addr)?).ok());
+    let labeled: HashSet<usize> = program.labeled_addresses().chain(relative_targets).collect();
+    for (addr, pair) in insts.windows(2).enumerate() {
+        let kind = match pair {
+            [Opcode::Leave, Opcode::Mvsp(_)] => WarningKind::MvspAfterLeave,
+            [Opcode::Halt, _] if !labeled.contains(&(addr + 1)) => WarningKind::UnreachableAfterHalt,
+            _ => continue,
+        };
+        warnings.push(Warning { addr: addr + 1, kind });
+    }
+
+    warnings.sort();
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_warnings() {
+        let code = b"
+            main:
+                call f
+                halt
+                wr
+            f:
+                enter
+                leave
+                mvsp 1
+                ret
+            skip:
+                halt
+            start:
+                pushi end-start
+            end:
+                halt
+        ";
+        let program = Program::assemble(&code[..]).unwrap();
+
+        assert_eq!(check(&program), [
+            Warning { addr: 2, kind: WarningKind::UnreachableAfterHalt },
+            Warning { addr: 5, kind: WarningKind::MvspAfterLeave },
+            Warning { addr: 7, kind: WarningKind::UnusedLabel("skip".to_string()) },
+        ]);
+
+        // Labels used only by an expression are not reported
+        let program = Program::assemble(&b".equ SIZE end-start\nstart:\npushi SIZE\nend:\nhalt\n"[..]).unwrap();
+        assert!(check(&program).is_empty());
+    }
+
+    #[test]
+    fn compiler_output() {
+        // `read` is emitted by the compiler but never called by `test.out`
+        let program = Program::assemble(&include_bytes!("../test.out")[..]).unwrap();
+        assert!(check(&program).is_empty());
+    }
+}
@@ -0,0 +1,692 @@
+use crate::config::Config;
+use crate::error::Error;
+use crate::opcode::Opcode;
+use crate::program::Program;
+use crate::vm::{VM_INST_MEMORY_SIZE, VM_STACK_SIZE};
+
+// Indices of imported functions
+const READ_INT: u32 = 0;
+const READ_TOKEN: u32 = 1;
+const WRITE_INT: u32 = 2;
+const WRITE_CHAR: u32 = 3;
+const WRITE: u32 = 4;
+const FAIL: u32 = 5;
+
+// Indices of locals
+const PC: u32 = 0;
+const SP: u32 = 1;
+const FP: u32 = 2;
+const T1: u32 = 3;
+const T2: u32 = 4;
+const V: u32 = 5;
+
+// Error codes passed to `fail`
+const STACK_OVERFLOW: i32 = 1;
+const STACK_UNDERFLOW: i32 = 2;
+const STACK_OUT_OF_BOUND: i32 = 3;
+const ADDRESS_OUT_OF_BOUND: i32 = 4;
+const DIVISION_BY_ZERO: i32 = 5;
+const FELL_OFF_END: i32 = 6;
+const MEMORY_OUT_OF_BOUND: i32 = 7;
+const INVALID_RETURN: i32 = 8;
+
+// Instructions of WebAssembly
+const UNREACHABLE: u8 = 0x00;
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0b;
+const BR: u8 = 0x0c;
+const BR_TABLE: u8 = 0x0e;
+const RETURN: u8 = 0x0f;
+const CALL: u8 = 0x10;
+const SELECT: u8 = 0x1b;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const LOCAL_TEE: u8 = 0x22;
+const I32_LOAD: u8 = 0x28;
+const I32_STORE: u8 = 0x36;
+const I32_CONST: u8 = 0x41;
+const I32_EQZ: u8 = 0x45;
+const I32_EQ: u8 = 0x46;
+const I32_NE: u8 = 0x47;
+const I32_LT_S: u8 = 0x48;
+const I32_GT_S: u8 = 0x4a;
+const I32_LE_S: u8 = 0x4c;
+const I32_GE_S: u8 = 0x4e;
+const I32_GT_U: u8 = 0x4b;
+const I32_GE_U: u8 = 0x4f;
+const I32_ADD: u8 = 0x6a;
+const I32_SUB: u8 = 0x6b;
+const I32_MUL: u8 = 0x6c;
+const I32_DIV_S: u8 = 0x6d;
+const I32_REM_S: u8 = 0x6f;
+const I32_REM_U: u8 = 0x70;
+const I32_SHL: u8 = 0x74;
+
+const I32: u8 = 0x7f;
+const EMPTY: u8 = 0x40;
+const PAGE_SIZE: usize = 65536;
+
+/// Compiles a program into a WebAssembly module.
+///
+/// The module exports a function `run` and its linear memory `memory`,
+/// where the word at address `a` of the VM is stored at byte `4 * a`.
+/// `run` behaves like [`PicocVm`](crate::PicocVm) configured by `config`
+/// except for the flush policy and echoing input, which are up to the host,
+/// and errors, which are reported by the codes below.
+///
+/// The module imports the following functions from `picoc`:
+///
+/// | Function | Type | Description |
+/// |---|---|---|
+/// | `read_int` | `() -> i32` | Reads a line as an integer for `rd`. |
+/// | `read_token` | `() -> i32` | Reads a token as an integer for `rdt`. |
+/// | `write_int` | `(value: i32, width: i32, zero_pad: i32)` | Writes a value right-aligned in a field. |
+/// | `write_char` | `(code_point: i32)` | Writes a character. |
+/// | `write` | `(ptr: i32, len: i32)` | Writes UTF-8 bytes in the memory. |
+/// | `fail` | `(code: i32)` | Reports an error, after which the module traps. |
+///
+/// The error codes passed to `fail` are:
+/// 1. stack overflow
+/// 2. stack underflow
+/// 3. SP out of bounds
+/// 4. address out of memory
+/// 5. division by zero
+/// 6. execution fell off the end of the program
+/// 7. PC out of bounds
+/// 8. `ret` to an address which is not next to a `call`
+///
+/// # Errors
+///
+/// Returns [`Error::LabelNotFound`] if a jump refers to an undefined label,
+/// or [`Error::UnsupportedInstruction`] if the program uses the heap, reference cells, or strings.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+/// use picoc_vm::{compile_wasm, Config, Error, Program};
+///
+/// fn main() -> Result<(), Error> {
+///     let program = Program::assemble(Cursor::new(b"pushi 5\nwr\nhalt\n"))?;
+///
+///     let module = compile_wasm(&program, &Config::default())?;
+///
+///     assert_eq!(&module[..4], b"\0asm");
+///
+///     Ok(())
+/// }
+/// ```
+pub fn compile_wasm(program: &Program, config: &Config) -> Result<Vec<u8>, Error> {
+    let memory_size = VM_STACK_SIZE + config.data_size + config.heap_size;
+
+    // Written after each value by `wr`
+    let format = &config.output_format;
+    let mut suffix = format.separator.clone();
+    if format.newline {
+        suffix.push('\n');
+    }
+    let suffix_ptr = memory_size * 4;
+    let pages = (suffix_ptr + suffix.len()).div_ceil(PAGE_SIZE);
+
+    let mut body = Function {
+        code: Vec::new(),
+        suffix: (suffix_ptr as i32, suffix.len() as i32),
+        format_width: format.width,
+        memory_size,
+        depth: 0,
+    };
+    body.translate(program, config)?;
+
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+
+    // Type section
+    let types: [&[u8]; 5] = [&[], &[I32, I32, I32], &[I32], &[I32, I32], &[]];
+    let results: [&[u8]; 5] = [&[I32], &[], &[], &[], &[]];
+    let mut section = Vec::new();
+    uleb(&mut section, types.len() as u64);
+    for (params, results) in types.iter().zip(results) {
+        section.push(0x60);
+        uleb(&mut section, params.len() as u64);
+        section.extend_from_slice(params);
+        uleb(&mut section, results.len() as u64);
+        section.extend_from_slice(results);
+    }
+    push_section(&mut module, 1, &section);
+
+    // Import section
+    let imports = [
+        ("read_int", 0),
+        ("read_token", 0),
+        ("write_int", 1),
+        ("write_char", 2),
+        ("write", 3),
+        ("fail", 2),
+    ];
+    let mut section = Vec::new();
+    uleb(&mut section, imports.len() as u64);
+    for (name, type_index) in imports {
+        push_name(&mut section, "picoc");
+        push_name(&mut section, name);
+        section.push(0x00);
+        uleb(&mut section, type_index);
+    }
+    push_section(&mut module, 2, &section);
+
+    // Function section
+    push_section(&mut module, 3, &[1, 4]);
+
+    // Memory section
+    let mut section = vec![1, 0x00];
+    uleb(&mut section, pages as u64);
+    push_section(&mut module, 5, &section);
+
+    // Export section
+    let mut section = vec![2];
+    push_name(&mut section, "run");
+    section.push(0x00);
+    uleb(&mut section, imports.len() as u64);
+    push_name(&mut section, "memory");
+    section.extend_from_slice(&[0x02, 0]);
+    push_section(&mut module, 7, &section);
+
+    // Code section
+    let mut function = vec![1];
+    uleb(&mut function, 6);
+    function.push(I32);
+    function.extend_from_slice(&body.code);
+    function.push(END);
+    let mut section = vec![1];
+    uleb(&mut section, function.len() as u64);
+    section.extend_from_slice(&function);
+    push_section(&mut module, 10, &section);
+
+    // Data section
+    let mut section = vec![1, 0x00, I32_CONST];
+    sleb(&mut section, suffix_ptr as i64);
+    section.push(END);
+    uleb(&mut section, suffix.len() as u64);
+    section.extend_from_slice(suffix.as_bytes());
+    push_section(&mut module, 11, &section);
+
+    Ok(module)
+}
+
+fn uleb(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn sleb(buf: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn push_name(buf: &mut Vec<u8>, name: &str) {
+    uleb(buf, name.len() as u64);
+    buf.extend_from_slice(name.as_bytes());
+}
+
+fn push_section(module: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    module.push(id);
+    uleb(module, contents.len() as u64);
+    module.extend_from_slice(contents);
+}
+
+/// The body of the `run` function.
+///
+/// Each basic block is placed after the end of a nested `block`,
+/// and a `br_table` in the innermost `block` jumps to the block which PC points to.
+struct Function {
+    code: Vec<u8>,
+    suffix: (i32, i32),
+    format_width: usize,
+    memory_size: usize,
+    /// The depth of the dispatch loop from the current basic block.
+    depth: u32,
+}
+
+impl Function {
+    fn translate(&mut self, program: &Program, config: &Config) -> Result<(), Error> {
+        let insts = program.insts();
+        let leaders = program.leaders()?;
+        let blocks: Vec<usize> = leaders.iter().copied().filter(|&addr| addr < insts.len()).collect();
+        let count = blocks.len() as u32;
+
+        self.i32_const(VM_STACK_SIZE as i32);
+        self.local(LOCAL_TEE, SP);
+        self.local(LOCAL_SET, FP);
+
+        self.code.extend_from_slice(&[LOOP, EMPTY]);
+        if config.legacy_end {
+            self.local(LOCAL_GET, PC);
+            self.i32_const(VM_INST_MEMORY_SIZE as i32);
+            self.code.push(I32_REM_U);
+            self.local(LOCAL_SET, PC);
+        }
+        // Beyond the program and a PC not pointing to a block
+        self.code.extend_from_slice(&[BLOCK, EMPTY, BLOCK, EMPTY]);
+        for _ in &blocks {
+            self.code.extend_from_slice(&[BLOCK, EMPTY]);
+        }
+
+        self.local(LOCAL_GET, PC);
+        self.code.push(BR_TABLE);
+        uleb(&mut self.code, insts.len() as u64);
+        for addr in 0..insts.len() {
+            let target = blocks.binary_search(&addr).map_or(count, |i| i as u32);
+            uleb(&mut self.code, target as u64);
+        }
+        uleb(&mut self.code, count as u64 + 1);
+
+        for (i, &start) in blocks.iter().enumerate() {
+            self.code.push(END);
+            self.depth = count - i as u32 + 1;
+
+            let end = blocks.get(i + 1).copied().unwrap_or(insts.len());
+            let mut terminated = false;
+            for (addr, inst) in insts.iter().enumerate().take(end).skip(start) {
+                terminated = self.translate_inst(program, addr, inst)?;
+            }
+            if !terminated {
+                self.jump(end);
+            }
+        }
+
+        // PC points to the middle of a block
+        self.code.push(END);
+        self.fail(INVALID_RETURN);
+
+        // PC is beyond the program
+        self.code.push(END);
+        if config.legacy_end {
+            self.code.push(RETURN);
+        } else {
+            self.local(LOCAL_GET, PC);
+            self.i32_const(insts.len() as i32);
+            self.code.push(I32_EQ);
+            self.fail_if(FELL_OFF_END);
+            self.fail(MEMORY_OUT_OF_BOUND);
+        }
+        self.code.push(END);
+
+        Ok(())
+    }
+
+    /// Translates an instruction and returns whether it leaves the basic block.
+    fn translate_inst(&mut self, program: &Program, addr: usize, inst: &Opcode) -> Result<bool, Error> {
+        let target = |label: &String| program.target(label).unwrap_or_default();
+        // The target is checked by `leaders`
+        let relative_target = || inst.relative_target(addr).unwrap_or_default() as usize;
+
+        match inst {
+            Opcode::Pushl(n) => {
+                self.stack_address(FP, *n);
+                self.load(T1);
+                self.local(LOCAL_SET, V);
+                self.push(V);
+            },
+            Opcode::Storel(n) | Opcode::Storet(n) => {
+                let base = if matches!(inst, Opcode::Storel(_)) { FP } else { SP };
+                self.check_not_empty();
+                self.stack_address(base, *n);
+                self.byte_address(T1);
+                self.load(SP);
+                self.code.extend_from_slice(&[I32_STORE, 2, 0]);
+            },
+            Opcode::Pushi(d) => {
+                self.i32_const(*d);
+                self.local(LOCAL_SET, V);
+                self.push(V);
+            },
+            Opcode::Pushpc => {
+                self.i32_const(addr as i32);
+                self.local(LOCAL_SET, V);
+                self.push(V);
+            },
+            Opcode::Pushsp => self.push(SP),
+            Opcode::Pushfp => self.push(FP),
+            Opcode::Call(label) => {
+                self.i32_const(addr as i32 + 1);
+                self.local(LOCAL_SET, V);
+                self.push(V);
+                self.jump(target(label));
+                return Ok(true);
+            },
+            Opcode::Ret => {
+                self.pop(PC);
+                self.code.push(BR);
+                uleb(&mut self.code, self.depth as u64);
+                return Ok(true);
+            },
+            Opcode::Enter => {
+                self.push(FP);
+                self.local(LOCAL_GET, SP);
+                self.local(LOCAL_SET, FP);
+            },
+            Opcode::Leave => {
+                self.local(LOCAL_GET, FP);
+                self.local(LOCAL_SET, SP);
+                self.pop(FP);
+                self.check_stack_pointer(FP);
+            },
+            Opcode::Mvsp(n) => {
+                self.local(LOCAL_GET, SP);
+                self.i32_const(*n);
+                self.code.push(I32_ADD);
+                self.local(LOCAL_SET, T1);
+                self.check_stack_pointer(T1);
+                self.local(LOCAL_GET, T1);
+                self.local(LOCAL_SET, SP);
+            },
+            Opcode::Jp(label) => {
+                self.jump(target(label));
+                return Ok(true);
+            },
+            Opcode::Jr(_) => {
+                self.jump(relative_target());
+                return Ok(true);
+            },
+            Opcode::Jt(_) | Opcode::Jf(_) | Opcode::Jrt(_) | Opcode::Jrf(_) => {
+                let taken_to = match inst {
+                    Opcode::Jt(label) | Opcode::Jf(label) => target(label),
+                    _ => relative_target(),
+                };
+                let (taken, not_taken) = if matches!(inst, Opcode::Jt(_) | Opcode::Jrt(_)) {
+                    (taken_to, addr + 1)
+                } else {
+                    (addr + 1, taken_to)
+                };
+                self.pop(V);
+                self.i32_const(taken as i32);
+                self.i32_const(not_taken as i32);
+                self.local(LOCAL_GET, V);
+                self.code.push(SELECT);
+                self.local(LOCAL_SET, PC);
+                self.code.push(BR);
+                uleb(&mut self.code, self.depth as u64);
+                return Ok(true);
+            },
+            Opcode::Add => self.binary(I32_ADD),
+            Opcode::Sub => self.binary(I32_SUB),
+            Opcode::Mul => self.binary(I32_MUL),
+            Opcode::Div => self.binary(I32_DIV_S),
+            Opcode::Mod => self.binary(I32_REM_S),
+            Opcode::Eq => self.binary(I32_EQ),
+            Opcode::Ne => self.binary(I32_NE),
+            Opcode::Gt => self.binary(I32_GT_S),
+            Opcode::Ge => self.binary(I32_GE_S),
+            Opcode::Lt => self.binary(I32_LT_S),
+            Opcode::Le => self.binary(I32_LE_S),
+            Opcode::Rd | Opcode::Rdt => {
+                self.call(if matches!(inst, Opcode::Rd) { READ_INT } else { READ_TOKEN });
+                self.local(LOCAL_SET, V);
+                self.push(V);
+            },
+            Opcode::Wr => self.write_field(self.format_width, false),
+            Opcode::Wrf(width) => self.write_field(*width, false),
+            Opcode::Wrz(width) => self.write_field(*width, true),
+            Opcode::Wrln => {
+                self.i32_const('\n' as i32);
+                self.call(WRITE_CHAR);
+            },
+            Opcode::Wrch => {
+                self.pop(V);
+                self.local(LOCAL_GET, V);
+                self.call(WRITE_CHAR);
+            },
+            Opcode::Ld => {
+                self.pop(T1);
+                self.check_data_address(T1);
+                self.load(T1);
+                self.local(LOCAL_SET, V);
+                self.push(V);
+            },
+            Opcode::St => {
+                self.pop(V);
+                self.pop(T1);
+                self.check_data_address(T1);
+                self.byte_address(T1);
+                self.local(LOCAL_GET, V);
+                self.code.extend_from_slice(&[I32_STORE, 2, 0]);
+                self.push(V);
+            },
+            Opcode::Halt => {
+                self.code.push(RETURN);
+                return Ok(true);
+            },
+            _ => return Err(Error::UnsupportedInstruction(inst.to_string())),
+        }
+
+        Ok(false)
+    }
+
+    fn local(&mut self, op: u8, index: u32) {
+        self.code.push(op);
+        uleb(&mut self.code, index as u64);
+    }
+
+    fn i32_const(&mut self, value: i32) {
+        self.code.push(I32_CONST);
+        sleb(&mut self.code, value as i64);
+    }
+
+    fn call(&mut self, func: u32) {
+        self.code.push(CALL);
+        uleb(&mut self.code, func as u64);
+    }
+
+    fn fail(&mut self, code: i32) {
+        self.i32_const(code);
+        self.call(FAIL);
+        self.code.push(UNREACHABLE);
+    }
+
+    /// Fails if the value on the top of the operand stack is true.
+    fn fail_if(&mut self, code: i32) {
+        self.code.extend_from_slice(&[IF, EMPTY]);
+        self.fail(code);
+        self.code.push(END);
+    }
+
+    fn jump(&mut self, target: usize) {
+        self.i32_const(target as i32);
+        self.local(LOCAL_SET, PC);
+        self.code.push(BR);
+        uleb(&mut self.code, self.depth as u64);
+    }
+
+    /// Pushes the byte address of a word address in a local.
+    fn byte_address(&mut self, local: u32) {
+        self.local(LOCAL_GET, local);
+        self.i32_const(2);
+        self.code.push(I32_SHL);
+    }
+
+    /// Pushes the word at an address in a local.
+    fn load(&mut self, local: u32) {
+        self.byte_address(local);
+        self.code.extend_from_slice(&[I32_LOAD, 2, 0]);
+    }
+
+    /// Sets `base + offset` to T1, checking that it is in the stack.
+    fn stack_address(&mut self, base: u32, offset: i32) {
+        self.local(LOCAL_GET, base);
+        self.i32_const(offset);
+        self.code.push(I32_ADD);
+        self.local(LOCAL_TEE, T1);
+        self.i32_const(VM_STACK_SIZE as i32);
+        self.code.push(I32_GE_U);
+        self.fail_if(STACK_OUT_OF_BOUND);
+    }
+
+    /// Fails unless a local is a valid SP or FP, i.e. within the stack or at its end.
+    fn check_stack_pointer(&mut self, local: u32) {
+        self.local(LOCAL_GET, local);
+        self.i32_const(VM_STACK_SIZE as i32);
+        self.code.push(I32_GT_U);
+        self.fail_if(STACK_OUT_OF_BOUND);
+    }
+
+    fn check_data_address(&mut self, local: u32) {
+        self.local(LOCAL_GET, local);
+        self.i32_const(self.memory_size as i32);
+        self.code.push(I32_GE_U);
+        self.fail_if(ADDRESS_OUT_OF_BOUND);
+    }
+
+    /// Pushes a value in a local onto the VM stack.
+    fn push(&mut self, local: u32) {
+        self.local(LOCAL_GET, SP);
+        self.i32_const(1);
+        self.code.push(I32_SUB);
+        self.local(LOCAL_TEE, T2);
+        self.i32_const(VM_STACK_SIZE as i32);
+        self.code.push(I32_GE_U);
+        self.fail_if(STACK_OVERFLOW);
+
+        self.byte_address(T2);
+        self.local(LOCAL_GET, local);
+        self.code.extend_from_slice(&[I32_STORE, 2, 0]);
+        self.local(LOCAL_GET, T2);
+        self.local(LOCAL_SET, SP);
+    }
+
+    /// Fails if the VM stack is empty.
+    fn check_not_empty(&mut self) {
+        self.local(LOCAL_GET, SP);
+        self.i32_const(VM_STACK_SIZE as i32);
+        self.code.push(I32_GE_U);
+        self.fail_if(STACK_UNDERFLOW);
+    }
+
+    /// Pops a value from the VM stack into a local.
+    fn pop(&mut self, local: u32) {
+        self.check_not_empty();
+        self.load(SP);
+        self.local(LOCAL_SET, local);
+        self.local(LOCAL_GET, SP);
+        self.i32_const(1);
+        self.code.push(I32_ADD);
+        self.local(LOCAL_SET, SP);
+    }
+
+    fn binary(&mut self, op: u8) {
+        self.pop(T1);
+        self.pop(V);
+        if op == I32_DIV_S || op == I32_REM_S {
+            self.local(LOCAL_GET, T1);
+            self.code.push(I32_EQZ);
+            self.fail_if(DIVISION_BY_ZERO);
+        }
+        if op == I32_DIV_S {
+            // `i32.div_s` traps on `i32::MIN / -1`, which wraps in the VM
+            self.local(LOCAL_GET, T1);
+            self.i32_const(-1);
+            self.code.push(I32_EQ);
+            self.code.extend_from_slice(&[IF, I32]);
+            self.i32_const(0);
+            self.local(LOCAL_GET, V);
+            self.code.push(I32_SUB);
+            self.code.push(ELSE);
+            self.local(LOCAL_GET, V);
+            self.local(LOCAL_GET, T1);
+            self.code.push(op);
+            self.code.push(END);
+        } else {
+            self.local(LOCAL_GET, V);
+            self.local(LOCAL_GET, T1);
+            self.code.push(op);
+        }
+        self.local(LOCAL_SET, V);
+        self.push(V);
+    }
+
+    fn write_field(&mut self, width: usize, zero_pad: bool) {
+        self.pop(V);
+        self.local(LOCAL_GET, V);
+        self.i32_const(width as i32);
+        self.i32_const(zero_pad as i32);
+        self.call(WRITE_INT);
+
+        let (ptr, len) = self.suffix;
+        if len > 0 {
+            self.i32_const(ptr);
+            self.i32_const(len);
+            self.call(WRITE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn leb128() {
+        let mut buf = Vec::new();
+        uleb(&mut buf, 624485);
+        assert_eq!(buf, [0xe5, 0x8e, 0x26]);
+
+        buf.clear();
+        sleb(&mut buf, -123456);
+        assert_eq!(buf, [0xc0, 0xbb, 0x78]);
+
+        buf.clear();
+        sleb(&mut buf, 64);
+        assert_eq!(buf, [0xc0, 0x00]);
+    }
+
+    #[test]
+    fn module_layout() {
+        let program = Program::assemble(io::Cursor::new(b"
+                rd
+                call subr
+                wr
+                halt
+            subr:
+                ret
+        ")).unwrap();
+
+        let module = compile_wasm(&program, &Config::default()).unwrap();
+
+        assert_eq!(&module[..8], b"\0asm\x01\0\0\0");
+        // Sections appear in order of their ids
+        let mut ids = Vec::new();
+        let mut rest = &module[8..];
+        while let [id, ..] = rest {
+            let (mut size, mut len) = (0, 1);
+            while rest[len] & 0x80 != 0 {
+                size |= ((rest[len] & 0x7f) as usize) << (7 * (len - 1));
+                len += 1;
+            }
+            size |= (rest[len] as usize) << (7 * (len - 1));
+            ids.push(*id);
+            rest = &rest[len + 1 + size..];
+        }
+        assert_eq!(ids, [1, 2, 3, 5, 7, 10, 11]);
+
+        let program = Program::assemble(io::Cursor::new(b"pushs \"a\"\n")).unwrap();
+        assert!(matches!(
+            compile_wasm(&program, &Config::default()),
+            Err(Error::UnsupportedInstruction(_))
+        ));
+    }
+}
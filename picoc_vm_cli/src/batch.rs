@@ -0,0 +1,116 @@
+use std::io::{self, BufReader};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use picoc_vm::{PicocVm, Config, Program};
+
+/// The result of running a program on an input file.
+enum Verdict {
+    /// The output matches the expected file.
+    Pass,
+    /// The output differs from the expected file at a line.
+    Fail(usize),
+    /// No expected file is found.
+    Unchecked,
+}
+
+/// Finds the first line where two outputs differ.
+fn first_difference(actual: &str, expected: &str) -> Option<usize> {
+    let mut actual_lines = actual.lines();
+    let mut expected_lines = expected.lines();
+
+    for line in 1.. {
+        match (actual_lines.next(), expected_lines.next()) {
+            (None, None) => return None,
+            (a, e) if a != e => return Some(line),
+            _ => (),
+        }
+    }
+
+    None
+}
+
+/// Finds input files (`*.in`) in a directory.
+fn input_files(dir: &Path) -> Result<Vec<PathBuf>, picoc_vm::Error> {
+    let mut inputs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "in") {
+            inputs.push(path);
+        }
+    }
+    inputs.sort();
+
+    Ok(inputs)
+}
+
+/// Runs a program once per input file (`NAME.in`) in a directory.
+///
+/// The output of each run is written to `NAME.actual`,
+/// and compared with `NAME.out` if it exists.
+/// Returns the number of failed runs.
+pub fn run_batch(program: &Program, dir: &str, config: &Config) -> Result<usize, picoc_vm::Error> {
+    let mut passed = 0;
+    let mut failed = 0;
+    let mut unchecked = 0;
+
+    eprintln!("{:<24} {:<32} result", "input", "status");
+    for input_path in input_files(Path::new(dir))? {
+        let mut input = BufReader::new(File::open(&input_path)?);
+        let mut output = Vec::new();
+        let mut prompt = io::sink();
+
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config.clone());
+        vm.set_prompt_output(&mut prompt);
+        vm.load_program(program.clone())?;
+        let status = match vm.run_until_halt().and_then(|()| vm.flush()) {
+            Ok(()) => "halted".to_string(),
+            Err(err) => format!("error: {}", err),
+        };
+        drop(vm);
+
+        let actual = String::from_utf8_lossy(&output);
+        fs::write(input_path.with_extension("actual"), actual.as_bytes())?;
+
+        let verdict = match fs::read_to_string(input_path.with_extension("out")) {
+            Ok(expected) => match first_difference(&actual, &expected) {
+                Some(line) => Verdict::Fail(line),
+                None => Verdict::Pass,
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Verdict::Unchecked,
+            Err(err) => return Err(err.into()),
+        };
+        let result = match verdict {
+            Verdict::Pass => {
+                passed += 1;
+                "pass".to_string()
+            },
+            Verdict::Fail(line) => {
+                failed += 1;
+                format!("FAIL (line {})", line)
+            },
+            Verdict::Unchecked => {
+                unchecked += 1;
+                "-".to_string()
+            },
+        };
+
+        let name = input_path.file_name().unwrap_or_default().to_string_lossy();
+        eprintln!("{:<24} {:<32} {}", name, status, result);
+    }
+    eprintln!("{} passed, {} failed, {} unchecked", passed, failed, unchecked);
+
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_outputs() {
+        assert_eq!(first_difference("1\n2\n", "1\n2\n"), None);
+        assert_eq!(first_difference("1\n2\n", "1\n3\n"), Some(2));
+        assert_eq!(first_difference("1\n", "1\n2\n"), Some(2));
+        assert_eq!(first_difference("1\n2\n", "1\n"), Some(2));
+    }
+}
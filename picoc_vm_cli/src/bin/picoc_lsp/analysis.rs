@@ -0,0 +1,123 @@
+use picoc_vm::{DefaultDialect, Dialect, Error, Opcode, Program, WarningKind};
+
+/// How serious a diagnostic is, numbered as `DiagnosticSeverity` of LSP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error = 1,
+    Warning = 2,
+}
+
+/// A problem found in a line of a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The line from 0.
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Splits each line of a document into words, or nothing if it cannot be split.
+fn split_lines(text: &str) -> Vec<Vec<String>> {
+    text.lines().map(|line| DefaultDialect.split_line(line).unwrap_or_default()).collect()
+}
+
+fn is_label(words: &[String]) -> bool {
+    words.get(1).is_some_and(|c| c == ":")
+}
+
+/// Finds the line (from 0) of each instruction.
+///
+/// An instruction is a line which is neither blank, a label, nor a directive, as the assembler counts.
+fn instruction_lines(text: &str) -> Vec<usize> {
+    split_lines(text).iter().enumerate()
+        .filter(|(_, words)| !words.is_empty() && !is_label(words) && !words[0].starts_with('.'))
+        .map(|(line, _)| line)
+        .collect()
+}
+
+/// Assembles a document and reports an error or warnings.
+pub fn diagnose(text: &str) -> Vec<Diagnostic> {
+    let program = match Program::assemble(text.as_bytes()) {
+        Ok(program) => program,
+        Err(Error::InSource(location, _, err)) => {
+            return vec![Diagnostic { line: location.line - 1, severity: Severity::Error, message: err.to_string() }];
+        },
+        Err(err) => return vec![Diagnostic { line: 0, severity: Severity::Error, message: err.to_string() }],
+    };
+
+    let lines = instruction_lines(text);
+    let line_of = |addr: usize| lines.get(addr).copied().unwrap_or_default();
+    let mut diagnostics = Vec::new();
+
+    // Undefined call targets are rejected when the program is loaded into a VM
+    for (addr, inst) in program.insts().iter().enumerate() {
+        if let Opcode::Call(label) = inst {
+            if program.target(label).is_none() {
+                let message = Error::LabelNotFound(label.clone()).to_string();
+                diagnostics.push(Diagnostic { line: line_of(addr), severity: Severity::Error, message });
+            }
+        }
+    }
+    for warning in program.warnings() {
+        // A label is reported at its own line rather than the instruction following it
+        let line = match &warning.kind {
+            WarningKind::UnusedLabel(name) => label_line(text, name).unwrap_or(line_of(warning.addr)),
+            _ => line_of(warning.addr),
+        };
+        diagnostics.push(Diagnostic { line, severity: Severity::Warning, message: warning.kind.to_string() });
+    }
+
+    diagnostics
+}
+
+/// Finds the line (from 0) where a label is defined.
+pub fn label_line(text: &str, name: &str) -> Option<usize> {
+    split_lines(text).iter().position(|words| is_label(words) && words[0] == name)
+}
+
+/// Lists the labels defined in a document.
+pub fn labels(text: &str) -> Vec<String> {
+    split_lines(text).into_iter()
+        .filter(|words| is_label(words))
+        .map(|mut words| words.swap_remove(0))
+        .collect()
+}
+
+/// Gets the word at a position (from 0) of a document.
+pub fn word_at(text: &str, line: usize, character: usize) -> Option<String> {
+    let chars: Vec<char> = text.lines().nth(line)?.chars().collect();
+    let is_word = |c: char| !c.is_whitespace() && c != ':' && c != '#';
+
+    let mut start = character.min(chars.len());
+    while start > 0 && is_word(chars[start - 1]) {
+        start -= 1;
+    }
+    let mut end = character.min(chars.len());
+    while end < chars.len() && is_word(chars[end]) {
+        end += 1;
+    }
+
+    (start < end).then(|| chars[start..end].iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn analyze_document() {
+        let text = "main:\n    pushi 1  # one\n    call f\n    halt\nunused:\n    wr\n";
+
+        assert_eq!(instruction_lines(text), [1, 2, 3, 5]);
+        assert_eq!(diagnose(text), [
+            Diagnostic { line: 2, severity: Severity::Error, message: "Label 'f' is not found".to_string() },
+            Diagnostic { line: 4, severity: Severity::Warning, message: "Label 'unused' is never used".to_string() },
+        ]);
+        assert_eq!(diagnose("pushi\n")[0].line, 0);
+        assert_eq!(label_line(text, "unused"), Some(4));
+        assert_eq!(labels(text), ["main", "unused"]);
+        assert_eq!(word_at(text, 2, 10).as_deref(), Some("f"));
+        assert_eq!(word_at(text, 1, 6).as_deref(), Some("pushi"));
+        assert_eq!(word_at(text, 1, 0), None);
+    }
+}
@@ -0,0 +1,226 @@
+//! A language server of picoc assembly, which talks LSP over stdin and stdout.
+//!
+//! It reports assembly errors and warnings of open documents,
+//! and provides go-to-definition of labels, hover of mnemonics, and completion.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::process;
+use picoc_vm::Opcode;
+use picoc_vm_cli::json::Json;
+
+mod analysis;
+
+use analysis::{diagnose, label_line, labels, word_at};
+
+/// `TextDocumentSyncKind.Full`: a change has the whole text of a document.
+const SYNC_FULL: usize = 1;
+/// `CompletionItemKind.Keyword`
+const KIND_KEYWORD: usize = 14;
+/// `CompletionItemKind.Reference`
+const KIND_REFERENCE: usize = 18;
+/// The JSON-RPC error of an unknown method.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Reads a message, or `None` at the end of the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let Some(len) = len else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Content-Length is not found"));
+    };
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+
+    Json::parse(&String::from_utf8_lossy(&body))
+        .map(Some)
+        .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Makes a range covering a whole line.
+fn line_range(line: usize) -> Json {
+    let position = |character: usize| Json::object([("line", line.into()), ("character", character.into())]);
+    // The end of a line is clamped by clients
+    Json::object([("start", position(0)), ("end", position(i32::MAX as usize))])
+}
+
+/// The state of the server: the texts of open documents.
+#[derive(Default)]
+struct Server {
+    documents: HashMap<String, String>,
+    is_shut_down: bool,
+}
+
+impl Server {
+    /// Handles a message, returning the messages to send.
+    fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or_default();
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let Some(id) = message.get("id") else {
+            return self.notify(method, params);
+        };
+
+        let result = match method {
+            "initialize" => Json::object([
+                ("capabilities", Json::object([
+                    ("textDocumentSync", SYNC_FULL.into()),
+                    ("definitionProvider", true.into()),
+                    ("hoverProvider", true.into()),
+                    ("completionProvider", Json::object([])),
+                ])),
+                ("serverInfo", Json::object([("name", "picoc_lsp".into())])),
+            ]),
+            "shutdown" => {
+                self.is_shut_down = true;
+                Json::Null
+            },
+            "textDocument/definition" => self.definition(params).unwrap_or(Json::Null),
+            "textDocument/hover" => self.hover(params).unwrap_or(Json::Null),
+            "textDocument/completion" => self.completion(params),
+            _ => {
+                let error = Json::object([
+                    ("code", METHOD_NOT_FOUND.into()),
+                    ("message", format!("Unknown method '{}'", method).into()),
+                ]);
+                return vec![Json::object([("jsonrpc", "2.0".into()), ("id", id.clone()), ("error", error)])];
+            },
+        };
+
+        vec![Json::object([("jsonrpc", "2.0".into()), ("id", id.clone()), ("result", result)])]
+    }
+
+    /// Handles a notification, returning diagnostics to publish.
+    fn notify(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let document = params.get("textDocument");
+        let Some(uri) = document.and_then(|doc| doc.get("uri")).and_then(Json::as_str) else {
+            if method == "exit" {
+                process::exit(if self.is_shut_down { 0 } else { 1 });
+            }
+            return Vec::new();
+        };
+
+        let text = match method {
+            "textDocument/didOpen" => document.and_then(|doc| doc.get("text")).and_then(Json::as_str),
+            "textDocument/didChange" => match params.get("contentChanges") {
+                Some(Json::Array(changes)) => changes.last().and_then(|change| change.get("text")).and_then(Json::as_str),
+                _ => None,
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![publish_diagnostics(uri, Vec::new())];
+            },
+            _ => None,
+        };
+        let Some(text) = text else {
+            return Vec::new();
+        };
+
+        let diagnostics = diagnose(text).into_iter()
+            .map(|diagnostic| Json::object([
+                ("range", line_range(diagnostic.line)),
+                ("severity", (diagnostic.severity as usize).into()),
+                ("source", "picoc".into()),
+                ("message", diagnostic.message.into()),
+            ]))
+            .collect();
+        self.documents.insert(uri.to_string(), text.to_string());
+
+        vec![publish_diagnostics(uri, diagnostics)]
+    }
+
+    /// Gets the text of a document and the word at the position of a request.
+    fn word<'a>(&'a self, params: &'a Json) -> Option<(&'a str, &'a str, String)> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        let text = self.documents.get(uri)?;
+        let position = params.get("position")?;
+        let line = position.get("line")?.as_u64()? as usize;
+        let character = position.get("character")?.as_u64()? as usize;
+
+        Some((uri, text, word_at(text, line, character)?))
+    }
+
+    fn definition(&self, params: &Json) -> Option<Json> {
+        let (uri, text, word) = self.word(params)?;
+        let line = label_line(text, &word)?;
+
+        Some(Json::object([("uri", uri.into()), ("range", line_range(line))]))
+    }
+
+    fn hover(&self, params: &Json) -> Option<Json> {
+        let (_, _, word) = self.word(params)?;
+        let doc = Opcode::doc(&word.to_lowercase())?;
+
+        Some(Json::object([("contents", Json::object([("kind", "markdown".into()), ("value", doc.into())]))]))
+    }
+
+    fn completion(&self, params: &Json) -> Json {
+        let mnemonics = Opcode::mnemonics().into_iter().map(|mnemonic| {
+            let summary = Opcode::doc(mnemonic).and_then(|doc| doc.lines().next().map(str::to_string));
+            Json::object([
+                ("label", mnemonic.into()),
+                ("kind", KIND_KEYWORD.into()),
+                ("detail", summary.unwrap_or_default().into()),
+            ])
+        });
+
+        let text = params.get("textDocument").and_then(|doc| doc.get("uri")).and_then(Json::as_str)
+            .and_then(|uri| self.documents.get(uri));
+        let labels = text.map(|text| labels(text)).unwrap_or_default().into_iter()
+            .map(|label| Json::object([("label", label.into()), ("kind", KIND_REFERENCE.into())]));
+
+        Json::Array(mnemonics.chain(labels).collect())
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        ("params", Json::object([("uri", uri.into()), ("diagnostics", diagnostics.into())])),
+    ])
+}
+
+fn main() {
+    let mut input = io::stdin().lock();
+    let mut output = io::stdout().lock();
+    let mut server = Server::default();
+
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                process::exit(1);
+            },
+        };
+
+        for reply in server.handle(&message) {
+            if let Err(err) = write_message(&mut output, &reply) {
+                eprintln!("error: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,320 @@
+//! An execution service of picoc programs, which talks JSON-RPC over stdin and stdout.
+//!
+//! Each line of the input is a request, and each line of the output is a response.
+//! The method `run` runs a program like this:
+//!
+//! ```text
+//! {"jsonrpc":"2.0","id":1,"method":"run","params":{"code":"rd\nwr\nhalt\n","input":"42\n","limits":{"max_steps":1000}}}
+//! ```
+//!
+//! and returns a report of the run (or an array of reports if `inputs` is given instead of `input`).
+//! Assembled programs are cached by their code, and VMs are kept for each configuration
+//! (i.e. each set of limits) and reset between runs, so a backend can keep one process for many submissions.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Cursor, Write};
+use std::process;
+use std::time::Duration;
+use picoc_vm::{Config, ExecutionLimits, ExitStatus, Judge, JudgeReport, PicocVm, Program};
+use picoc_vm_cli::json::Json;
+
+/// The JSON-RPC error of an invalid document.
+const PARSE_ERROR: i64 = -32700;
+/// The JSON-RPC error of an unknown method.
+const METHOD_NOT_FOUND: i64 = -32601;
+/// The JSON-RPC error of invalid parameters.
+const INVALID_PARAMS: i64 = -32602;
+/// The number of assembled programs kept, beyond which the least recently used one is dropped.
+const PROGRAM_CACHE_SIZE: usize = 64;
+/// The number of VMs kept, beyond which the least recently used one is dropped.
+const VM_POOL_SIZE: usize = 4;
+
+fn status_name(status: ExitStatus) -> &'static str {
+    match status {
+        ExitStatus::Halted => "halted",
+        ExitStatus::AssembleError => "assemble_error",
+        ExitStatus::RuntimeError => "runtime_error",
+        ExitStatus::LimitExceeded => "limit_exceeded",
+        ExitStatus::InfiniteLoop => "infinite_loop",
+    }
+}
+
+fn report_json(report: JudgeReport) -> Json {
+    Json::object([
+        ("output", report.output.into()),
+        ("status", status_name(report.status).into()),
+        ("steps", Json::Number(report.steps as f64)),
+        ("cycles", Json::Number(report.cycles as f64)),
+        ("max_stack", report.max_stack.into()),
+        ("error", report.error.map_or(Json::Null, Json::from)),
+    ])
+}
+
+fn response(id: Json, result: Result<Json, (i64, String)>) -> Json {
+    match result {
+        Ok(result) => Json::object([("jsonrpc", "2.0".into()), ("id", id), ("result", result)]),
+        Err((code, message)) => {
+            let error = Json::object([("code", code.into()), ("message", message.into())]);
+            Json::object([("jsonrpc", "2.0".into()), ("id", id), ("error", error)])
+        },
+    }
+}
+
+/// Reads the limits of a request over the defaults of [`Judge`].
+fn parse_limits(limits: Option<&Json>) -> Result<ExecutionLimits, String> {
+    let mut parsed = Judge::default().config.limits;
+    let Some(limits) = limits else {
+        return Ok(parsed);
+    };
+    let Json::Object(members) = limits else {
+        return Err("'limits' is not an object".to_string());
+    };
+
+    for (key, value) in members {
+        let n = value.as_u64().ok_or_else(|| format!("Limit '{}' is not a non-negative integer", key))?;
+        match key.as_str() {
+            "max_steps" => parsed.max_steps = Some(n),
+            "max_time_ms" => parsed.max_time = Some(Duration::from_millis(n)),
+            "max_stack_depth" => parsed.max_stack_depth = Some(n as usize),
+            "max_calls" => parsed.max_calls = Some(n as usize),
+            "max_output_bytes" => parsed.max_output_bytes = Some(n as usize),
+            "max_memory_bytes" => parsed.max_memory_bytes = Some(n as usize),
+            _ => return Err(format!("Unknown limit '{}'", key)),
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Assembled programs keyed by their code.
+#[derive(Default)]
+struct ProgramCache {
+    /// Each program with when it is used last.
+    programs: HashMap<String, (Program, u64)>,
+    clock: u64,
+}
+
+impl ProgramCache {
+    /// Assembles a program, or gets it from the cache.
+    fn get(&mut self, code: &str) -> Result<&Program, picoc_vm::Error> {
+        self.clock += 1;
+        if let Some((_, used)) = self.programs.get_mut(code) {
+            *used = self.clock;
+        } else {
+            let program = Program::assemble(code.as_bytes())?;
+            if self.programs.len() >= PROGRAM_CACHE_SIZE {
+                let oldest = self.programs.iter()
+                    .min_by_key(|(_, (_, used))| *used)
+                    .map(|(code, _)| code.clone());
+                if let Some(oldest) = oldest {
+                    self.programs.remove(&oldest);
+                }
+            }
+            self.programs.insert(code.to_string(), (program, self.clock));
+        }
+
+        Ok(&self.programs[code].0)
+    }
+}
+
+/// The streams lent to a pooled VM, which outlive the VMs.
+#[derive(Default)]
+struct Streams {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+type PooledVm<'a> = PicocVm<'a, Cursor<Vec<u8>>, Vec<u8>>;
+
+/// VMs kept across requests, each for a configuration.
+struct VmPool<'a> {
+    /// The streams which no VM uses.
+    free: Vec<(&'a mut Cursor<Vec<u8>>, &'a mut Vec<u8>)>,
+    /// VMs with the configuration of their judge, from the least recently used.
+    vms: Vec<(Config, PooledVm<'a>)>,
+}
+
+impl<'a> VmPool<'a> {
+    fn new(streams: &'a mut [Streams]) -> Self {
+        Self {
+            free: streams.iter_mut().map(|streams| (&mut streams.input, &mut streams.output)).collect(),
+            vms: Vec::new(),
+        }
+    }
+
+    /// Gets the VM for a configuration, creating it on the streams of the least recently used VM if needed.
+    fn get(&mut self, config: &Config) -> &mut PooledVm<'a> {
+        let index = match self.vms.iter().position(|(key, _)| key == config) {
+            Some(index) => index,
+            None => {
+                let (input, output) = match self.free.pop() {
+                    Some(streams) => streams,
+                    None => self.vms.remove(0).1.into_streams(),
+                };
+                // Prompts are not a part of the output of a judge
+                let vm = PicocVm::with_config(input, output, Config { no_prompt: true, ..config.clone() });
+                self.vms.push((config.clone(), vm));
+                self.vms.len() - 1
+            },
+        };
+        self.vms[index..].rotate_left(1);
+
+        &mut self.vms.last_mut().unwrap().1
+    }
+}
+
+/// The state of the server: the programs assembled and the VMs created so far.
+struct Server<'a> {
+    programs: ProgramCache,
+    vms: VmPool<'a>,
+}
+
+impl<'a> Server<'a> {
+    fn new(streams: &'a mut [Streams]) -> Self {
+        Self { programs: ProgramCache::default(), vms: VmPool::new(streams) }
+    }
+
+    /// Handles a line of the input, returning the response if it is a request.
+    fn handle(&mut self, line: &str) -> Option<Json> {
+        let message = match Json::parse(line) {
+            Ok(message) => message,
+            Err(message) => return Some(response(Json::Null, Err((PARSE_ERROR, message)))),
+        };
+        let id = message.get("id")?.clone();
+        let method = message.get("method").and_then(Json::as_str).unwrap_or_default();
+        let params = message.get("params").unwrap_or(&Json::Null);
+
+        let result = match method {
+            "run" => self.run(params).map_err(|message| (INVALID_PARAMS, message)),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+        };
+
+        Some(response(id, result))
+    }
+
+    fn run(&mut self, params: &Json) -> Result<Json, String> {
+        let code = params.get("code").and_then(Json::as_str).ok_or("'code' is not given")?;
+        let (inputs, batch) = match (params.get("input"), params.get("inputs")) {
+            (_, Some(Json::Array(inputs))) => {
+                let inputs = inputs.iter()
+                    .map(|input| input.as_str().ok_or("An input is not a string"))
+                    .collect::<Result<Vec<_>, _>>()?;
+                (inputs, true)
+            },
+            (_, Some(_)) => return Err("'inputs' is not an array".to_string()),
+            (Some(input), None) => (vec![input.as_str().ok_or("'input' is not a string")?], false),
+            (None, None) => (vec![""], false),
+        };
+        let judge = Judge::new(Config { limits: parse_limits(params.get("limits"))?, ..Config::default() });
+
+        let reports = match self.programs.get(code) {
+            Ok(program) => judge.run_inputs_on(self.vms.get(&judge.config), program, &inputs),
+            Err(err) => inputs.iter().map(|_| JudgeReport {
+                output: String::new(),
+                status: ExitStatus::AssembleError,
+                steps: 0,
+                cycles: 0,
+                max_stack: 0,
+                error: Some(err.to_string()),
+            }).collect(),
+        };
+        let mut reports: Vec<Json> = reports.into_iter().map(report_json).collect();
+
+        Ok(if batch { reports.into() } else { reports.remove(0) })
+    }
+}
+
+fn main() {
+    let input = io::stdin().lock();
+    let mut output = io::stdout().lock();
+    let mut streams: Vec<Streams> = (0..VM_POOL_SIZE).map(|_| Streams::default()).collect();
+    let mut server = Server::new(&mut streams);
+
+    for line in input.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                process::exit(1);
+            },
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(reply) = server.handle(&line) {
+            if let Err(err) = writeln!(output, "{}", reply).and_then(|()| output.flush()) {
+                eprintln!("error: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(server: &mut Server, params: &str) -> String {
+        let request = format!(r#"{{"jsonrpc":"2.0","id":7,"method":"run","params":{}}}"#, params);
+        server.handle(&request).unwrap().to_string()
+    }
+
+    #[test]
+    fn run_requests() {
+        let mut streams: Vec<Streams> = (0..VM_POOL_SIZE).map(|_| Streams::default()).collect();
+        let mut server = Server::new(&mut streams);
+
+        let reply = run(&mut server, r#"{"code":"rd\npushi 2\nmul\nwr\nhalt\n","input":"21\n"}"#);
+        assert_eq!(
+            reply,
+            r#"{"id":7,"jsonrpc":"2.0","result":{"cycles":5,"error":null,"max_stack":2,"output":"42 ","status":"halted","steps":5}}"#,
+        );
+
+        let reply = run(&mut server, r#"{"code":"rd\npushi 2\nmul\nwr\nhalt\n","inputs":["1\n","x\n"]}"#);
+        assert!(reply.contains(r#""output":"2 ","status":"halted""#));
+        assert!(reply.contains(r#""status":"runtime_error""#));
+        assert_eq!(server.programs.programs.len(), 1);
+
+        let reply = run(&mut server, r#"{"code":"loop:\njp loop\n","limits":{"max_steps":10}}"#);
+        assert!(reply.contains(r#""status":"limit_exceeded","steps":10"#));
+
+        let reply = run(&mut server, r#"{"code":"foo\n"}"#);
+        assert!(reply.contains(r#""status":"assemble_error""#));
+
+        let reply = run(&mut server, r#"{"code":"halt\n","limits":{"max_steps":-1}}"#);
+        assert!(reply.contains(r#""error":{"code":-32602,"message":"Limit 'max_steps' is not a non-negative integer"}"#));
+
+        let reply = server.handle(r#"{"jsonrpc":"2.0","id":1,"method":"stop"}"#).unwrap().to_string();
+        assert!(reply.contains("-32601"));
+        assert!(server.handle(r#"{"jsonrpc":"2.0","method":"run"}"#).is_none());
+        assert!(server.handle("{").unwrap().to_string().contains("-32700"));
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut cache = ProgramCache::default();
+        for i in 0..PROGRAM_CACHE_SIZE {
+            cache.get(&format!("pushi {}\nhalt\n", i)).unwrap();
+        }
+        // The first program is used again, so the second one is dropped instead
+        cache.get("pushi 0\nhalt\n").unwrap();
+        cache.get("halt\n").unwrap();
+
+        assert_eq!(cache.programs.len(), PROGRAM_CACHE_SIZE);
+        assert!(cache.programs.contains_key("pushi 0\nhalt\n"));
+        assert!(!cache.programs.contains_key("pushi 1\nhalt\n"));
+
+        let mut streams: Vec<Streams> = (0..2).map(|_| Streams::default()).collect();
+        let mut pool = VmPool::new(&mut streams);
+        let configs: Vec<Config> = (1..=3).map(|n| Config { data_size: n, ..Config::default() }).collect();
+        pool.get(&configs[0]);
+        pool.get(&configs[1]);
+        pool.get(&configs[0]);
+        // The VM of the second configuration lends its streams to the third
+        assert_eq!(pool.get(&configs[2]).config().data_size, 3);
+
+        let kept: Vec<usize> = pool.vms.iter().map(|(config, _)| config.data_size).collect();
+        assert_eq!(kept, [1, 3]);
+    }
+}
@@ -0,0 +1,366 @@
+use getopts::{Matches, Options};
+use crate::error::CliError;
+use crate::fmt::format_files;
+use crate::harness::run_tests;
+use crate::remote::serve;
+use crate::run::{asm_files, check_files, disasm_files, run_vm, STDIN_FILE};
+use crate::watch::run_watch;
+
+/// How an option takes its argument.
+#[derive(Clone, Copy)]
+pub enum OptArg {
+    /// No argument.
+    Flag,
+    /// One argument described by a hint (e.g. `FILE`).
+    Value(&'static str),
+    /// An argument which can be given repeatedly.
+    Multi(&'static str),
+}
+
+/// The definition of an option, from which both the parser and the completion scripts are made.
+pub struct OptSpec {
+    pub short: &'static str,
+    pub long: &'static str,
+    pub desc: &'static str,
+    pub arg: OptArg,
+}
+
+impl OptSpec {
+    pub const fn flag(short: &'static str, long: &'static str, desc: &'static str) -> Self {
+        Self { short, long, desc, arg: OptArg::Flag }
+    }
+
+    pub const fn value(short: &'static str, long: &'static str, desc: &'static str, hint: &'static str) -> Self {
+        Self { short, long, desc, arg: OptArg::Value(hint) }
+    }
+
+    pub const fn multi(short: &'static str, long: &'static str, desc: &'static str, hint: &'static str) -> Self {
+        Self { short, long, desc, arg: OptArg::Multi(hint) }
+    }
+
+    /// Gets the names of the option with dashes, e.g. `["-o"]` or `["--heap"]`.
+    pub fn names(&self) -> Vec<String> {
+        let short = (!self.short.is_empty()).then(|| format!("-{}", self.short));
+        let long = (!self.long.is_empty()).then(|| format!("--{}", self.long));
+
+        short.into_iter().chain(long).collect()
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        match self.arg {
+            OptArg::Flag => None,
+            OptArg::Value(hint) | OptArg::Multi(hint) => Some(hint),
+        }
+    }
+}
+
+/// Makes a parser of options.
+pub fn build_options(specs: &[OptSpec]) -> Options {
+    let mut opts = Options::new();
+    for spec in specs {
+        match spec.arg {
+            OptArg::Flag => opts.optflag(spec.short, spec.long, spec.desc),
+            OptArg::Value(hint) => opts.optopt(spec.short, spec.long, spec.desc, hint),
+            OptArg::Multi(hint) => opts.optmulti(spec.short, spec.long, spec.desc, hint),
+        };
+    }
+
+    opts
+}
+
+/// Parsed options of a command, with the flags implied by the command.
+#[derive(Clone)]
+pub struct Args {
+    matches: Matches,
+    implied: &'static [&'static str],
+}
+
+impl Args {
+    pub fn new(matches: Matches, implied: &'static [&'static str]) -> Self {
+        Self { matches, implied }
+    }
+
+    /// Returns whether a flag is given or implied.
+    ///
+    /// An option which the command does not define is never given.
+    pub fn flag(&self, name: &str) -> bool {
+        self.implied.contains(&name) || (self.matches.opt_defined(name) && self.matches.opt_present(name))
+    }
+
+    /// Gets the argument of an option.
+    pub fn value(&self, name: &str) -> Option<String> {
+        self.matches.opt_defined(name).then(|| self.matches.opt_str(name)).flatten()
+    }
+
+    /// Gets every argument of an option which can be given repeatedly.
+    pub fn values(&self, name: &str) -> Vec<String> {
+        if self.matches.opt_defined(name) { self.matches.opt_strs(name) } else { Vec::new() }
+    }
+
+    /// Gets the files given after the options.
+    pub fn files(&self) -> &[String] {
+        &self.matches.free
+    }
+}
+
+/// A subcommand of the CLI, e.g. `run` in `picoc_vm_cli run FILE`.
+pub struct Command {
+    pub name: &'static str,
+    pub summary: &'static str,
+    pub specs: Vec<OptSpec>,
+    /// Flags which are always set (e.g. `-r` for `debug`).
+    pub implied: &'static [&'static str],
+    pub action: fn(Args) -> Result<(), CliError>,
+}
+
+fn help_spec() -> OptSpec {
+    OptSpec::flag("h", "help", "print help and exit")
+}
+
+/// Options on how each FILE is read into a program.
+fn source_specs() -> Vec<OptSpec> {
+    vec![
+        OptSpec::value("", "compiler", "command compiling picoc source files (.pc, .c) into assembly (default: picoc)", "CMD"),
+        OptSpec::flag("", "emit-asm", "write the assembly compiled from each source FILE to FILE.s"),
+        OptSpec::flag("", "compat", "accept the listing format of the original picoc toolchain (addresses, ';' and '//' comments)"),
+        OptSpec::flag("", "aliases", "accept aliases of mnemonics in other VMs (e.g. jmp, push, end)"),
+        OptSpec::flag("", "deny-warnings", "fail when assembling FILE gives warnings"),
+        OptSpec::flag("", "permissive", "load unknown instructions as traps which fail only if executed"),
+    ]
+}
+
+/// Options on the VM and its input and output.
+fn vm_specs() -> Vec<OptSpec> {
+    vec![
+        OptSpec::flag("p", "", "write prompts to stderr"),
+        OptSpec::flag("q", "quiet", "do not write prompts of rd and rdt"),
+        OptSpec::flag("", "no-prompt", "same as --quiet"),
+        OptSpec::flag("e", "", "echo input values after prompts"),
+        OptSpec::value("i", "", "read the program input from FILE instead of stdin", "FILE"),
+        OptSpec::value("o", "", "write the program output to FILE instead of stdout", "FILE"),
+        OptSpec::value("", "heap", "size of the heap segment in words", "WORDS"),
+        OptSpec::value("", "base", "load the program at ADDR of the instruction memory", "ADDR"),
+        OptSpec::flag("", "writable-code", "allow storei to write instructions into the instruction memory"),
+        OptSpec::value("", "dump-state", "write the VM state as JSON to FILE when a runtime error occurs", "FILE"),
+        OptSpec::value("", "max-output", "abort when the program writes more than BYTES", "BYTES"),
+        OptSpec::value("", "max-calls", "abort when calls are nested deeper than DEPTH", "DEPTH"),
+        OptSpec::flag("", "check-uninit", "report reads of stack slots never written since they are allocated"),
+        OptSpec::flag("", "poison-frames", "report reads of stack slots released by leave or mvsp"),
+        OptSpec::flag("", "shadow-stack", "fail when ret pops a return address other than the one pushed by call"),
+        OptSpec::flag("", "tag-slots", "tag stack slots as data, saved FP, or return address, and fail when ret, leave, or arithmetic consumes a wrong one"),
+        OptSpec::flag("", "detect-loops", "fail when the registers and the top of the stack recur without I/O (a likely infinite loop)"),
+        OptSpec::flag("", "deterministic", "measure time limits in cycles, so runs stop at the same step"),
+        OptSpec::value("", "costs", "count simulated cycles with the costs of instructions in FILE (lines of MNEMONIC CYCLES)", "FILE"),
+        OptSpec::value("", "flush", "output flushing policy (write, line, manual)", "POLICY"),
+        OptSpec::value("", "radix", "radix of integers read by rd and rdt (2, 8, 10, 16)", "N"),
+    ]
+}
+
+fn trace_filter_specs() -> Vec<OptSpec> {
+    vec![
+        OptSpec::multi("", "trace-function", "restrict tracing and profiling to the instructions of a function", "LABEL"),
+        OptSpec::multi("", "trace-between", "restrict tracing and profiling to the instructions from START up to END", "START,END"),
+    ]
+}
+
+fn trace_specs() -> Vec<OptSpec> {
+    let mut specs = vec![
+        OptSpec::flag("r", "", "trace registers"),
+        OptSpec::flag("s", "", "trace stack"),
+        OptSpec::flag("", "explain", "describe what each executed instruction does in plain words"),
+        OptSpec::value("t", "", "write every executed instruction with registers to FILE (compressed by gzip if FILE ends with .gz)", "FILE"),
+    ];
+    specs.extend(trace_filter_specs());
+
+    specs
+}
+
+fn profile_specs() -> Vec<OptSpec> {
+    vec![
+        OptSpec::flag("", "profile", "count executed instructions and print a report by function to stderr"),
+        OptSpec::value("", "sample", "profile by recording PC once every N instructions", "N"),
+        OptSpec::value("", "html", "write an HTML report with coverage, profile, final stack, and I/O transcript to FILE", "FILE"),
+        OptSpec::value("", "chrome-trace", "write call spans and instruction counts per block of the last program as Chrome trace events to FILE", "FILE"),
+        OptSpec::flag("", "report", "print the instructions, max stack depth, calls, reads, writes, time, and exit status of each run to stderr"),
+        OptSpec::value("", "report-format", "format of --report: text or json (implies --report, default: text)", "FORMAT"),
+    ]
+}
+
+fn diff_spec() -> OptSpec {
+    OptSpec::flag("", "diff", "run two FILEs on the same input and report where they diverge (with -r/-s, compare traces)")
+}
+
+fn map_spec() -> OptSpec {
+    OptSpec::flag("", "map", "write the label table and the source location of each instruction to FILE.map")
+}
+
+#[cfg(feature = "jit")]
+fn jit_spec() -> OptSpec {
+    OptSpec::flag("", "jit", "compile the code into native code (tracing uses the interpreter)")
+}
+
+/// Defines the options accepted without a subcommand, which are the options of earlier versions.
+pub fn legacy_specs() -> Vec<OptSpec> {
+    let mut specs = vec![OptSpec::flag("d", "", "dump instruction memory")];
+    specs.extend(trace_specs());
+    specs.extend(profile_specs());
+    specs.extend(vm_specs());
+    specs.extend(source_specs());
+    specs.extend([
+        map_spec(),
+        OptSpec::value("", "batch", "run each FILE once per input DIR/NAME.in, comparing the output with DIR/NAME.out", "DIR"),
+        diff_spec(),
+        OptSpec::flag("", "watch", "run the FILEs again whenever any of them changes, clearing the screen"),
+        OptSpec::flag("", "transpile", "write each FILE as a Rust source file instead of running it"),
+        OptSpec::flag("", "wasm", "write each FILE as a WebAssembly module instead of running it"),
+        OptSpec::flag("", "binary", "write each FILE as a binary program (FILE.pcb) and its symbols (FILE.sym) instead of running it"),
+    ]);
+    #[cfg(feature = "jit")]
+    specs.push(jit_spec());
+    specs.push(help_spec());
+
+    specs
+}
+
+/// Runs the programs, or runs them again whenever they change with `--watch`.
+pub fn run_files(args: Args) -> Result<(), CliError> {
+    if args.flag("watch") {
+        if args.files().iter().any(|file| file == STDIN_FILE) {
+            return Err(CliError::Usage("--watch cannot read a program from stdin".to_string()));
+        }
+        run_watch(args);
+    }
+
+    run_vm(args)
+}
+
+fn trace_files(args: Args) -> Result<(), CliError> {
+    if !["r", "s", "explain", "profile"].iter().any(|name| args.flag(name)) && args.value("t").is_none() && args.value("sample").is_none() {
+        return Err(CliError::Usage("trace needs -r, -s, -t, --explain, --profile, or --sample".to_string()));
+    }
+
+    run_vm(args)
+}
+
+/// Defines the subcommands of the CLI.
+pub fn commands() -> Vec<Command> {
+    let mut run_specs = vm_specs();
+    run_specs.extend(source_specs());
+    run_specs.extend(profile_specs());
+    run_specs.extend([
+        map_spec(),
+        OptSpec::value("", "batch", "run each FILE once per input DIR/NAME.in, comparing the output with DIR/NAME.out", "DIR"),
+        diff_spec(),
+        OptSpec::flag("", "watch", "run the FILEs again whenever any of them changes, clearing the screen"),
+    ]);
+    #[cfg(feature = "jit")]
+    run_specs.push(jit_spec());
+    run_specs.push(help_spec());
+
+    let mut trace_specs = trace_specs();
+    trace_specs.extend(profile_specs());
+    trace_specs.extend(vm_specs());
+    trace_specs.extend(source_specs());
+    trace_specs.extend([diff_spec(), help_spec()]);
+
+    let mut debug_specs = trace_filter_specs();
+    debug_specs.push(OptSpec::flag("", "interactive", "stop for a command from stdin (step [N], continue, quit, history, !N, or empty to repeat) before every instruction; needs -i"));
+    debug_specs.extend(vm_specs());
+    debug_specs.extend(source_specs());
+    debug_specs.push(help_spec());
+
+    let mut check_specs = source_specs();
+    check_specs.push(help_spec());
+
+    let mut asm_specs = vec![
+        OptSpec::value("f", "format", "output format: binary (FILE.pcb and FILE.sym), rust (FILE.rs), or wasm (FILE.wasm)", "FORMAT"),
+        map_spec(),
+        OptSpec::value("", "heap", "size of the heap segment in words", "WORDS"),
+    ];
+    asm_specs.extend(source_specs());
+    asm_specs.push(help_spec());
+
+    let mut disasm_specs = vec![OptSpec::value("o", "", "write the assembly to FILE instead of stdout", "FILE")];
+    disasm_specs.extend(source_specs());
+    disasm_specs.push(help_spec());
+
+    let mut serve_specs = vec![
+        OptSpec::value("", "heap", "size of the heap segment in words", "WORDS"),
+        OptSpec::value("", "radix", "radix of integers read by rd and rdt (2, 8, 10, 16)", "N"),
+        OptSpec::flag("", "writable-code", "allow storei to write instructions into the instruction memory"),
+    ];
+    serve_specs.extend(source_specs());
+    serve_specs.push(help_spec());
+
+    let mut test_specs = vec![
+        OptSpec::flag("", "directives", "only run the #! input: and #! expect: directives in each FILE"),
+        OptSpec::value("", "max-steps", "fail a test executing more than N instructions (default: 10000000)", "N"),
+        OptSpec::value("", "timeout", "fail a test running longer than SECS seconds (default: 10)", "SECS"),
+        OptSpec::flag("", "deterministic", "measure the timeout in cycles (1 ns each) instead of the wall time"),
+        OptSpec::value("", "heap", "size of the heap segment in words", "WORDS"),
+        OptSpec::value("", "radix", "radix of integers read by rd and rdt (2, 8, 10, 16)", "N"),
+        OptSpec::flag("", "writable-code", "allow storei to write instructions into the instruction memory"),
+    ];
+    test_specs.extend(source_specs());
+    test_specs.push(help_spec());
+
+    let fmt_specs = vec![
+        OptSpec::flag("w", "write", "rewrite each FILE instead of writing to stdout"),
+        OptSpec::flag("", "check", "only check that each FILE is formatted"),
+        help_spec(),
+    ];
+
+    vec![
+        Command { name: "run", summary: "run programs", specs: run_specs, implied: &[], action: run_files },
+        Command {
+            name: "trace",
+            summary: "run programs, tracing or profiling the executed instructions",
+            specs: trace_specs,
+            implied: &[],
+            action: trace_files,
+        },
+        Command {
+            name: "debug",
+            summary: "run programs, dumping the instructions and showing the registers and the stack at every step",
+            specs: debug_specs,
+            implied: &["d", "r", "s"],
+            action: run_vm,
+        },
+        Command {
+            name: "check",
+            summary: "assemble programs and report errors without running them",
+            specs: check_specs,
+            implied: &[],
+            action: check_files,
+        },
+        Command {
+            name: "asm",
+            summary: "write programs as binary programs, Rust, or WebAssembly",
+            specs: asm_specs,
+            implied: &[],
+            action: asm_files,
+        },
+        Command {
+            name: "disasm",
+            summary: "write the instructions of programs as assembly",
+            specs: disasm_specs,
+            implied: &[],
+            action: disasm_files,
+        },
+        Command {
+            name: "test",
+            summary: "run each FILE (or *.s in a directory) against NAME.in and NAME.expected or #! directives",
+            specs: test_specs,
+            implied: &[],
+            action: run_tests,
+        },
+        Command { name: "fmt", summary: "format assembly files", specs: fmt_specs, implied: &[], action: format_files },
+        Command {
+            name: "serve",
+            summary: "serve VMs controlled by JSON messages over WebSocket at an address (e.g. 127.0.0.1:8080)",
+            specs: serve_specs,
+            implied: &[],
+            action: serve,
+        },
+    ]
+}
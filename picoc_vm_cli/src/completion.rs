@@ -0,0 +1,210 @@
+use crate::command::{Command, OptArg, OptSpec};
+
+/// Quotes a string for a shell, e.g. `it's` into `'it'\''s'`.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Makes a name usable in a shell function name, e.g. `picoc-vm` into `picoc_vm`.
+fn identifier(name: &str) -> String {
+    name.replace(['-', '.'], "_")
+}
+
+fn bash(program: &str, commands: &[Command], legacy: &[OptSpec]) -> String {
+    let all_specs = legacy.iter().chain(commands.iter().flat_map(|command| &command.specs));
+    let mut cases = String::new();
+    let mut seen = Vec::new();
+    for spec in all_specs {
+        let names = spec.names();
+        if seen.contains(&names) {
+            continue;
+        }
+        let action = match spec.hint() {
+            None => continue,
+            Some("FILE") => r#"COMPREPLY=($(compgen -f -- "$cur"))"#,
+            Some("DIR") => r#"COMPREPLY=($(compgen -d -- "$cur"))"#,
+            Some(_) => "COMPREPLY=()",
+        };
+        cases += &format!("        {})\n            {}\n            return;;\n", names.join("|"), action);
+        seen.push(names);
+    }
+
+    let words = |specs: &[OptSpec]| quote(&specs.iter().flat_map(OptSpec::names).collect::<Vec<_>>().join(" "));
+    let mut command_cases = String::new();
+    for command in commands {
+        command_cases += &format!("        {}) words={};;\n", command.name, words(&command.specs));
+    }
+    let names: Vec<&str> = commands.iter().map(|command| command.name).collect();
+    let function = format!("_{}", identifier(program));
+
+    format!(
+r#"{function}() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    local words
+
+    case "$prev" in
+{cases}    esac
+
+    case "${{COMP_WORDS[1]}}" in
+{command_cases}        *) words={legacy};;
+    esac
+
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "$words" -- "$cur"))
+    elif [[ $COMP_CWORD -eq 1 ]]; then
+        COMPREPLY=($(compgen -W {names} -- "$cur") $(compgen -f -- "$cur"))
+    else
+        COMPREPLY=($(compgen -f -- "$cur"))
+    fi
+}}
+complete -o filenames -F {function} {program}
+"#,
+        legacy = words(legacy),
+        names = quote(&names.join(" ")),
+    )
+}
+
+/// Writes a function completing options by `_arguments`.
+fn zsh_arguments(function: &str, specs: &[OptSpec]) -> String {
+    let mut script = format!("{}() {{\n    _arguments -s \\\n", function);
+    for spec in specs {
+        let desc = spec.desc.replace('[', r"\[").replace(']', r"\]").replace(':', r"\:");
+        let action = match spec.hint() {
+            None => String::new(),
+            Some(hint @ ("FILE" | "DIR")) => format!(":{}:_files{}", hint, if hint == "DIR" { " -/" } else { "" }),
+            Some(hint) => format!(":{}: ", hint),
+        };
+        let repeat = if matches!(spec.arg, OptArg::Multi(_)) { "*" } else { "" };
+        for name in spec.names() {
+            let arg = format!("{}{}[{}]{}", repeat, name, desc, action);
+            script += &format!("        {} \\\n", quote(&arg));
+        }
+    }
+    script += "        '*:file:_files'\n}\n\n";
+
+    script
+}
+
+fn zsh(program: &str, commands: &[Command], legacy: &[OptSpec]) -> String {
+    let function = format!("_{}", identifier(program));
+    let mut script = format!("#compdef {}\n\n", program);
+
+    script += &zsh_arguments(&format!("{}_legacy", function), legacy);
+    let mut descriptions = String::new();
+    let mut cases = String::new();
+    for command in commands {
+        let command_function = format!("{}_{}", function, command.name);
+        script += &zsh_arguments(&command_function, &command.specs);
+        descriptions += &format!("        {}\n", quote(&format!("{}:{}", command.name, command.summary.replace(':', r"\:"))));
+        cases += &format!("        {})\n            shift words\n            (( CURRENT-- ))\n            {};;\n", command.name, command_function);
+    }
+
+    script += &format!(
+r#"{function}() {{
+    if (( CURRENT == 2 )); then
+        local -a commands
+        commands=(
+{descriptions}        )
+        _describe -t commands command commands
+    fi
+
+    case $words[2] in
+{cases}        *)
+            {function}_legacy;;
+    esac
+}}
+
+{function} "$@"
+"#
+    );
+
+    script
+}
+
+fn fish_options(program: &str, condition: &str, specs: &[OptSpec]) -> String {
+    let mut script = String::new();
+    for spec in specs {
+        script += &format!("complete -c {} -n {}", program, quote(condition));
+        if !spec.short.is_empty() {
+            script += &format!(" -s {}", spec.short);
+        }
+        if !spec.long.is_empty() {
+            script += &format!(" -l {}", spec.long);
+        }
+        match spec.hint() {
+            None => (),
+            Some("FILE" | "DIR") => script += " -r -F",
+            Some(_) => script += " -x",
+        }
+        script += &format!(" -d {}\n", quote(spec.desc));
+    }
+
+    script
+}
+
+fn fish(program: &str, commands: &[Command], legacy: &[OptSpec]) -> String {
+    let mut script = String::new();
+    for command in commands {
+        script += &format!(
+            "complete -c {} -n __fish_use_subcommand -a {} -d {}\n",
+            program, command.name, quote(command.summary),
+        );
+    }
+    script += &fish_options(program, "__fish_use_subcommand", legacy);
+    for command in commands {
+        script += &fish_options(program, &format!("__fish_seen_subcommand_from {}", command.name), &command.specs);
+    }
+
+    script
+}
+
+/// Generates a completion script of `program` for a shell (`bash`, `zsh`, or `fish`).
+///
+/// The subcommands are completed first, and the options of a subcommand after it.
+/// The `legacy` options are completed when no subcommand is given.
+pub fn completion_script(shell: &str, program: &str, commands: &[Command], legacy: &[OptSpec]) -> Option<String> {
+    match shell {
+        "bash" => Some(bash(program, commands, legacy)),
+        "zsh" => Some(zsh(program, commands, legacy)),
+        "fish" => Some(fish(program, commands, legacy)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_scripts() {
+        let commands = [Command {
+            name: "run",
+            summary: "run programs",
+            specs: vec![
+                OptSpec::value("o", "", "write the output to FILE", "FILE"),
+                OptSpec::multi("", "trace-function", "restrict [tracing]: it's a LABEL", "LABEL"),
+            ],
+            implied: &[],
+            action: |_| Ok(()),
+        }];
+        let legacy = [OptSpec::flag("d", "", "dump instruction memory")];
+
+        let bash = completion_script("bash", "picoc_vm_cli", &commands, &legacy).unwrap();
+        assert!(bash.contains("        run) words='-o --trace-function';;\n        *) words='-d';;"));
+        assert!(bash.contains("        -o)\n            COMPREPLY=($(compgen -f -- \"$cur\"))"));
+
+        let zsh = completion_script("zsh", "picoc_vm_cli", &commands, &legacy).unwrap();
+        assert!(zsh.contains(r"'*--trace-function[restrict \[tracing\]\: it'\''s a LABEL]:LABEL: '"));
+        assert!(zsh.contains("'-o[write the output to FILE]:FILE:_files'"));
+        assert!(zsh.contains("        'run:run programs'\n"));
+
+        let fish = completion_script("fish", "picoc_vm_cli", &commands, &legacy).unwrap();
+        assert!(fish.contains("complete -c picoc_vm_cli -n __fish_use_subcommand -a run -d 'run programs'\n"));
+        assert!(fish.contains(
+            "complete -c picoc_vm_cli -n '__fish_seen_subcommand_from run' -s o -r -F -d 'write the output to FILE'\n"
+        ));
+
+        assert!(completion_script("powershell", "picoc_vm_cli", &commands, &legacy).is_none());
+    }
+}
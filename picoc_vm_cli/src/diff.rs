@@ -0,0 +1,143 @@
+use std::io::{self, Cursor};
+use picoc_vm::{PicocVm, Config, Program};
+
+/// What is compared at every step.
+pub struct TraceOptions {
+    pub registers: bool,
+    pub stack: bool,
+}
+
+/// Finds the line and the column where two outputs diverge first.
+fn divergence(a: &[u8], b: &[u8]) -> Option<(usize, usize)> {
+    let pos = a.iter().zip(b).position(|(x, y)| x != y)
+        .or_else(|| (a.len() != b.len()).then(|| a.len().min(b.len())))?;
+
+    let line = a[..pos].iter().filter(|&&c| c == b'\n').count() + 1;
+    let column = pos - a[..pos].iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1) + 1;
+
+    Some((line, column))
+}
+
+/// Gets a line of an output.
+fn nth_line(output: &[u8], line: usize) -> String {
+    let text = String::from_utf8_lossy(output);
+
+    match text.split('\n').nth(line - 1) {
+        Some(l) => format!("{:?}", l),
+        None => "(end of output)".to_string(),
+    }
+}
+
+/// Steps both programs in lockstep until their registers or stacks diverge.
+///
+/// Returns whether a divergence is found.
+fn diff_traces(
+    programs: [&Program; 2],
+    input: &[u8],
+    config: &Config,
+    trace: &TraceOptions
+) -> Result<bool, picoc_vm::Error> {
+    let (mut input_a, mut input_b) = (Cursor::new(input), Cursor::new(input));
+    let (mut output_a, mut output_b) = (io::sink(), io::sink());
+    let (mut prompt_a, mut prompt_b) = (io::sink(), io::sink());
+
+    let mut a = PicocVm::with_config(&mut input_a, &mut output_a, config.clone());
+    let mut b = PicocVm::with_config(&mut input_b, &mut output_b, config.clone());
+    a.set_prompt_output(&mut prompt_a);
+    b.set_prompt_output(&mut prompt_b);
+    a.load_program(programs[0].clone())?;
+    b.load_program(programs[1].clone())?;
+
+    for step in 0.. {
+        let regs_differ = trace.registers && a.registers() != b.registers();
+        let stack_differ = trace.stack && a.stack() != b.stack();
+        if regs_differ || stack_differ {
+            let (ra, rb) = (a.registers(), b.registers());
+            eprintln!("traces diverge at step {}", step);
+            eprintln!("  1: PC = {:05}, SP = {:05}, FP = {:05}, stack = {:?}", ra.pc, ra.sp, ra.fp, a.stack());
+            eprintln!("  2: PC = {:05}, SP = {:05}, FP = {:05}, stack = {:?}", rb.pc, rb.sp, rb.fp, b.stack());
+            return Ok(true);
+        }
+
+        match (a.step(), b.step()) {
+            (Ok(()), Ok(())) => (),
+            (Err(ea), Err(eb)) if ea.to_string() == eb.to_string() => break,
+            (ra, rb) => {
+                let describe = |r: Result<(), picoc_vm::Error>| match r {
+                    Ok(()) => "running".to_string(),
+                    Err(err) => err.to_string(),
+                };
+                eprintln!("traces diverge at step {}", step + 1);
+                eprintln!("  1: {}", describe(ra));
+                eprintln!("  2: {}", describe(rb));
+                return Ok(true);
+            },
+        }
+    }
+
+    Ok(false)
+}
+
+/// Runs a program to the end, returning its output and how it stopped.
+fn run_to_end(program: &Program, input: &[u8], config: &Config) -> Result<(Vec<u8>, String), picoc_vm::Error> {
+    let mut input = Cursor::new(input);
+    let mut output = Vec::new();
+    let mut prompt = io::sink();
+
+    let mut vm = PicocVm::with_config(&mut input, &mut output, config.clone());
+    vm.set_prompt_output(&mut prompt);
+    vm.load_program(program.clone())?;
+    let status = match vm.run_until_halt().and_then(|()| vm.flush()) {
+        Ok(()) => "halted".to_string(),
+        Err(err) => format!("error: {}", err),
+    };
+    drop(vm);
+
+    Ok((output, status))
+}
+
+/// Runs two programs on the same input and reports where they diverge.
+///
+/// Returns whether a divergence is found.
+pub fn run_diff(
+    programs: [&Program; 2],
+    input: &[u8],
+    config: &Config,
+    trace: &TraceOptions
+) -> Result<bool, picoc_vm::Error> {
+    if (trace.registers || trace.stack) && diff_traces(programs, input, config, trace)? {
+        return Ok(true);
+    }
+
+    let (output_a, status_a) = run_to_end(programs[0], input, config)?;
+    let (output_b, status_b) = run_to_end(programs[1], input, config)?;
+
+    if let Some((line, column)) = divergence(&output_a, &output_b) {
+        eprintln!("outputs diverge at line {}, column {}", line, column);
+        eprintln!("  1: {}", nth_line(&output_a, line));
+        eprintln!("  2: {}", nth_line(&output_b, line));
+        return Ok(true);
+    }
+    if status_a != status_b {
+        eprintln!("outputs are identical, but the programs stopped differently");
+        eprintln!("  1: {}", status_a);
+        eprintln!("  2: {}", status_b);
+        return Ok(true);
+    }
+
+    eprintln!("outputs are identical ({} bytes)", output_a.len());
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_divergence() {
+        assert_eq!(divergence(b"1\n2\n", b"1\n2\n"), None);
+        assert_eq!(divergence(b"1\n23\n", b"1\n24\n"), Some((2, 2)));
+        assert_eq!(divergence(b"1\n", b"1\n2\n"), Some((2, 1)));
+        assert_eq!(divergence(b"abc", b"xbc"), Some((1, 1)));
+    }
+}
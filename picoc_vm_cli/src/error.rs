@@ -0,0 +1,104 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+
+/// The exit status for invalid options or arguments, or an I/O error.
+pub const EXIT_USAGE: i32 = 1;
+/// The exit status when a program cannot be assembled or loaded.
+pub const EXIT_ASSEMBLY: i32 = 2;
+/// The exit status when a program fails while running.
+pub const EXIT_RUNTIME: i32 = 3;
+/// The exit status when a program exceeds a limit (e.g. `--max-output`).
+pub const EXIT_LIMIT: i32 = 4;
+/// The exit status when `--diff` finds that two programs diverge.
+pub const EXIT_DIVERGED: i32 = 5;
+
+/// An error which ends the CLI.
+#[derive(Debug)]
+pub enum CliError {
+    /// Options or arguments are invalid.
+    Usage(String),
+    /// An error from the VM, whose exit status depends on its kind.
+    Vm(picoc_vm::Error),
+    /// A command finds a problem (e.g. outputs which differ), with the exit status.
+    Failed(String, i32),
+}
+
+impl CliError {
+    /// Gets the exit status of the process.
+    pub fn exit_code(&self) -> i32 {
+        use picoc_vm::Error::*;
+
+        match self {
+            CliError::Usage(_) => EXIT_USAGE,
+            CliError::Failed(_, code) => *code,
+            // Errors of the source are located, unlike those of the input read by `rd` or `rdt`
+            CliError::Vm(InSource(..)) => EXIT_ASSEMBLY,
+            CliError::Vm(err) => match err {
+                IoError(_) | InvalidCostTable(_) => EXIT_USAGE,
+                ChecksumMismatch(..)
+                | DuplicateLabel(..)
+                | InvalidBinary(_)
+                | InvalidExpression(_)
+                | InvalidLabel(..)
+                | InvalidSymbolFile(_)
+                | LabelNotFound(_)
+                | OpcodeNotFound
+                | OperandNotFound
+                | TargetOutOfBound(_)
+                | UndefinedSymbol(_)
+                | UnknownDirective(_)
+                | UnknownOpcode(_)
+                | UnsupportedFormatVersion(_) => EXIT_ASSEMBLY,
+                CallDepthExceeded(..)
+                | MemoryLimitExceeded(_)
+                | OutputLimitExceeded(_)
+                | StackLimitExceeded(_)
+                | StepLimitExceeded(_)
+                | TimeLimitExceeded(_) => EXIT_LIMIT,
+                _ => EXIT_RUNTIME,
+            },
+        }
+    }
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            CliError::Usage(message) | CliError::Failed(message, _) => write!(f, "{}", message),
+            CliError::Vm(err) => err.fmt(f),
+        }
+    }
+}
+
+impl From<picoc_vm::Error> for CliError {
+    fn from(error: picoc_vm::Error) -> Self {
+        CliError::Vm(error)
+    }
+}
+
+impl From<io::Error> for CliError {
+    fn from(error: io::Error) -> Self {
+        CliError::Vm(picoc_vm::Error::IoError(error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(CliError::Usage("no file".to_string()).exit_code(), EXIT_USAGE);
+        assert_eq!(CliError::Vm(picoc_vm::Error::UnknownOpcode("puhsi".to_string())).exit_code(), EXIT_ASSEMBLY);
+        let located = picoc_vm::Program::assemble(&b"puhsi 1\n"[..]).unwrap_err().in_file("foo.s");
+        assert_eq!(CliError::Vm(located).exit_code(), EXIT_ASSEMBLY);
+        assert_eq!(CliError::Vm(picoc_vm::Error::StackUnderflow).exit_code(), EXIT_RUNTIME);
+        assert_eq!(CliError::Vm(picoc_vm::Error::Trap(5, "vext 1 2".to_string())).exit_code(), EXIT_RUNTIME);
+        let input = "abc".parse::<i32>().unwrap_err();
+        assert_eq!(CliError::Vm(picoc_vm::Error::ParseIntError(input)).exit_code(), EXIT_RUNTIME);
+        assert_eq!(CliError::Vm(picoc_vm::Error::InvalidLiteral("99999999999".to_string())).exit_code(), EXIT_RUNTIME);
+        assert_eq!(CliError::Vm(picoc_vm::Error::OutputLimitExceeded(10)).exit_code(), EXIT_LIMIT);
+        assert_eq!(CliError::from(io::Error::other("broken")).exit_code(), EXIT_USAGE);
+        assert_eq!(CliError::Vm(picoc_vm::Error::NoMoreInput).exit_code(), EXIT_RUNTIME);
+    }
+}
@@ -0,0 +1,132 @@
+use std::fs;
+use picoc_vm::{DefaultDialect, Dialect};
+use crate::command::Args;
+use crate::error::{CliError, EXIT_USAGE};
+
+/// Splits a line into the code and the comment starting with `#`, unless it is quoted.
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return (&line[..i], Some(&line[i..])),
+            _ => (),
+        }
+    }
+
+    (line, None)
+}
+
+/// Formats assembly in the style of the picoc compiler.
+///
+/// A label starts a line, and an instruction or a directive is indented by a tab
+/// with a tab between the mnemonic and the operands.
+/// Comments are kept, and consecutive blank lines are merged.
+fn format_code(code: &str) -> Result<String, picoc_vm::Error> {
+    let mut formatted = String::new();
+    let mut was_blank = true;
+
+    for line in code.lines() {
+        let (text, comment) = split_comment(line);
+        let words = DefaultDialect.split_line(text)?;
+
+        if words.is_empty() && comment.is_none() {
+            if !was_blank {
+                formatted.push('\n');
+            }
+            was_blank = true;
+            continue;
+        }
+        was_blank = false;
+
+        let mut lines = Vec::new();
+        if words.get(1).is_some_and(|word| word == ":") {
+            // Words after a label are ignored by the assembler, so they are kept on the line
+            let mut label_line = String::new();
+            for word in words {
+                if !label_line.is_empty() && word != ":" {
+                    label_line.push(' ');
+                }
+                label_line += &word;
+            }
+            lines.push(label_line);
+        } else if let Some((op, operands)) = words.split_first() {
+            if operands.is_empty() {
+                lines.push(format!("\t{}", op));
+            } else {
+                lines.push(format!("\t{}\t{}", op, operands.join(" ")));
+            }
+        }
+
+        if let Some(comment) = comment {
+            match lines.last_mut() {
+                Some(last) => *last += &format!(" {}", comment.trim_end()),
+                None if line.starts_with(char::is_whitespace) => lines.push(format!("\t{}", comment.trim_end())),
+                None => lines.push(comment.trim_end().to_string()),
+            }
+        }
+
+        for line in lines {
+            formatted += &line;
+            formatted.push('\n');
+        }
+    }
+
+    if was_blank {
+        formatted.truncate(formatted.trim_end().len());
+        formatted.push('\n');
+    }
+
+    Ok(formatted)
+}
+
+/// Formats each file, writing it to stdout, in place with `-w`,
+/// or only checking that it is formatted with `--check`.
+pub fn format_files(args: Args) -> Result<(), CliError> {
+    let mut unformatted = 0;
+
+    for file in args.files() {
+        let code = fs::read_to_string(file)?;
+        let formatted = format_code(&code)?;
+
+        if args.flag("check") {
+            if formatted != code {
+                eprintln!("{} is not formatted", file);
+                unformatted += 1;
+            }
+        } else if args.flag("w") {
+            if formatted != code {
+                fs::write(file, formatted)?;
+                eprintln!("formatted {}", file);
+            }
+        } else {
+            print!("{}", formatted);
+        }
+    }
+
+    if unformatted > 0 {
+        return Err(CliError::Failed(format!("{} files are not formatted", unformatted), EXIT_USAGE));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_assembly() {
+        let code = "# header\nmain:\n   PUSHI   1   # one\n\n\n  loop: end:\n    pushs \"a # b\"\n  # note\n.equ SIZE  4 * 2\n\n";
+
+        assert_eq!(
+            format_code(code).unwrap(),
+            "# header\nmain:\n\tPUSHI\t1 # one\n\nloop: end:\n\tpushs\t\"a # b\"\n\t# note\n\t.equ\tSIZE 4 * 2\n"
+        );
+        assert_eq!(format_code(&format_code(code).unwrap()).unwrap(), format_code(code).unwrap());
+    }
+}
@@ -0,0 +1,242 @@
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use picoc_vm::{Config, Directives, Judge, PicocVm, Program};
+use crate::command::Args;
+use crate::error::{CliError, EXIT_RUNTIME};
+use crate::run::{make_config, parse_number, read_program, source_options};
+
+/// The step limit of a test unless `--max-steps` is given.
+const DEFAULT_MAX_STEPS: u64 = 10_000_000;
+/// The time limit (in seconds) of a test unless `--timeout` is given.
+const DEFAULT_TIMEOUT: u64 = 10;
+
+/// A pair of an input and the output expected from it.
+struct TestCase {
+    /// The name of the file of the expected output, e.g. `sum.expected`.
+    name: String,
+    input: String,
+    expected: String,
+}
+
+/// Finds assembly files (`*.s`) given directly or in directories.
+fn test_files(args: &[String]) -> Result<Vec<PathBuf>, CliError> {
+    let mut files = Vec::new();
+    for arg in args {
+        let path = Path::new(arg);
+        if !path.is_dir() {
+            files.push(path.to_path_buf());
+            continue;
+        }
+
+        let mut found = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "s") {
+                found.push(path);
+            }
+        }
+        found.sort();
+        files.extend(found);
+    }
+
+    Ok(files)
+}
+
+/// Reads a companion file, or `None` if it does not exist.
+fn read_companion(path: &Path) -> Result<Option<String>, CliError> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Finds the test case of an assembly file: `NAME.expected` with `NAME.in` (if exists),
+/// or `None` if there is no `NAME.expected`.
+fn test_case(file: &Path) -> Result<Option<TestCase>, CliError> {
+    let expected_path = file.with_extension("expected");
+    let Some(expected) = read_companion(&expected_path)? else {
+        return Ok(None);
+    };
+    let input = read_companion(&file.with_extension("in"))?.unwrap_or_default();
+    let name = expected_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+    Ok(Some(TestCase { name, input, expected }))
+}
+
+/// Runs a program on the input of a case, returning the output or the message of an error.
+fn run_case(program: &Program, case: &TestCase, config: &Config) -> Result<String, String> {
+    let mut input = Cursor::new(case.input.as_bytes());
+    let mut output = Vec::new();
+
+    let mut vm = PicocVm::with_config(&mut input, &mut output, config.clone());
+    let result = vm.load_program(program.clone())
+        .and_then(|()| vm.run_until_halt())
+        .and_then(|()| vm.flush());
+    drop(vm);
+
+    result.map(|()| String::from_utf8_lossy(&output).to_string()).map_err(|err| err.to_string())
+}
+
+/// Runs a program against the directives in its comments by a judge,
+/// returning the output or the message of an error like [`run_case`].
+fn check_directives(program: &Program, directives: &Directives, judge: &Judge) -> Result<String, String> {
+    match directives.check(judge, program) {
+        Ok(report) => Ok(report.output),
+        // An output which differs is shown as a diff
+        Err(report) => report.error.map_or(Ok(report.output), Err),
+    }
+}
+
+/// Prints the result of a case named `name`, and returns whether it passes.
+fn print_result(file: &Path, name: &str, expected: &str, result: Result<String, String>) -> bool {
+    match result {
+        Ok(actual) => {
+            let diff = line_diff(expected, &actual);
+            if diff.is_empty() {
+                println!("pass {} ({})", file.display(), name);
+                return true;
+            }
+
+            println!("FAIL {} ({}): the output differs", file.display(), name);
+            for line in diff {
+                println!("    {}", line);
+            }
+        },
+        Err(message) => println!("FAIL {} ({}): {}", file.display(), name, message),
+    }
+
+    false
+}
+
+/// Compares two outputs line by line, returning the lines of the difference.
+///
+/// Trailing whitespace of each line is ignored, since `wr` writes a space after a value.
+/// Lines only in the expected output are marked with `-`, and lines only in the actual output with `+`.
+/// Returns nothing if the outputs are the same.
+fn line_diff(expected: &str, actual: &str) -> Vec<String> {
+    let expected: Vec<&str> = expected.lines().map(str::trim_end).collect();
+    let actual: Vec<&str> = actual.lines().map(str::trim_end).collect();
+    if expected == actual {
+        return Vec::new();
+    }
+
+    // The lengths of the longest common subsequences of the suffixes
+    let mut lcs = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            diff.push(format!(" {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j == actual.len() || (i < expected.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push(format!("-{}", expected[i]));
+            i += 1;
+        } else {
+            diff.push(format!("+{}", actual[j]));
+            j += 1;
+        }
+    }
+
+    diff
+}
+
+/// Runs each assembly file (or each `*.s` in a directory) against its test cases,
+/// and prints a summary of passed and failed tests with diffs.
+///
+/// A case is given by `NAME.expected` (the output) with `NAME.in` (the input, empty if it does not exist),
+/// or by `#! input:` and `#! expect:` directives in comments of `NAME.s` (see [`Directives`]).
+/// With `--directives`, only the directives are used.
+/// A file without any case is skipped.
+pub fn run_tests(args: Args) -> Result<(), CliError> {
+    let source = source_options(&args);
+    let only_directives = args.flag("directives");
+    let mut config = Config { no_prompt: true, ..make_config(&args)? };
+    config.limits.max_steps = Some(match args.value("max-steps") {
+        Some(steps) => parse_number(&steps, "step limit")?,
+        None => DEFAULT_MAX_STEPS,
+    });
+    config.limits.max_time = Some(Duration::from_secs(match args.value("timeout") {
+        Some(secs) => parse_number(&secs, "timeout")?,
+        None => DEFAULT_TIMEOUT,
+    }));
+    let judge = Judge::new(config.clone());
+
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for file in test_files(args.files())? {
+        let case = if only_directives { None } else { test_case(&file)? };
+        let directives = read_companion(&file)?.as_deref().and_then(Directives::parse);
+        if case.is_none() && directives.is_none() {
+            skipped += 1;
+            continue;
+        }
+
+        let program = match read_program(&file.to_string_lossy(), &source) {
+            Ok(program) => program,
+            Err(err) => {
+                println!("FAIL {}: {}", file.display(), err);
+                failed += usize::from(case.is_some()) + usize::from(directives.is_some());
+                continue;
+            },
+        };
+
+        let mut results = Vec::new();
+        if let Some(case) = case {
+            results.push(print_result(&file, &case.name, &case.expected, run_case(&program, &case, &config)));
+        }
+        if let Some(directives) = directives {
+            let result = check_directives(&program, &directives, &judge);
+            results.push(print_result(&file, "directives", &directives.expected, result));
+        }
+        passed += results.iter().filter(|&&pass| pass).count();
+        failed += results.iter().filter(|&&pass| !pass).count();
+    }
+    println!("{} passed, {} failed, {} skipped", passed, failed, skipped);
+
+    if failed > 0 {
+        return Err(CliError::Failed(format!("{} tests failed", failed), EXIT_RUNTIME));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        assert!(line_diff("1\n2\n", "1 \n2").is_empty());
+        assert_eq!(line_diff("1\n2\n3\n", "1\n4\n3\n5\n"), [" 1", "-2", "+4", " 3", "+5"]);
+    }
+
+    #[test]
+    fn test_directives() {
+        let code = "#! input: 3\n#! expect: 3\nrd\nwr\nhalt\n";
+        let program = Program::assemble(code.as_bytes()).unwrap();
+        let directives = Directives::parse(code).unwrap();
+        let judge = Judge::default();
+
+        assert_eq!(check_directives(&program, &directives, &judge), Ok("3 ".to_string()));
+
+        let wrong = Directives { expected: "4\n".to_string(), ..directives.clone() };
+        assert_eq!(check_directives(&program, &wrong, &judge), Ok("3 ".to_string()));
+        assert!(!line_diff(&wrong.expected, "3 ").is_empty());
+
+        let short = Directives { input: String::new(), ..directives };
+        assert!(check_directives(&program, &short, &judge).is_err());
+    }
+}
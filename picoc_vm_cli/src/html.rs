@@ -0,0 +1,221 @@
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::rc::Rc;
+use picoc_vm::{Error, PicocVm, Profiler};
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; }
+pre, .code td { font-family: monospace; }
+table { border-collapse: collapse; }
+td, th { padding: 0 0.5em; text-align: left; }
+.code .hit { background: #dfd; }
+.code .miss { background: #fdd; }
+.code .label td { font-weight: bold; background: none; }
+.count { text-align: right; }
+.bar { background: #58c; height: 0.8em; }
+.input { color: #27a; }
+.error { color: #c22; }
+";
+
+/// A piece of the I/O of programs, in the order it happens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Chunk {
+    Input(String),
+    Output(String),
+}
+
+/// The I/O shared by the streams of VMs and a report.
+type Transcript = Rc<RefCell<Vec<Chunk>>>;
+
+/// An input stream which records the bytes read into a transcript.
+pub struct TranscriptReader<R> {
+    inner: R,
+    transcript: Transcript,
+}
+
+impl<R: BufRead> Read for TranscriptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.fill_buf()?.read(buf)?;
+        self.consume(len);
+
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for TranscriptReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // The buffer is still filled, so this does not read the input
+        if let Ok(buf) = self.inner.fill_buf() {
+            let text = String::from_utf8_lossy(&buf[..amt.min(buf.len())]).into_owned();
+            self.transcript.borrow_mut().push(Chunk::Input(text));
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// An output stream which records the bytes written into a transcript.
+pub struct TranscriptWriter<W> {
+    inner: W,
+    transcript: Transcript,
+}
+
+impl<W: Write> Write for TranscriptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.transcript.borrow_mut().push(Chunk::Output(String::from_utf8_lossy(&buf[..len]).into_owned()));
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Escapes a text for HTML.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// A self-contained HTML report of runs: for each program, a profile by function,
+/// the code colored by coverage, the final stack, and the transcript of the I/O.
+#[derive(Default)]
+pub struct HtmlReport {
+    sections: Vec<String>,
+    transcript: Transcript,
+}
+
+impl HtmlReport {
+    /// Wraps an input stream to record what programs read.
+    pub fn reader<R: BufRead>(&self, inner: R) -> TranscriptReader<R> {
+        TranscriptReader { inner, transcript: self.transcript.clone() }
+    }
+
+    /// Wraps an output stream to record what programs write.
+    pub fn writer<W: Write>(&self, inner: W) -> TranscriptWriter<W> {
+        TranscriptWriter { inner, transcript: self.transcript.clone() }
+    }
+
+    /// Adds a run of a program, with the I/O recorded since the last run.
+    ///
+    /// `coverage` is an exact profiler which has sampled every step.
+    pub fn add_run<T, U>(&mut self, file: &str, vm: &PicocVm<T, U>, coverage: &Profiler, error: Option<&Error>)
+    where
+        T: BufRead,
+        U: Write,
+    {
+        let program = vm.program();
+        let mut html = format!("<h1>{}</h1>\n", escape(file));
+
+        let status = match error {
+            Some(err) => format!("<span class=\"error\">{}</span>", escape(&err.to_string())),
+            None => "halted".to_string(),
+        };
+        let executed = (0..program.len()).filter(|&pc| coverage.count(pc) > 0).count();
+        writeln!(
+            html,
+            "<p>{}; {} steps; {} of {} instructions executed</p>",
+            status, vm.steps(), executed, program.len(),
+        ).unwrap();
+
+        html += "<h2>Profile</h2>\n<table>\n<tr><th>function</th><th class=\"count\">steps</th><th class=\"count\">%</th><th></th></tr>\n";
+        for (name, count) in coverage.by_function(program) {
+            let percent = count as f64 * 100.0 / coverage.total().max(1) as f64;
+            writeln!(
+                html,
+                "<tr><td>{}</td><td class=\"count\">{}</td><td class=\"count\">{:.1}</td>\
+                 <td style=\"width: 20em\"><div class=\"bar\" style=\"width: {:.1}%\"></div></td></tr>",
+                escape(&name), count, percent, percent,
+            ).unwrap();
+        }
+        html += "</table>\n";
+
+        let mut labels: Vec<_> = program.labels().iter().map(|(label, &addr)| (addr, label)).collect();
+        labels.sort();
+        let mut labels = labels.into_iter().peekable();
+        html += "<h2>Code</h2>\n<table class=\"code\">\n";
+        for (pc, inst) in program.insts().iter().enumerate() {
+            while let Some((_, label)) = labels.next_if(|&(addr, _)| addr <= pc) {
+                writeln!(html, "<tr class=\"label\"><td></td><td></td><td>{}:</td></tr>", escape(label)).unwrap();
+            }
+            let count = coverage.count(pc);
+            writeln!(
+                html,
+                "<tr class=\"{}\"><td>{:05}</td><td class=\"count\">{}</td><td>{}</td></tr>",
+                if count > 0 { "hit" } else { "miss" }, pc, count, escape(&inst.to_string()),
+            ).unwrap();
+        }
+        html += "</table>\n";
+
+        writeln!(html, "<h2>Stack</h2>\n<pre>{}</pre>", escape(&vm.format_stack())).unwrap();
+
+        html += "<h2>Transcript</h2>\n<pre>";
+        for chunk in self.transcript.borrow_mut().drain(..) {
+            match chunk {
+                Chunk::Input(text) => write!(html, "<span class=\"input\">{}</span>", escape(&text)).unwrap(),
+                Chunk::Output(text) => html += &escape(&text),
+            }
+        }
+        html += "</pre>\n";
+
+        self.sections.push(html);
+    }
+
+    /// Renders the report as a whole document.
+    pub fn render(&self) -> String {
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>picoc vm report</title>\n\
+             <style>\n{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+            STYLE, self.sections.join("<hr>\n"),
+        )
+    }
+
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.render())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn report_run() {
+        let mut report = HtmlReport::default();
+        let mut input = report.reader(Cursor::new(b"21\n"));
+        let mut output = report.writer(Vec::new());
+
+        let mut vm = PicocVm::new(&mut input, &mut output);
+        vm.load(Cursor::new(b"rd\npushi 2\nmul\njp end\nwr\nend:\nwr\nhalt\n")).unwrap();
+        let mut coverage = Profiler::exact();
+        coverage.run(&mut vm).unwrap();
+        vm.flush().unwrap();
+
+        report.add_run("a<b>.s", &vm, &coverage, None);
+        let html = report.render();
+
+        assert!(html.contains("<h1>a&lt;b&gt;.s</h1>"));
+        assert!(html.contains("halted; 6 steps; 6 of 7 instructions executed"));
+        assert!(html.contains("<tr class=\"miss\"><td>00004</td><td class=\"count\">0</td><td>wr</td></tr>"));
+        assert!(html.contains("<tr class=\"label\"><td></td><td></td><td>end:</td></tr>"));
+        assert!(html.contains("<pre>? <span class=\"input\">21\n</span>42 </pre>"));
+    }
+}
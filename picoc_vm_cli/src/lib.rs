@@ -0,0 +1,3 @@
+//! Utilities shared by the binaries of picoc vm.
+
+pub mod json;
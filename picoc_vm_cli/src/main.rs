@@ -1,41 +1,123 @@
 use std::env;
+use std::path::Path;
 use std::process;
 use getopts::Options;
 
+mod batch;
+mod command;
+mod completion;
+mod diff;
+mod error;
+mod fmt;
+mod harness;
+mod html;
+mod remote;
+mod resources;
 mod run;
+mod stepper;
+mod watch;
+mod websocket;
 
-use run::run_vm;
+use command::{build_options, commands, legacy_specs, run_files, Args, Command, OptSpec};
+use completion::completion_script;
+use error::{EXIT_ASSEMBLY, EXIT_DIVERGED, EXIT_LIMIT, EXIT_RUNTIME, EXIT_USAGE};
+use run::STDIN_FILE;
 
-fn print_usage(program: &str, opts: Options, exit_code: i32) -> ! {
-    let brief = format!("Usage: {} [OPTION] FILE...", program);
+fn print_usage(program: &str, commands: &[Command], opts: &Options, exit_code: i32) -> ! {
+    let mut brief = format!("Usage: {} COMMAND [OPTION] FILE...\n\nCommands:\n", program);
+    for command in commands {
+        brief += &format!("    {:<8}{}\n", command.name, command.summary);
+    }
+    brief += "\nA FILE of '-' (at most once) reads the assembly from stdin, so the program input then needs -i.\n";
+    brief += &format!("\nRun '{} help COMMAND' for the options of a command.\n", program);
+    brief += &format!(
+        "\nExit status: 0 on success, {} for invalid options or I/O errors, {} for assembly errors,\n\
+         {} for runtime errors, {} when a limit is exceeded, and {} when --diff finds a divergence.\n\n",
+        EXIT_USAGE, EXIT_ASSEMBLY, EXIT_RUNTIME, EXIT_LIMIT, EXIT_DIVERGED,
+    );
+    brief += "Without a COMMAND, FILEs are run with the following options.";
     print!("{}", opts.usage(&brief));
     process::exit(exit_code);
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+fn print_command_usage(program: &str, command: &Command, opts: &Options, exit_code: i32) -> ! {
+    let brief = format!("Usage: {} {} [OPTION] FILE...\n\n{}.", program, command.name, command.summary);
+    print!("{}", opts.usage(&brief));
+    process::exit(exit_code);
+}
 
-    let mut opts = Options::new();
-    opts.optflag("d", "", "dump instruction memory");
-    opts.optflag("r", "", "trace registers");
-    opts.optflag("s", "", "trace stack");
-    opts.optflag("h", "help", "print help and exit");
+/// Parses the options of a command and runs it, exiting if it fails.
+///
+/// Without a command, the options of earlier versions (`legacy`) are parsed.
+fn run_command(program: &str, commands: &[Command], command: Option<&Command>, legacy: &[OptSpec], args: &[String]) {
+    let opts = build_options(command.map_or(legacy, |command| &command.specs));
+    let usage = |opts: &Options, exit_code| match command {
+        Some(command) => print_command_usage(program, command, opts, exit_code),
+        None => print_usage(program, commands, opts, exit_code),
+    };
 
-    let matches = match opts.parse(&args[1..]) {
+    let matches = match opts.parse(args) {
         Ok(m) => { m },
-        Err(f) => { panic!("{}", f.to_string()) },
+        Err(f) => {
+            eprintln!("error: {}", f);
+            eprintln!("Run '{} help' for usage.", program);
+            process::exit(EXIT_USAGE);
+        },
     };
 
     if matches.opt_present("h") {
-        print_usage(&args[0], opts, 0);
+        usage(&opts, 0);
     }
 
-    if matches.free.len() == 0 {
-        print_usage(&args[0], opts, 1);
+    if matches.free.is_empty() {
+        usage(&opts, EXIT_USAGE);
+    }
+    // stdin is read to the end by the first '-', so a second one would be an empty program
+    if matches.free.iter().filter(|file| *file == STDIN_FILE).count() > 1 {
+        eprintln!("error: '{}' is given more than once", STDIN_FILE);
+        process::exit(EXIT_USAGE);
     }
 
-    match run_vm(matches) {
+    let (implied, action) = command.map_or((&[][..], run_files as fn(Args) -> _), |command| (command.implied, command.action));
+    match action(Args::new(matches, implied)) {
         Ok(()) => (),
-        Err(err) => { panic!("{}", err.to_string()) },
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(err.exit_code());
+        },
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = &args[0];
+    let commands = commands();
+    let specs = legacy_specs();
+
+    match args.get(1).map(String::as_str) {
+        // A hidden command printing a completion script, e.g. `picoc_vm_cli completions bash`
+        Some("completions") => {
+            let shell = args.get(2).map_or("", String::as_str);
+            let name = Path::new(program).file_name().map_or("picoc_vm_cli".into(), |name| name.to_string_lossy());
+            match completion_script(shell, &name, &commands, &specs) {
+                Some(script) => print!("{}", script),
+                None => {
+                    eprintln!("error: Unsupported shell '{}' (bash, zsh, fish)", shell);
+                    process::exit(EXIT_USAGE);
+                },
+            }
+        },
+        Some("help") => {
+            let name = args.get(2).map_or("", String::as_str);
+            match commands.iter().find(|command| command.name == name) {
+                Some(command) => print_command_usage(program, command, &build_options(&command.specs), 0),
+                None => print_usage(program, &commands, &build_options(&specs), 0),
+            }
+        },
+        Some(name) if commands.iter().any(|command| command.name == name) => {
+            let command = commands.iter().find(|command| command.name == name);
+            run_command(program, &commands, command, &specs, &args[2..]);
+        },
+        _ => run_command(program, &commands, None, &specs, &args[1..]),
     }
 }
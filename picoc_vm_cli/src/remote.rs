@@ -0,0 +1,272 @@
+use std::io::{self, BufRead, Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use picoc_vm::{Config, Error, PicocVm};
+use picoc_vm_cli::json::{quote, Json};
+use crate::command::Args;
+use crate::error::CliError;
+use crate::run::{assemble, make_config, source_options, SourceOptions};
+use crate::websocket::WebSocket;
+
+/// The number of steps between messages of the output while running.
+const OUTPUT_INTERVAL: u64 = 1024;
+/// The number of steps executed by `run` unless `max_steps` is given.
+const DEFAULT_RUN_STEPS: u64 = 10_000_000;
+/// The maximum number of steps executed by a request, to which `count` and `max_steps` are clamped.
+const MAX_RUN_STEPS: u64 = 1_000_000_000;
+/// The maximum number of connections served at once.
+const MAX_CONNECTIONS: usize = 16;
+
+/// The input of a remote VM, which is given by `input` requests.
+///
+/// Reading a line which has not been given fails with [`io::ErrorKind::WouldBlock`],
+/// so that the VM stops at `rd` until the client sends the input.
+#[derive(Default)]
+struct InputQueue {
+    buf: Vec<u8>,
+    closed: bool,
+}
+
+impl Read for InputQueue {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+
+        Ok(len)
+    }
+}
+
+impl BufRead for InputQueue {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Only complete lines are read, so that a line is never split
+        if self.closed || self.buf.contains(&b'\n') {
+            Ok(&self.buf)
+        } else {
+            Err(io::Error::new(io::ErrorKind::WouldBlock, "waiting for input"))
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.buf.drain(..amt);
+    }
+}
+
+type RemoteVm<'a> = PicocVm<'a, InputQueue, Vec<u8>>;
+
+/// Why a remote VM stops running.
+enum Stop {
+    /// The number of steps requested are executed.
+    Paused,
+    Halted,
+    /// `rd` or `rdt` waits for an `input` request.
+    InputNeeded,
+    Failed(Error),
+}
+
+/// Serves VMs over WebSocket at the address given as the argument, e.g. `127.0.0.1:8080`.
+///
+/// Each connection has its own VM and is served on its own thread, up to 16 connections at once;
+/// beyond that, a connection is refused with `503 Service Unavailable`.
+/// A client sends requests as JSON objects in text messages:
+///
+/// - `{"op": "load", "source": ASSEMBLY}` loads a program.
+/// - `{"op": "step", "count": N}` executes up to N instructions (1 by default).
+/// - `{"op": "run", "max_steps": N}` runs until the VM halts, fails, or waits for input,
+///   executing up to N instructions (10,000,000 by default).
+///
+///   N of both is clamped to 1,000,000,000, so that a request cannot hold a thread indefinitely.
+/// - `{"op": "input", "text": TEXT}` gives lines read by `rd` and `rdt`.
+/// - `{"op": "close_input"}` ends the input.
+/// - `{"op": "inspect"}` and `{"op": "reset"}` reply with the state.
+///
+/// Every request is answered with one message: `{"event": "loaded", "warnings": [...]}` for `load`,
+/// `{"event": "state", "status": STATUS, "state": STATE}` for the others,
+/// or `{"event": "error", "message": MESSAGE}`.
+/// STATUS is one of `ready`, `paused`, `halted`, `input_needed`, and `failed`,
+/// and STATE is given by [`VmState::to_json`](picoc_vm::VmState::to_json()).
+/// The output of the program is streamed before the answer as `{"event": "output", "text": TEXT}`.
+pub fn serve(args: Args) -> Result<(), CliError> {
+    let config = Config { no_prompt: true, ..make_config(&args)? };
+    let source = source_options(&args);
+    let addr = &args.files()[0];
+
+    let listener = TcpListener::bind(addr)?;
+    eprintln!("listening on ws://{}", listener.local_addr()?);
+
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("connection error: {}", err);
+                continue;
+            },
+        };
+        let Some(slot) = ConnectionSlot::acquire(&connections) else {
+            // The client may be gone already, which does not matter
+            let _ = stream.write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n");
+            continue;
+        };
+
+        let (config, source) = (config.clone(), source.clone());
+        // A long run does not keep other clients waiting
+        thread::spawn(move || {
+            let _slot = slot;
+            let result = WebSocket::accept(stream)
+                .and_then(|mut ws| serve_connection(&mut ws, &config, &source));
+            // A broken connection does not stop the server
+            if let Err(err) = result {
+                eprintln!("connection error: {}", err);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// A connection counted against [`MAX_CONNECTIONS`] until dropped.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    fn acquire(connections: &Arc<AtomicUsize>) -> Option<Self> {
+        connections.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| (count < MAX_CONNECTIONS).then_some(count + 1))
+            .ok()
+            .map(|_| Self(Arc::clone(connections)))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+fn serve_connection(ws: &mut WebSocket, config: &Config, source: &SourceOptions) -> io::Result<()> {
+    let mut input = InputQueue::default();
+    let mut output = Vec::new();
+    let mut vm = PicocVm::with_config(&mut input, &mut output, config.clone());
+
+    while let Some(message) = ws.receive()? {
+        let reply = match Json::parse(&message) {
+            Ok(request) => handle_request(&mut vm, ws, &request, source)?,
+            Err(message) => Err(format!("invalid request: {}", message)),
+        };
+        match reply {
+            Ok(reply) => ws.send(&reply)?,
+            Err(message) => ws.send(&format!(r#"{{"event":"error","message":{}}}"#, quote(&message)))?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles a request, returning the answer or the message of an error.
+fn handle_request(vm: &mut RemoteVm, ws: &mut WebSocket, request: &Json, source: &SourceOptions) -> io::Result<Result<String, String>> {
+    let count = |key| match request.get(key) {
+        None => Ok(None),
+        Some(count) => count.as_u64()
+            .map(|count| Some(count.min(MAX_RUN_STEPS)))
+            .ok_or(format!("'{}' must be a non-negative integer", key)),
+    };
+
+    let stop = match request.get("op").and_then(Json::as_str) {
+        Some("load") => {
+            let Some(code) = request.get("source").and_then(Json::as_str) else {
+                return Ok(Err("'source' is required".to_string()));
+            };
+            let program = match assemble(code.as_bytes(), source) {
+                Ok(program) => program,
+                Err(err) => return Ok(Err(err.to_string())),
+            };
+            let warnings: Vec<String> = program.warnings().iter().map(|w| quote(&w.to_string())).collect();
+            if let Err(err) = vm.load_program(program) {
+                return Ok(Err(err.to_string()));
+            }
+
+            return Ok(Ok(format!(r#"{{"event":"loaded","warnings":[{}]}}"#, warnings.join(","))));
+        },
+        Some("step") => match count("count") {
+            Ok(count) => run(vm, ws, count.unwrap_or(1))?,
+            Err(message) => return Ok(Err(message)),
+        },
+        Some("run") => match count("max_steps") {
+            Ok(max_steps) => run(vm, ws, max_steps.unwrap_or(DEFAULT_RUN_STEPS))?,
+            Err(message) => return Ok(Err(message)),
+        },
+        Some("input") => {
+            let Some(text) = request.get("text").and_then(Json::as_str) else {
+                return Ok(Err("'text' is required".to_string()));
+            };
+            let input = &mut vm.input_mut().buf;
+            input.extend_from_slice(text.as_bytes());
+            if !text.ends_with('\n') {
+                input.push(b'\n');
+            }
+            None
+        },
+        Some("close_input") => {
+            vm.input_mut().closed = true;
+            None
+        },
+        Some("inspect") => None,
+        Some("reset") => {
+            vm.reset();
+            None
+        },
+        Some(op) => return Ok(Err(format!("unknown op '{}'", op))),
+        None => return Ok(Err("'op' is required".to_string())),
+    };
+
+    let status = match &stop {
+        None => "ready",
+        Some(Stop::Paused) => "paused",
+        Some(Stop::Halted) => "halted",
+        Some(Stop::InputNeeded) => "input_needed",
+        Some(Stop::Failed(_)) => "failed",
+    };
+    let mut reply = format!(r#"{{"event":"state","status":"{}","state":{}"#, status, vm.state().to_json());
+    if let Some(Stop::Failed(err)) = &stop {
+        reply += &format!(r#","message":{}"#, quote(&err.to_string()));
+    }
+    reply.push('}');
+
+    Ok(Ok(reply))
+}
+
+/// Runs a VM up to `max_steps` instructions, streaming the output.
+fn run(vm: &mut RemoteVm, ws: &mut WebSocket, max_steps: u64) -> io::Result<Option<Stop>> {
+    let mut steps = 0;
+    let stop = loop {
+        if steps >= max_steps {
+            break if vm.state().halted { Stop::Halted } else { Stop::Paused };
+        }
+
+        match vm.step() {
+            Ok(()) => (),
+            Err(Error::VmHalted) => break Stop::Halted,
+            Err(Error::IoError(err)) if err.kind() == io::ErrorKind::WouldBlock => break Stop::InputNeeded,
+            Err(err) => break Stop::Failed(err),
+        }
+        steps += 1;
+
+        if steps % OUTPUT_INTERVAL == 0 {
+            send_output(vm, ws)?;
+        }
+    };
+    send_output(vm, ws)?;
+
+    Ok(Some(stop))
+}
+
+fn send_output(vm: &mut RemoteVm, ws: &mut WebSocket) -> io::Result<()> {
+    let output = std::mem::take(vm.output_mut());
+    if output.is_empty() {
+        return Ok(());
+    }
+
+    ws.send(&format!(r#"{{"event":"output","text":{}}}"#, quote(&String::from_utf8_lossy(&output))))
+}
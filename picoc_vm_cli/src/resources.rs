@@ -0,0 +1,150 @@
+use std::io::{BufRead, Write};
+use std::time::Duration;
+use picoc_vm::{Error, ExitStatus, Opcode, PicocVm};
+use picoc_vm_cli::json::Json;
+
+/// How a resource report is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// Lines for people.
+    Text,
+    /// A JSON object per program in a line.
+    Json,
+}
+
+/// Counts the resources used by a run: a summary printed with `--report`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceReport {
+    instructions: u64,
+    /// The maximum number of words on the stack.
+    max_stack: usize,
+    calls: u64,
+    /// Words read from the memory by `pushl` and `ld`.
+    reads: u64,
+    /// Words written into the memory by `storel`, `storet`, and `st`.
+    writes: u64,
+    time: Duration,
+}
+
+impl ResourceReport {
+    /// Counts the instruction which the VM executes next.
+    ///
+    /// This should be called before every step.
+    pub fn sample<T, U>(&mut self, vm: &PicocVm<T, U>)
+    where
+        T: BufRead,
+        U: Write,
+    {
+        self.max_stack = self.max_stack.max(vm.memory_map().stack.end().saturating_sub(vm.registers().sp));
+        match vm.program().insts().get(vm.registers().pc) {
+            Some(Opcode::Call(_)) => self.calls += 1,
+            Some(Opcode::Pushl(_) | Opcode::Ld) => self.reads += 1,
+            Some(Opcode::Storel(_) | Opcode::Storet(_) | Opcode::St) => self.writes += 1,
+            _ => (),
+        }
+    }
+
+    /// Takes the counters of the VM after the run.
+    pub fn finish<T, U>(&mut self, vm: &PicocVm<T, U>)
+    where
+        T: BufRead,
+        U: Write,
+    {
+        self.max_stack = self.max_stack.max(vm.memory_map().stack.end().saturating_sub(vm.registers().sp));
+        self.instructions = vm.steps();
+        self.time = vm.elapsed();
+    }
+
+    /// Writes the report of a program which stopped with `error` (if any).
+    pub fn write<W: Write>(&self, file: &str, error: Option<&Error>, format: ReportFormat, mut w: W) -> std::io::Result<()> {
+        let status = match error.map(ExitStatus::of_error).unwrap_or(ExitStatus::Halted) {
+            ExitStatus::Halted => "halted",
+            ExitStatus::AssembleError => "assemble_error",
+            ExitStatus::RuntimeError => "runtime_error",
+            ExitStatus::LimitExceeded => "limit_exceeded",
+            ExitStatus::InfiniteLoop => "infinite_loop",
+        };
+
+        match format {
+            ReportFormat::Text => {
+                writeln!(w, "{}: report", file)?;
+                match error {
+                    Some(err) => writeln!(w, "  status:          {} ({})", status, err)?,
+                    None => writeln!(w, "  status:          {}", status)?,
+                }
+                writeln!(w, "  instructions:    {}", self.instructions)?;
+                writeln!(w, "  max stack depth: {} words", self.max_stack)?;
+                writeln!(w, "  calls:           {}", self.calls)?;
+                writeln!(w, "  reads:           {}", self.reads)?;
+                writeln!(w, "  writes:          {}", self.writes)?;
+                writeln!(w, "  time:            {:?}", self.time)
+            },
+            ReportFormat::Json => {
+                let json = Json::object([
+                    ("file", file.into()),
+                    ("status", status.into()),
+                    ("error", error.map_or(Json::Null, |err| err.to_string().into())),
+                    ("instructions", Json::Number(self.instructions as f64)),
+                    ("max_stack", self.max_stack.into()),
+                    ("calls", Json::Number(self.calls as f64)),
+                    ("reads", Json::Number(self.reads as f64)),
+                    ("writes", Json::Number(self.writes as f64)),
+                    ("time_us", Json::Number(self.time.as_micros() as f64)),
+                ]);
+                writeln!(w, "{}", json)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use picoc_vm::Config;
+
+    #[test]
+    fn report_run() {
+        let mut input = Cursor::new(b"");
+        let mut output = Vec::new();
+        let config = Config { deterministic: true, ..Config::default() };
+
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config);
+        vm.load(Cursor::new(b"
+            main:
+                pushi 1
+                call f
+                halt
+            f:
+                enter
+                pushl 2
+                storel 2
+                leave
+                ret")).unwrap();
+
+        let mut report = ResourceReport::default();
+        let result = loop {
+            report.sample(&vm);
+            if let Err(err) = vm.step() {
+                break err;
+            }
+        };
+        report.finish(&vm);
+        assert!(matches!(result, Error::VmHalted));
+
+        let mut text = Vec::new();
+        report.write("a.s", None, ReportFormat::Text, &mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("instructions:    8\n"));
+        assert!(text.contains("max stack depth: 4 words\n"));
+        assert!(text.contains("calls:           1\n"));
+
+        let mut json = Vec::new();
+        report.write("a.s", Some(&Error::StepLimitExceeded(5)), ReportFormat::Json, &mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "{\"calls\":1,\"error\":\"Execution exceeds 5 steps\",\"file\":\"a.s\",\"instructions\":8,\
+             \"max_stack\":4,\"reads\":1,\"status\":\"limit_exceeded\",\"time_us\":0,\"writes\":1}\n",
+        );
+    }
+}
@@ -1,9 +1,19 @@
-use std::io::{self, BufReader, BufRead, Write};
-use std::fs::File;
+use std::io::{self, BufReader, BufRead, BufWriter, Read, Write};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
 use std::iter;
-use getopts::Matches;
-use picoc_vm::{PicocVm, Opcode};
-use picoc_vm::VM_STACK_SIZE;
+use picoc_vm::{PicocVm, ChromeTrace, Opcode, Config, CycleCosts, FlushPolicy, Radix, Program, AliasDialect, CompatDialect, DefaultDialect, Dialect, LoadMode, Profiler, TraceFilter, Tracer, transpile, compile_wasm};
+use crate::batch::run_batch;
+use crate::command::Args;
+use crate::error::{CliError, EXIT_ASSEMBLY, EXIT_DIVERGED, EXIT_RUNTIME};
+use crate::diff::{run_diff, TraceOptions};
+use crate::html::HtmlReport;
+use crate::resources::{ReportFormat, ResourceReport};
+use crate::stepper::Stepper;
+
+/// The number of latest instructions printed after a runtime error.
+const RECENT_DEPTH: usize = 16;
 
 fn dump_inst_memory<T, U>(vm: &PicocVm<T, U>)
 where
@@ -14,7 +24,7 @@ where
     let label_table = vm.label_table();
 
     for (i, inst) in iter::zip(0..imem.len(), imem) {
-        eprint!("{:4}: {}", i, inst.to_string());
+        eprint!("{:4}: {}", i, inst);
         match inst {
             Opcode::Call(l)
                 | Opcode::Jp(l)
@@ -26,29 +36,42 @@ where
                 },
             _ => (),
         }
-        eprintln!("");
+        eprintln!();
     }
 }
 
+/// Writes a map file: the label table, and each instruction with its source location.
+fn write_map(program: &Program, path: &Path) -> Result<(), CliError> {
+    let mut map = io::BufWriter::new(File::create(path)?);
+    let debug_info = program.debug_info();
+
+    let mut labels: Vec<_> = program.labels().iter().map(|(label, &addr)| (addr, label)).collect();
+    labels.sort();
+
+    writeln!(map, "# labels")?;
+    for (addr, label) in labels {
+        writeln!(map, "{:05} {}", addr, label)?;
+    }
+
+    writeln!(map, "# instructions")?;
+    for (addr, inst) in program.insts().iter().enumerate() {
+        let location = debug_info.location(addr).map_or("-".to_string(), |loc| loc.to_string());
+        match debug_info.function(addr) {
+            Some(func) => writeln!(map, "{:05} {:<24} {} ({})", addr, inst.to_string(), location, func)?,
+            None => writeln!(map, "{:05} {:<24} {}", addr, inst.to_string(), location)?,
+        }
+    }
+    map.flush()?;
+
+    Ok(())
+}
+
 fn trace_stack<T, U>(vm: &PicocVm<T, U>)
 where
     T: BufRead,
     U: Write,
 {
-    let stack = vm.stack();
-    let reg = vm.registers();
-    let bottom = VM_STACK_SIZE - stack.len();
-
-    for (i, data) in iter::zip(bottom..VM_STACK_SIZE, stack).rev() {
-        eprintln!(
-            "{:04} {:11}{}{}",
-            i,
-            data,
-            if i == reg.fp { " <-- FP" } else { "" },
-            if i == reg.sp { " <-- SP" } else { "" },
-        );
-    }
-    eprintln!("");
+    eprintln!("{}", vm.format_stack());
 }
 
 fn trace_registers<T, U>(vm: &PicocVm<T, U>)
@@ -61,42 +84,690 @@ where
     eprintln!("PC = {:05}, SP = {:05}, FP = {:05}", reg.pc, reg.sp, reg.fp);
 }
 
-pub fn run_vm(matches: Matches) -> Result<(), picoc_vm::Error> {
-    let dump_imem = matches.opt_present("d");
-    let trace_regs = matches.opt_present("r");
-    let trace_stk = matches.opt_present("s");
+/// A trace file written by `-t`, which may be compressed by a `gzip` process.
+struct TraceFile {
+    tracer: Tracer<Box<dyn Write>>,
+    gzip: Option<Child>,
+}
 
-    for file in matches.free {
-        let mut input = io::stdin().lock();
-        let mut output = io::stdout();
+impl TraceFile {
+    fn create(path: &str) -> io::Result<Self> {
+        if !path.ends_with(".gz") {
+            let file = BufWriter::new(File::create(path)?);
+            return Ok(Self { tracer: Tracer::new(Box::new(file)), gzip: None });
+        }
 
-        let mut vm = PicocVm::new(&mut input, &mut output);
+        let mut gzip = Command::new("gzip")
+            .arg("-c")
+            .stdin(Stdio::piped())
+            .stdout(File::create(path)?)
+            .spawn()?;
+        let stdin = BufWriter::new(gzip.stdin.take().expect("stdin of gzip is piped"));
 
-        let file = File::open(file)?;
-        let code = BufReader::new(file);
+        Ok(Self { tracer: Tracer::new(Box::new(stdin)), gzip: Some(gzip) })
+    }
 
-        vm.load(code)?;
+    /// Flushes the trace and waits until `gzip` finishes.
+    fn finish(self) -> io::Result<()> {
+        let mut writer = self.tracer.into_inner();
+        writer.flush()?;
+        drop(writer);
+
+        if let Some(mut gzip) = self.gzip {
+            let status = gzip.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("gzip failed ({})", status)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_trace_filters(args: &Args) -> Result<Vec<TraceFilter>, CliError> {
+    let functions = args.values("trace-function").into_iter().map(|label| Ok(TraceFilter::Function(label)));
+    let ranges = args.values("trace-between").into_iter().map(|range| {
+        let (start, end) = range.split_once(',')
+            .ok_or_else(|| CliError::Usage(format!("Invalid trace range '{}' (expected START,END)", range)))?;
+        Ok(TraceFilter::Labels(start.to_string(), end.to_string()))
+    });
+
+    functions.chain(ranges).collect()
+}
+
+fn parse_flush_policy(policy: &str) -> Result<FlushPolicy, CliError> {
+    match policy {
+        "write" => Ok(FlushPolicy::EveryWrite),
+        "line" => Ok(FlushPolicy::EveryLine),
+        "manual" => Ok(FlushPolicy::Manual),
+        other => Err(CliError::Usage(format!("Unknown flush policy '{}'", other))),
+    }
+}
+
+fn parse_radix(radix: &str) -> Result<Radix, CliError> {
+    match radix {
+        "2" => Ok(Radix::Binary),
+        "8" => Ok(Radix::Octal),
+        "10" => Ok(Radix::Decimal),
+        "16" => Ok(Radix::Hexadecimal),
+        other => Err(CliError::Usage(format!("Unsupported radix '{}'", other))),
+    }
+}
+
+/// Parses the argument of an option as a number.
+pub fn parse_number<N: std::str::FromStr>(value: &str, what: &str) -> Result<N, CliError> {
+    value.parse().map_err(|_| CliError::Usage(format!("Invalid {} '{}'", what, value)))
+}
+
+pub fn make_config(args: &Args) -> Result<Config, CliError> {
+    let mut config = Config {
+        echo_input: args.flag("e"),
+        no_prompt: args.flag("q") || args.flag("no-prompt"),
+        writable_code: args.flag("writable-code"),
+        check_uninitialized: args.flag("check-uninit"),
+        poison_frames: args.flag("poison-frames"),
+        shadow_stack: args.flag("shadow-stack"),
+        tag_slots: args.flag("tag-slots"),
+        detect_loops: args.flag("detect-loops"),
+        deterministic: args.flag("deterministic"),
+        recent_depth: RECENT_DEPTH,
+        ..Config::default()
+    };
+
+    if let Some(policy) = args.value("flush") {
+        config.flush_policy = parse_flush_policy(&policy)?;
+    }
+    if let Some(radix) = args.value("radix") {
+        config.input_radix = parse_radix(&radix)?;
+    }
+    if let Some(size) = args.value("heap") {
+        config.heap_size = parse_number(&size, "heap size")?;
+    }
+    if let Some(size) = args.value("max-output") {
+        config.limits.max_output_bytes = Some(parse_number(&size, "output limit")?);
+    }
+    if let Some(depth) = args.value("max-calls") {
+        config.limits.max_calls = Some(parse_number(&depth, "call depth limit")?);
+    }
+    if let Some(path) = args.value("costs") {
+        config.cycle_costs = CycleCosts::parse(&fs::read_to_string(path)?)?;
+    }
+
+    Ok(config)
+}
+
+/// Describes where an instruction is, e.g. `00012 in f (main.c:7)`.
+fn describe_address(program: &Program, pc: usize, function: Option<&str>) -> String {
+    let mut text = format!("{:05}", pc);
+    if let Some(function) = function {
+        text += &format!(" in {}", function);
+    }
+    if let Some(loc) = program.debug_info().location(pc) {
+        text += &format!(" ({})", loc);
+    }
+
+    text
+}
+
+/// Prints the call stack, the latest executed instructions, and the instructions around PC
+/// after a runtime error.
+fn print_backtrace<T, U>(vm: &PicocVm<T, U>)
+where
+    T: BufRead,
+    U: Write
+{
+    let program = vm.program();
+
+    eprintln!("backtrace:");
+    let mut pc = vm.registers().pc;
+    for (i, frame) in vm.frames().iter().enumerate() {
+        eprintln!("  #{} {}", i, describe_address(program, pc, frame.function_label.as_deref()));
+        // The caller is running the `call` just before the return address
+        pc = frame.return_pc.unwrap_or_default().saturating_sub(1);
+    }
+
+    let mut recent = vm.recent_instructions().peekable();
+    if recent.peek().is_some() {
+        eprintln!("recent instructions:");
+        for (addr, inst) in recent {
+            eprintln!("  {:05}: {}", addr, inst);
+        }
+    }
+
+    let pc = vm.registers().pc;
+    let insts = program.insts();
+    if insts.is_empty() {
+        return;
+    }
+    eprintln!("disassembly:");
+    let start = pc.saturating_sub(3).min(insts.len() - 1);
+    let end = (pc + 4).min(insts.len());
+    for (addr, inst) in insts.iter().enumerate().take(end).skip(start) {
+        let marker = if addr == pc { "=>" } else { "  " };
+        eprintln!("  {} {:05}: {}", marker, addr, inst);
+    }
+}
+
+/// The FILE argument reading a program from stdin, e.g. `picoc file.c | picoc_vm_cli -`.
+pub const STDIN_FILE: &str = "-";
+
+/// Names the file written for a FILE by its extension, e.g. `a.pcb` for `a.s`.
+///
+/// A program read from stdin has no name to derive one from, so it is rejected.
+fn derived_path(file: &str, extension: &str) -> Result<PathBuf, CliError> {
+    if file == STDIN_FILE {
+        return Err(CliError::Usage(format!("Cannot name the .{} file of a program read from stdin", extension)));
+    }
+
+    Ok(Path::new(file).with_extension(extension))
+}
+
+/// Reads the assembly of a file, compiling a picoc source file (`.pc` or `.c`)
+/// with the companion compiler, which writes the assembly to stdout.
+///
+/// The assembly is read from stdin if the file is [`STDIN_FILE`].
+fn read_assembly(file: &str, compiler: &str, emit_asm: bool) -> Result<Vec<u8>, CliError> {
+    if file == STDIN_FILE {
+        let mut code = Vec::new();
+        io::stdin().read_to_end(&mut code)?;
+        return Ok(code);
+    }
+
+    let path = Path::new(file);
+    let is_source = path.extension()
+        .is_some_and(|ext| ext == "pc" || ext == "c");
+    if !is_source {
+        return Ok(fs::read(path)?);
+    }
+
+    let mut args = compiler.split_whitespace();
+    let command = args.next().unwrap_or("picoc");
+    let output = Command::new(command).args(args).arg(path).output()?;
+    io::stderr().write_all(&output.stderr)?;
+    if !output.status.success() {
+        return Err(CliError::Failed(
+            format!("{} failed to compile {} ({})", command, file, output.status),
+            EXIT_ASSEMBLY,
+        ));
+    }
+
+    if emit_asm {
+        let asm_path = path.with_extension("s");
+        fs::write(&asm_path, &output.stdout)?;
+        eprintln!("{} -> {}", file, asm_path.display());
+    }
+
+    Ok(output.stdout)
+}
+
+/// How each FILE is read into a program.
+#[derive(Clone)]
+pub struct SourceOptions {
+    /// The command compiling picoc source files.
+    compiler: String,
+    /// Whether the assembly compiled from a source file is written.
+    emit_asm: bool,
+    /// Whether the listing format of the original picoc toolchain is accepted.
+    compat: bool,
+    /// Whether aliases of mnemonics are accepted.
+    aliases: bool,
+    /// Whether warnings are errors.
+    deny_warnings: bool,
+    /// How unknown instructions are loaded.
+    load_mode: LoadMode,
+}
+
+pub fn source_options(args: &Args) -> SourceOptions {
+    SourceOptions {
+        compiler: args.value("compiler").unwrap_or("picoc".to_string()),
+        emit_asm: args.flag("emit-asm"),
+        compat: args.flag("compat"),
+        aliases: args.flag("aliases"),
+        deny_warnings: args.flag("deny-warnings"),
+        load_mode: if args.flag("permissive") { LoadMode::Permissive } else { LoadMode::Strict },
+    }
+}
+
+/// Reads and assembles a program from a file.
+///
+/// A binary program (`.pcb`) is loaded as it is, with its symbol file (`.sym`) if exists.
+pub fn read_program(file: &str, source: &SourceOptions) -> Result<Program, CliError> {
+    let path = Path::new(file);
+    if path.extension().is_some_and(|ext| ext == "pcb") {
+        let mut program = Program::from_bytes(&fs::read(path)?)?;
+        match File::open(path.with_extension("sym")) {
+            Ok(f) => program.load_symbols(BufReader::new(f))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err.into()),
+        }
+
+        return Ok(program);
+    }
+
+    let code = read_assembly(file, &source.compiler, source.emit_asm)?;
+    let name = if file == STDIN_FILE { "<stdin>" } else { file };
+    let program = assemble(&code, source).map_err(|err| err.in_file(name))?;
+
+    let warnings = program.warnings();
+    for warning in &warnings {
+        eprintln!("{}: warning: {}", name, warning);
+    }
+    if source.deny_warnings && !warnings.is_empty() {
+        return Err(CliError::Failed(
+            format!("{} warnings are denied by --deny-warnings", warnings.len()),
+            EXIT_ASSEMBLY,
+        ));
+    }
+
+    Ok(program)
+}
+
+/// Assembles a program in the dialect given by the options.
+pub fn assemble(code: &[u8], source: &SourceOptions) -> Result<Program, picoc_vm::Error> {
+    let dialect: &dyn Dialect = if source.compat { &CompatDialect } else { &DefaultDialect };
+    if source.aliases {
+        Program::assemble_with_mode(code, &AliasDialect::with_common_aliases(dialect), source.load_mode)
+    } else {
+        Program::assemble_with_mode(code, dialect, source.load_mode)
+    }
+}
+
+fn transpile_files(files: &[String], config: &Config, source: &SourceOptions) -> Result<(), CliError> {
+    for file in files {
+        let path = derived_path(file, "rs")?;
+        let program = read_program(file, source)?;
+
+        fs::write(&path, transpile(&program, config)?)?;
+        eprintln!("{} -> {}", file, path.display());
+    }
+
+    Ok(())
+}
+
+fn compile_wasm_files(files: &[String], config: &Config, source: &SourceOptions) -> Result<(), CliError> {
+    for file in files {
+        let path = derived_path(file, "wasm")?;
+        let program = read_program(file, source)?;
+
+        fs::write(&path, compile_wasm(&program, config)?)?;
+        eprintln!("{} -> {}", file, path.display());
+    }
+
+    Ok(())
+}
+
+fn write_binary_files(files: &[String], source: &SourceOptions) -> Result<(), CliError> {
+    for file in files {
+        let path = derived_path(file, "pcb")?;
+        let program = read_program(file, source)?;
+
+        fs::write(&path, program.to_bytes())?;
+        eprintln!("{} -> {}", file, path.display());
+
+        let sym_path = path.with_extension("sym");
+        program.write_symbols(File::create(&sym_path)?)?;
+        eprintln!("{} -> {}", file, sym_path.display());
+    }
+
+    Ok(())
+}
+
+fn diff_files(
+    files: &[String],
+    input_path: Option<String>,
+    config: &Config,
+    source: &SourceOptions,
+    trace: &TraceOptions
+) -> Result<(), CliError> {
+    let [file_a, file_b] = files else {
+        return Err(CliError::Usage("--diff needs exactly two files".to_string()));
+    };
+
+    let program_a = read_program(file_a, source)?;
+    let program_b = read_program(file_b, source)?;
+    // Both programs read the same input
+    let input = match input_path {
+        Some(path) => fs::read(path)?,
+        None => {
+            let mut buf = Vec::new();
+            io::stdin().read_to_end(&mut buf)?;
+            buf
+        },
+    };
+
+    if run_diff([&program_a, &program_b], &input, config, trace)? {
+        return Err(CliError::Failed("programs diverge".to_string(), EXIT_DIVERGED));
+    }
+
+    Ok(())
+}
+
+fn run_batch_files(
+    files: &[String],
+    dir: &str,
+    config: &Config,
+    source: &SourceOptions
+) -> Result<(), CliError> {
+    let mut failed = 0;
+    for file in files {
+        let program = read_program(file, source)?;
+
+        eprintln!("{}:", file);
+        failed += run_batch(&program, dir, config)?;
+    }
+
+    if failed > 0 {
+        return Err(CliError::Failed(format!("{} runs failed", failed), EXIT_RUNTIME));
+    }
+
+    Ok(())
+}
+
+/// Assembles each file without running it, reporting every file which has an error.
+pub fn check_files(args: Args) -> Result<(), CliError> {
+    let source = source_options(&args);
+
+    let mut failed = 0;
+    for file in args.files() {
+        let result = read_program(file, &source)
+            .and_then(|program| Ok(program.check_call_targets().map(|()| program)?));
+        match result {
+            Ok(program) => eprintln!("{}: ok ({} instructions)", file, program.len()),
+            // An error in the source already begins with the file name
+            Err(err @ CliError::Vm(picoc_vm::Error::InSource(..))) => {
+                eprintln!("{}", err);
+                failed += 1;
+            },
+            Err(err) => {
+                eprintln!("{}: {}", file, err);
+                failed += 1;
+            },
+        }
+    }
+
+    if failed > 0 {
+        return Err(CliError::Failed(format!("{} files have errors", failed), EXIT_ASSEMBLY));
+    }
+
+    Ok(())
+}
+
+/// Writes each file in the format given by `-f` (binary, rust, or wasm).
+pub fn asm_files(args: Args) -> Result<(), CliError> {
+    let config = make_config(&args)?;
+    let source = source_options(&args);
+
+    if args.flag("map") {
+        for file in args.files() {
+            let map_path = derived_path(file, "map")?;
+            write_map(&read_program(file, &source)?, &map_path)?;
+            eprintln!("{} -> {}", file, map_path.display());
+        }
+    }
+
+    match args.value("f").as_deref().unwrap_or("binary") {
+        "binary" => write_binary_files(args.files(), &source),
+        "rust" => transpile_files(args.files(), &config, &source),
+        "wasm" => compile_wasm_files(args.files(), &config, &source),
+        other => Err(CliError::Usage(format!("Unknown output format '{}' (binary, rust, wasm)", other))),
+    }
+}
+
+/// Writes the instructions of each file as assembly, with a label before each labeled instruction.
+pub fn disasm_files(args: Args) -> Result<(), CliError> {
+    let source = source_options(&args);
+    let mut output: Box<dyn Write> = match args.value("o") {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    for file in args.files() {
+        let program = read_program(file, &source)?;
+
+        let mut labels: Vec<_> = program.labels().iter().map(|(label, &addr)| (addr, label)).collect();
+        labels.sort();
+        let mut labels = labels.into_iter().peekable();
+
+        writeln!(output, "# {}", file)?;
+        for addr in 0..=program.len() {
+            while let Some((_, label)) = labels.next_if(|&(label_addr, _)| label_addr <= addr) {
+                writeln!(output, "{}:", label)?;
+            }
+            if let Some(inst) = program.insts().get(addr) {
+                match inst.to_string().split_once(' ') {
+                    Some((op, operand)) => writeln!(output, "\t{}\t{}", op, operand)?,
+                    None => writeln!(output, "\t{}", inst)?,
+                }
+            }
+        }
+    }
+    output.flush()?;
+
+    Ok(())
+}
+
+pub fn run_vm(args: Args) -> Result<(), CliError> {
+    let dump_imem = args.flag("d");
+    let trace_regs = args.flag("r");
+    let trace_stk = args.flag("s");
+    let explain = args.flag("explain");
+    let interactive = args.flag("interactive");
+    let prompt_to_stderr = args.flag("p");
+    let emit_map = args.flag("map");
+    let state_path = args.value("dump-state");
+    let base = args.value("base").map(|base| parse_number(&base, "base address")).transpose()?.unwrap_or(0);
+    let mut config = make_config(&args)?;
+    let source = source_options(&args);
+    let trace_path = args.value("t");
+    let trace_filters = parse_trace_filters(&args)?;
+    let sample_interval = args.value("sample")
+        .map(|n| parse_number(&n, "sampling interval"))
+        .transpose()?;
+    let profile = args.flag("profile") || sample_interval.is_some();
+    let html_path = args.value("html");
+    let chrome_path = args.value("chrome-trace");
+    let report_format = match args.value("report-format").as_deref() {
+        None if args.flag("report") => Some(ReportFormat::Text),
+        None => None,
+        Some("text") => Some(ReportFormat::Text),
+        Some("json") => Some(ReportFormat::Json),
+        Some(format) => return Err(CliError::Usage(format!("Unknown report format '{}'", format))),
+    };
+    // Calls are found by the events of the VM
+    if chrome_path.is_some() {
+        config.record_events = true;
+    }
+    // Tracing and checking the stack need the interpreter to stop at every instruction
+    #[cfg(feature = "jit")]
+    let use_jit = args.flag("jit") && !trace_regs && !trace_stk && !explain && !interactive && trace_path.is_none() && !profile
+        && !config.check_uninitialized && !config.poison_frames && html_path.is_none()
+        && chrome_path.is_none() && report_format.is_none();
+    // Recording recent instructions would make the JIT interpret, so backtraces go without them
+    #[cfg(feature = "jit")]
+    if use_jit {
+        config.recent_depth = 0;
+    }
+
+    if args.flag("transpile") {
+        return transpile_files(args.files(), &config, &source);
+    }
+    if args.flag("wasm") {
+        return compile_wasm_files(args.files(), &config, &source);
+    }
+    if args.flag("binary") {
+        return write_binary_files(args.files(), &source);
+    }
+    if args.flag("diff") {
+        let trace = TraceOptions { registers: trace_regs, stack: trace_stk };
+        return diff_files(args.files(), args.value("i"), &config, &source, &trace);
+    }
+    if let Some(dir) = args.value("batch") {
+        return run_batch_files(args.files(), &dir, &config, &source);
+    }
+
+    // Commands are read from stdin, which must not be the input of the programs as well
+    if interactive && args.value("i").is_none() {
+        return Err(CliError::Usage("--interactive reads commands from stdin, so the input must be given by -i".to_string()));
+    }
+    let mut stepper = interactive.then(Stepper::default);
+
+    // Programs are read before stdin is locked as the input, since one of them may be read from stdin
+    let programs = args.files().iter()
+        .map(|file| read_program(file, &source))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Programs share the input, which continues from where the last one stopped
+    let mut input: Box<dyn BufRead> = match args.value("i") {
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(io::stdin().lock()),
+    };
+
+    // Prompts stay on the terminal when the output is written to a file
+    let output_path = args.value("o");
+    let mut output: Box<dyn Write> = match &output_path {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+
+    // Traces of all programs are written to the same file
+    let mut trace = trace_path.as_deref().map(TraceFile::create).transpose()?;
+
+    // So is the report, which records the I/O of all programs
+    let mut html = html_path.as_ref().map(|_| HtmlReport::default());
+    if let Some(html) = &html {
+        input = Box::new(html.reader(input));
+        output = Box::new(html.writer(output));
+    }
+
+    for (file, program) in iter::zip(args.files(), programs) {
+        let mut stdout = io::stdout();
+        let mut stderr = io::stderr();
+
+        let mut vm = PicocVm::with_config(&mut input, &mut output, config.clone());
+        if prompt_to_stderr {
+            vm.set_prompt_output(&mut stderr);
+        } else if output_path.is_some() {
+            vm.set_prompt_output(&mut stdout);
+        }
+
+        vm.load_program_at(program, base)?;
+
+        if emit_map {
+            let map_path = derived_path(file, "map")?;
+            write_map(vm.program(), &map_path)?;
+            eprintln!("{} -> {}", file, map_path.display());
+        }
 
         if dump_imem {
             dump_inst_memory(&vm);
         }
 
+        let ranges = trace_filters.iter()
+            .map(|filter| filter.resolve(vm.program()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(trace) = &mut trace {
+            trace.tracer.set_ranges(ranges.clone());
+        }
+        let mut profiler = profile.then(|| {
+            let mut profiler = Profiler::sampling(sample_interval.unwrap_or(1));
+            profiler.set_ranges(ranges.clone());
+            profiler
+        });
+        let is_traced = |pc| ranges.is_empty() || ranges.iter().any(|range| range.contains(&pc));
+        let mut coverage = html.is_some().then(Profiler::exact);
+        let mut chrome = chrome_path.is_some().then(ChromeTrace::default);
+        let mut resources = report_format.map(|_| ResourceReport::default());
+
         let mut result = Ok(());
+        // The JIT runs until the VM halts, so errors are reported below as the interpreter's are
+        #[cfg(feature = "jit")]
+        if use_jit {
+            result = picoc_vm::Jit::default().run(&mut vm).and(Err(picoc_vm::Error::VmHalted));
+        }
         while result.is_ok() {
-            if trace_stk {
+            let traced = is_traced(vm.registers().pc);
+            if trace_stk && traced {
                 trace_stack(&vm);
             }
-            if trace_regs {
+            if trace_regs && traced {
                 trace_registers(&vm);
             }
-            result = vm.step();
+            if let Some(trace) = &mut trace {
+                trace.tracer.trace(&vm)?;
+            }
+            if let Some(profiler) = &mut profiler {
+                profiler.sample(&vm);
+            }
+            if let Some(coverage) = &mut coverage {
+                coverage.sample(&vm);
+            }
+            if let Some(chrome) = &mut chrome {
+                chrome.sample(&mut vm);
+            }
+            if let Some(resources) = &mut resources {
+                resources.sample(&vm);
+            }
+            if let Some(stepper) = &mut stepper {
+                if !stepper.next(&mut io::stdin().lock(), &mut io::stderr())? {
+                    break;
+                }
+            }
+            if explain && traced {
+                let pc = vm.registers().pc;
+                result = vm.step_explained().map(|text| eprintln!("{:05}: {}", pc, text));
+            } else {
+                result = vm.step();
+            }
+        }
+
+        vm.flush()?;
+        for issue in vm.stack_issues() {
+            eprintln!("{}: warning: {}", file, issue);
+        }
+        if args.value("costs").is_some() {
+            eprintln!("{}: {} steps, {} cycles", file, vm.steps(), vm.cycles());
+        }
+        if let Some(profiler) = &profiler {
+            profiler.write_report(vm.program(), io::stderr())?;
+        }
+        if let (Some(html), Some(coverage)) = (&mut html, &coverage) {
+            let error = result.as_ref().err().filter(|err| !matches!(err, picoc_vm::Error::VmHalted));
+            html.add_run(file, &vm, coverage, error);
+        }
+        if let (Some(resources), Some(format)) = (&mut resources, report_format) {
+            resources.finish(&vm);
+            let error = result.as_ref().err().filter(|err| !matches!(err, picoc_vm::Error::VmHalted));
+            resources.write(file, error, format, io::stderr())?;
+        }
+        if let (Some(chrome), Some(path)) = (&mut chrome, &chrome_path) {
+            chrome.sample(&mut vm);
+            chrome.write_json(vm.program(), BufWriter::new(File::create(path)?))?;
         }
 
         match result {
             Ok(()) | Err(picoc_vm::Error::VmHalted) => (),
-            Err(err) => return Err(err),
+            Err(err) => {
+                print_backtrace(&vm);
+                if let Some(path) = &state_path {
+                    fs::write(path, vm.state().to_json() + "\n")?;
+                }
+                if let Some(trace) = trace {
+                    trace.finish()?;
+                }
+                if let (Some(html), Some(path)) = (&html, &html_path) {
+                    html.write(path)?;
+                }
+                return Err(err.into());
+            },
         }
     }
 
+    if let Some(trace) = trace {
+        trace.finish()?;
+    }
+    if let (Some(html), Some(path)) = (&html, &html_path) {
+        html.write(path)?;
+    }
+
     Ok(())
 }
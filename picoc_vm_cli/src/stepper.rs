@@ -0,0 +1,133 @@
+use std::io::{self, BufRead, Write};
+
+/// The help of the commands, printed for an unknown command.
+const HELP: &str = "commands: s[tep] [N], c[ontinue], q[uit], h[istory], !N (repeat command N), \
+                    or an empty line to repeat the last command";
+
+/// What the debugger does after a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    /// Executes a number of instructions before the next command.
+    Step(u64),
+    /// Runs to the end without stopping.
+    Continue,
+    Quit,
+}
+
+/// Reads the commands of `debug --interactive`, keeping their history in memory.
+#[derive(Debug, Default)]
+pub struct Stepper {
+    history: Vec<String>,
+    /// The instructions to execute before reading the next command.
+    remaining: u64,
+    continuing: bool,
+}
+
+impl Stepper {
+    /// Decides whether to execute the next instruction, reading commands if needed.
+    ///
+    /// Returns `false` if the user quits.
+    /// The end of `commands` continues the program, so that piped commands run out gracefully.
+    pub fn next(&mut self, commands: &mut impl BufRead, prompt: &mut impl Write) -> io::Result<bool> {
+        if self.continuing {
+            return Ok(true);
+        }
+        while self.remaining == 0 {
+            write!(prompt, "(debug) ")?;
+            prompt.flush()?;
+
+            let mut line = String::new();
+            if commands.read_line(&mut line)? == 0 {
+                self.continuing = true;
+                return Ok(true);
+            }
+
+            match self.command(line.trim()) {
+                Ok(Some(Action::Step(n))) => self.remaining = n,
+                Ok(Some(Action::Continue)) => {
+                    self.continuing = true;
+                    return Ok(true);
+                },
+                Ok(Some(Action::Quit)) => return Ok(false),
+                Ok(None) => {
+                    for (i, command) in self.history.iter().enumerate() {
+                        writeln!(prompt, "{:4}  {}", i + 1, command)?;
+                    }
+                },
+                Err(message) => writeln!(prompt, "{}", message)?,
+            }
+        }
+
+        self.remaining -= 1;
+        Ok(true)
+    }
+
+    /// Parses a command, expanding an empty line and `!N` from the history.
+    ///
+    /// Returns `None` for `history`, which is not recorded.
+    fn command(&mut self, line: &str) -> Result<Option<Action>, String> {
+        let line = if line.is_empty() {
+            self.history.last().cloned().unwrap_or_else(|| "step".to_string())
+        } else if let Some(n) = line.strip_prefix('!') {
+            n.parse::<usize>().ok()
+                .and_then(|n| self.history.get(n.checked_sub(1)?))
+                .cloned()
+                .ok_or_else(|| format!("no command {} in the history", line))?
+        } else {
+            line.to_string()
+        };
+
+        let mut words = line.split_whitespace();
+        let action = match (words.next(), words.next()) {
+            (Some("s" | "step"), None) => Action::Step(1),
+            (Some("s" | "step"), Some(n)) => match n.parse() {
+                Ok(n) if n > 0 => Action::Step(n),
+                _ => return Err(format!("'{}' is not a positive number of steps", n)),
+            },
+            (Some("c" | "continue"), None) => Action::Continue,
+            (Some("q" | "quit"), None) => Action::Quit,
+            (Some("h" | "history"), None) => return Ok(None),
+            _ => return Err(format!("unknown command '{}'\n{}", line, HELP)),
+        };
+        self.history.push(line);
+
+        Ok(Some(action))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts the instructions executed until the user quits or the commands run out.
+    fn count_steps(commands: &str, limit: usize) -> (usize, String) {
+        let mut stepper = Stepper::default();
+        let mut commands = commands.as_bytes();
+        let mut prompt = Vec::new();
+
+        let steps = (0..limit)
+            .take_while(|_| stepper.next(&mut commands, &mut prompt).unwrap())
+            .count();
+
+        (steps, String::from_utf8(prompt).unwrap())
+    }
+
+    #[test]
+    fn repeat_commands() {
+        // `step 2` twice by an empty line, once more by `!1`, and one step of `s`
+        let (steps, _) = count_steps("step 2\n\n!1\ns\nq\n", 100);
+        assert_eq!(steps, 7);
+
+        let (steps, prompt) = count_steps("s 3\nhistory\nfoo\n!9\nq\n", 100);
+        assert_eq!(steps, 3);
+        assert!(prompt.contains("   1  s 3\n"));
+        assert!(prompt.contains("unknown command 'foo'"));
+        assert!(prompt.contains("no command !9 in the history"));
+
+        // The end of the commands continues the program
+        let (steps, _) = count_steps("s\n", 100);
+        assert_eq!(steps, 100);
+        let (steps, _) = count_steps("c\nq\n", 100);
+        assert_eq!(steps, 100);
+    }
+}
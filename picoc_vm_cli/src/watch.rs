@@ -0,0 +1,41 @@
+use std::io::{self, Write};
+use std::fs;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use crate::command::Args;
+use crate::run::run_vm;
+
+/// How often the files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Gets the modification time of each file, or `None` if it cannot be read (e.g. while being saved).
+fn modified_times(files: &[String]) -> Vec<Option<SystemTime>> {
+    files.iter()
+        .map(|file| fs::metadata(file).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+}
+
+/// Runs the programs, and runs them again whenever any of the files changes.
+///
+/// The screen is cleared before every run, and an error is printed instead of exiting.
+pub fn run_watch(args: Args) -> ! {
+    loop {
+        let times = modified_times(args.files());
+
+        // Clear the screen and move the cursor to the top left
+        print!("\x1b[2J\x1b[H");
+        let _ = io::stdout().flush();
+
+        if let Err(err) = run_vm(args.clone()) {
+            eprintln!("error: {}", err);
+        }
+        let _ = io::stdout().flush();
+        eprintln!("\n[watching {} for changes]", args.files().join(", "));
+
+        while modified_times(args.files()) == times {
+            thread::sleep(POLL_INTERVAL);
+        }
+        // Editors may write a file in several steps
+        thread::sleep(POLL_INTERVAL);
+    }
+}
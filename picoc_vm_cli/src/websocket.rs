@@ -0,0 +1,335 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// The GUID appended to a key of the opening handshake (RFC 6455).
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// The maximum size of a message from a client.
+const MAX_MESSAGE_SIZE: u64 = 16 << 20;
+/// The maximum length of a line of the opening handshake, including CRLF.
+const MAX_HEADER_LINE: u64 = 8 << 10;
+/// The maximum number of header fields of the opening handshake.
+const MAX_HEADERS: usize = 64;
+/// How long a client may take to send the opening handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+/// Computes SHA-1 of bytes, which is only used by the handshake.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476, 0xc3d2_e1f0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (h, x) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(x);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Computes `Sec-WebSocket-Accept` from `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, HANDSHAKE_GUID).as_bytes()))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads a line of the opening handshake without CRLF.
+fn read_header_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.take(MAX_HEADER_LINE).read_line(&mut line)? == 0 {
+        return Err(invalid_data("connection closed during handshake"));
+    }
+    if !line.ends_with('\n') {
+        return Err(invalid_data("header line is too long"));
+    }
+
+    Ok(line.trim_end().to_string())
+}
+
+/// Whether a comma-separated header value contains a token, ignoring case.
+fn has_token(value: &str, token: &str) -> bool {
+    value.split(',').any(|item| item.trim().eq_ignore_ascii_case(token))
+}
+
+/// A server side of a WebSocket connection, which exchanges text messages.
+pub struct WebSocket {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl WebSocket {
+    /// Accepts the opening handshake of a client.
+    ///
+    /// The request must be a `GET` with `Upgrade: websocket`, `Connection: Upgrade`,
+    /// and `Sec-WebSocket-Key`, in at most [`MAX_HEADERS`] lines of [`MAX_HEADER_LINE`] bytes
+    /// sent within [`HANDSHAKE_TIMEOUT`].
+    pub fn accept(stream: TcpStream) -> io::Result<Self> {
+        // A client which never completes the handshake does not hold the connection
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        let request_line = read_header_line(&mut reader)?;
+        let mut parts = request_line.split_whitespace();
+        let is_get = parts.next() == Some("GET") && parts.next().is_some()
+            && parts.next().is_some_and(|version| version.starts_with("HTTP/1."))
+            && parts.next().is_none();
+
+        let (mut key, mut upgrade, mut connection) = (None, false, false);
+        let mut headers = 0;
+        loop {
+            let line = read_header_line(&mut reader)?;
+            if line.is_empty() {
+                break;
+            }
+            headers += 1;
+            if headers > MAX_HEADERS {
+                return Err(invalid_data("too many header fields"));
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                let (name, value) = (name.trim(), value.trim());
+                if name.eq_ignore_ascii_case("sec-websocket-key") {
+                    key = Some(value.to_string());
+                } else if name.eq_ignore_ascii_case("upgrade") {
+                    upgrade = has_token(value, "websocket");
+                } else if name.eq_ignore_ascii_case("connection") {
+                    connection = has_token(value, "upgrade");
+                }
+            }
+        }
+
+        let Some(key) = key.filter(|_| is_get && upgrade && connection) else {
+            writer.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+            return Err(invalid_data("not a WebSocket handshake"));
+        };
+        write!(
+            writer,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(&key),
+        )?;
+        writer.set_read_timeout(None)?;
+
+        Ok(Self { reader, writer })
+    }
+
+    /// Receives a text message, or `None` if the client closes the connection.
+    ///
+    /// Pings are answered while waiting.
+    /// A frame which is not masked fails, since every frame from a client must be (RFC 6455).
+    pub fn receive(&mut self) -> io::Result<Option<String>> {
+        let mut message = Vec::new();
+        loop {
+            let mut header = [0; 2];
+            match self.reader.read_exact(&mut header) {
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                result => result?,
+            }
+            let fin = header[0] & 0x80 != 0;
+            let opcode = header[0] & 0x0f;
+            if header[1] & 0x80 == 0 {
+                return Err(invalid_data("frame from the client is not masked"));
+            }
+
+            let len = match header[1] & 0x7f {
+                126 => {
+                    let mut len = [0; 2];
+                    self.reader.read_exact(&mut len)?;
+                    u16::from_be_bytes(len) as u64
+                },
+                127 => {
+                    let mut len = [0; 8];
+                    self.reader.read_exact(&mut len)?;
+                    u64::from_be_bytes(len)
+                },
+                len => len as u64,
+            };
+            // A length near u64::MAX must not wrap around the limit
+            if len > MAX_MESSAGE_SIZE - message.len() as u64 {
+                return Err(invalid_data("message is too large"));
+            }
+
+            let mut mask = [0; 4];
+            self.reader.read_exact(&mut mask)?;
+            let mut payload = vec![0; len as usize];
+            self.reader.read_exact(&mut payload)?;
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+
+            match opcode {
+                OP_CLOSE => {
+                    self.send_frame(OP_CLOSE, &payload)?;
+                    return Ok(None);
+                },
+                OP_PING => self.send_frame(OP_PONG, &payload)?,
+                OP_PONG => (),
+                OP_TEXT | OP_BINARY | OP_CONTINUATION => {
+                    message.extend_from_slice(&payload);
+                    if fin {
+                        return String::from_utf8(message)
+                            .map(Some)
+                            .map_err(|_| invalid_data("message is not UTF-8"));
+                    }
+                },
+                _ => return Err(invalid_data("unknown frame")),
+            }
+        }
+    }
+
+    /// Sends a text message.
+    pub fn send(&mut self, text: &str) -> io::Result<()> {
+        self.send_frame(OP_TEXT, text.as_bytes())
+    }
+
+    fn send_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(len as u8),
+            len @ 126..=0xffff => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            },
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            },
+        }
+        frame.extend_from_slice(payload);
+
+        self.writer.write_all(&frame)?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshake_key() {
+        // The example of RFC 6455
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    /// Connects a client to a server socket.
+    fn connect() -> (TcpStream, TcpStream) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        (client, stream)
+    }
+
+    fn handshake(request: &[u8]) -> io::Result<WebSocket> {
+        let (mut client, stream) = connect();
+        client.write_all(request).unwrap();
+
+        WebSocket::accept(stream)
+    }
+
+    #[test]
+    fn validate_handshake() {
+        let request = "GET /vm HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+                       Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        assert!(handshake(request.as_bytes()).is_ok());
+
+        for invalid in [
+            request.replace("GET", "POST"),
+            request.replace("Upgrade: websocket", "Upgrade: h2c"),
+            request.replace("keep-alive, Upgrade", "keep-alive"),
+            request.replace("Host: localhost", &format!("Host: {}", "a".repeat(MAX_HEADER_LINE as usize))),
+            request.replace("Host: localhost\r\n", &"X: y\r\n".repeat(MAX_HEADERS + 1)),
+        ] {
+            let err = handshake(invalid.as_bytes()).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn unmasked_frame_is_rejected() {
+        let (mut client, stream) = connect();
+        let mut socket = WebSocket { reader: BufReader::new(stream.try_clone().unwrap()), writer: stream };
+
+        client.write_all(&[0x80 | OP_TEXT, 1, b'a']).unwrap();
+
+        let err = socket.receive().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn huge_continuation_is_rejected() {
+        let (mut client, stream) = connect();
+        let mut socket = WebSocket { reader: BufReader::new(stream.try_clone().unwrap()), writer: stream };
+
+        // A text frame without FIN, and a continuation of 2^64 - 1 bytes, both masked by zeros
+        client.write_all(&[OP_TEXT, 0x80 | 1, 0, 0, 0, 0, b'a', 0x80 | OP_CONTINUATION, 0x80 | 127]).unwrap();
+        client.write_all(&u64::MAX.to_be_bytes()).unwrap();
+
+        let err = socket.receive().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}